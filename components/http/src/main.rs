@@ -16,12 +16,9 @@
 use clap::Parser;
 use std::sync::Arc;
 
-use dynamo_llm::{
-    http::service::{
-        discovery::{model_watcher, ModelWatchState},
-        service_v2::HttpService,
-    },
-    model_type::ModelType,
+use dynamo_llm::http::service::{
+    discovery::{model_watcher, ModelWatchState},
+    service_v2::HttpService,
 };
 use dynamo_runtime::{
     logging, transports::etcd::PrefixWatcher, DistributedRuntime, Result, Runtime, Worker,
@@ -76,27 +73,19 @@ async fn app(runtime: Runtime) -> Result<()> {
         .component(&args.component)?;
     let etcd_root = component.etcd_path();
 
-    // Create watchers for all model types
-    let mut watcher_tasks = Vec::new();
-
-    for model_type in ModelType::all() {
-        let etcd_path = format!("{}/models/{}/", etcd_root, model_type.as_str());
-
-        let state = Arc::new(ModelWatchState {
-            prefix: etcd_path.clone(),
-            model_type,
-            manager: manager.clone(),
-            drt: distributed.clone(),
-        });
-
-        if let Some(etcd_client) = distributed.etcd_client() {
-            let models_watcher: PrefixWatcher =
-                etcd_client.kv_get_and_watch_prefix(etcd_path).await?;
-
-            let (_prefix, _watcher, receiver) = models_watcher.dissolve();
-            let watcher_task = tokio::spawn(model_watcher(state, receiver));
-            watcher_tasks.push(watcher_task);
-        }
+    // One watcher for all model types, each is under its own directory of models/
+    let etcd_path = format!("{}/models/", etcd_root);
+    let state = Arc::new(ModelWatchState {
+        prefix: etcd_path.clone(),
+        manager: manager.clone(),
+        drt: distributed.clone(),
+        namespace: None,
+    });
+    if let Some(etcd_client) = distributed.etcd_client() {
+        let models_watcher: PrefixWatcher = etcd_client.kv_get_and_watch_prefix(etcd_path).await?;
+
+        let (_prefix, _watcher, receiver) = models_watcher.dissolve();
+        let _watcher_task = tokio::spawn(model_watcher(state, receiver));
     }
 
     // Run the service
@@ -14,6 +14,7 @@
 // limitations under the License.
 
 use std::env;
+use std::path::Path;
 use std::process::Command;
 
 fn main() {
@@ -23,6 +24,49 @@ fn main() {
     if is_mac() && !has_feature("metal") {
         println!("cargo:warning=Metal not enabled, re-run with `--features metal`");
     }
+    #[cfg(feature = "grpc")]
+    if let Err(err) = tonic_build::compile_protos("proto/chat.proto") {
+        panic!(
+            "Failed compiling proto/chat.proto, `--features grpc` needs protoc installed: {err}"
+        );
+    }
+    // Reported by `GET /version`
+    if let Some(sha) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=DYNAMO_GIT_SHA={sha}");
+        rerun_if_head_moves();
+    }
+}
+
+/// Build again when the sha would change: on checkout, which rewrites HEAD, and on commit,
+/// which moves the branch HEAD points at. Also watches this file, as any rerun-if-changed
+/// replaces cargo's default of watching the whole package.
+fn rerun_if_head_moves() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let mut paths = vec!["HEAD".to_string(), "packed-refs".to_string()];
+    // None on a detached HEAD, where HEAD holds the sha itself
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        paths.push(head_ref);
+    }
+    for path in paths {
+        // Asks git, as a worktree keeps HEAD and the refs in different directories
+        let Some(path) = git(&["rev-parse", "--git-path", &path]) else {
+            continue;
+        };
+        // cargo reruns every build for a missing path, e.g. before refs are first packed
+        if Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={path}");
+        }
+    }
+}
+
+/// The trimmed output of a git command. None when not building from a git checkout.
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (!output.is_empty()).then_some(output)
 }
 
 fn has_feature(s: &str) -> bool {
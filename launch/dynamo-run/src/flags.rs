@@ -16,12 +16,28 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 
 use clap::ValueEnum;
+use dynamo_llm::engines::openai::{UpstreamPool, DEFAULT_UPSTREAM_POOL_SIZE};
+use dynamo_llm::engines::{EchoDelays, MaxTokensLimits};
+use dynamo_llm::http::service::{
+    listener::HttpVersion, metrics_export::MetricsExport, ModelMetadata,
+};
+use dynamo_llm::preprocessor::tools::ToolCallFormat;
+use dynamo_llm::preprocessor::{
+    EngineCapabilities, PreprocessorOptions, DEFAULT_MAX_STOP_SEQUENCES,
+};
+use dynamo_llm::tokenizers::StreamGranularity;
 use dynamo_runtime::component::RouterMode as RuntimeRouterMode;
+use dynamo_runtime::distributed::DistributedConfig;
+use serde::Serialize;
 
 /// Required options depend on the in and out choices
-#[derive(clap::Parser, Debug, Clone)]
+///
+/// Every flag can also be set with a `DYNEMO_` environment variable, e.g. `--http-port` with
+/// `DYNEMO_HTTP_PORT`. The flag wins if both are given.
+#[derive(clap::Parser, Debug, Clone, Serialize)]
 #[command(version, about, long_about = None)]
 pub struct Flags {
     /// The model. The options depend on the engine.
@@ -31,19 +47,213 @@ pub struct Flags {
     /// - Full path of a checked out Hugging Face repository containing safetensor files
     /// - Name of a Hugging Face repository, e.g 'google/flan-t5-small'. The model will be
     ///   downloaded and cached.
+    /// - A `.tar.gz`, `.tgz` or `.zip` archive of a Hugging Face repository checkout. It will be
+    ///   extracted to `~/.cache/dynamo/models` and loaded from there.
     #[arg(index = 1)]
     pub model_path_pos: Option<PathBuf>,
 
     // `--model-path`. The one above is `dynamo-run <positional-model-path>`
-    #[arg(long = "model-path")]
+    #[arg(long = "model-path", env = "DYNEMO_MODEL_PATH")]
     pub model_path_flag: Option<PathBuf>,
 
+    /// Treat likely mistakes on the command line as errors instead of warnings, for example a
+    /// positional model path and a different `--model-path`.
+    #[arg(long, env = "DYNEMO_STRICT", default_value = "false")]
+    pub strict: bool,
+
     /// HTTP port. `in=http` only
-    #[arg(long, default_value = "8080")]
+    #[arg(long, env = "DYNEMO_HTTP_PORT", default_value = "8080")]
     pub http_port: u16,
 
+    /// gRPC port. `in=grpc` only
+    #[arg(long, env = "DYNEMO_GRPC_PORT", default_value = "50051")]
+    pub grpc_port: u16,
+
+    /// Enable TCP keep-alive on HTTP client connections once idle this many seconds, so
+    /// connections to clients which went away get closed. `in=http` only. Off by default.
+    #[arg(long, env = "DYNEMO_HTTP_KEEPALIVE")]
+    pub http_keepalive: Option<u64>,
+
+    /// HTTP versions to serve: `1` (HTTP/1.1), `2` (HTTP/2 over cleartext, h2c, with prior
+    /// knowledge) or `auto` for whichever each client speaks. `in=http` only.
+    #[arg(long, env = "DYNEMO_HTTP_VERSION", default_value = "auto")]
+    pub http_version: HttpVersion,
+
+    /// Report this `system_fingerprint` in all responses instead of the engine's, so clients
+    /// can pin to a known backend revision. `in=http` only.
+    #[arg(long, env = "DYNEMO_SYSTEM_FINGERPRINT")]
+    pub system_fingerprint: Option<String>,
+
+    /// Parse request bodies as JSON even when the `Content-Type` isn't `application/json`, for
+    /// clients which send `text/plain` or nothing. `in=http` only. Otherwise those get a 415.
+    #[arg(long, env = "DYNEMO_LENIENT_CONTENT_TYPE", default_value = "false")]
+    pub lenient_content_type: bool,
+
+    /// Accept numbers given as strings (`"temperature": "0.7"`) and integers given as floats
+    /// (`"max_tokens": 100.0`) in chat requests. `in=http` only. Otherwise those get a 422.
+    #[arg(long, env = "DYNEMO_LENIENT_NUMBERS", default_value = "false")]
+    pub lenient_numbers: bool,
+
+    /// Fail requests whose engine ends the stream without a finish reason, with an error event
+    /// when streaming and a 500 otherwise. `in=http` only. By default they get `stop`, and if the
+    /// engine sent nothing at all, an empty response with a `warning` annotation.
+    #[arg(long, env = "DYNEMO_STRICT_FINISH_REASON", default_value = "false")]
+    pub strict_finish_reason: bool,
+
+    /// Reject `/v1/completions` requests asking for `logprobs` with a 400 if the engine doesn't
+    /// report scores. `in=http` only. By default they are served without them, as `echo` is.
+    #[arg(long, env = "DYNEMO_STRICT_LOGPROBS", default_value = "false")]
+    pub strict_logprobs: bool,
+
+    /// Serve requests for a model we don't have with the one we do, for clients which send a
+    /// fixed `model`. Only when serving a single model. `in=http` only. Otherwise those get a 404
+    /// `model_not_found`.
+    #[arg(long, env = "DYNEMO_ALLOW_ANY_MODEL", default_value = "false")]
+    pub allow_any_model: bool,
+
+    /// Repair trailing commas and unclosed strings, arrays and objects in the output of chat
+    /// requests asking for `response_format` `json_object` or `json_schema`. `in=http` only.
+    /// Streamed content can't be changed once sent, so those responses end with a `json_repair`
+    /// annotation with the repaired content instead. Output which can't be repaired is sent as
+    /// generated, flagged with `null` there or `X-JSON-Repair: failed`.
+    #[arg(long, env = "DYNEMO_REPAIR_JSON", default_value = "false")]
+    pub repair_json: bool,
+
+    /// Stop requests whose engine sends nothing for this many seconds, for example because it
+    /// hung. The clock restarts with every token, so long generations are fine. `in=http` and
+    /// `in=text` only. Streaming HTTP clients get the tokens so far then an error event,
+    /// non-streaming ones a 500. No timeout by default.
+    #[arg(long, env = "DYNEMO_REQUEST_TIMEOUT", value_parser = parse_seconds)]
+    pub request_timeout: Option<Duration>,
+
+    /// Send streamed responses with more than this many characters of content in one chunk as
+    /// several smaller events, for clients with small buffers. The content is unchanged once
+    /// concatenated. `in=http` only. Chunks are sent as the engine made them by default.
+    #[arg(long, env = "DYNEMO_MAX_CHUNK_CHARS")]
+    pub max_chunk_chars: Option<usize>,
+
+    /// Reject requests asking for more than this many choices (the OpenAI `n` parameter) with a
+    /// 400, to bound their cost. `in=http` only. Raise it only for engines which support `n`.
+    #[arg(long, env = "DYNEMO_MAX_N", default_value_t = 1)]
+    pub max_n: u8,
+
+    /// Let clients send `X-Debug-Token-Ids: true` to get their prompt's token ids back in the
+    /// `X-Debug-Token-Ids` response header, to check tokenization. `in=http` only, and only for
+    /// engines where we do the pre-processing. Off by default as it reveals the prompt template.
+    #[arg(long, env = "DYNEMO_DEBUG_TOKEN_IDS", default_value = "false")]
+    pub debug_token_ids: bool,
+
+    /// Also write the response of requests with an `X-Tee: true` header to this JSONL file, one
+    /// line per chunk, for debugging. Never slows down the client: if the file can't keep up
+    /// chunks are dropped, with a warning. `in=http` only.
+    #[arg(long, env = "DYNEMO_TEE_FILE")]
+    pub tee_file: Option<PathBuf>,
+
+    /// JSON file of named sampling presets, e.g.
+    /// `{"creative": {"temperature": 1.1, "top_p": 0.95}, "precise": {"temperature": 0.2}}`.
+    /// Requests pick one with the `X-Sampling-Profile: <name>` header, and it fills in the
+    /// temperature, top_p, frequency_penalty and presence_penalty they don't set. `in=http` only.
+    #[arg(long, env = "DYNEMO_SAMPLING_PROFILES")]
+    pub sampling_profiles: Option<PathBuf>,
+
+    /// Answer repeated greedy (`temperature: 0`), non-streaming chat requests from a cache of
+    /// this many responses, instead of running the engine again. Cached responses have the
+    /// header `X-Cache: HIT`. `in=http` only. 0, the default, disables the cache.
+    #[arg(long, env = "DYNEMO_RESPONSE_CACHE_SIZE", default_value_t = 0)]
+    pub response_cache_size: usize,
+
+    /// Serve the resolved configuration on `GET /admin/config`, and the requests in flight on
+    /// `GET /admin/requests`, to clients sending `Authorization: Bearer <key>` with this key.
+    /// `in=http` only. Not served by default.
+    #[arg(long, env = "DYNEMO_ADMIN_API_KEY", hide_env_values = true)]
+    pub admin_api_key: Option<String>,
+
+    /// On shutdown (SIGTERM, Ctrl+C) give in-flight requests this many seconds to finish before
+    /// the HTTP server stops. New requests get a 503 meanwhile, and responses still streaming
+    /// after that are cut off. `in=http` only. Default 30.
+    #[arg(long, env = "DYNEMO_SHUTDOWN_GRACE_SECS")]
+    pub shutdown_grace_secs: Option<u64>,
+
+    /// Serve `GET /debug/headers`, which returns the request's headers as JSON, to check what
+    /// reaches the server behind proxies and load balancers. Credentials (`Authorization`,
+    /// `Cookie`, `X-Api-Key`, ...) are redacted. `in=http` only. Off by default.
+    #[arg(long, env = "DYNEMO_DEBUG_ECHO_HEADERS", default_value = "false")]
+    pub debug_echo_headers: bool,
+
+    /// Maximum number of open HTTP connections. Connections beyond that are answered with a 503
+    /// and closed. `in=http` only. Unlimited by default.
+    #[arg(long, env = "DYNEMO_HTTP_MAX_CONNECTIONS")]
+    pub http_max_connections: Option<usize>,
+
+    /// Start the HTTP server before loading the model, so orchestrators can follow loading on
+    /// `GET /health/startup`. It returns 503 with the current stage until the model is served.
+    /// `in=http` only.
+    #[arg(long, env = "DYNEMO_SERVE_BEFORE_READY", default_value = "false")]
+    pub serve_before_ready: bool,
+
+    /// Maximum number of requests sent to the engine at once. `in=http` only.
+    /// Further requests wait in a queue, ordered by priority. Requests with `service_tier: flex`
+    /// are lowest priority. Unlimited if not set.
+    #[arg(long, env = "DYNEMO_MAX_CONCURRENT_REQUESTS")]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// `<name>=<n>`. Requests for model `name` queue ahead of requests for models with a lower
+    /// priority, when `--max-concurrent-requests` is reached. Models default to 0, `n` can be
+    /// negative. Can be given more than once. `in=http` only.
+    #[arg(long, env = "DYNEMO_MODEL_PRIORITY", value_parser = parse_model_priority)]
+    pub model_priority: Vec<(String, i32)>,
+
+    /// Push the HTTP metrics to a collector as well as serving them on `/metrics`. `in=http` only.
+    /// `otlp=<url>` for an OTLP/HTTP collector, e.g. `otlp=http://localhost:4318`, or
+    /// `statsd=<host:port>` for StatsD. Can be given more than once.
+    #[arg(long, env = "DYNEMO_METRICS_EXPORT")]
+    pub metrics_export: Vec<MetricsExport>,
+
+    /// Also serve the HTTP metrics on `/metrics` at this port, in Prometheus text format, for
+    /// scrapers which shouldn't reach the client facing port. `in=http` only.
+    #[arg(long, env = "DYNEMO_METRICS_PORT")]
+    pub metrics_port: Option<u16>,
+
+    /// Context length to report in `/v1/models`. `in=http` only.
+    /// Engines where we do the pre-processing report the model's own context length by default.
+    #[arg(long, env = "DYNEMO_CONTEXT_LENGTH")]
+    pub context_length: Option<usize>,
+
+    /// Maximum output tokens to report in `/v1/models`. `in=http` only.
+    #[arg(long, env = "DYNEMO_MAX_OUTPUT_TOKENS")]
+    pub max_output_tokens: Option<usize>,
+
+    /// `max_tokens` for requests which don't set it, whichever the input. With `in=http` this is
+    /// the default for all models, and models discovered from `in=dyn://` workers started with
+    /// their own `--max-tokens` use theirs instead.
+    #[arg(long, visible_alias = "default-max-tokens", env = "DYNEMO_MAX_TOKENS")]
+    pub max_tokens: Option<u32>,
+
+    /// The most tokens a request may ask for. Larger `max_tokens` are lowered to this (see
+    /// `--strict-max-tokens`), and requests which don't set it get at most this many. Does not
+    /// apply to out=dyn://, set it on the worker instead.
+    #[arg(long, env = "DYNEMO_MAX_MAX_TOKENS")]
+    pub max_max_tokens: Option<u32>,
+
+    /// Reject requests with `max_tokens` above `--max-max-tokens` with a 400, instead of
+    /// lowering it.
+    #[arg(long, env = "DYNEMO_STRICT_MAX_TOKENS", default_value = "false")]
+    pub strict_max_tokens: bool,
+
+    /// Run identical requests which arrive while the first of them is still running only once,
+    /// and stream that response to all of them. Only deterministic requests (`temperature` 0,
+    /// `n` 1) are coalesced. Does not apply to out=dyn://, set it on the worker instead.
+    #[arg(long, env = "DYNEMO_COALESCE_REQUESTS", default_value = "false")]
+    pub coalesce_requests: bool,
+
+    /// Seed for sampling, for reproducible output. Requests which set their own `seed` use that
+    /// instead. Engines which can't seed their sampling warn once and ignore it. Does not apply
+    /// to out=dyn://, set it on the worker instead.
+    #[arg(long, env = "DYNEMO_SEED")]
+    pub seed: Option<i64>,
+
     /// The name of the model we are serving
-    #[arg(long)]
+    #[arg(long, env = "DYNEMO_MODEL_NAME")]
     pub model_name: Option<String>,
 
     /// llamacpp only
@@ -52,35 +262,70 @@ pub struct Flags {
     /// - llama_cpp only runs GGUF files
     /// - our engine is a 'core' engine in that we do the tokenization, so we need the vocab
     /// - TODO: we don't yet extract that from the GGUF. Once we do we can remove this flag.
-    #[arg(long)]
+    #[arg(long, env = "DYNEMO_MODEL_CONFIG")]
     pub model_config: Option<PathBuf>,
 
+    /// Directory to load the tokenizer from (tokenizer.json, and tokenizer_config.json or
+    /// chat_template.jinja if present), instead of `--model-path` / `--model-config`.
+    /// Useful when the weights are GGUF but the tokenizer is an HF repo somewhere else.
+    /// Only applies to engines where we do the pre-processing. Full engines such as mistralrs
+    /// load their own tokenizer from `--model-path` and ignore this.
+    #[arg(long, env = "DYNEMO_TOKENIZER_PATH")]
+    pub tokenizer_path: Option<PathBuf>,
+
+    /// Jinja chat template, either a path to a file or the template itself (anything containing
+    /// `{{` or `{%`). Overrides the model's own template, whether that comes from
+    /// `chat_template.jinja` or `tokenizer_config.json`. Checked at startup by rendering a
+    /// sample message, which fails if it uses undefined variables.
+    /// Only applies to engines where we do the pre-processing.
+    #[arg(long, env = "DYNEMO_CHAT_TEMPLATE")]
+    pub chat_template: Option<String>,
+
+    /// How the model writes tool calls: `json`, `tool_call_token` (`<|tool_call|>`) or `hermes`
+    /// (`<tool_call>`). Requests with `tools` then get the calls back as `tool_calls` instead of
+    /// in the content. Saved in the model deployment card, so it also applies where a remote
+    /// worker's model is served. Only applies to engines where we do the pre-processing.
+    #[arg(long, env = "DYNEMO_TOOL_CALL_FORMAT")]
+    pub tool_call_format: Option<ToolCallFormat>,
+
     /// sglang, vllm
     ///
     /// How many GPUs to use at once, total across all nodes.
     /// This must divide by num_nodes, and each node must use the same number of GPUs.
-    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..256))]
+    #[arg(long, env = "DYNEMO_TENSOR_PARALLEL_SIZE", default_value = "1", value_parser = clap::value_parser!(u32).range(1..256))]
     pub tensor_parallel_size: u32,
 
     /// sglang only
-    /// vllm uses CUDA_VISIBLE_DEVICES env var
+    /// vllm uses CUDA_VISIBLE_DEVICES env var, see `--auto-cuda-visible-devices`
     ///
     /// Use GPUs from this ID upwards.
     /// If your machine has four GPUs but the first two (0 and 1) are in use,
     /// pass --base-gpu-id 2 to use the third GPU (and up, if tensor_parallel_size > 1)
-    #[arg(long, default_value = "0", value_parser = clap::value_parser!(u32).range(0..256))]
+    #[arg(long, env = "DYNEMO_BASE_GPU_ID", default_value = "0", value_parser = clap::value_parser!(u32).range(0..256))]
     pub base_gpu_id: u32,
 
+    /// vllm only
+    ///
+    /// Set CUDA_VISIBLE_DEVICES from `--base-gpu-id` and `--tensor-parallel-size` before
+    /// starting vllm, so several instances can share a machine. An existing CUDA_VISIBLE_DEVICES
+    /// is left alone.
+    #[arg(
+        long,
+        env = "DYNEMO_AUTO_CUDA_VISIBLE_DEVICES",
+        default_value = "false"
+    )]
+    pub auto_cuda_visible_devices: bool,
+
     /// vllm and sglang only
     ///
     /// How many nodes/hosts to use
-    #[arg(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..256))]
+    #[arg(long, env = "DYNEMO_NUM_NODES", default_value = "1", value_parser = clap::value_parser!(u32).range(1..256))]
     pub num_nodes: u32,
 
     /// vllm and sglang only
     ///
     /// This nodes' unique ID, running from 0 to num_nodes.
-    #[arg(long, default_value = "0", value_parser = clap::value_parser!(u32).range(0..255))]
+    #[arg(long, env = "DYNEMO_NODE_RANK", default_value = "0", value_parser = clap::value_parser!(u32).range(0..255))]
     pub node_rank: u32,
 
     /// For multi-node / pipeline parallel this is the <host>:<port> of the first node.
@@ -92,16 +337,179 @@ pub struct Flags {
     ///   This expects to use the nccl backend (transparently to us here).
     ///   All nodes must use the same address here, which is node_rank == 0's address.
     ///
-    #[arg(long)]
+    #[arg(long, env = "DYNEMO_LEADER_ADDR")]
     pub leader_addr: Option<String>,
 
     /// If using `out=dyn://..` with multiple backends, this says how to route the requests.
     ///
     /// Mostly interesting for KV-aware routing.
     /// Defaults to RouterMode::Random
-    #[arg(long, default_value = "random")]
+    #[arg(long, env = "DYNEMO_ROUTER_MODE", default_value = "random")]
     pub router_mode: RouterMode,
 
+    /// The region this node is in, e.g. `us-east-1`. With `in=dyn://..` our endpoint is
+    /// registered in it. With `out=dyn://..` requests go to endpoints in the same region, and to
+    /// other regions only while there are none here.
+    #[arg(long, env = "DYNEMO_REGION")]
+    pub region: Option<String>,
+
+    /// With `out=dyn://..`, retry requests which couldn't reach a worker, for example because it
+    /// restarted, up to this many times. The wait between tries doubles each time, from 200ms up
+    /// to 5s. Off by default. Models `in=http` discovers come and go with their workers, so there
+    /// it only applies with `--long-prompt-threshold`.
+    #[arg(long, env = "DYNEMO_DYNAMIC_MAX_RETRIES", default_value_t = 0)]
+    pub dynamic_max_retries: u32,
+
+    /// With `out=dyn://..`, requests whose prompt is estimated at more than this many tokens are
+    /// sent to `--long-prompt-endpoint` instead. The estimate is about four characters of message
+    /// content per token, plus four tokens per message.
+    #[arg(
+        long,
+        env = "DYNEMO_LONG_PROMPT_THRESHOLD",
+        requires = "long_prompt_endpoint"
+    )]
+    pub long_prompt_threshold: Option<usize>,
+
+    /// The `dyn://namespace.component.endpoint` which serves long prompts.
+    /// See `--long-prompt-threshold`.
+    #[arg(
+        long,
+        env = "DYNEMO_LONG_PROMPT_ENDPOINT",
+        requires = "long_prompt_threshold"
+    )]
+    pub long_prompt_endpoint: Option<String>,
+
+    /// Let requests switch off the model's end-of-sequence tokens with `nvext.ignore_eos`.
+    ///
+    /// By default a request's `stop` sequences are added to the model's EOS tokens and
+    /// `ignore_eos` is ignored, so generation always halts at EOS. Only applies to engines where
+    /// we do the pre-processing.
+    #[arg(long, env = "DYNEMO_ALLOW_DISABLE_EOS", default_value = "false")]
+    pub allow_disable_eos: bool,
+
+    /// Most tokens a request may use, prompt plus completion. `max_tokens`, or `--max-tokens` for
+    /// requests which don't set it, is lowered to fit what the prompt leaves, and requests whose
+    /// prompt alone doesn't fit get a 400. Only applies to engines where we do the
+    /// pre-processing.
+    #[arg(long, env = "DYNEMO_MAX_TOTAL_TOKENS")]
+    pub max_total_tokens: Option<usize>,
+
+    /// Reject requests with more `stop` sequences than this with a 400, as every sequence slows
+    /// down decoding. Only applies to engines where we do the pre-processing.
+    #[arg(long, env = "DYNEMO_MAX_STOP_SEQUENCES", default_value_t = DEFAULT_MAX_STOP_SEQUENCES)]
+    pub max_stop_sequences: usize,
+
+    /// Ignore stop sequences and EOS until at least this many tokens were generated, for requests
+    /// which don't set `nvext.min_tokens`. Stops output being cut short by a stop sequence matching
+    /// right away. Only applies to engines where we do the pre-processing.
+    #[arg(long, env = "DYNEMO_MIN_TOKENS")]
+    pub min_tokens: Option<u32>,
+
+    /// Concatenate a request's system messages into one before applying the chat template, for
+    /// templates which fail on more than one. Only applies to engines where we do the
+    /// pre-processing.
+    #[arg(long, env = "DYNEMO_MERGE_SYSTEM_MESSAGES", default_value = "false")]
+    pub merge_system_messages: bool,
+
+    /// How to split the generated text into streamed chunks: `utf8` holds tokens back until they
+    /// make complete characters, `token` sends a chunk for every token, with U+FFFD standing in
+    /// for the bytes of a character split across tokens. Only applies to engines where we do the
+    /// pre-processing.
+    #[arg(long, env = "DYNEMO_STREAM_GRANULARITY", default_value = "utf8")]
+    pub stream_granularity: StreamGranularity,
+
+    /// Text to put before every prompt, e.g. for guardrails. It goes before the rendered prompt
+    /// for engines where we do the pre-processing, and before the content of the last user
+    /// message for engines which do their own. The prompt template never sees it.
+    #[arg(long, env = "DYNEMO_PROMPT_PREFIX")]
+    pub prompt_prefix: Option<String>,
+
+    /// Text to put after every prompt, see `--prompt-prefix`
+    #[arg(long, env = "DYNEMO_PROMPT_SUFFIX")]
+    pub prompt_suffix: Option<String>,
+
+    /// Send at most this many tokens per second to each client, however fast the engine produces
+    /// them. To simulate a slower model, or protect clients with little bandwidth. Does not apply
+    /// to out=dyn://, set it on the worker instead.
+    #[arg(long, env = "DYNEMO_MAX_OUTPUT_TPS")]
+    pub max_output_tps: Option<u32>,
+
+    /// in=batch only
+    ///
+    /// Write each result to the output file as soon as it is done, tagged with `index`, its line
+    /// number in the input counting from 0. Results are in the order they finish, not the input
+    /// order, so downstream consumers can start before the whole batch is done.
+    #[arg(long, env = "DYNEMO_BATCH_STREAM", default_value = "false")]
+    pub batch_stream: bool,
+
+    /// in=batch only
+    ///
+    /// Run at most this many input lines through the engine at once. Unlimited by default.
+    #[arg(long, env = "DYNEMO_BATCH_CONCURRENCY")]
+    pub batch_concurrency: Option<usize>,
+
+    /// in=batch only
+    ///
+    /// Where to write the results, `-` for stdout. Defaults to `output.jsonl` next to the input
+    /// file.
+    #[arg(long, env = "DYNEMO_OUTPUT_FILE")]
+    pub output_file: Option<PathBuf>,
+
+    /// vllm and sglang only
+    ///
+    /// Keep the engine's warmup artifacts (compiled kernels, CUDA graphs) under this directory,
+    /// keyed by model and configuration, and reuse them on restart. A configuration change
+    /// discards them.
+    #[arg(long, env = "DYNEMO_WARMUP_CACHE_DIR")]
+    pub warmup_cache_dir: Option<PathBuf>,
+
+    /// pystr and pytok only
+    ///
+    /// Call the engine's `generate` again, up to this many times, when it raises before yielding
+    /// anything. For generators making flaky external calls. Errors after the first response are
+    /// never retried.
+    #[arg(long, env = "DYNEMO_PYTHON_GENERATE_RETRIES", default_value_t = 0)]
+    pub python_generate_retries: u32,
+
+    /// pystr and pytok only
+    ///
+    /// Seconds to wait for the python asyncio event loop to start before failing. Defaults to 30.
+    #[arg(long, env = "DYNEMO_PYTHON_EVENT_LOOP_TIMEOUT")]
+    pub python_event_loop_timeout: Option<u64>,
+
+    /// pystr and pytok only
+    ///
+    /// How many requests can drive the python generator at once. Further requests wait for one to
+    /// finish. Unlimited by default.
+    #[arg(long, env = "DYNEMO_PYTHON_MAX_CONCURRENT", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub python_max_concurrent: Option<usize>,
+
+    /// echo_full and echo_core only
+    ///
+    /// Milliseconds to wait between echoed tokens. Defaults to `DYN_TOKEN_ECHO_DELAY_MS`, or 10.
+    #[arg(long, env = "DYNEMO_ECHO_DELAY_MS")]
+    pub echo_delay_ms: Option<u64>,
+
+    /// echo_full and echo_core only
+    ///
+    /// Milliseconds to wait before the first echoed token, to imitate a slow prompt. Defaults to
+    /// the delay between tokens.
+    #[arg(long, env = "DYNEMO_ECHO_TTFT_MS")]
+    pub echo_ttft_ms: Option<u64>,
+
+    /// out=openai only
+    ///
+    /// Most connections to the server kept open between requests, for reuse. 0 opens a new one
+    /// for every request.
+    #[arg(long, env = "DYNEMO_UPSTREAM_POOL_SIZE", default_value_t = DEFAULT_UPSTREAM_POOL_SIZE)]
+    pub upstream_pool_size: usize,
+
+    /// out=openai only
+    ///
+    /// Seconds before an unused connection to the server is closed. Defaults to 90.
+    #[arg(long, env = "DYNEMO_UPSTREAM_IDLE_TIMEOUT", value_parser = parse_seconds)]
+    pub upstream_idle_timeout: Option<Duration>,
+
     /// Internal use only.
     // Start the python vllm engine sub-process.
     #[arg(long, hide = true, default_value = "false")]
@@ -119,7 +527,7 @@ pub struct Flags {
 
     /// Additional engine-specific arguments from a JSON file.
     /// Contains a mapping of parameter names to values.
-    #[arg(long)]
+    #[arg(long, env = "DYNEMO_EXTRA_ENGINE_ARGS")]
     pub extra_engine_args: Option<PathBuf>,
 
     /// Everything after a `--`.
@@ -129,6 +537,37 @@ pub struct Flags {
 }
 
 impl Flags {
+    /// The model path, positional or `--model-path`. If both are given and differ the positional
+    /// one wins, with a warning, or an error with `--strict`.
+    pub fn model_path(&self) -> anyhow::Result<Option<PathBuf>> {
+        if let Some(msg) = self.model_path_mismatch() {
+            if self.strict {
+                anyhow::bail!("{msg}");
+            }
+            tracing::warn!("{msg}");
+        }
+        Ok(self.model_path_pos.clone().or(self.model_path_flag.clone()))
+    }
+
+    /// What's wrong if the positional model path and `--model-path` point at different models
+    fn model_path_mismatch(&self) -> Option<String> {
+        let (Some(pos), Some(flag)) = (&self.model_path_pos, &self.model_path_flag) else {
+            return None;
+        };
+        let same = match (pos.canonicalize(), flag.canonicalize()) {
+            (Ok(pos), Ok(flag)) => pos == flag,
+            _ => pos == flag,
+        };
+        (!same).then(|| {
+            format!(
+                "Model path given twice, as {} and --model-path {}. Using {}.",
+                pos.display(),
+                flag.display(),
+                pos.display()
+            )
+        })
+    }
+
     /// Convert the flags back to a command line. Including only the non-null values, but
     /// include the defaults. Includes the canonicalized model path and normalized model name.
     ///
@@ -170,6 +609,75 @@ impl Flags {
         out
     }
 
+    /// How `OpenAIPreprocessor` should treat requests for an engine which can do `capabilities`
+    pub fn preprocessor_options(&self, capabilities: EngineCapabilities) -> PreprocessorOptions {
+        PreprocessorOptions {
+            allow_disable_eos: self.allow_disable_eos,
+            max_stop_sequences: self.max_stop_sequences,
+            min_tokens: self.min_tokens,
+            // Filled in here too, else --max-total-tokens would fill it first. Capped the same
+            // way, so --strict-max-tokens doesn't reject a default above --max-max-tokens.
+            default_max_tokens: self.max_tokens_limits().apply(None).ok().flatten(),
+            merge_system_messages: self.merge_system_messages,
+            prompt_prefix: self.prompt_prefix.clone(),
+            prompt_suffix: self.prompt_suffix.clone(),
+            max_total_tokens: self.max_total_tokens,
+            capabilities,
+            ignore_unsupported_logprobs: !self.strict_logprobs,
+        }
+    }
+
+    /// How to connect to etcd and NATS, for `in=dyn://..` and `out=dyn://..`
+    pub fn distributed_config(&self) -> DistributedConfig {
+        DistributedConfig {
+            region: self.region.clone(),
+            ..DistributedConfig::from_settings(false)
+        }
+    }
+
+    /// How slowly the echo engines respond
+    pub fn echo_delays(&self) -> EchoDelays {
+        let default = EchoDelays::default();
+        let between_tokens = self
+            .echo_delay_ms
+            .map(Duration::from_millis)
+            .unwrap_or(default.between_tokens);
+        EchoDelays {
+            first_token: self
+                .echo_ttft_ms
+                .map(Duration::from_millis)
+                .unwrap_or(between_tokens),
+            between_tokens,
+        }
+    }
+
+    /// The connections `out=openai` keeps open to its server
+    pub fn upstream_pool(&self) -> UpstreamPool {
+        let default = UpstreamPool::default();
+        UpstreamPool {
+            max_idle: self.upstream_pool_size,
+            idle_timeout: self.upstream_idle_timeout.unwrap_or(default.idle_timeout),
+        }
+    }
+
+    /// The `max_tokens` defaults and limits for the engine
+    pub fn max_tokens_limits(&self) -> MaxTokensLimits {
+        MaxTokensLimits {
+            default: self.max_tokens,
+            max: self.max_max_tokens,
+            reject_over_max: self.strict_max_tokens,
+        }
+    }
+
+    /// The model details given on the command line, for `/v1/models`
+    pub fn model_metadata(&self) -> ModelMetadata {
+        ModelMetadata {
+            context_length: self.context_length,
+            max_output_tokens: self.max_output_tokens,
+            default_max_tokens: self.max_tokens,
+        }
+    }
+
     /// Load extra engine arguments from a JSON file
     /// Returns a HashMap of parameter names to values
     pub fn load_extra_engine_args(
@@ -185,7 +693,7 @@ impl Flags {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct SgLangFlags {
     pub pipe_fd: u32,
     pub tp_rank: u32,
@@ -209,7 +717,24 @@ fn parse_sglang_flags(s: &str) -> Result<SgLangFlags, String> {
     })
 }
 
-#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug)]
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let secs = u64::from_str(s).map_err(|e| format!("'{s}': {e}"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+fn parse_model_priority(s: &str) -> Result<(String, i32), String> {
+    let Some((name, priority)) = s.rsplit_once('=') else {
+        return Err(format!("Invalid model priority '{s}', expected <name>=<n>"));
+    };
+    if name.is_empty() {
+        return Err(format!("Model priority '{s}' is missing the model name"));
+    }
+    let priority = i32::from_str(priority).map_err(|e| format!("'{priority}': {e}"))?;
+    Ok((name.to_string(), priority))
+}
+
+#[derive(Default, PartialEq, Eq, ValueEnum, Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum RouterMode {
     #[default]
     Random,
@@ -234,3 +759,84 @@ impl From<RouterMode> for RuntimeRouterMode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Flags;
+    use clap::Parser;
+
+    fn parse(args: &[&str]) -> Flags {
+        Flags::try_parse_from(std::iter::once("dynamo-run").chain(args.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn test_model_path_mismatch() {
+        let flags = parse(&["/models/a", "--model-path", "/models/b"]);
+        let msg = flags.model_path_mismatch().unwrap();
+        assert!(
+            msg.contains("/models/a") && msg.contains("/models/b"),
+            "{msg}"
+        );
+        // The positional one wins
+        assert_eq!(
+            flags.model_path().unwrap().unwrap().to_str(),
+            Some("/models/a")
+        );
+
+        let flags = parse(&["/models/a", "--model-path", "/models/b", "--strict"]);
+        let err = flags.model_path().unwrap_err();
+        assert!(err.to_string().contains("given twice"), "{err}");
+
+        for args in [
+            &["/models/a", "--model-path", "/models/a"][..],
+            &["/models/a"],
+            &["--model-path", "/models/b", "--strict"],
+        ] {
+            let flags = parse(args);
+            assert!(flags.model_path_mismatch().is_none(), "{args:?}");
+            assert!(flags.model_path().unwrap().is_some());
+        }
+    }
+
+    #[test]
+    fn test_flag_overrides_env() {
+        // No other test looks at the port, so setting it can't race with them
+        std::env::set_var("DYNEMO_HTTP_PORT", "9123");
+        let from_env = parse(&[]).http_port;
+        let from_flag = parse(&["--http-port", "9456"]).http_port;
+        std::env::remove_var("DYNEMO_HTTP_PORT");
+
+        assert_eq!(from_env, 9123);
+        assert_eq!(from_flag, 9456);
+        assert_eq!(parse(&[]).http_port, 8080);
+    }
+
+    #[test]
+    fn test_default_max_tokens_with_max_total_tokens() {
+        // The pre-processor fills in the default before fitting the request in the budget
+        let options = parse(&["--default-max-tokens", "20", "--max-total-tokens", "200"])
+            .preprocessor_options(Default::default());
+        assert_eq!(options.default_max_tokens, Some(20));
+        assert_eq!(options.max_total_tokens, Some(200));
+
+        // capped like any other request
+        let options = parse(&[
+            "--default-max-tokens",
+            "20",
+            "--max-max-tokens",
+            "10",
+            "--strict-max-tokens",
+        ])
+        .preprocessor_options(Default::default());
+        assert_eq!(options.default_max_tokens, Some(10));
+    }
+
+    #[test]
+    fn test_python_max_concurrent() {
+        let flags = parse(&["--python-max-concurrent", "4"]);
+        assert_eq!(flags.python_max_concurrent, Some(4));
+        // no request could ever run
+        let args = ["--python-max-concurrent", "0"];
+        assert!(Flags::try_parse_from(std::iter::once("dynamo-run").chain(args)).is_err());
+    }
+}
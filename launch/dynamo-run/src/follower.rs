@@ -0,0 +1,204 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Multi-node followers, node_rank > 0, take their input from the leader. All they do themselves
+//! is hold an etcd lease while they run, so orchestration can see they are alive. With
+//! `in=dyn://..` they also put a key under the lease, at [`follower_key`], so the leader can watch
+//! the `{namespace}/nodes/{component}/` prefix for which followers are up.
+
+use async_trait::async_trait;
+use dynamo_runtime::transports::etcd;
+use tokio_util::sync::CancellationToken;
+
+/// Time to live of a follower's lease. It's renewed at half that.
+pub const FOLLOWER_LEASE_TTL_SECS: i64 = 10;
+
+/// Where leases come from. etcd, or a stand-in in tests.
+#[async_trait]
+pub trait LeaseStore: Send + Sync {
+    type Lease: Send;
+
+    /// Grant a lease which is kept alive until it's released
+    async fn acquire(&self, ttl_secs: i64) -> anyhow::Result<Self::Lease>;
+
+    fn lease_id(lease: &Self::Lease) -> i64;
+
+    /// Create `key`, attached to `lease` so it goes when the lease does. Fails if it exists.
+    async fn put(&self, lease: &Self::Lease, key: String, value: Vec<u8>) -> anyhow::Result<()>;
+
+    async fn release(&self, lease: Self::Lease);
+}
+
+#[async_trait]
+impl LeaseStore for etcd::Client {
+    type Lease = etcd::Lease;
+
+    async fn acquire(&self, ttl_secs: i64) -> anyhow::Result<etcd::Lease> {
+        self.create_lease(ttl_secs).await
+    }
+
+    fn lease_id(lease: &etcd::Lease) -> i64 {
+        lease.id()
+    }
+
+    async fn put(&self, lease: &etcd::Lease, key: String, value: Vec<u8>) -> anyhow::Result<()> {
+        self.kv_create(key, value, Some(lease.id())).await
+    }
+
+    async fn release(&self, lease: etcd::Lease) {
+        // The keep alive task revokes it in etcd
+        lease.revoke();
+    }
+}
+
+/// Where a follower announces it is running
+pub fn follower_key(namespace: &str, component: &str, node_rank: u32) -> String {
+    format!("{namespace}/nodes/{component}/{node_rank}")
+}
+
+/// Hold a lease, and `key` if given, until `cancel_token` is cancelled, then release it. If the
+/// process dies instead the lease expires after [`FOLLOWER_LEASE_TTL_SECS`], taking the key.
+pub async fn run<S: LeaseStore>(
+    store: &S,
+    node_rank: u32,
+    key: Option<String>,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<()> {
+    let lease = store.acquire(FOLLOWER_LEASE_TTL_SECS).await?;
+    let lease_id = S::lease_id(&lease);
+    if let Some(key) = key {
+        let value = serde_json::to_vec(&serde_json::json!({ "node_rank": node_rank }))?;
+        if let Err(err) = store.put(&lease, key.clone(), value).await {
+            store.release(lease).await;
+            anyhow::bail!(
+                "Failed registering node {node_rank} at {key}, is it already running? {err}"
+            );
+        }
+        tracing::info!(key, "Registered follower node");
+    }
+    tracing::info!(node_rank, lease_id, "Follower node running");
+    cancel_token.cancelled().await;
+    store.release(lease).await;
+    tracing::debug!(node_rank, lease_id, "Released follower lease");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// Records what happens to its leases and keys instead of talking to etcd
+    #[derive(Default)]
+    struct MockEtcd {
+        live: Mutex<Vec<(i64, i64)>>,
+        released: Mutex<Vec<i64>>,
+        /// Key to the lease it's attached to
+        keys: Mutex<HashMap<String, i64>>,
+    }
+
+    #[async_trait]
+    impl LeaseStore for MockEtcd {
+        type Lease = i64;
+
+        async fn acquire(&self, ttl_secs: i64) -> anyhow::Result<i64> {
+            let mut live = self.live.lock().unwrap();
+            let id = live.len() as i64 + 1;
+            live.push((id, ttl_secs));
+            Ok(id)
+        }
+
+        fn lease_id(lease: &i64) -> i64 {
+            *lease
+        }
+
+        async fn put(&self, lease: &i64, key: String, _value: Vec<u8>) -> anyhow::Result<()> {
+            let mut keys = self.keys.lock().unwrap();
+            if keys.contains_key(&key) {
+                anyhow::bail!("{key} exists");
+            }
+            keys.insert(key, *lease);
+            Ok(())
+        }
+
+        async fn release(&self, lease: i64) {
+            self.live.lock().unwrap().retain(|(id, _)| *id != lease);
+            self.keys.lock().unwrap().retain(|_, id| *id != lease);
+            self.released.lock().unwrap().push(lease);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follower_holds_lease_until_shutdown() {
+        let etcd = Arc::new(MockEtcd::default());
+        let cancel_token = CancellationToken::new();
+        let task = tokio::spawn({
+            let etcd = etcd.clone();
+            let cancel_token = cancel_token.clone();
+            async move { run(etcd.as_ref(), 1, None, cancel_token).await }
+        });
+
+        while etcd.live.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            *etcd.live.lock().unwrap(),
+            vec![(1, FOLLOWER_LEASE_TTL_SECS)]
+        );
+        assert!(etcd.released.lock().unwrap().is_empty());
+
+        cancel_token.cancel();
+        task.await.unwrap().unwrap();
+        assert!(etcd.live.lock().unwrap().is_empty());
+        assert_eq!(*etcd.released.lock().unwrap(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_follower_key_goes_with_lease() {
+        let etcd = Arc::new(MockEtcd::default());
+        let key = follower_key("ns", "backend", 1);
+        assert_eq!(key, "ns/nodes/backend/1");
+        let cancel_token = CancellationToken::new();
+        let task = tokio::spawn({
+            let etcd = etcd.clone();
+            let key = key.clone();
+            let cancel_token = cancel_token.clone();
+            async move { run(etcd.as_ref(), 1, Some(key), cancel_token).await }
+        });
+
+        while etcd.keys.lock().unwrap().is_empty() {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(etcd.keys.lock().unwrap().get(&key), Some(&1));
+
+        // A second node claiming the same rank fails, and gives its lease back
+        let err = run(
+            etcd.as_ref(),
+            1,
+            Some(key.clone()),
+            CancellationToken::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("already running"), "{err}");
+        assert_eq!(*etcd.released.lock().unwrap(), vec![2]);
+
+        cancel_token.cancel();
+        task.await.unwrap().unwrap();
+        assert!(etcd.keys.lock().unwrap().is_empty());
+        assert!(etcd.live.lock().unwrap().is_empty());
+    }
+}
@@ -16,5 +16,7 @@
 pub mod batch;
 mod common;
 pub mod endpoint;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod http;
 pub mod text;
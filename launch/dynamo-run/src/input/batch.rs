@@ -15,10 +15,13 @@
 
 use anyhow::Context as _;
 use async_openai::types::FinishReason;
+use dynamo_llm::backend::ANNOTATION_STOP_CAUSE;
 use dynamo_llm::model_card::model::ModelDeploymentCard;
 use dynamo_llm::preprocessor::OpenAIPreprocessor;
+use dynamo_llm::protocols::common::StopCause;
 use dynamo_llm::types::openai::chat_completions::{
-    NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+    OpenAIChatCompletionsStreamingEngine,
 };
 use dynamo_runtime::{pipeline::Context, runtime::CancellationToken, Runtime};
 use futures::StreamExt;
@@ -28,7 +31,8 @@ use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Semaphore;
 
 use crate::input::common;
 use crate::{EngineConfig, Flags};
@@ -39,6 +43,9 @@ const MAX_TOKENS: u32 = 8192;
 
 const OUTPUT_FILENAME: &str = "output.jsonl";
 
+/// `--output-file` for stdout
+const STDOUT_FILENAME: &str = "-";
+
 #[derive(Serialize, Deserialize, Default, Debug)]
 struct Entry {
     // The input files only have this
@@ -58,10 +65,44 @@ struct Entry {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     finish_reason: Option<FinishReason>,
 
+    /// Why generation ended, for engines which run behind the backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stop_cause: Option<StopCause>,
+
     #[serde(skip, default)]
     request_id: usize,
 }
 
+/// An output line with `--batch-stream`
+#[derive(Serialize)]
+struct IndexedEntry<'a> {
+    /// Line number of the entry in the input file, not counting blank lines
+    index: usize,
+
+    #[serde(flatten)]
+    entry: &'a Entry,
+}
+
+/// The output line for an input line which is a whole chat completions request. Always tagged
+/// with `index`, as they are written in the order they finish.
+#[derive(Serialize)]
+struct ChatResult {
+    /// Line number of the request in the input file, not counting blank lines
+    index: usize,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response: Option<NvCreateChatCompletionResponse>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A finished input line, for the output writer
+enum Done {
+    Entry(Entry),
+    Chat(ChatResult),
+}
+
 pub async fn run(
     runtime: Runtime,
     flags: Flags,
@@ -78,6 +119,26 @@ pub async fn run(
         );
     }
 
+    let stream_output = flags.batch_stream;
+    let concurrency = flags
+        .batch_concurrency
+        .map(|n| Arc::new(Semaphore::new(n.max(1))));
+    let output_file = match flags.output_file.clone() {
+        Some(output_file) => output_file,
+        None => {
+            let mut output_file = input_jsonl.clone();
+            output_file.set_file_name(OUTPUT_FILENAME);
+            output_file
+        }
+    };
+    let output: Box<dyn AsyncWrite + Unpin + Send> = if output_file == Path::new(STDOUT_FILENAME) {
+        Box::new(tokio::io::stdout())
+    } else {
+        let f = tokio::fs::File::create(&output_file)
+            .await
+            .with_context(|| output_file.display().to_string())?;
+        Box::new(f)
+    };
     let (service_name, engine, _inspect_template) =
         common::prepare_engine(runtime, flags, engine_config).await?;
     let service_name_ref = Arc::new(service_name);
@@ -89,10 +150,10 @@ pub async fn run(
     };
     let (done_entries_tx, done_entries_rx) = tokio::sync::mpsc::channel(64);
     let dw_cancel_token = cancel_token.clone();
-    let mut output_file = input_jsonl.clone();
-    output_file.set_file_name(OUTPUT_FILENAME);
-    tokio::spawn(async move {
-        if let Err(err) = output_writer(dw_cancel_token, done_entries_rx, &output_file).await {
+    let writer = tokio::spawn(async move {
+        if let Err(err) =
+            output_writer(dw_cancel_token, done_entries_rx, output, stream_output).await
+        {
             tracing::error!(%err, "Failed writing output to {}", output_file.display());
         }
     });
@@ -118,7 +179,60 @@ pub async fn run(
         }
         let request_id = num_entries;
         num_entries += 1;
-        let mut entry: Entry = match serde_json::from_str(&line) {
+        let value: serde_json::Value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(err) => {
+                anyhow::bail!("Error parsing entry: '{line}'. {err}");
+            }
+        };
+        // With --batch-concurrency don't even read ahead of the engine
+        let permit = match &concurrency {
+            Some(concurrency) => Some(concurrency.clone().acquire_owned().await?),
+            None => None,
+        };
+
+        // A whole chat completions request, rather than a prompt
+        if value.get("messages").is_some() {
+            let request: NvCreateChatCompletionRequest = match serde_json::from_value(value) {
+                Ok(request) => request,
+                Err(err) => {
+                    anyhow::bail!("Error parsing request: '{line}'. {err}");
+                }
+            };
+            let engine = engine.clone();
+            let tokens_in = tokens_in.clone();
+            let tokens_out = tokens_out.clone();
+            let done_entries_tx = done_entries_tx.clone();
+            let handle = tokio::spawn(async move {
+                let _permit = permit;
+                let result = match evaluate_request(engine, request).await {
+                    Ok(response) => {
+                        if let Some(usage) = response.inner.usage.as_ref() {
+                            tokens_in.fetch_add(usage.prompt_tokens as u64, Ordering::Relaxed);
+                            tokens_out.fetch_add(usage.completion_tokens as u64, Ordering::Relaxed);
+                        }
+                        ChatResult {
+                            index: request_id,
+                            response: Some(response),
+                            error: None,
+                        }
+                    }
+                    Err(err) => {
+                        tracing::error!(%err, request_id, "Failed evaluating request");
+                        ChatResult {
+                            index: request_id,
+                            response: None,
+                            error: Some(err.to_string()),
+                        }
+                    }
+                };
+                let _ = done_entries_tx.send(Done::Chat(result)).await;
+            });
+            handles.push(handle);
+            continue;
+        }
+
+        let mut entry: Entry = match serde_json::from_value(value) {
             Ok(entry) => entry,
             Err(err) => {
                 anyhow::bail!("Error parsing entry: '{line}'. {err}");
@@ -133,6 +247,7 @@ pub async fn run(
         let done_entries_tx = done_entries_tx.clone();
         let service_name_ref = service_name_ref.clone();
         let handle = tokio::spawn(async move {
+            let _permit = permit;
             let local_start = Instant::now();
             let response =
                 match evaluate(request_id, service_name_ref.as_str(), engine, &mut entry).await {
@@ -166,7 +281,7 @@ pub async fn run(
             }
             entry.response = Some(response);
 
-            let _ = done_entries_tx.send(entry).await;
+            let _ = done_entries_tx.send(Done::Entry(entry)).await;
         });
         handles.push(handle);
     }
@@ -182,7 +297,9 @@ pub async fn run(
     let elapsed_clean = Duration::from_millis(elapsed.as_millis() as u64);
     let tokens_in = Arc::into_inner(tokens_in).unwrap().into_inner();
     let tokens_out = Arc::into_inner(tokens_out).unwrap().into_inner();
-    tokio::time::sleep(Duration::from_millis(1)).await; // Let output_writer finish stdout write
+    // Let output_writer write and flush what's left
+    drop(done_entries_tx);
+    let _ = writer.await;
     tracing::info!(
         "Ran {} files in {}. Tokens in: {} ({}/s). Tokens out: {} ({}/s)",
         num_entries,
@@ -196,6 +313,19 @@ pub async fn run(
     Ok(())
 }
 
+/// Run a chat completions request from the input file through the engine. It's streamed from the
+/// engine whatever the request says, and aggregated into one response.
+async fn evaluate_request(
+    engine: OpenAIChatCompletionsStreamingEngine,
+    mut request: NvCreateChatCompletionRequest,
+) -> anyhow::Result<NvCreateChatCompletionResponse> {
+    request.inner.stream = Some(true);
+    let stream = engine.generate(Context::new(request)).await?;
+    NvCreateChatCompletionResponse::from_annotated_stream(Box::pin(stream))
+        .await
+        .map_err(|err| anyhow::anyhow!(err))
+}
+
 // Run a single prompt through the engine
 async fn evaluate(
     request_id: usize,
@@ -217,7 +347,12 @@ async fn evaluate(
         .stream(true)
         .max_completion_tokens(MAX_TOKENS)
         .build()?;
-    let req = NvCreateChatCompletionRequest { inner, nvext: None };
+    let req = NvCreateChatCompletionRequest {
+        inner,
+        nvext: None,
+        service_tier: None,
+        stop_token_ids: None,
+    };
     let mut stream = engine.generate(Context::new(req)).await?;
     let mut output = String::new();
     while let Some(item) = stream.next().await {
@@ -246,6 +381,13 @@ async fn evaluate(
                     tracing::error!(request_id, "Engine error: {err}");
                 }
             }
+            (None, Some(ANNOTATION_STOP_CAUSE)) => {
+                entry.stop_cause = item
+                    .comment
+                    .as_ref()
+                    .and_then(|comment| comment.first())
+                    .and_then(|cause| serde_json::from_str(cause).ok());
+            }
             (None, Some(annotation)) => {
                 tracing::debug!(request_id, "Annotation. {annotation}: {:?}", item.comment);
             }
@@ -259,11 +401,11 @@ async fn evaluate(
 
 async fn output_writer(
     cancel_token: CancellationToken,
-    mut entries_rx: tokio::sync::mpsc::Receiver<Entry>,
-    output_file: &Path,
+    mut entries_rx: tokio::sync::mpsc::Receiver<Done>,
+    mut f: Box<dyn AsyncWrite + Unpin + Send>,
+    stream_output: bool,
 ) -> anyhow::Result<()> {
     let mut num_completed = 0;
-    let mut f = tokio::fs::File::create(output_file).await?;
     loop {
         let entry = tokio::select! {
             _ = cancel_token.cancelled() => {
@@ -276,14 +418,214 @@ async fn output_writer(
                 }
             }
         };
-        let mut s = serde_json::to_string(&entry)?;
+        let (mut s, request_id) = match &entry {
+            Done::Entry(entry) if stream_output => (
+                serde_json::to_string(&IndexedEntry {
+                    index: entry.request_id,
+                    entry,
+                })?,
+                entry.request_id,
+            ),
+            Done::Entry(entry) => (serde_json::to_string(entry)?, entry.request_id),
+            Done::Chat(result) => (serde_json::to_string(result)?, result.index),
+        };
         s.push('\n');
         f.write_all(s.as_bytes()).await?;
+        if stream_output {
+            // Make it visible to whoever is tailing the file
+            f.flush().await?;
+        }
 
         num_completed += 1;
         // TODO: Progress bar. We'd have to count the lines in the input first,
         // and the input maybe be large
-        tracing::info!(entry.request_id, entry.tokens_out, "Saved {num_completed}");
+        tracing::info!(request_id, "Saved {num_completed}");
     }
+    f.flush().await?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use dynamo_llm::engines::{
+        make_engine_core_with_delays, make_engine_full_with_delays, EchoDelays,
+    };
+    use tokio::io::AsyncReadExt;
+
+    const PROMPTS: [&str; 2] = ["hello world", "the quick brown fox"];
+
+    /// A fresh directory for one test's input and output files
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dynamo-run-batch-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const NO_DELAYS: EchoDelays = EchoDelays {
+        first_token: Duration::ZERO,
+        between_tokens: Duration::ZERO,
+    };
+
+    fn echo_full() -> EngineConfig {
+        EngineConfig::StaticFull {
+            service_name: "echo".to_string(),
+            engine: make_engine_full_with_delays(NO_DELAYS),
+            embeddings: None,
+        }
+    }
+
+    /// Runs two prompts and one chat request through the engine, returns the output lines
+    async fn run_batch(
+        name: &str,
+        extra_args: &[&str],
+        engine_config: EngineConfig,
+    ) -> Vec<serde_json::Value> {
+        let dir = test_dir(name);
+        let input = dir.join("input.jsonl");
+        let output = dir.join("out.jsonl");
+        let chat = serde_json::json!({
+            "model": "echo",
+            "messages": [{"role": "user", "content": "a whole request"}],
+        });
+        let lines = [
+            serde_json::json!({ "text": PROMPTS[0] }).to_string(),
+            String::new(),
+            serde_json::json!({ "text": PROMPTS[1] }).to_string(),
+            chat.to_string(),
+        ];
+        std::fs::write(&input, lines.join("\n")).unwrap();
+
+        let output_arg = output.display().to_string();
+        let args = ["--output-file", output_arg.as_str()];
+        let flags = Flags::try_parse_from(
+            std::iter::once("dynamo-run").chain(args.iter().chain(extra_args).copied()),
+        )
+        .unwrap();
+        let runtime = Runtime::from_current().unwrap();
+        run(runtime, flags, None, input, engine_config)
+            .await
+            .unwrap();
+
+        let out = std::fs::read_to_string(&output).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        out.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn chat_content(line: &serde_json::Value) -> &str {
+        line["response"]["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_echo() {
+        let lines = run_batch("echo", &[], echo_full()).await;
+        assert_eq!(lines.len(), 3, "{lines:?}");
+
+        let mut responses: Vec<_> = lines
+            .iter()
+            .filter(|line| line.get("text").is_some())
+            .map(|line| {
+                assert!(line.get("index").is_none(), "{line}");
+                assert_eq!(line["finish_reason"], "stop");
+                (
+                    line["text"].as_str().unwrap(),
+                    line["response"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        responses.sort();
+        assert_eq!(
+            responses,
+            vec![(PROMPTS[0], PROMPTS[0]), (PROMPTS[1], PROMPTS[1])]
+        );
+
+        // Chat requests are always tagged, blank lines aren't counted
+        let chat = lines
+            .iter()
+            .find(|line| line.get("text").is_none())
+            .unwrap();
+        assert_eq!(chat["index"], 2);
+        assert_eq!(chat_content(chat), "a whole request");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_stream_echo() {
+        let lines = run_batch(
+            "stream",
+            &["--batch-stream", "--batch-concurrency", "1"],
+            echo_full(),
+        )
+        .await;
+        // One at a time, so they finish in input order
+        let indexes: Vec<_> = lines.iter().map(|line| line["index"].clone()).collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+        assert_eq!(lines[0]["response"], PROMPTS[0]);
+        assert_eq!(lines[1]["response"], PROMPTS[1]);
+        assert_eq!(chat_content(&lines[2]), "a whole request");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_stop_cause() {
+        let card = ModelDeploymentCard::from_local_path(
+            "../../lib/llm/tests/data/sample-models/mock-llama-3.1-8b-instruct",
+            None,
+        )
+        .await
+        .unwrap();
+        let engine_config = EngineConfig::StaticCore {
+            service_name: "echo".to_string(),
+            engine: make_engine_core_with_delays(NO_DELAYS),
+            card: Box::new(card),
+            capabilities: Default::default(),
+        };
+        let lines = run_batch("stop-cause", &[], engine_config).await;
+        let prompts: Vec<_> = lines
+            .iter()
+            .filter(|line| line.get("text").is_some())
+            .collect();
+        assert_eq!(prompts.len(), 2, "{lines:?}");
+        // The echoed prompt template ends with an EOS, which the backend stops at
+        for line in prompts {
+            assert_eq!(line["stop_cause"], "stop", "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_writer_streams_lines() {
+        let (writer, mut reader) = tokio::io::duplex(4096);
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+        let handle = tokio::spawn(output_writer(
+            CancellationToken::new(),
+            rx,
+            Box::new(writer),
+            true,
+        ));
+
+        let mut buf = vec![0u8; 4096];
+        for request_id in [3, 1] {
+            let entry = Entry {
+                text: "prompt".to_string(),
+                response: Some("response".to_string()),
+                request_id,
+                ..Default::default()
+            };
+            tx.send(Done::Entry(entry)).await.unwrap();
+            // Each line is flushed as soon as it's done, without waiting for the rest
+            let n = reader.read(&mut buf).await.unwrap();
+            let line = std::str::from_utf8(&buf[..n]).unwrap();
+            assert!(line.ends_with('\n'), "{line}");
+            let value: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(value["index"], request_id);
+            assert_eq!(value["response"], "response");
+        }
+        drop(tx);
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -13,9 +13,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{flags::RouterMode, EngineConfig, Flags};
+use crate::{flags::RouterMode, EngineConfig, Flags, ENDPOINT_SCHEME};
 use dynamo_llm::{
     backend::Backend,
+    length_router::PromptLengthRouter,
     preprocessor::OpenAIPreprocessor,
     types::{
         openai::chat_completions::{
@@ -26,10 +27,17 @@ use dynamo_llm::{
     },
 };
 use dynamo_runtime::{
+    component::RouterMode as RuntimeRouterMode,
     pipeline::{ManyOut, Operator, ServiceBackend, ServiceFrontend, SingleIn, Source},
+    protocols::Endpoint,
     DistributedRuntime, Runtime,
 };
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for the instance in `dyn://..@<instance id>` to be discovered. The other
+/// instances of the endpoint may be found first.
+const INSTANCE_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Turns an EngineConfig into an OpenAIChatCompletionsStreamingEngine.
 pub async fn prepare_engine(
@@ -39,33 +47,16 @@ pub async fn prepare_engine(
 ) -> anyhow::Result<(String, OpenAIChatCompletionsStreamingEngine, bool)> {
     match engine_config {
         EngineConfig::Dynamic(endpoint_id) => {
-            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
-
-            let endpoint = distributed_runtime
-                .namespace(endpoint_id.namespace.clone())?
-                .component(endpoint_id.component.clone())?
-                .endpoint(endpoint_id.name.clone());
-
-            let mut client = endpoint.client::<NvCreateChatCompletionRequest, Annotated<NvCreateChatCompletionStreamResponse>>().await?;
-
-            match &flags.router_mode {
-                RouterMode::Random | RouterMode::RoundRobin => {
-                    client.set_router_mode(flags.router_mode.into());
-                    tracing::info!("Waiting for remote model..");
-                    client.wait_for_endpoints().await?;
-                    tracing::info!("Model discovered");
-                }
-                RouterMode::KV => todo!(),
-            }
-
-            // The service_name isn't used for text chat outside of logs,
-            // so use the path. That avoids having to listen on etcd for model registration.
-            let service_name = endpoint.subject();
-            Ok((service_name, Arc::new(client), false))
+            let distributed_runtime =
+                DistributedRuntime::new(runtime.clone(), flags.distributed_config()).await?;
+            let (service_name, engine) =
+                dynamic_engine(&distributed_runtime, endpoint_id, &flags).await?;
+            Ok((service_name, engine, false))
         }
         EngineConfig::StaticFull {
             service_name,
             engine,
+            ..
         } => {
             tracing::debug!("Model: {service_name}");
             Ok((service_name, engine, false))
@@ -74,17 +65,24 @@ pub async fn prepare_engine(
             service_name,
             engine: inner_engine,
             card,
+            capabilities,
         } => {
             let frontend = ServiceFrontend::<
                 SingleIn<NvCreateChatCompletionRequest>,
                 ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
             >::new();
-            let preprocessor = OpenAIPreprocessor::new(*card.clone())
-                .await?
-                .into_operator();
-            let backend = Backend::from_tokenizer(card.tokenizer_hf()?)
-                .await?
-                .into_operator();
+            let preprocessor = OpenAIPreprocessor::new_with_options(
+                *card.clone(),
+                flags.preprocessor_options(capabilities),
+            )
+            .await?
+            .into_operator();
+            let backend = Backend::from_tokenizer_with_granularity(
+                card.tokenizer_hf()?,
+                flags.stream_granularity,
+            )
+            .await?
+            .into_operator();
             let engine = ServiceBackend::from_engine(inner_engine);
 
             let pipeline = frontend
@@ -101,3 +99,69 @@ pub async fn prepare_engine(
         EngineConfig::None => unreachable!(),
     }
 }
+
+/// Connect to a remote chat completions endpoint. If `--long-prompt-threshold` is set, requests
+/// with long prompts go to `--long-prompt-endpoint` instead.
+///
+/// The service_name isn't used for text chat outside of logs, so it's the endpoint path. That
+/// avoids having to listen on etcd for model registration.
+pub async fn dynamic_engine(
+    distributed_runtime: &DistributedRuntime,
+    endpoint_id: Endpoint,
+    flags: &Flags,
+) -> anyhow::Result<(String, OpenAIChatCompletionsStreamingEngine)> {
+    let (service_name, engine) = connect(distributed_runtime, endpoint_id, flags).await?;
+    let (Some(threshold), Some(long_path)) = (
+        flags.long_prompt_threshold,
+        flags.long_prompt_endpoint.as_ref(),
+    ) else {
+        return Ok((service_name, engine));
+    };
+    let Some(long_path) = long_path.strip_prefix(ENDPOINT_SCHEME) else {
+        anyhow::bail!("--long-prompt-endpoint must be in format {ENDPOINT_SCHEME}namespace.component.endpoint");
+    };
+    let (long_name, long_engine) = connect(distributed_runtime, long_path.parse()?, flags).await?;
+    tracing::info!("Prompts over {threshold} tokens will go to {long_name}");
+    let router = PromptLengthRouter::new(threshold, engine, long_engine);
+    Ok((service_name, Arc::new(router)))
+}
+
+async fn connect(
+    distributed_runtime: &DistributedRuntime,
+    endpoint_id: Endpoint,
+    flags: &Flags,
+) -> anyhow::Result<(String, OpenAIChatCompletionsStreamingEngine)> {
+    let endpoint = distributed_runtime
+        .namespace(endpoint_id.namespace.clone())?
+        .component(endpoint_id.component.clone())?
+        .endpoint(endpoint_id.name.clone());
+
+    let mut client = endpoint
+        .client::<NvCreateChatCompletionRequest, Annotated<NvCreateChatCompletionStreamResponse>>()
+        .await?;
+
+    match &flags.router_mode {
+        RouterMode::Random | RouterMode::RoundRobin => {
+            client.set_router_mode(flags.router_mode.clone().into());
+            tracing::info!("Waiting for remote model..");
+            client.wait_for_endpoints().await?;
+            tracing::info!("Model discovered");
+        }
+        RouterMode::KV => todo!(),
+    }
+    if let Some(instance_id) = endpoint_id.instance_id {
+        client
+            .wait_for_instance(instance_id, INSTANCE_DISCOVERY_TIMEOUT)
+            .await?;
+        client.set_router_mode(RuntimeRouterMode::Direct(instance_id));
+        tracing::info!("Sending every request to instance {instance_id:x}");
+    }
+
+    let engine: OpenAIChatCompletionsStreamingEngine = Arc::new(client);
+    let engine = if flags.dynamic_max_retries > 0 {
+        dynamo_llm::engines::make_retrying_engine_full(engine, flags.dynamic_max_retries)
+    } else {
+        engine
+    };
+    Ok((endpoint.subject(), engine))
+}
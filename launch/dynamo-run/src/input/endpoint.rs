@@ -15,6 +15,7 @@
 
 use dynamo_llm::{
     backend::Backend,
+    engines,
     http::service::discovery::ModelEntry,
     model_type::ModelType,
     preprocessor::OpenAIPreprocessor,
@@ -26,15 +27,16 @@ use dynamo_llm::{
     },
 };
 use dynamo_runtime::pipeline::{
-    network::Ingress, ManyOut, Operator, SegmentSource, ServiceBackend, SingleIn, Source,
+    network::Ingress, ManyOut, Operator, ServiceBackend, ServiceFrontend, SingleIn, Source,
 };
 use dynamo_runtime::{protocols::Endpoint, DistributedRuntime};
 
-use crate::EngineConfig;
+use crate::{EngineConfig, Flags};
 
 pub async fn run(
     distributed_runtime: DistributedRuntime,
     path: String,
+    flags: Flags,
     engine_config: EngineConfig,
 ) -> anyhow::Result<()> {
     // This will attempt to connect to NATS and etcd
@@ -48,20 +50,31 @@ pub async fn run(
         EngineConfig::StaticFull {
             service_name,
             engine,
-        } => (Ingress::for_engine(engine)?, service_name),
+            ..
+        } => (
+            Ingress::for_engine(engines::make_aggregating_engine(engine))?,
+            service_name,
+        ),
         EngineConfig::StaticCore {
             service_name,
             engine: inner_engine,
             card,
+            capabilities,
         } => {
-            let frontend = SegmentSource::<
+            let frontend = ServiceFrontend::<
                 SingleIn<NvCreateChatCompletionRequest>,
                 ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
             >::new();
-            let preprocessor = OpenAIPreprocessor::new(*card.clone())
-                .await?
-                .into_operator();
-            let backend = Backend::from_mdc(*card.clone()).await?.into_operator();
+            let preprocessor = OpenAIPreprocessor::new_with_options(
+                *card.clone(),
+                flags.preprocessor_options(capabilities),
+            )
+            .await?
+            .into_operator();
+            let backend =
+                Backend::from_mdc_with_granularity(*card.clone(), flags.stream_granularity)
+                    .await?
+                    .into_operator();
             let engine = ServiceBackend::from_engine(inner_engine);
 
             let pipeline = frontend
@@ -72,7 +85,10 @@ pub async fn run(
                 .link(preprocessor.backward_edge())?
                 .link(frontend)?;
 
-            (Ingress::for_pipeline(pipeline)?, service_name)
+            (
+                Ingress::for_engine(engines::make_aggregating_engine(pipeline))?,
+                service_name,
+            )
         }
         EngineConfig::Dynamic(_) => {
             anyhow::bail!("Cannot use endpoint for both in and out");
@@ -84,6 +100,7 @@ pub async fn run(
         name: service_name.to_string(),
         endpoint: endpoint_id.clone(),
         model_type: ModelType::Chat,
+        default_max_tokens: flags.max_tokens,
     };
 
     let component = distributed_runtime
@@ -0,0 +1,288 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `in=grpc`: the `Chat` service of `proto/chat.proto`. Each request becomes a streaming chat
+//! completion request to the engine, and each chunk the engine sends back becomes a
+//! `ChatResponse` with the new text.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use async_openai::types::FinishReason;
+use dynamo_llm::http::service::error::HttpError;
+use dynamo_llm::types::{
+    openai::chat_completions::{
+        NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+        OpenAIChatCompletionsStreamingEngine,
+    },
+    Annotated,
+};
+use dynamo_runtime::{pipeline::Context, Runtime};
+use futures::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+use crate::input::common;
+use crate::{EngineConfig, Flags};
+
+mod proto {
+    tonic::include_proto!("dynamo.chat.v1");
+}
+use proto::chat_server::{Chat, ChatServer};
+use proto::{ChatRequest, ChatResponse, Usage};
+
+/// Serve the engine on `--grpc-port` until the runtime shuts down
+pub async fn run(
+    runtime: Runtime,
+    flags: Flags,
+    engine_config: EngineConfig,
+) -> anyhow::Result<()> {
+    let cancel_token = runtime.primary_token();
+    let address = SocketAddr::from(([0, 0, 0, 0], flags.grpc_port));
+    let (service_name, engine, _) = common::prepare_engine(runtime, flags, engine_config).await?;
+
+    tracing::info!(%address, "Starting gRPC service for {service_name}");
+    tonic::transport::Server::builder()
+        .add_service(ChatServer::new(ChatService { engine }))
+        .serve_with_shutdown(address, cancel_token.cancelled())
+        .await?;
+    Ok(())
+}
+
+struct ChatService {
+    engine: OpenAIChatCompletionsStreamingEngine,
+}
+
+type ChatResponseStream = Pin<Box<dyn Stream<Item = Result<ChatResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Chat for ChatService {
+    type GenerateStream = ChatResponseStream;
+
+    async fn generate(
+        &self,
+        request: Request<ChatRequest>,
+    ) -> Result<Response<Self::GenerateStream>, Status> {
+        let request = chat_request(request.into_inner())?;
+        let stream = self
+            .engine
+            .generate(Context::new(request))
+            .await
+            .map_err(engine_error)?;
+        // If the client goes away tonic drops this stream, and with it the engine's
+        let stream = stream.filter_map(|item| async move { chat_response(item).transpose() });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// The streaming chat completion request equivalent to a gRPC request
+fn chat_request(request: ChatRequest) -> Result<NvCreateChatCompletionRequest, Status> {
+    if request.messages.is_empty() {
+        return Err(Status::invalid_argument("messages must not be empty"));
+    }
+    let messages = request
+        .messages
+        .into_iter()
+        .map(|message| serde_json::json!({"role": message.role, "content": message.content}))
+        .collect::<Vec<_>>();
+    let mut body = serde_json::json!({
+        "model": request.model,
+        "messages": messages,
+        "stream": true,
+        "max_tokens": request.max_tokens,
+        "temperature": request.temperature,
+        "top_p": request.top_p,
+        "frequency_penalty": request.frequency_penalty,
+        "presence_penalty": request.presence_penalty,
+        "seed": request.seed,
+    });
+    if !request.stop.is_empty() {
+        body["stop"] = request.stop.into();
+    }
+    serde_json::from_value(body)
+        .map_err(|err| Status::invalid_argument(format!("Invalid chat request: {err}")))
+}
+
+/// The gRPC response for a chunk from the engine. None for chunks with nothing to tell the
+/// client, such as annotations or a first chunk with only the role.
+fn chat_response(
+    item: Annotated<NvCreateChatCompletionStreamResponse>,
+) -> Result<Option<ChatResponse>, Status> {
+    if item.is_error() {
+        let message = item.comment.unwrap_or_default().join(", ");
+        return Err(Status::internal(message));
+    }
+    let Some(data) = item.data else {
+        return Ok(None);
+    };
+    let choice = data.inner.choices.first();
+    let delta = choice
+        .and_then(|choice| choice.delta.content.clone())
+        .unwrap_or_default();
+    let finish_reason = choice
+        .and_then(|choice| choice.finish_reason)
+        .map(|reason| finish_reason_str(reason).to_string());
+    let usage = data.inner.usage.map(|usage| Usage {
+        prompt_tokens: usage.prompt_tokens,
+        completion_tokens: usage.completion_tokens,
+    });
+    if delta.is_empty() && finish_reason.is_none() && usage.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(ChatResponse {
+        id: data.inner.id,
+        delta,
+        finish_reason,
+        usage,
+    }))
+}
+
+/// The name OpenAI gives `reason`
+fn finish_reason_str(reason: FinishReason) -> &'static str {
+    match reason {
+        FinishReason::Stop => "stop",
+        FinishReason::Length => "length",
+        FinishReason::ToolCalls => "tool_calls",
+        FinishReason::ContentFilter => "content_filter",
+        FinishReason::FunctionCall => "function_call",
+    }
+}
+
+/// Client errors the engine reports as a 4xx [`HttpError`] are the client's fault here too
+fn engine_error(err: anyhow::Error) -> Status {
+    match err.downcast::<HttpError>() {
+        Ok(err) if (400..500).contains(&err.code) => Status::invalid_argument(err.message),
+        Ok(err) => Status::internal(err.message),
+        Err(err) => Status::internal(format!("Failed to generate: {err}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::Code;
+
+    fn chunk(
+        content: Option<&str>,
+        finish_reason: Option<&str>,
+    ) -> Annotated<NvCreateChatCompletionStreamResponse> {
+        let chunk = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 1,
+            "model": "test",
+            "choices": [{
+                "index": 0,
+                "delta": {"role": "assistant", "content": content},
+                "finish_reason": finish_reason,
+            }],
+        }))
+        .unwrap();
+        Annotated::from_data(chunk)
+    }
+
+    #[test]
+    fn test_chat_request() {
+        let request = chat_request(ChatRequest {
+            model: "test".to_string(),
+            messages: vec![
+                proto::Message {
+                    role: "system".to_string(),
+                    content: "Be brief.".to_string(),
+                },
+                proto::Message {
+                    role: "user".to_string(),
+                    content: "Hi".to_string(),
+                },
+            ],
+            max_tokens: Some(16),
+            temperature: Some(0.5),
+            stop: vec!["\n".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(request.inner.model, "test");
+        assert_eq!(request.inner.messages.len(), 2);
+        assert_eq!(request.inner.stream, Some(true));
+        #[allow(deprecated)]
+        let max_tokens = request.inner.max_tokens;
+        assert_eq!(max_tokens, Some(16));
+        assert_eq!(request.inner.temperature, Some(0.5));
+        assert!(request.inner.top_p.is_none());
+        assert!(request.inner.stop.is_some());
+
+        let err = chat_request(ChatRequest::default()).unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+
+        let err = chat_request(ChatRequest {
+            messages: vec![proto::Message {
+                role: "robot".to_string(),
+                content: "Hi".to_string(),
+            }],
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_chat_response() {
+        let response = chat_response(chunk(Some("Hello"), None)).unwrap().unwrap();
+        assert_eq!(response.id, "chatcmpl-1");
+        assert_eq!(response.delta, "Hello");
+        assert!(response.finish_reason.is_none());
+
+        let response = chat_response(chunk(None, Some("length"))).unwrap().unwrap();
+        assert_eq!(response.delta, "");
+        assert_eq!(response.finish_reason.as_deref(), Some("length"));
+
+        // Nothing to tell the client
+        assert!(chat_response(chunk(None, None)).unwrap().is_none());
+        assert!(
+            chat_response(Annotated::from_annotation("event", &"value").unwrap())
+                .unwrap()
+                .is_none()
+        );
+
+        let err = chat_response(Annotated::from_error("engine died".to_string())).unwrap_err();
+        assert_eq!(err.code(), Code::Internal);
+        assert_eq!(err.message(), "engine died");
+    }
+
+    #[test]
+    fn test_engine_error() {
+        let status = engine_error(
+            HttpError {
+                code: 400,
+                message: "bad prompt".to_string(),
+            }
+            .into(),
+        );
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "bad prompt");
+
+        let status = engine_error(
+            HttpError {
+                code: 503,
+                message: "busy".to_string(),
+            }
+            .into(),
+        );
+        assert_eq!(status.code(), Code::Internal);
+
+        let status = engine_error(anyhow::anyhow!("no engine"));
+        assert_eq!(status.code(), Code::Internal);
+        assert!(status.message().contains("no engine"));
+    }
+}
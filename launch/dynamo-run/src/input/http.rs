@@ -14,57 +14,172 @@
 // limitations under the License.
 
 use std::sync::Arc;
+use std::time::Duration;
 
 use dynamo_llm::{
     backend::Backend,
-    http::service::{discovery, service_v2},
+    http::service::{
+        discovery, sampling_profiles, service_v2, tee::TeeRecorder, version::VersionInfo,
+        ModelMetadata,
+    },
     model_type::ModelType,
     preprocessor::OpenAIPreprocessor,
     types::{
-        openai::chat_completions::{
-            NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+        openai::{
+            chat_completions::{
+                NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+            },
+            completions::{CompletionRequest, CompletionResponse},
         },
         Annotated,
     },
 };
 use dynamo_runtime::{
     pipeline::{ManyOut, Operator, ServiceBackend, ServiceFrontend, SingleIn, Source},
+    runtime::CancellationToken,
     DistributedRuntime, Runtime,
 };
 
-use crate::{EngineConfig, Flags};
+use crate::{input::common, EngineConfig, Flags};
+
+/// With `--shutdown-grace-secs`, how much longer than the grace period the process has to stop,
+/// for everything after the HTTP server
+const SHUTDOWN_MARGIN_SECS: u64 = 5;
 
 /// Build and run an HTTP service
 pub async fn run(
     runtime: Runtime,
     flags: Flags,
     engine_config: EngineConfig,
+    resolved_config: serde_json::Value,
+    version: VersionInfo,
 ) -> anyhow::Result<()> {
-    let http_service = service_v2::HttpService::builder()
+    let http_service = build_service(&flags, runtime.primary_token()).await?;
+    http_service.resolved_config().set(resolved_config);
+    http_service.version().set(version);
+    add_engine(&runtime, &flags, engine_config, &http_service).await?;
+    http_service.run(runtime.primary_token()).await
+}
+
+/// The HTTP service configured from the flags, with no models yet
+pub async fn build_service(
+    flags: &Flags,
+    cancel_token: CancellationToken,
+) -> anyhow::Result<service_v2::HttpService> {
+    let mut http_service_builder = service_v2::HttpService::builder()
         .port(flags.http_port)
+        .http_version(flags.http_version)
         .enable_chat_endpoints(true)
         .enable_cmpl_endpoints(true)
-        .build()?;
+        .metrics_export(flags.metrics_export.clone())
+        .model_priorities(flags.model_priority.iter().cloned().collect())
+        .serve_before_ready(flags.serve_before_ready)
+        .lenient_content_type(flags.lenient_content_type)
+        .lenient_numbers(flags.lenient_numbers)
+        .strict_finish_reason(flags.strict_finish_reason)
+        .allow_any_model(flags.allow_any_model)
+        .repair_json(flags.repair_json)
+        .max_n(flags.max_n)
+        .debug_token_ids(flags.debug_token_ids)
+        .debug_echo_headers(flags.debug_echo_headers)
+        .response_cache_size(flags.response_cache_size);
+    if let Some(max_concurrent_requests) = flags.max_concurrent_requests {
+        http_service_builder =
+            http_service_builder.max_concurrent_requests(max_concurrent_requests);
+    }
+    if let Some(metrics_port) = flags.metrics_port {
+        http_service_builder = http_service_builder.metrics_port(metrics_port);
+    }
+    if let Some(max_connections) = flags.http_max_connections {
+        http_service_builder = http_service_builder.max_connections(max_connections);
+    }
+    if let Some(admin_api_key) = flags.admin_api_key.clone() {
+        http_service_builder = http_service_builder.admin_api_key(admin_api_key);
+    }
+    if let Some(system_fingerprint) = flags.system_fingerprint.clone() {
+        http_service_builder = http_service_builder.system_fingerprint(system_fingerprint);
+    }
+    if let Some(tee_file) = flags.tee_file.as_ref() {
+        // Flushed when the runtime shuts down
+        let recorder = TeeRecorder::new(cancel_token, tee_file, None, None, None).await?;
+        http_service_builder = http_service_builder.tee_sink(recorder.event_sender());
+    }
+    if let Some(sampling_profiles) = flags.sampling_profiles.as_ref() {
+        http_service_builder =
+            http_service_builder.sampling_profiles(sampling_profiles::load(sampling_profiles)?);
+    }
+    if let Some(keepalive) = flags.http_keepalive {
+        http_service_builder = http_service_builder.keepalive(Duration::from_secs(keepalive));
+    }
+    if let Some(grace_secs) = flags.shutdown_grace_secs {
+        http_service_builder = http_service_builder.drain_timeout(Duration::from_secs(grace_secs));
+        // Don't let the runtime stop us before the requests had their time
+        dynamo_runtime::worker::set_graceful_shutdown_timeout(Duration::from_secs(
+            grace_secs + SHUTDOWN_MARGIN_SECS,
+        ));
+    }
+    if let Some(request_timeout) = flags.request_timeout {
+        http_service_builder = http_service_builder.request_timeout(request_timeout);
+    }
+    if let Some(max_chunk_chars) = flags.max_chunk_chars {
+        http_service_builder = http_service_builder.max_chunk_chars(max_chunk_chars);
+    }
+    http_service_builder.build()
+}
+
+/// Serve the engine's model, or the models discovered for it, from `http_service`
+pub async fn add_engine(
+    runtime: &Runtime,
+    flags: &Flags,
+    engine_config: EngineConfig,
+    http_service: &service_v2::HttpService,
+) -> anyhow::Result<()> {
     match engine_config {
+        EngineConfig::Dynamic(endpoint)
+            if flags.long_prompt_threshold.is_some() || endpoint.instance_id.is_some() =>
+        {
+            // Routing by prompt length needs both endpoints up front, and pinning an instance
+            // needs the endpoint, so we connect to them directly instead of discovering models.
+            let distributed_runtime =
+                DistributedRuntime::new(runtime.clone(), flags.distributed_config()).await?;
+            let (service_name, engine) =
+                common::dynamic_engine(&distributed_runtime, endpoint, flags).await?;
+            let model_name = flags.model_name.clone().unwrap_or(service_name);
+            let manager = http_service.model_manager();
+            manager.add_chat_completions_model(&model_name, engine)?;
+            manager.set_model_metadata(&model_name, flags.model_metadata());
+        }
         EngineConfig::Dynamic(endpoint) => {
-            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+            let distributed_runtime =
+                DistributedRuntime::new(runtime.clone(), flags.distributed_config()).await?;
             match distributed_runtime.etcd_client() {
                 Some(etcd_client) => {
                     // This will attempt to connect to NATS and etcd
 
-                    let component = distributed_runtime
-                        .namespace(endpoint.namespace)?
-                        .component(endpoint.component)?;
-                    let network_prefix = component.service_name();
+                    let namespace = distributed_runtime.namespace(endpoint.namespace)?;
+                    // With `dyn://<namespace>/*` serve every model in the namespace
+                    let (network_prefix, only_namespace) =
+                        if endpoint.component == crate::ALL_COMPONENTS {
+                            let prefix = namespace.service_name_prefix();
+                            (prefix, Some(namespace.name().to_string()))
+                        } else {
+                            let component = namespace.component(endpoint.component)?;
+                            (component.service_name(), None)
+                        };
+
+                    http_service
+                        .model_manager()
+                        .set_default_model_metadata(flags.model_metadata());
 
-                    // Listen for models registering themselves in etcd, add them to HTTP service
+                    // Listen for models registering themselves in etcd, add them to HTTP
+                    // service. One watcher for every model type.
+                    tracing::info!("Waiting for remote model at {network_prefix}");
                     let state = Arc::new(discovery::ModelWatchState {
                         prefix: network_prefix.clone(),
-                        model_type: ModelType::Chat,
                         manager: http_service.model_manager().clone(),
                         drt: distributed_runtime.clone(),
+                        namespace: only_namespace,
                     });
-                    tracing::info!("Waiting for remote model at {network_prefix}");
                     let models_watcher =
                         etcd_client.kv_get_and_watch_prefix(network_prefix).await?;
                     let (_prefix, _watcher, receiver) = models_watcher.dissolve();
@@ -78,39 +193,79 @@ pub async fn run(
         EngineConfig::StaticFull {
             service_name,
             engine,
-            ..
+            embeddings,
         } => {
-            http_service
-                .model_manager()
-                .add_chat_completions_model(&service_name, engine)?;
+            let manager = http_service.model_manager();
+            // The engine only understands chat, completions prompts become a user message
+            let completions_engine = dynamo_llm::engines::make_completions_engine(engine.clone());
+            manager.add_chat_completions_model(&service_name, engine)?;
+            manager.add_completions_model(&service_name, completions_engine)?;
+            if let Some(embeddings) = embeddings {
+                manager.add_embeddings_model(&service_name, embeddings)?;
+            }
+            manager.set_model_metadata(&service_name, flags.model_metadata());
         }
         EngineConfig::StaticCore {
             service_name,
             engine: inner_engine,
             card,
+            capabilities,
         } => {
             let frontend = ServiceFrontend::<
                 SingleIn<NvCreateChatCompletionRequest>,
                 ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
             >::new();
-            let preprocessor = OpenAIPreprocessor::new(*card.clone())
-                .await?
-                .into_operator();
-            let backend = Backend::from_mdc(*card.clone()).await?.into_operator();
-            let engine = ServiceBackend::from_engine(inner_engine);
+            let preprocessor = OpenAIPreprocessor::new_with_options(
+                *card.clone(),
+                flags.preprocessor_options(capabilities),
+            )
+            .await?;
+            http_service
+                .model_manager()
+                .set_model_preprocessor(&service_name, preprocessor.clone());
+            let chat_preprocessor = preprocessor.into_operator();
+            let backend =
+                Backend::from_mdc_with_granularity(*card.clone(), flags.stream_granularity)
+                    .await?
+                    .into_operator();
+            let engine = ServiceBackend::from_engine(inner_engine.clone());
 
             let pipeline = frontend
-                .link(preprocessor.forward_edge())?
+                .link(chat_preprocessor.forward_edge())?
                 .link(backend.forward_edge())?
                 .link(engine)?
                 .link(backend.backward_edge())?
-                .link(preprocessor.backward_edge())?
+                .link(chat_preprocessor.backward_edge())?
                 .link(frontend)?;
-            http_service
-                .model_manager()
-                .add_chat_completions_model(&service_name, pipeline)?;
+
+            // The same engine behind /v1/completions, the prompt goes through the same template
+            let completions_frontend = ServiceFrontend::<
+                SingleIn<CompletionRequest>,
+                ManyOut<Annotated<CompletionResponse>>,
+            >::new();
+            let completions_preprocessor = preprocessor.into_operator();
+            let completions_backend =
+                Backend::from_mdc_with_granularity(*card.clone(), flags.stream_granularity)
+                    .await?
+                    .into_operator();
+            let completions_engine = ServiceBackend::from_engine(inner_engine);
+            let completions_pipeline = completions_frontend
+                .link(completions_preprocessor.forward_edge())?
+                .link(completions_backend.forward_edge())?
+                .link(completions_engine)?
+                .link(completions_backend.backward_edge())?
+                .link(completions_preprocessor.backward_edge())?
+                .link(completions_frontend)?;
+
+            let metadata = ModelMetadata::from_card(&card)
+                .await?
+                .merge(flags.model_metadata());
+            let manager = http_service.model_manager();
+            manager.add_chat_completions_model(&service_name, pipeline)?;
+            manager.add_completions_model(&service_name, completions_pipeline)?;
+            manager.set_model_metadata(&service_name, metadata);
         }
         EngineConfig::None => unreachable!(),
     }
-    http_service.run(runtime.primary_token()).await
+    Ok(())
 }
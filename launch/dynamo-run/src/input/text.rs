@@ -13,12 +13,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use dynamo_llm::backend::ANNOTATION_STOP_CAUSE;
+use dynamo_llm::protocols::common::StopCause;
 use dynamo_llm::types::openai::chat_completions::{
     NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
 };
 use dynamo_runtime::{pipeline::Context, runtime::CancellationToken, Runtime};
 use futures::StreamExt;
 use std::io::{ErrorKind, Write};
+use std::time::Duration;
 
 use crate::input::common;
 use crate::{EngineConfig, Flags};
@@ -34,6 +37,7 @@ pub async fn run(
     engine_config: EngineConfig,
 ) -> anyhow::Result<()> {
     let cancel_token = runtime.primary_token();
+    let request_timeout = flags.request_timeout;
     let (service_name, engine, inspect_template): (
         String,
         OpenAIChatCompletionsStreamingEngine,
@@ -45,6 +49,7 @@ pub async fn run(
         engine,
         single_prompt,
         inspect_template,
+        request_timeout,
     )
     .await
 }
@@ -55,6 +60,7 @@ async fn main_loop(
     engine: OpenAIChatCompletionsStreamingEngine,
     mut initial_prompt: Option<String>,
     _inspect_template: bool,
+    request_timeout: Option<Duration>,
 ) -> anyhow::Result<()> {
     if initial_prompt.is_none() {
         tracing::info!("Ctrl-c to exit");
@@ -117,10 +123,18 @@ async fn main_loop(
         //     req_builder.min_tokens(8192);
         // }
 
-        let req = NvCreateChatCompletionRequest { inner, nvext: None };
+        let req = NvCreateChatCompletionRequest {
+            inner,
+            nvext: None,
+            service_tier: None,
+            stop_token_ids: None,
+        };
 
         // Call the model
         let mut stream = engine.generate(Context::new(req)).await?;
+        if let Some(timeout) = request_timeout {
+            stream = dynamo_llm::engines::with_inter_token_timeout(stream, timeout);
+        }
 
         // Stream the output to stdout
         let mut stdout = std::io::stdout();
@@ -150,6 +164,17 @@ async fn main_loop(
                         tracing::error!("Engine error: {err}");
                     }
                 }
+                (None, Some(ANNOTATION_STOP_CAUSE)) => {
+                    let stop_cause = item
+                        .comment
+                        .as_ref()
+                        .and_then(|comment| comment.first())
+                        .and_then(|cause| serde_json::from_str::<StopCause>(cause).ok());
+                    tracing::debug!(
+                        stop_cause = stop_cause.map(|cause| cause.as_str()),
+                        "Generation stopped"
+                    );
+                }
                 (None, Some(annotation)) => {
                     tracing::debug!("Annotation. {annotation}: {:?}", item.comment);
                 }
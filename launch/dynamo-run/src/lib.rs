@@ -15,17 +15,24 @@
 
 #[cfg(any(feature = "vllm", feature = "sglang"))]
 use std::{future::Future, pin::Pin};
-use std::{io::Read, sync::Arc};
+use std::{io::Read, path::Path, sync::Arc};
 
 use dynamo_llm::{
-    backend::ExecutionContext, kv_router::publisher::KvMetricsPublisher,
-    model_card::model::ModelDeploymentCard,
-    types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine,
+    backend::ExecutionContext,
+    engines::MaxTokensLimits,
+    http::service::version::VersionInfo,
+    kv_router::publisher::KvMetricsPublisher,
+    model_card::{archive, model::ModelDeploymentCard},
+    preprocessor::EngineCapabilities,
+    types::openai::{
+        chat_completions::OpenAIChatCompletionsStreamingEngine, embeddings::OpenAIEmbeddingsEngine,
+    },
 };
 use dynamo_runtime::{protocols::Endpoint, DistributedRuntime};
 
 mod flags;
 pub use flags::Flags;
+mod follower;
 mod hub;
 mod input;
 #[cfg(any(feature = "vllm", feature = "sglang"))]
@@ -38,6 +45,10 @@ pub use opt::{Input, Output};
 /// concatenations.
 const ENDPOINT_SCHEME: &str = "dyn://";
 
+/// The component in `out=dyn://<namespace>/*`, for an HTTP server in front of every model in the
+/// namespace
+const ALL_COMPONENTS: &str = "*";
+
 /// When `in=text` the user doesn't need to know the model name, and doesn't need to provide it on
 /// the command line. Hence it's optional, and defaults to this.
 const INVISIBLE_MODEL_NAME: &str = "dynamo-run";
@@ -61,6 +72,8 @@ pub enum EngineConfig {
     StaticFull {
         service_name: String,
         engine: OpenAIChatCompletionsStreamingEngine,
+        /// For engines which also embed, served at `/v1/embeddings` under the same name
+        embeddings: Option<OpenAIEmbeddingsEngine>,
     },
 
     /// A core engine expects to be wrapped with pre/post processors that handle tokenization.
@@ -68,12 +81,229 @@ pub enum EngineConfig {
         service_name: String,
         engine: ExecutionContext,
         card: Box<ModelDeploymentCard>,
+        /// What the engine can do beyond plain generation, the pre-processor rejects the rest
+        capabilities: EngineCapabilities,
     },
 
     /// vllm multi-node doesn't run an engine on nodes other than 0. 'ray' does all the work.
     None,
 }
 
+impl EngineConfig {
+    /// Limit the engine's output to `max_tps` tokens per second
+    fn paced(self, max_tps: u32) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_paced_engine_full(engine, max_tps),
+                embeddings,
+            },
+            EngineConfig::StaticCore {
+                service_name,
+                engine,
+                card,
+                capabilities,
+            } => EngineConfig::StaticCore {
+                service_name,
+                engine: dynamo_llm::engines::make_paced_engine_core(engine, max_tps),
+                card,
+                capabilities,
+            },
+            EngineConfig::Dynamic(_) => {
+                tracing::warn!(
+                    "--max-output-tps does not apply to remote engines, set it on the worker"
+                );
+                self
+            }
+            EngineConfig::None => self,
+        }
+    }
+
+    /// Put `prefix` and `suffix` around the last user message for engines which do their own
+    /// pre-processing. The pre-processor puts them around the rendered prompt for the others.
+    fn prompt_wrapped(self, prefix: Option<String>, suffix: Option<String>) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_prompt_wrapping_engine_full(
+                    engine, prefix, suffix,
+                ),
+                embeddings,
+            },
+            EngineConfig::Dynamic(_) => {
+                tracing::warn!(
+                    "--prompt-prefix and --prompt-suffix do not apply to remote engines, set them on the worker"
+                );
+                self
+            }
+            EngineConfig::StaticCore { .. } | EngineConfig::None => self,
+        }
+    }
+
+    /// Fill in and cap `max_tokens` before requests reach the engine
+    fn max_tokens_limited(self, limits: MaxTokensLimits) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_max_tokens_engine_full(engine, limits),
+                embeddings,
+            },
+            EngineConfig::StaticCore {
+                service_name,
+                engine,
+                card,
+                capabilities,
+            } => EngineConfig::StaticCore {
+                service_name,
+                engine: dynamo_llm::engines::make_max_tokens_engine_core(engine, limits),
+                card,
+                capabilities,
+            },
+            EngineConfig::Dynamic(_) => {
+                // The HTTP service applies the default, the workers their own limits
+                if limits.max.is_some() {
+                    tracing::warn!(
+                        "--max-max-tokens does not apply to remote engines, set it on the worker"
+                    );
+                }
+                self
+            }
+            EngineConfig::None => self,
+        }
+    }
+
+    /// Reject requests with a `response_format` JSON mode, for engines which would ignore it
+    fn unconstrained(self) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_unconstrained_engine_full(engine),
+                embeddings,
+            },
+            EngineConfig::StaticCore {
+                service_name,
+                engine,
+                card,
+                capabilities,
+            } => EngineConfig::StaticCore {
+                service_name,
+                engine: dynamo_llm::engines::make_unconstrained_engine_core(engine),
+                card,
+                capabilities,
+            },
+            EngineConfig::Dynamic(_) | EngineConfig::None => self,
+        }
+    }
+
+    /// Fail only the request, not the process, when the engine panics
+    fn panic_safe(self) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_panic_safe_engine_full(engine),
+                embeddings,
+            },
+            EngineConfig::StaticCore {
+                service_name,
+                engine,
+                card,
+                capabilities,
+            } => EngineConfig::StaticCore {
+                service_name,
+                engine: dynamo_llm::engines::make_panic_safe_engine_core(engine),
+                card,
+                capabilities,
+            },
+            // A remote engine panicking is the worker's problem, it has its own wrapper
+            EngineConfig::Dynamic(_) | EngineConfig::None => self,
+        }
+    }
+
+    /// Give requests which don't set a `seed` this one
+    fn seeded(self, seed: i64) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_seed_engine_full(engine, seed),
+                embeddings,
+            },
+            EngineConfig::StaticCore {
+                service_name,
+                engine,
+                card,
+                capabilities,
+            } => EngineConfig::StaticCore {
+                service_name,
+                engine: dynamo_llm::engines::make_seed_engine_core(engine, seed),
+                card,
+                capabilities,
+            },
+            EngineConfig::Dynamic(_) => {
+                tracing::warn!("--seed does not apply to remote engines, set it on the worker");
+                self
+            }
+            EngineConfig::None => self,
+        }
+    }
+
+    /// Share one run of the engine between identical deterministic requests in flight together
+    fn coalesced(self) -> Self {
+        match self {
+            EngineConfig::StaticFull {
+                service_name,
+                engine,
+                embeddings,
+            } => EngineConfig::StaticFull {
+                service_name,
+                engine: dynamo_llm::engines::make_coalescing_engine_full(engine),
+                embeddings,
+            },
+            EngineConfig::StaticCore {
+                service_name,
+                engine,
+                card,
+                capabilities,
+            } => EngineConfig::StaticCore {
+                service_name,
+                engine: dynamo_llm::engines::make_coalescing_engine_core(engine),
+                card,
+                capabilities,
+            },
+            EngineConfig::Dynamic(_) => {
+                tracing::warn!(
+                    "--coalesce-requests does not apply to remote engines, set it on the worker"
+                );
+                self
+            }
+            EngineConfig::None => self,
+        }
+    }
+}
+
 /// Distributed system values
 struct DynInput {
     endpoint_id: Endpoint,
@@ -90,18 +320,28 @@ pub async fn run(
 ) -> anyhow::Result<()> {
     let cancel_token = runtime.primary_token();
 
+    // With --serve-before-ready the HTTP port is up while we load the model
+    let early_http = if in_opt == Input::Http && flags.serve_before_ready {
+        let http_service = crate::input::http::build_service(&flags, cancel_token.clone()).await?;
+        let handle = http_service.spawn(cancel_token.clone()).await;
+        Some((http_service, handle))
+    } else {
+        None
+    };
+    let report_stage = |stage: &str| {
+        if let Some((http_service, _)) = early_http.as_ref() {
+            http_service.startup_progress().update(stage);
+        }
+    };
+
     // Turn relative paths into absolute paths
-    let mut model_path = flags
-        .model_path_pos
-        .clone()
-        .or(flags.model_path_flag.clone())
-        .and_then(|p| {
-            if p.exists() {
-                p.canonicalize().ok()
-            } else {
-                Some(p)
-            }
-        });
+    let mut model_path = flags.model_path()?.and_then(|p| {
+        if p.exists() {
+            p.canonicalize().ok()
+        } else {
+            Some(p)
+        }
+    });
 
     // Serve the model under the name provided, or the name of the GGUF file or HF repo.
     let mut model_name = flags
@@ -121,6 +361,17 @@ pub async fn run(
             }
         });
 
+    // If it's an archive (.tar.gz, .zip) of a model directory unpack it
+    if let Some(inner_model_path) = model_path.clone() {
+        if inner_model_path.is_file() && archive::is_archive(&inner_model_path) {
+            if flags.model_name.is_none() {
+                model_name = archive::archive_stem(&inner_model_path);
+            }
+            report_stage("extracting model archive");
+            model_path = Some(archive::unpack(&inner_model_path).await?);
+        }
+    }
+
     // If it's an HF repo download it
     if let Some(inner_model_path) = model_path.as_ref() {
         if !inner_model_path.exists() {
@@ -128,17 +379,25 @@ pub async fn run(
                 .iter()
                 .next_back()
                 .map(|s| s.to_string_lossy().to_string());
+            report_stage("downloading model");
             model_path = Some(hub::from_hf(inner_model_path).await?);
         }
     }
 
     // Load the model deployment card, if any
+    report_stage("loading model card");
     // Only used by some engines, so without those feature flags it's unused.
     #[allow(unused_variables)]
-    let maybe_card = match (&model_path, &flags.model_config) {
+    let mut maybe_card = match (&model_path, &flags.model_config) {
         // --model-config takes precedence
         (_, Some(model_config)) => {
-            match ModelDeploymentCard::from_local_path(model_config, model_name.as_deref()).await {
+            match ModelDeploymentCard::from_local_path_with_tokenizer(
+                model_config,
+                flags.tokenizer_path.as_deref(),
+                model_name.as_deref(),
+            )
+            .await
+            {
                 Ok(card) => Some(card),
                 Err(e) => {
                     tracing::error!(
@@ -151,7 +410,13 @@ pub async fn run(
         }
         // If --model-path is an HF repo use that
         (Some(model_path), _) if model_path.is_dir() => {
-            match ModelDeploymentCard::from_local_path(model_path, model_name.as_deref()).await {
+            match ModelDeploymentCard::from_local_path_with_tokenizer(
+                model_path,
+                flags.tokenizer_path.as_deref(),
+                model_name.as_deref(),
+            )
+            .await
+            {
                 Ok(card) => Some(card),
                 Err(e) => {
                     tracing::error!(
@@ -163,7 +428,17 @@ pub async fn run(
             }
         }
         (Some(model_path), _) if model_path.is_file() => {
-            match ModelDeploymentCard::from_gguf(model_path, model_name.as_deref()).await {
+            let card = match ModelDeploymentCard::from_gguf(model_path, model_name.as_deref()).await
+            {
+                Ok(mut card) => match flags.tokenizer_path.as_deref() {
+                    Some(tokenizer_path) => {
+                        card.set_tokenizer_dir(tokenizer_path).await.map(|_| card)
+                    }
+                    None => Ok(card),
+                },
+                Err(e) => Err(e),
+            };
+            match card {
                 Ok(card) => Some(card),
                 Err(e) => {
                     tracing::error!(
@@ -180,11 +455,22 @@ pub async fn run(
             None
         }
     };
+    if let (Some(card), Some(chat_template)) = (maybe_card.as_mut(), flags.chat_template.as_ref()) {
+        if is_inline_template(chat_template) {
+            card.set_chat_template(chat_template);
+        } else {
+            card.set_chat_template_file(Path::new(chat_template))?;
+        }
+    }
+    if let (Some(card), Some(format)) = (maybe_card.as_mut(), flags.tool_call_format) {
+        card.set_tool_call_format(format);
+    }
 
     // If we are in a distributed system, we need to know our component upfront
     let dyn_input = match &in_opt {
         Input::Endpoint(endpoint_path) => {
-            let distributed_runtime = DistributedRuntime::from_settings(runtime.clone()).await?;
+            let distributed_runtime =
+                DistributedRuntime::new(runtime.clone(), flags.distributed_config()).await?;
             let endpoint_id: Endpoint = endpoint_path.parse()?;
             Some(DynInput {
                 endpoint_id,
@@ -197,7 +483,31 @@ pub async fn run(
     #[cfg(any(feature = "vllm", feature = "sglang"))]
     let mut extra: Option<Pin<Box<dyn Future<Output = ()> + Send>>> = None; // vllm and sglang sub-process
 
+    // What `GET /admin/config` reports
+    let resolved_config = serde_json::json!({
+        "in": in_opt.to_string(),
+        "out": out_opt.to_string(),
+        "model_name": model_name,
+        "model_path": model_path,
+        "extra_engine_args": flags.load_extra_engine_args()?,
+        "flags": flags,
+    });
+
+    // What `GET /version` reports
+    let version = VersionInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: option_env!("DYNAMO_GIT_SHA").map(|sha| sha.to_string()),
+        features: enabled_features()
+            .into_iter()
+            .map(|feature| feature.to_string())
+            .collect(),
+        engine: Some(out_opt.to_string()),
+    };
+
+    let supports_output_constraints = out_opt.supports_output_constraints();
+
     // Create the engine matching `out`
+    report_stage("loading engine");
     let engine_config = match out_opt {
         Output::EchoFull => {
             let Some(model_name) = model_name else {
@@ -207,7 +517,8 @@ pub async fn run(
             };
             EngineConfig::StaticFull {
                 service_name: model_name,
-                engine: dynamo_llm::engines::make_engine_full(),
+                engine: dynamo_llm::engines::make_engine_full_with_delays(flags.echo_delays()),
+                embeddings: None,
             }
         }
         Output::EchoCore => {
@@ -219,14 +530,47 @@ pub async fn run(
             card.requires_preprocessing = true;
             EngineConfig::StaticCore {
                 service_name: card.service_name.clone(),
-                engine: dynamo_llm::engines::make_engine_core(),
+                engine: dynamo_llm::engines::make_engine_core_with_delays(flags.echo_delays()),
                 card: Box::new(card),
+                capabilities: Default::default(),
             }
         }
         Output::Endpoint(path) => {
             let endpoint: Endpoint = path.parse()?;
+            if endpoint.component == ALL_COMPONENTS && in_opt != Input::Http {
+                anyhow::bail!(
+                    "out=dyn://<namespace>/* serves every model in the namespace, it needs in=http"
+                );
+            }
+            if endpoint.component == ALL_COMPONENTS && endpoint.instance_id.is_some() {
+                anyhow::bail!(
+                    "out=dyn://<namespace>/* serves every model in the namespace, it can't pin an instance"
+                );
+            }
             EngineConfig::Dynamic(endpoint)
         }
+        Output::OpenAI {
+            base_url,
+            api_key_env,
+        } => {
+            let Some(model_name) = model_name else {
+                anyhow::bail!("out=openai: needs --model-name, the model to ask the server for");
+            };
+            let api_key = std::env::var(&api_key_env).ok();
+            if api_key.is_none() {
+                // Fine for a local vllm, but OpenAI and most hosted servers will refuse us
+                tracing::info!("{api_key_env} is not set, sending requests without an API key");
+            }
+            EngineConfig::StaticFull {
+                service_name: model_name,
+                engine: dynamo_llm::engines::openai::make_engine_with_pool(
+                    &base_url,
+                    api_key,
+                    flags.upstream_pool(),
+                )?,
+                embeddings: None,
+            }
+        }
         #[cfg(feature = "mistralrs")]
         Output::MistralRs => {
             let Some(model_path) = model_path else {
@@ -235,9 +579,23 @@ pub async fn run(
             let Some(model_name) = model_name else {
                 unreachable!("We checked model_path earlier, and set model_name from model_path");
             };
+            let shards = if model_path
+                .join(dynamo_llm::model_card::weights::SAFETENSORS_INDEX_FILE)
+                .exists()
+            {
+                // Check the shards are all there before spending time loading some of them
+                let shards = dynamo_llm::model_card::weights::safetensors_files(&model_path)?;
+                tracing::debug!("Loading weights from {} safetensors file(s)", shards.len());
+                Some(shards)
+            } else {
+                None
+            };
+            let (engine, embeddings) =
+                dynamo_engine_mistralrs::make_engine(&model_path, shards).await?;
             EngineConfig::StaticFull {
                 service_name: model_name,
-                engine: dynamo_engine_mistralrs::make_engine(&model_path).await?,
+                engine,
+                embeddings: Some(embeddings),
             }
         }
         #[cfg(feature = "sglang")]
@@ -271,6 +629,7 @@ pub async fn run(
                 }
             }
 
+            let warmup_cache = open_warmup_cache(&flags, "sglang", &card.service_name)?;
             let (engine, sglang_process) = dynamo_engine_sglang::make_engine(
                 cancel_token.clone(),
                 &model_path,
@@ -281,6 +640,9 @@ pub async fn run(
                 flags.extra_engine_args.clone(),
             )
             .await?;
+            if let Some(warmup_cache) = warmup_cache {
+                warmup_cache.mark_warm()?;
+            }
             extra = Some(Box::pin(async move {
                 let _ = sglang_process.await;
             }));
@@ -288,13 +650,12 @@ pub async fn run(
                 service_name: card.service_name.clone(),
                 engine,
                 card: Box::new(card),
+                capabilities: Default::default(),
             }
         }
         #[cfg(feature = "vllm")]
         Output::Vllm0_7 => {
-            if flags.base_gpu_id != 0 {
-                anyhow::bail!("vllm does not support base_gpu_id. Set environment variable CUDA_VISIBLE_DEVICES instead.");
-            }
+            set_vllm_visible_devices(&flags)?;
             let Some(model_path) = model_path else {
                 anyhow::bail!(
                     "out=vllm requires flag --model-path=<full-path-to-hf-repo-or-model-gguf>"
@@ -338,6 +699,7 @@ pub async fn run(
                 };
 
                 // vllm multi-node only the leader runs vllm
+                let warmup_cache = open_warmup_cache(&flags, "vllm0_7", &card.service_name)?;
                 let (engine, vllm_future) = dynamo_engine_vllm0_7::make_leader_engine(
                     cancel_token.clone(),
                     &model_path,
@@ -348,6 +710,9 @@ pub async fn run(
                     kv_metrics_publisher,
                 )
                 .await?;
+                if let Some(warmup_cache) = warmup_cache {
+                    warmup_cache.mark_warm()?;
+                }
                 extra = Some(Box::pin(async move {
                     let _ = vllm_future.await;
                 }));
@@ -355,6 +720,7 @@ pub async fn run(
                     service_name: card.service_name.clone(),
                     engine,
                     card: Box::new(card),
+                    capabilities: Default::default(),
                 }
             } else {
                 // Nodes rank > 0 only run 'ray'
@@ -367,9 +733,7 @@ pub async fn run(
 
         #[cfg(feature = "vllm")]
         Output::Vllm | Output::Vllm0_8 => {
-            if flags.base_gpu_id != 0 {
-                anyhow::bail!("vllm does not support base_gpu_id. Set environment variable CUDA_VISIBLE_DEVICES instead.");
-            }
+            set_vllm_visible_devices(&flags)?;
             let Some(model_path) = model_path else {
                 anyhow::bail!(
                     "out=vllm requires flag --model-path=<full-path-to-hf-repo-or-model-gguf>"
@@ -385,6 +749,7 @@ pub async fn run(
                 node_rank: flags.node_rank,
                 leader_addr: flags.leader_addr.clone().unwrap_or_default(),
             };
+            let warmup_cache = open_warmup_cache(&flags, "vllm0_8", &card.service_name)?;
             let engine = dynamo_engine_vllm0_8::make_engine(
                 cancel_token.clone(),
                 &model_path,
@@ -393,10 +758,18 @@ pub async fn run(
                 flags.extra_engine_args.clone(),
             )
             .await?;
+            if let Some(warmup_cache) = warmup_cache {
+                warmup_cache.mark_warm()?;
+            }
             EngineConfig::StaticCore {
                 service_name: card.service_name.clone(),
                 engine,
                 card: Box::new(card),
+                // vllm scores the tokens it samples
+                capabilities: EngineCapabilities {
+                    logprobs: true,
+                    ..Default::default()
+                },
             }
         }
 
@@ -410,7 +783,7 @@ pub async fn run(
             }
             let Some(card) = maybe_card.clone() else {
                 anyhow::bail!(
-                    "Pass --model-config so we can find the tokenizer, should be an HF checkout."
+                    "Could not build the tokenizer from the GGUF's metadata, see the error above. Pass --model-config with an HF checkout instead."
                 );
             };
             let engine =
@@ -419,6 +792,7 @@ pub async fn run(
                 service_name: card.service_name.clone(),
                 engine,
                 card: Box::new(card),
+                capabilities: Default::default(),
             }
         }
         #[cfg(feature = "python")]
@@ -426,13 +800,28 @@ pub async fn run(
             let Some(model_name) = model_name else {
                 anyhow::bail!("Provide model service name as `--model-name <this>`");
             };
-            let py_args = flags.as_vec(&path_str, &model_name);
+            let (path_str, entrypoint) = split_python_entrypoint(&path_str);
+            let py_args = flags.as_vec(path_str, &model_name);
             let p = std::path::PathBuf::from(path_str);
-            let engine =
-                dynamo_engine_python::make_string_engine(cancel_token.clone(), &p, py_args).await?;
+            let engine = dynamo_engine_python::make_string_engine(
+                cancel_token.clone(),
+                &p,
+                py_args,
+                dynamo_engine_python::EngineOptions {
+                    generate_retries: flags.python_generate_retries,
+                    entrypoint,
+                    event_loop_startup_timeout: flags
+                        .python_event_loop_timeout
+                        .map(std::time::Duration::from_secs),
+                    max_concurrent: flags.python_max_concurrent,
+                    ..Default::default()
+                },
+            )
+            .await?;
             EngineConfig::StaticFull {
                 service_name: model_name,
                 engine,
+                embeddings: None,
             }
         }
         #[cfg(feature = "python")]
@@ -443,21 +832,92 @@ pub async fn run(
             let Some(model_name) = model_name else {
                 unreachable!("If we have a card we must have a model name");
             };
-            let py_args = flags.as_vec(&path_str, &model_name);
+            let (path_str, entrypoint) = split_python_entrypoint(&path_str);
+            let py_args = flags.as_vec(path_str, &model_name);
             let p = std::path::PathBuf::from(path_str);
-            let engine =
-                dynamo_engine_python::make_token_engine(cancel_token.clone(), &p, py_args).await?;
+            let engine = dynamo_engine_python::make_token_engine(
+                cancel_token.clone(),
+                &p,
+                py_args,
+                dynamo_engine_python::EngineOptions {
+                    generate_retries: flags.python_generate_retries,
+                    entrypoint,
+                    event_loop_startup_timeout: flags
+                        .python_event_loop_timeout
+                        .map(std::time::Duration::from_secs),
+                    max_concurrent: flags.python_max_concurrent,
+                    ..Default::default()
+                },
+            )
+            .await?;
             EngineConfig::StaticCore {
                 service_name: model_name.clone(),
                 engine,
                 card: Box::new(card),
+                capabilities: Default::default(),
             }
         }
     };
+    // We will be doing the pre-processing, so we need a template. Catch that now, not on the
+    // first request.
+    if let EngineConfig::StaticCore { card, .. } = &engine_config {
+        dynamo_llm::preprocessor::prompt::PromptFormatter::check_chat_template(card)?;
+    }
+    let engine_config = engine_config.panic_safe();
+    let engine_config = match flags.max_output_tps {
+        Some(max_tps) => engine_config.paced(max_tps),
+        None => engine_config,
+    };
+    let engine_config = if flags.prompt_prefix.is_some() || flags.prompt_suffix.is_some() {
+        engine_config.prompt_wrapped(flags.prompt_prefix.clone(), flags.prompt_suffix.clone())
+    } else {
+        engine_config
+    };
+    let max_tokens_limits = flags.max_tokens_limits();
+    let engine_config = if max_tokens_limits.is_set() {
+        engine_config.max_tokens_limited(max_tokens_limits)
+    } else {
+        engine_config
+    };
+    let engine_config = if supports_output_constraints {
+        engine_config
+    } else {
+        engine_config.unconstrained()
+    };
+    let engine_config = match flags.seed {
+        Some(seed) => engine_config.seeded(seed),
+        None => engine_config,
+    };
+    let engine_config = if flags.coalesce_requests {
+        engine_config.coalesced()
+    } else {
+        engine_config
+    };
 
     match in_opt {
-        Input::Http => {
-            crate::input::http::run(runtime.clone(), flags, engine_config).await?;
+        Input::Http => match early_http {
+            Some((http_service, handle)) => {
+                http_service.resolved_config().set(resolved_config);
+                http_service.version().set(version);
+                crate::input::http::add_engine(&runtime, &flags, engine_config, &http_service)
+                    .await?;
+                http_service.startup_progress().set_ready();
+                handle.await??;
+            }
+            None => {
+                crate::input::http::run(
+                    runtime.clone(),
+                    flags,
+                    engine_config,
+                    resolved_config,
+                    version,
+                )
+                .await?;
+            }
+        },
+        #[cfg(feature = "grpc")]
+        Input::Grpc => {
+            crate::input::grpc::run(runtime.clone(), flags, engine_config).await?;
         }
         Input::Text => {
             crate::input::text::run(runtime.clone(), flags, None, engine_config).await?;
@@ -475,13 +935,45 @@ pub async fn run(
             let Some(dyn_input) = dyn_input else {
                 unreachable!("We set dyn_input earlier");
             };
-            crate::input::endpoint::run(dyn_input.distributed_runtime, path, engine_config).await?;
+            crate::input::endpoint::run(dyn_input.distributed_runtime, path, flags, engine_config)
+                .await?;
         }
         Input::None => {
             // Multi-node setup. The engine sub-process has been started and is talking
-            // to it's node_rank 0 controller. We do nothing.
-            // TODO: Acquire an etcd lease, we are running
-            cancel_token.cancelled().await;
+            // to it's node_rank 0 controller. We only hold a lease to say we are running, with
+            // our key under it if we know where to put it.
+            let key = dyn_input.as_ref().map(|dyn_input| {
+                follower::follower_key(
+                    &dyn_input.endpoint_id.namespace,
+                    &dyn_input.endpoint_id.component,
+                    flags.node_rank,
+                )
+            });
+            if key.is_none() {
+                tracing::warn!(
+                    "Not registering node {} under a key, that needs in=dyn://..",
+                    flags.node_rank
+                );
+            }
+            let etcd_client = match dyn_input {
+                Some(dyn_input) => dyn_input.distributed_runtime.etcd_client(),
+                None => match DistributedRuntime::from_settings(runtime.clone()).await {
+                    Ok(distributed_runtime) => distributed_runtime.etcd_client(),
+                    Err(err) => {
+                        tracing::warn!(%err, "Cannot reach etcd");
+                        None
+                    }
+                },
+            };
+            match etcd_client {
+                Some(etcd_client) => {
+                    follower::run(&etcd_client, flags.node_rank, key, cancel_token.clone()).await?;
+                }
+                None => {
+                    tracing::warn!("Running without an etcd lease, others can't see this node");
+                    cancel_token.cancelled().await;
+                }
+            }
         }
     }
 
@@ -493,3 +985,144 @@ pub async fn run(
 
     Ok(())
 }
+
+/// The optional features this binary was built with, as reported by `GET /version`
+pub fn enabled_features() -> Vec<&'static str> {
+    [
+        ("mistralrs", cfg!(feature = "mistralrs")),
+        ("llamacpp", cfg!(feature = "llamacpp")),
+        ("vllm", cfg!(feature = "vllm")),
+        ("sglang", cfg!(feature = "sglang")),
+        ("python", cfg!(feature = "python")),
+        ("grpc", cfg!(feature = "grpc")),
+        ("cuda", cfg!(feature = "cuda")),
+        ("metal", cfg!(feature = "metal")),
+        ("vulkan", cfg!(feature = "vulkan")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+/// Is `--chat-template` the template itself, rather than a path to one
+fn is_inline_template(chat_template: &str) -> bool {
+    chat_template.contains("{{") || chat_template.contains("{%")
+}
+
+/// vllm has no base GPU setting, it uses the GPUs in CUDA_VISIBLE_DEVICES. With
+/// `--auto-cuda-visible-devices` set that from `--base-gpu-id`, unless the user already did.
+#[cfg(feature = "vllm")]
+fn set_vllm_visible_devices(flags: &Flags) -> anyhow::Result<()> {
+    let existing = std::env::var("CUDA_VISIBLE_DEVICES").ok();
+    if let Some(devices) = vllm_visible_devices(flags, existing.as_deref())? {
+        tracing::info!("Setting CUDA_VISIBLE_DEVICES={devices}");
+        std::env::set_var("CUDA_VISIBLE_DEVICES", devices);
+    }
+    Ok(())
+}
+
+/// The CUDA_VISIBLE_DEVICES vllm needs, if we have to set it. `existing` is the value already in
+/// the environment, which is left alone.
+#[cfg(feature = "vllm")]
+fn vllm_visible_devices(flags: &Flags, existing: Option<&str>) -> anyhow::Result<Option<String>> {
+    if !flags.auto_cuda_visible_devices {
+        if flags.base_gpu_id != 0 {
+            anyhow::bail!("vllm does not support base_gpu_id. Set environment variable CUDA_VISIBLE_DEVICES instead, or pass --auto-cuda-visible-devices.");
+        }
+        return Ok(None);
+    }
+    if let Some(existing) = existing {
+        tracing::info!("CUDA_VISIBLE_DEVICES is already set to '{existing}', not changing it");
+        return Ok(None);
+    }
+    // tensor_parallel_size is the total across all nodes
+    let gpus_per_node = flags.tensor_parallel_size / flags.num_nodes;
+    Ok(Some(dynamo_llm::engines::cuda_visible_devices(
+        flags.base_gpu_id,
+        gpus_per_node,
+    )))
+}
+
+/// With `--warmup-cache-dir`, keep the engine's compiled kernels and graphs in a directory for
+/// this model and configuration, so the next start with the same settings reuses them.
+#[cfg(any(feature = "vllm", feature = "sglang"))]
+fn open_warmup_cache(
+    flags: &Flags,
+    engine: &str,
+    model_name: &str,
+) -> anyhow::Result<Option<dynamo_llm::engines::warmup_cache::WarmupCache>> {
+    let Some(root) = flags.warmup_cache_dir.as_ref() else {
+        return Ok(None);
+    };
+    // Everything which changes what the engine compiles. Sorted, so the hash is stable.
+    let extra_engine_args: Option<std::collections::BTreeMap<_, _>> = flags
+        .load_extra_engine_args()?
+        .map(|args| args.into_iter().collect());
+    let config = serde_json::json!({
+        "engine": engine,
+        "tensor_parallel_size": flags.tensor_parallel_size,
+        "num_nodes": flags.num_nodes,
+        "extra_engine_args": extra_engine_args,
+    });
+    let cache = dynamo_llm::engines::warmup_cache::WarmupCache::open(root, model_name, &config)?;
+    if cache.is_warm() {
+        tracing::info!("Reusing warmup artifacts from {}", cache.dir().display());
+    } else {
+        tracing::info!("Saving warmup artifacts to {}", cache.dir().display());
+    }
+    for (name, value) in cache.env_vars() {
+        std::env::set_var(name, value);
+    }
+    Ok(Some(cache))
+}
+
+/// `pystr:` and `pytok:` take an optional function name after the file, as in
+/// `pystr:handlers.py:my_entry`. Without one the engine calls `generate`.
+#[cfg(feature = "python")]
+fn split_python_entrypoint(path_str: &str) -> (&str, Option<String>) {
+    match path_str.rsplit_once(':') {
+        Some((path, name))
+            if !path.is_empty()
+                && !name.is_empty()
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+        {
+            (path, Some(name.to_string()))
+        }
+        _ => (path_str, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "vllm")]
+    #[test]
+    fn test_vllm_visible_devices() {
+        use super::{vllm_visible_devices, Flags};
+        use clap::Parser;
+
+        let parse = |args: &[&str]| {
+            Flags::try_parse_from(std::iter::once("dynamo-run").chain(args.iter().copied()))
+                .unwrap()
+        };
+
+        let flags = parse(&[
+            "--auto-cuda-visible-devices",
+            "--base-gpu-id",
+            "2",
+            "--tensor-parallel-size",
+            "2",
+        ]);
+        assert_eq!(
+            vllm_visible_devices(&flags, None).unwrap().as_deref(),
+            Some("2,3")
+        );
+        // The user's own setting wins
+        assert_eq!(vllm_visible_devices(&flags, Some("5,6")).unwrap(), None);
+
+        // Without the flag a base GPU ID is an error, as before
+        let flags = parse(&["--base-gpu-id", "2"]);
+        assert!(vllm_visible_devices(&flags, None).is_err());
+        let flags = parse(&[]);
+        assert_eq!(vllm_visible_devices(&flags, None).unwrap(), None);
+    }
+}
@@ -32,7 +32,7 @@ Example:
 
 const ZMQ_SOCKET_PREFIX: &str = "dyn";
 
-const USAGE: &str = "USAGE: dynamo-run in=[http|text|dyn://<path>|batch:<folder>|none] out=ENGINE_LIST [--http-port 8080] [--model-path <path>] [--model-name <served-model-name>] [--model-config <hf-repo>] [--tensor-parallel-size=1] [--num-nodes=1] [--node-rank=0] [--leader-addr=127.0.0.1:9876] [--base-gpu-id=0] [--extra-engine-args=args.json] [--router-mode random|round-robin]";
+const USAGE: &str = "USAGE: dynamo-run in=[INPUT_LIST] out=ENGINE_LIST [--http-port 8080] [--model-path <path>] [--model-name <served-model-name>] [--model-config <hf-repo>] [--tensor-parallel-size=1] [--num-nodes=1] [--node-rank=0] [--leader-addr=127.0.0.1:9876] [--base-gpu-id=0] [--extra-engine-args=args.json] [--router-mode random|round-robin]";
 
 fn main() -> anyhow::Result<()> {
     logging::init();
@@ -112,14 +112,19 @@ fn main() -> anyhow::Result<()> {
     worker.execute(wrapper)
 }
 
+/// [`USAGE`] with the inputs and engines this binary was built with
+fn usage() -> String {
+    USAGE
+        .replace("INPUT_LIST", &Input::available_inputs().join("|"))
+        .replace("ENGINE_LIST", &Output::available_engines().join("|"))
+}
+
 async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
     let mut in_opt = None;
     let mut out_opt = None;
     let args: Vec<String> = env::args().skip(1).collect();
     if args.is_empty() || args[0] == "-h" || args[0] == "--help" {
-        let engine_list = Output::available_engines().join("|");
-        let usage = USAGE.replace("ENGINE_LIST", &engine_list);
-        println!("{usage}");
+        println!("{}", usage());
         println!("{HELP}");
 
         return Ok(());
@@ -137,7 +142,10 @@ async fn wrapper(runtime: dynamo_runtime::Runtime) -> anyhow::Result<()> {
                 out_opt = Some(val.try_into()?);
             }
             _ => {
-                anyhow::bail!("Invalid argument, must start with 'in' or 'out. {USAGE}");
+                anyhow::bail!(
+                    "Invalid argument, must start with 'in' or 'out. {}",
+                    usage()
+                );
             }
         }
     }
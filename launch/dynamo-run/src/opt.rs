@@ -19,11 +19,20 @@ use crate::ENDPOINT_SCHEME;
 
 const BATCH_PREFIX: &str = "batch:";
 
+const OPENAI_PREFIX: &str = "openai:";
+
+/// Where `out=openai:` reads the API key from by default
+const DEFAULT_OPENAI_API_KEY_ENV: &str = "OPENAI_API_KEY";
+
 #[derive(PartialEq)]
 pub enum Input {
     /// Run an OpenAI compatible HTTP server
     Http,
 
+    /// Serve chat completions over gRPC, see `proto/chat.proto`
+    #[cfg(feature = "grpc")]
+    Grpc,
+
     /// Single prompt on stdin
     Stdin,
 
@@ -48,6 +57,8 @@ impl TryFrom<&str> for Input {
     fn try_from(s: &str) -> anyhow::Result<Self> {
         match s {
             "http" => Ok(Input::Http),
+            #[cfg(feature = "grpc")]
+            "grpc" => Ok(Input::Grpc),
             "text" => Ok(Input::Text),
             "stdin" => Ok(Input::Stdin),
             "none" => Ok(Input::None),
@@ -68,6 +79,8 @@ impl fmt::Display for Input {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = match self {
             Input::Http => "http",
+            #[cfg(feature = "grpc")]
+            Input::Grpc => "grpc",
             Input::Text => "text",
             Input::Stdin => "stdin",
             Input::Endpoint(path) => path,
@@ -78,6 +91,22 @@ impl fmt::Display for Input {
     }
 }
 
+impl Input {
+    /// The in= options this binary was built with, for the usage message
+    pub fn available_inputs() -> Vec<String> {
+        let mut out = vec![Input::Http.to_string()];
+        #[cfg(feature = "grpc")]
+        {
+            out.push(Input::Grpc.to_string());
+        }
+        out.push(Input::Text.to_string());
+        out.push(format!("{ENDPOINT_SCHEME}<path>"));
+        out.push(format!("{BATCH_PREFIX}<folder>"));
+        out.push(Input::None.to_string());
+        out
+    }
+}
+
 impl Default for Input {
     fn default() -> Self {
         if std::io::stdin().is_terminal() {
@@ -98,6 +127,15 @@ pub enum Output {
     /// Publish requests to a namespace/component/endpoint path.
     Endpoint(String),
 
+    /// Send requests to an OpenAI compatible HTTP server, such as vllm's own server or OpenAI.
+    /// `out=openai:<base_url>`, e.g. `out=openai:http://localhost:8000`. The API key is read from
+    /// the environment variable `api_key_env`, OPENAI_API_KEY unless the URL ends with
+    /// `#<variable name>`.
+    OpenAI {
+        base_url: String,
+        api_key_env: String,
+    },
+
     #[cfg(feature = "mistralrs")]
     /// Run inference on a model in a GGUF file using mistralrs w/ candle
     MistralRs,
@@ -165,6 +203,24 @@ impl TryFrom<&str> for Output {
                 Ok(Output::Endpoint(path.to_string()))
             }
 
+            openai if openai.starts_with(OPENAI_PREFIX) => {
+                let url = openai.strip_prefix(OPENAI_PREFIX).unwrap();
+                // A fragment means nothing to the server, so it can name the variable
+                let (base_url, api_key_env) = match url.split_once('#') {
+                    Some((base_url, api_key_env)) => (base_url, api_key_env),
+                    None => (url, DEFAULT_OPENAI_API_KEY_ENV),
+                };
+                if base_url.is_empty() {
+                    anyhow::bail!(
+                        "out=openai: needs the server's URL, e.g. out=openai:http://localhost:8000"
+                    );
+                }
+                Ok(Output::OpenAI {
+                    base_url: base_url.to_string(),
+                    api_key_env: api_key_env.to_string(),
+                })
+            }
+
             #[cfg(feature = "python")]
             python_str_gen if python_str_gen.starts_with(crate::PYTHON_STR_SCHEME) => {
                 let path = python_str_gen
@@ -209,6 +265,7 @@ impl fmt::Display for Output {
             Output::EchoCore => "echo_core",
 
             Output::Endpoint(path) => path,
+            Output::OpenAI { base_url, .. } => base_url,
 
             #[cfg(feature = "python")]
             Output::PythonStr(_) => "pystr",
@@ -287,4 +344,24 @@ impl Output {
 
         out
     }
+
+    /// Whether the engine honors `response_format` JSON modes. Requests asking for one from an
+    /// engine which can't are rejected rather than answered with unconstrained text. Engines we
+    /// hand the request on to, over the network or in python, get to decide for themselves.
+    pub fn supports_output_constraints(&self) -> bool {
+        match self {
+            Output::EchoFull | Output::EchoCore => false,
+            Output::Endpoint(_) | Output::OpenAI { .. } => true,
+            #[cfg(feature = "mistralrs")]
+            Output::MistralRs => true,
+            #[cfg(feature = "sglang")]
+            Output::SgLang => false,
+            #[cfg(feature = "llamacpp")]
+            Output::LlamaCpp => true,
+            #[cfg(feature = "vllm")]
+            Output::Vllm | Output::Vllm0_8 | Output::Vllm0_7 => false,
+            #[cfg(feature = "python")]
+            Output::PythonStr(_) | Output::PythonTok(_) => true,
+        }
+    }
 }
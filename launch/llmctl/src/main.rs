@@ -91,6 +91,12 @@ define_type_subcommands!(
         ["completions", "completion-model"],
         "Add a completion model"
     ),
+    (
+        Embedding,
+        "embedding",
+        ["embeddings", "embedding-model"],
+        "Add an embedding model"
+    ),
     // Add new model types here:
 );
 
@@ -253,12 +259,14 @@ async fn add_model(
         },
         component: parts[parts.len() - 2].to_string(),
         name: parts[parts.len() - 1].to_string(),
+        instance_id: None,
     };
 
     let model = ModelEntry {
         name: model_name.to_string(),
         endpoint,
         model_type,
+        default_max_tokens: None,
     };
 
     // add model to etcd
@@ -0,0 +1,390 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `response_format` as a GBNF grammar, which llama.cpp constrains sampling to.
+//!
+//! JSON schemas are covered as far as structured output usually needs: objects, arrays, strings,
+//! numbers, integers, booleans, null, `enum`, `const`, `anyOf` and `oneOf`. Required properties
+//! come first, in the order of `required`, then the optional ones, which the model may leave out.
+//! An object with no required properties gets all of them. Bounds such as `minimum` or
+//! `maxLength` are not enforced. Schemas using `$ref`, `allOf`, `not` or `pattern` are rejected.
+
+use dynamo_llm::protocols::common::OutputConstraint;
+use serde_json::Value;
+
+/// Keywords we can't turn into a grammar. Ignoring them would allow output the schema doesn't.
+const UNSUPPORTED: &[&str] = &["$ref", "allOf", "not", "pattern", "patternProperties", "if"];
+
+/// Rules any grammar may use: name, GBNF, and the shortest text they match
+const PRIMITIVES: &[(&str, &str, &str)] = &[
+    ("ws", r#"| " " | "\n" [ \t]{0,20}"#, ""),
+    (
+        "char",
+        r#"[^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F]{4})"#,
+        "a",
+    ),
+    ("string", r#""\"" char* "\"""#, r#""""#),
+    (
+        "number",
+        r#""-"? ([0-9] | [1-9] [0-9]{0,15}) ("." [0-9]+)? ([eE] [-+]? [0-9]{1,15})?"#,
+        "0",
+    ),
+    ("integer", r#""-"? ([0-9] | [1-9] [0-9]{0,15})"#, "0"),
+    ("boolean", r#""true" | "false""#, "true"),
+    ("null", r#""null""#, "null"),
+    (
+        "value",
+        r#"object | array | string | number | boolean | null"#,
+        "null",
+    ),
+    (
+        "object",
+        r#""{" ws (string ":" ws value ws ("," ws string ":" ws value ws)*)? "}""#,
+        "{}",
+    ),
+    (
+        "array",
+        r#""[" ws (value ws ("," ws value ws)*)? "]""#,
+        "[]",
+    ),
+];
+
+/// A grammar whose start rule is `root`
+#[derive(Debug)]
+pub struct Grammar {
+    /// The rules made from the schema, `root` first. The [`PRIMITIVES`] come after them.
+    rules: Vec<(String, Expr)>,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    /// Exactly this text
+    Literal(String),
+    /// Another rule, by name
+    Rule(String),
+    Seq(Vec<Expr>),
+    /// One of these, never empty
+    Alt(Vec<Expr>),
+    Optional(Box<Expr>),
+    /// Zero or more
+    Repeat(Box<Expr>),
+}
+
+impl Grammar {
+    /// The grammar for what `constraint` allows
+    pub fn from_constraint(constraint: &OutputConstraint) -> anyhow::Result<Grammar> {
+        match constraint {
+            OutputConstraint::JsonObject => Ok(Grammar {
+                rules: vec![("root".to_string(), Expr::Rule("object".to_string()))],
+            }),
+            OutputConstraint::JsonSchema(schema) => Grammar::from_schema(schema),
+        }
+    }
+
+    fn from_schema(schema: &Value) -> anyhow::Result<Grammar> {
+        let mut grammar = Grammar { rules: vec![] };
+        // root goes first, filled in once its parts have rules
+        grammar.rules.push(("root".to_string(), Expr::Seq(vec![])));
+        let root = grammar.expr(schema)?;
+        grammar.rules[0].1 = root;
+        Ok(grammar)
+    }
+
+    /// The grammar in GBNF, for llama.cpp
+    pub fn to_gbnf(&self) -> String {
+        let mut out = String::new();
+        for (name, expr) in &self.rules {
+            out.push_str(&format!("{name} ::= {}\n", expr.to_gbnf()));
+        }
+        for (name, gbnf, _) in PRIMITIVES {
+            out.push_str(&format!("{name} ::= {gbnf}\n"));
+        }
+        out
+    }
+
+    /// The shortest text the grammar allows, taking the first of every choice
+    #[cfg(test)]
+    fn sample(&self) -> String {
+        self.sample_rule("root")
+    }
+
+    #[cfg(test)]
+    fn sample_rule(&self, name: &str) -> String {
+        if let Some((_, expr)) = self.rules.iter().find(|(rule, _)| rule == name) {
+            return self.sample_expr(expr);
+        }
+        let (_, _, sample) = PRIMITIVES
+            .iter()
+            .find(|(primitive, _, _)| *primitive == name)
+            .unwrap();
+        sample.to_string()
+    }
+
+    #[cfg(test)]
+    fn sample_expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Literal(text) => text.clone(),
+            Expr::Rule(name) => self.sample_rule(name),
+            Expr::Seq(items) => items.iter().map(|item| self.sample_expr(item)).collect(),
+            Expr::Alt(choices) => self.sample_expr(&choices[0]),
+            Expr::Optional(_) | Expr::Repeat(_) => String::new(),
+        }
+    }
+
+    /// What `schema` allows. Objects and arrays get a rule of their own, to keep the GBNF
+    /// readable.
+    fn expr(&mut self, schema: &Value) -> anyhow::Result<Expr> {
+        let schema = match schema {
+            // `true` and `{}` allow anything
+            Value::Bool(true) => return Ok(Expr::Rule("value".to_string())),
+            Value::Object(schema) => schema,
+            _ => anyhow::bail!("Unsupported JSON schema: {schema}"),
+        };
+        if let Some(keyword) = UNSUPPORTED.iter().find(|k| schema.contains_key(**k)) {
+            anyhow::bail!("JSON schema keyword '{keyword}' is not supported");
+        }
+
+        if let Some(value) = schema.get("const") {
+            return Ok(Expr::Literal(value.to_string()));
+        }
+        if let Some(values) = schema.get("enum") {
+            let Some(values) = values.as_array().filter(|values| !values.is_empty()) else {
+                anyhow::bail!("JSON schema 'enum' must be a non-empty array");
+            };
+            let values = values
+                .iter()
+                .map(|value| Expr::Literal(value.to_string()))
+                .collect();
+            return Ok(Expr::Alt(values));
+        }
+        if let Some(choices) = schema.get("anyOf").or_else(|| schema.get("oneOf")) {
+            let Some(choices) = choices.as_array().filter(|choices| !choices.is_empty()) else {
+                anyhow::bail!("JSON schema 'anyOf' and 'oneOf' must be non-empty arrays");
+            };
+            let choices = choices
+                .iter()
+                .map(|choice| self.expr(choice))
+                .collect::<anyhow::Result<_>>()?;
+            return Ok(Expr::Alt(choices));
+        }
+
+        match schema.get("type") {
+            None => Ok(Expr::Rule("value".to_string())),
+            Some(Value::String(ty)) => self.typed_expr(ty, schema),
+            Some(Value::Array(types)) if !types.is_empty() => {
+                let types = types
+                    .iter()
+                    .map(|ty| match ty.as_str() {
+                        Some(ty) => self.typed_expr(ty, schema),
+                        None => anyhow::bail!("Invalid JSON schema type {ty}"),
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                Ok(Expr::Alt(types))
+            }
+            Some(ty) => anyhow::bail!("Invalid JSON schema type {ty}"),
+        }
+    }
+
+    fn typed_expr(
+        &mut self,
+        ty: &str,
+        schema: &serde_json::Map<String, Value>,
+    ) -> anyhow::Result<Expr> {
+        match ty {
+            "object" => self.object_expr(schema),
+            "array" => self.array_expr(schema),
+            "string" | "number" | "integer" | "boolean" | "null" => Ok(Expr::Rule(ty.to_string())),
+            _ => anyhow::bail!("Unknown JSON schema type '{ty}'"),
+        }
+    }
+
+    fn object_expr(&mut self, schema: &serde_json::Map<String, Value>) -> anyhow::Result<Expr> {
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            return Ok(Expr::Rule("object".to_string()));
+        };
+        if properties.is_empty() {
+            return Ok(Expr::Rule("object".to_string()));
+        }
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|required| required.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        if let Some(missing) = required
+            .iter()
+            .find(|name| !properties.contains_key(**name))
+        {
+            anyhow::bail!("JSON schema requires property '{missing}', which it doesn't define");
+        }
+        let optional = properties
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !required.contains(name));
+        // Without a required property to follow, an optional one's comma has nowhere to go
+        let (mandatory, optional): (Vec<&str>, Vec<&str>) = if required.is_empty() {
+            (optional.collect(), vec![])
+        } else {
+            (required.clone(), optional.collect())
+        };
+
+        let mut items = vec![Expr::Literal("{".to_string()), Expr::Rule("ws".to_string())];
+        for (i, name) in mandatory.iter().enumerate() {
+            if i > 0 {
+                items.push(Expr::Literal(",".to_string()));
+                items.push(Expr::Rule("ws".to_string()));
+            }
+            items.push(self.property_expr(name, &properties[*name])?);
+        }
+        for name in optional {
+            let property = self.property_expr(name, &properties[name])?;
+            items.push(Expr::Optional(Box::new(Expr::Seq(vec![
+                Expr::Literal(",".to_string()),
+                Expr::Rule("ws".to_string()),
+                property,
+            ]))));
+        }
+        items.push(Expr::Literal("}".to_string()));
+        Ok(self.add_rule(Expr::Seq(items)))
+    }
+
+    /// `"name": value`, and the space after it
+    fn property_expr(&mut self, name: &str, schema: &Value) -> anyhow::Result<Expr> {
+        Ok(Expr::Seq(vec![
+            Expr::Literal(Value::String(name.to_string()).to_string()),
+            Expr::Literal(":".to_string()),
+            Expr::Rule("ws".to_string()),
+            self.expr(schema)?,
+            Expr::Rule("ws".to_string()),
+        ]))
+    }
+
+    fn array_expr(&mut self, schema: &serde_json::Map<String, Value>) -> anyhow::Result<Expr> {
+        let item = match schema.get("items") {
+            Some(items) => self.expr(items)?,
+            None => Expr::Rule("value".to_string()),
+        };
+        let item_rule = self.add_rule(Expr::Seq(vec![item, Expr::Rule("ws".to_string())]));
+        let more = Expr::Repeat(Box::new(Expr::Seq(vec![
+            Expr::Literal(",".to_string()),
+            Expr::Rule("ws".to_string()),
+            item_rule.clone(),
+        ])));
+        let items = Expr::Seq(vec![item_rule, more]);
+        let min_items = schema.get("minItems").and_then(Value::as_u64).unwrap_or(0);
+        let items = if min_items > 0 {
+            items
+        } else {
+            Expr::Optional(Box::new(items))
+        };
+        Ok(self.add_rule(Expr::Seq(vec![
+            Expr::Literal("[".to_string()),
+            Expr::Rule("ws".to_string()),
+            items,
+            Expr::Literal("]".to_string()),
+        ])))
+    }
+
+    /// Name `expr` as a new rule, returning a reference to it
+    fn add_rule(&mut self, expr: Expr) -> Expr {
+        let name = format!("rule-{}", self.rules.len());
+        self.rules.push((name.clone(), expr));
+        Expr::Rule(name)
+    }
+}
+
+impl Expr {
+    fn to_gbnf(&self) -> String {
+        match self {
+            // A JSON string is a valid GBNF literal
+            Expr::Literal(text) => Value::String(text.clone()).to_string(),
+            Expr::Rule(name) => name.clone(),
+            Expr::Seq(items) => items
+                .iter()
+                .map(Expr::to_gbnf)
+                .collect::<Vec<_>>()
+                .join(" "),
+            Expr::Alt(choices) => format!(
+                "({})",
+                choices
+                    .iter()
+                    .map(Expr::to_gbnf)
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            ),
+            Expr::Optional(expr) => format!("({})?", expr.to_gbnf()),
+            Expr::Repeat(expr) => format!("({})*", expr.to_gbnf()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schema_grammar(schema: Value) -> Grammar {
+        Grammar::from_constraint(&OutputConstraint::JsonSchema(schema)).unwrap()
+    }
+
+    #[test]
+    fn test_required_fields() {
+        let grammar = schema_grammar(json!({
+            "type": "object",
+            "properties": {
+                "age": {"type": "integer"},
+                "name": {"type": "string"},
+                "nickname": {"type": "string"},
+                "tags": {"type": "array", "items": {"type": "string"}, "minItems": 1},
+                "role": {"enum": ["admin", "user"]},
+            },
+            "required": ["name", "age", "tags", "role"],
+        }));
+        let gbnf = grammar.to_gbnf();
+        assert!(gbnf.starts_with("root ::= rule-"), "{gbnf}");
+
+        let sample: Value = serde_json::from_str(&grammar.sample()).unwrap();
+        assert_eq!(
+            sample,
+            json!({"name": "", "age": 0, "tags": [""], "role": "admin"})
+        );
+    }
+
+    #[test]
+    fn test_json_object() {
+        let grammar = Grammar::from_constraint(&OutputConstraint::JsonObject).unwrap();
+        assert!(grammar.to_gbnf().starts_with("root ::= object\n"));
+        assert_eq!(grammar.sample(), "{}");
+    }
+
+    #[test]
+    fn test_literals_are_escaped() {
+        let grammar = schema_grammar(json!({"const": "say \"hi\"\n"}));
+        assert!(grammar
+            .to_gbnf()
+            .starts_with(r#"root ::= "\"say \\\"hi\\\"\\n\"""#));
+        let sample: Value = serde_json::from_str(&grammar.sample()).unwrap();
+        assert_eq!(sample, "say \"hi\"\n");
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let err = Grammar::from_constraint(&OutputConstraint::JsonSchema(json!({
+            "type": "object",
+            "properties": {"a": {"$ref": "#/$defs/a"}},
+        })))
+        .unwrap_err();
+        assert!(err.to_string().contains("$ref"), "{err}");
+    }
+}
@@ -15,8 +15,9 @@
 
 use std::{
     num::NonZeroU32,
+    panic::AssertUnwindSafe,
     path::Path,
-    sync::{Arc, Mutex, OnceLock},
+    sync::{Arc, Mutex, OnceLock, PoisonError},
 };
 
 use async_stream::stream;
@@ -37,6 +38,10 @@ use llama_cpp_2::{
 use dynamo_llm::backend::ExecutionContext;
 use dynamo_llm::protocols::common::llm_backend::{BackendInput, LLMEngineOutput};
 use dynamo_llm::protocols::common::preprocessor::PreprocessedRequest;
+use dynamo_llm::protocols::common::OutputConstraint;
+
+mod grammar;
+use grammar::Grammar;
 
 /// If user does not provide a max_tokens limit prompt+output to this many
 const DEFAULT_MAX_TOKENS: u32 = 8192;
@@ -197,20 +202,73 @@ async fn worker(
         let inner_ctx_set = ctx_set.clone();
 
         tokio::task::spawn_blocking(move || {
-            let mut ctx = LLAMA_CONTEXTS[ctx_pos].get().unwrap().lock().unwrap();
-            if let Err(err) = run_request(ct, work_request, &mut ctx) {
-                tracing::error!("run_request error: {err:#}");
-            }
-            let _ = inner_ctx_set.blocking_send(ctx_pos);
+            let response_channel = work_request.response_channel.clone();
+            run_in_slot(ctx_pos, &inner_ctx_set, &response_channel, || {
+                // A panic while holding the lock poisons it. The context is cleared before each
+                // request, so it's still fine to use.
+                let mut ctx = LLAMA_CONTEXTS[ctx_pos]
+                    .get()
+                    .unwrap()
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                run_request(ct, work_request, &mut ctx)
+            });
         });
     }
 }
 
+/// Run a request on the context in slot `ctx_pos`, then give the slot back to `ctx_set`. Panics
+/// are caught, so the slot always goes back and the client gets an error rather than a reply
+/// which stops short.
+fn run_in_slot<F>(
+    ctx_pos: usize,
+    ctx_set: &tokio::sync::mpsc::Sender<usize>,
+    response_channel: &tokio::sync::mpsc::Sender<Annotated<LLMEngineOutput>>,
+    request: F,
+) where
+    F: FnOnce() -> Result<()>,
+{
+    let err_msg = match std::panic::catch_unwind(AssertUnwindSafe(request)) {
+        Ok(Ok(())) => None,
+        Ok(Err(err)) => Some(format!("{err:#}")),
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown reason");
+            Some(format!("llamacpp engine panicked: {reason}"))
+        }
+    };
+    if let Some(err_msg) = err_msg {
+        tracing::error!(ctx_pos, "run_request error: {err_msg}");
+        let _ =
+            response_channel.blocking_send(Annotated::from_data(LLMEngineOutput::error(err_msg)));
+    }
+    let _ = ctx_set.blocking_send(ctx_pos);
+}
+
+/// Greedy sampling, restricted to tokens that keep the output inside `constraint`
+fn constrained_sampler(constraint: &OutputConstraint) -> anyhow::Result<LlamaSampler> {
+    let gbnf = Grammar::from_constraint(constraint)?.to_gbnf();
+    let Some(grammar) = LlamaSampler::grammar(LLAMA_MODEL.get().unwrap(), &gbnf, "root") else {
+        anyhow::bail!("llama.cpp rejected the grammar:\n{gbnf}");
+    };
+    Ok(LlamaSampler::chain_simple([
+        grammar,
+        LlamaSampler::greedy(),
+    ]))
+}
+
 fn run_request(
     cancel_token: CancellationToken,
     work_request: WorkRequest,
     llama_context: &mut ContextWrapper,
 ) -> Result<()> {
+    // Start from a clean context, whatever the previous request left behind when it failed
+    llama_context.0.clear_kv_cache();
+    llama_context.0.reset_timings();
+
     let tokens_list: Vec<LlamaToken> = work_request
         .request
         .token_ids
@@ -218,6 +276,30 @@ fn run_request(
         .map(|u| LlamaToken::new(u as i32))
         .collect();
 
+    let sampling_options = &work_request.request.sampling_options;
+    let has_penalty = |penalty: Option<f32>| penalty.is_some_and(|p| p != 0.0);
+    if has_penalty(sampling_options.frequency_penalty)
+        || has_penalty(sampling_options.presence_penalty)
+    {
+        // We only do greedy sampling so far
+        tracing::warn!("llamacpp engine ignores frequency_penalty and presence_penalty");
+    }
+
+    let mut sampler = match &work_request.request.output_constraint {
+        None => LlamaSampler::greedy(),
+        Some(constraint) => match constrained_sampler(constraint) {
+            Ok(sampler) => sampler,
+            Err(err) => {
+                let _ = work_request
+                    .response_channel
+                    .blocking_send(Annotated::from_data(LLMEngineOutput::error(format!(
+                        "Cannot honor response_format: {err:#}"
+                    ))));
+                return Ok(());
+            }
+        },
+    };
+
     let limit = DEFAULT_MAX_TOKENS; // - prompt_tokens;
     let max_output_tokens = std::cmp::min(
         work_request
@@ -246,7 +328,6 @@ fn run_request(
         .decode(&mut batch)
         .with_context(|| "llama_decode failed on first pass")?;
 
-    let mut sampler = LlamaSampler::greedy();
     let mut n_cur = batch.n_tokens() as u32;
 
     let mut used_output_tokens = 0;
@@ -313,9 +394,36 @@ fn run_request(
             .blocking_send(Annotated::from_data(LLMEngineOutput::stop()));
     }
 
-    // Clean context for next use
-    llama_context.0.clear_kv_cache();
-    llama_context.0.reset_timings();
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_panic_returns_slot() {
+        let (ctx_set, mut ctx_get) = tokio::sync::mpsc::channel(1);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+        tokio::task::spawn_blocking(move || {
+            run_in_slot(0, &ctx_set, &tx, || -> Result<()> {
+                panic!("out of memory");
+            });
+        })
+        .await
+        .unwrap();
+
+        // The client hears why, rather than the stream just ending
+        let out = rx.recv().await.unwrap().data.unwrap();
+        assert_eq!(
+            out.finish_reason,
+            Some(dynamo_llm::protocols::common::FinishReason::Error(
+                "llamacpp engine panicked: out of memory".to_string()
+            ))
+        );
+        assert!(rx.recv().await.is_none());
+
+        // and the next request can have the context
+        assert_eq!(ctx_get.recv().await, Some(0));
+    }
+}
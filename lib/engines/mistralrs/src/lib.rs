@@ -14,7 +14,12 @@
 // limitations under the License.
 
 use std::collections::HashMap;
-use std::{num::NonZero, path::Path, sync::Arc};
+use std::{
+    num::NonZero,
+    path::{Path, PathBuf},
+    sync::Arc,
+    sync::Once,
+};
 
 use async_openai::types::FinishReason;
 use async_stream::stream;
@@ -22,22 +27,29 @@ use async_trait::async_trait;
 use either::Either;
 use indexmap::IndexMap;
 use mistralrs::{
-    AutoDeviceMapParams, Constraint, DefaultSchedulerMethod, Device, DeviceMapSetting,
+    AutoDeviceMapParams, Constraint, DType, DefaultSchedulerMethod, Device, DeviceMapSetting,
     GGUFLoaderBuilder, GGUFSpecificConfig, MemoryGpuConfig, MistralRs, MistralRsBuilder,
     ModelDType, NormalLoaderBuilder, NormalRequest, NormalSpecificConfig, PagedAttentionConfig,
-    Request, RequestMessage, ResponseOk, SamplingParams, SchedulerConfig, StopTokens, TokenSource,
+    Request, RequestMessage, Response, ResponseOk, SamplingParams, SchedulerConfig, StopTokens,
+    TokenSource,
 };
-use tokio::sync::mpsc::channel;
+use tokio::sync::mpsc::{channel, Receiver};
 
 use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
 use dynamo_runtime::pipeline::error as pipeline_error;
 use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
 use dynamo_runtime::protocols::annotated::Annotated;
+use dynamo_runtime::stream::Stream;
 
+use dynamo_llm::protocols::common::OutputConstraint;
 use dynamo_llm::protocols::openai::chat_completions::{
     NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
 };
+use dynamo_llm::protocols::openai::embeddings::{
+    NvCreateEmbeddingRequest, NvCreateEmbeddingResponse,
+};
 use dynamo_llm::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+use dynamo_llm::types::openai::embeddings::OpenAIEmbeddingsEngine;
 
 /// How many requests mistral will run at once in the paged attention scheduler.
 /// It actually runs 1 fewer than this.
@@ -49,12 +61,17 @@ const PAGED_ATTENTION_MAX_NUM_SEQS: usize = 10;
 /// finish_reason=stop and no tokens for one of the requests.
 const EXP_ENABLE_PAGED_ATTENTION: bool = false;
 
+/// `shards` are the safetensors files to load from a Hugging Face repo directory, if it has a
+/// `model.safetensors.index.json`. Without them mistral.rs loads every `.safetensors` file in the
+/// directory.
+///
+/// Returns the chat engine and the embeddings engine, both running the one loaded model.
 pub async fn make_engine(
     gguf_path: &Path,
-) -> pipeline_error::Result<OpenAIChatCompletionsStreamingEngine> {
-    let engine = MistralRsEngine::new(gguf_path).await?;
-    let engine: OpenAIChatCompletionsStreamingEngine = Arc::new(engine);
-    Ok(engine)
+    shards: Option<Vec<PathBuf>>,
+) -> pipeline_error::Result<(OpenAIChatCompletionsStreamingEngine, OpenAIEmbeddingsEngine)> {
+    let engine = Arc::new(MistralRsEngine::new(gguf_path, shards).await?);
+    Ok((engine.clone(), engine))
 }
 
 /// Gets the best device, cpu, cuda if compiled with CUDA
@@ -71,10 +88,21 @@ fn best_device() -> pipeline_error::Result<Device> {
 
 struct MistralRsEngine {
     mistralrs: Arc<MistralRs>,
+    seed_ignored: Once,
+    /// The model directory as mistral.rs sees it, with only the shards to load
+    _weights_dir: Option<tempfile::TempDir>,
 }
 
 impl MistralRsEngine {
-    async fn new(model_path: &Path) -> pipeline_error::Result<Self> {
+    async fn new(model_path: &Path, shards: Option<Vec<PathBuf>>) -> pipeline_error::Result<Self> {
+        let weights_dir = match shards {
+            Some(shards) if !model_path.is_file() => {
+                let dir = tempfile::tempdir()?;
+                dynamo_llm::model_card::weights::link_shards(model_path, &shards, dir.path())?;
+                Some(dir)
+            }
+            _ => None,
+        };
         let loader = if model_path.is_file() {
             // Load from a GGUF
             let Some(model_filename) = model_path.file_name() else {
@@ -110,7 +138,14 @@ impl MistralRsEngine {
                 },
                 None,
                 None,
-                Some(model_path.display().to_string()),
+                Some(
+                    weights_dir
+                        .as_ref()
+                        .map(|dir| dir.path())
+                        .unwrap_or(model_path)
+                        .display()
+                        .to_string(),
+                ),
             )
             .build(None)?
         };
@@ -164,6 +199,8 @@ impl MistralRsEngine {
         let builder = MistralRsBuilder::new(pipeline.clone(), scheduler).with_prefix_cache_n(16);
         let engine = MistralRsEngine {
             mistralrs: builder.build(),
+            seed_ignored: Once::new(),
+            _weights_dir: weights_dir,
         };
         // skip the id used for dummy run https://github.com/EricLBuehler/mistral.rs/issues/1218
         let _ = engine.mistralrs.next_request_id();
@@ -185,7 +222,11 @@ impl
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
         let (request, context) = request.transfer(());
         let ctx = context.context();
-        let (tx, mut rx) = channel(10_000);
+        let (tx, rx) = channel(10_000);
+        // mistralrs has no per-request seed
+        if request.inner.seed.is_some() {
+            dynamo_llm::engines::warn_seed_ignored(&self.seed_ignored, "mistralrs");
+        }
 
         let mut messages = vec![];
         for m in request.inner.messages {
@@ -250,7 +291,13 @@ impl
             response: tx,
             return_logprobs: request.inner.logprobs.unwrap_or_default(),
             is_streaming: true,
-            constraint: Constraint::None,
+            constraint: request
+                .inner
+                .response_format
+                .as_ref()
+                .and_then(OutputConstraint::from_response_format)
+                .map(to_constraint)
+                .unwrap_or(Constraint::None),
             suffix: None,
             adapters: None,
             tools: None,
@@ -261,76 +308,214 @@ impl
 
         self.mistralrs.get_sender()?.send(mistralrs_request).await?;
 
-        let output = stream! {
-            while let Some(response) = rx.recv().await {
-                let response = match response.as_result() {
-                    Ok(r) => r,
-                    Err(err) => {
-                        tracing::error!(request_id, %err, "Failed converting mistralrs channel response to result.");
+        let output = chat_stream(request_id, rx);
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+/// Our responses for the chunks mistral.rs sends back for request `request_id`. If it stops
+/// before the request finished the last response is an error, rather than a reply which just
+/// stops short. A panic on mistral.rs's engine thread drops the sender, which ends up here too.
+fn chat_stream(
+    request_id: usize,
+    mut rx: Receiver<Response>,
+) -> impl Stream<Item = Annotated<NvCreateChatCompletionStreamResponse>> {
+    stream! {
+        let mut finished = false;
+        let mut error = None;
+        while let Some(response) = rx.recv().await {
+            let response = match response.as_result() {
+                Ok(r) => r,
+                Err(err) => {
+                    tracing::error!(request_id, %err, "Failed converting mistralrs channel response to result.");
+                    error = Some(format!("mistralrs failed request {request_id}: {err}"));
+                    break;
+                }
+            };
+            match response {
+                ResponseOk::Chunk(c) => {
+                    let Some(from_assistant) = c.choices[0].delta.content.clone() else {
+                        tracing::warn!(request_id, "No content from mistralrs. Abandoning request.");
                         break;
-                    }
-                };
-                match response {
-                    ResponseOk::Chunk(c) => {
-                        let Some(from_assistant) = c.choices[0].delta.content.clone() else {
-                            tracing::warn!(request_id, "No content from mistralrs. Abandoning request.");
-                            break;
-                        };
-                        let finish_reason = match &c.choices[0].finish_reason.as_deref() {
-                            Some("stop") | Some("canceled") => {
-                                Some(FinishReason::Stop)
-                            }
-                            Some("length") => {
-                                Some(FinishReason::Length)
-                            }
-                            Some(s) => {
-                                tracing::warn!(request_id, stop_reason = s, "Unknow stop reason");
-                                Some(FinishReason::Stop)
-                            }
-                            None => None,
-                        };
-                        //tracing::trace!("from_assistant: {from_assistant}");
-
-                        #[allow(deprecated)]
-                        let inner = async_openai::types::CreateChatCompletionStreamResponse{
-                            id: c.id,
-                            choices: vec![async_openai::types::ChatChoiceStream{
-                                index: 0,
-                                delta: async_openai::types::ChatCompletionStreamResponseDelta{
-                                    //role: c.choices[0].delta.role,
-                                    role: Some(async_openai::types::Role::Assistant),
-                                    content: Some(from_assistant),
-                                    tool_calls: None,
-                                    refusal: None,
-                                    function_call: None,
-                                },
-                                logprobs: None,
-                                finish_reason,
-                            }],
-                            model: c.model,
-                            created: c.created as u32,
-                            object: c.object.clone(),
-                            usage: None,
-                            system_fingerprint: Some(c.system_fingerprint),
-                            service_tier: None,
-                        };
-                        let delta = NvCreateChatCompletionStreamResponse{inner};
-                        let ann = Annotated{
-                            id: None,
-                            data: Some(delta),
-                            event: None,
-                            comment: None,
-                        };
-                        yield ann;
-
-                        if finish_reason.is_some() {
-                            //tracing::trace!(request_id, "Finish reason: {finish_reason:?}");
-                            break;
+                    };
+                    let finish_reason = match &c.choices[0].finish_reason.as_deref() {
+                        Some("stop") | Some("canceled") => {
+                            Some(FinishReason::Stop)
                         }
-                    },
-                    x => tracing::error!(request_id, "Unhandled. {x:?}"),
-                }
+                        Some("length") => {
+                            Some(FinishReason::Length)
+                        }
+                        Some(s) => {
+                            tracing::warn!(request_id, stop_reason = s, "Unknow stop reason");
+                            Some(FinishReason::Stop)
+                        }
+                        None => None,
+                    };
+                    //tracing::trace!("from_assistant: {from_assistant}");
+
+                    #[allow(deprecated)]
+                    let inner = async_openai::types::CreateChatCompletionStreamResponse{
+                        id: c.id,
+                        choices: vec![async_openai::types::ChatChoiceStream{
+                            index: 0,
+                            delta: async_openai::types::ChatCompletionStreamResponseDelta{
+                                //role: c.choices[0].delta.role,
+                                role: Some(async_openai::types::Role::Assistant),
+                                content: Some(from_assistant),
+                                tool_calls: None,
+                                refusal: None,
+                                function_call: None,
+                            },
+                            logprobs: None,
+                            finish_reason,
+                        }],
+                        model: c.model,
+                        created: c.created as u32,
+                        object: c.object.clone(),
+                        usage: None,
+                        system_fingerprint: Some(c.system_fingerprint),
+                        service_tier: None,
+                    };
+                    let delta = NvCreateChatCompletionStreamResponse{inner};
+                    let ann = Annotated{
+                        id: None,
+                        data: Some(delta),
+                        event: None,
+                        comment: None,
+                    };
+                    yield ann;
+
+                    if finish_reason.is_some() {
+                        //tracing::trace!(request_id, "Finish reason: {finish_reason:?}");
+                        finished = true;
+                        break;
+                    }
+                },
+                x => tracing::error!(request_id, "Unhandled. {x:?}"),
             }
+        }
+        if !finished {
+            let error = error.unwrap_or_else(|| {
+                format!("mistralrs stopped before finishing request {request_id}")
+            });
+            yield Annotated::from_error(error);
+        }
+    }
+}
+
+impl MistralRsEngine {
+    /// The mean of the model's logits over the prompt. The language model head is linear, so
+    /// this is the mean of the last hidden states projected onto the vocabulary. mistral.rs
+    /// doesn't return the hidden states themselves. Also returns the prompt's token count.
+    async fn embed(&self, text: String) -> pipeline_error::Result<(Vec<f32>, u32)> {
+        let (tx, mut rx) = channel(1);
+        let request_id = self.mistralrs.next_request_id();
+        let mistralrs_request = Request::Normal(NormalRequest {
+            id: request_id,
+            messages: RequestMessage::Completion {
+                text,
+                echo_prompt: false,
+                best_of: 1,
+            },
+            sampling_params: SamplingParams::deterministic(),
+            response: tx,
+            return_logprobs: false,
+            is_streaming: false,
+            constraint: Constraint::None,
+            suffix: None,
+            adapters: None,
+            tools: None,
+            tool_choice: None,
+            logits_processors: None,
+            // Run the prompt through the model, and return the logits instead of sampling
+            return_raw_logits: true,
+        });
+        self.mistralrs.get_sender()?.send(mistralrs_request).await?;
+
+        let Some(response) = rx.recv().await else {
+            pipeline_error::bail!("mistralrs dropped embedding request {request_id}");
+        };
+        let response = response
+            .as_result()
+            .map_err(|err| pipeline_error::anyhow!("mistralrs embedding failed: {err}"))?;
+        let (logits_chunks, tokens) = match response {
+            ResponseOk::Raw {
+                logits_chunks,
+                tokens,
+            } => (logits_chunks, tokens),
+            other => {
+                pipeline_error::bail!("Unexpected mistralrs response to an embedding: {other:?}")
+            }
+        };
+
+        let mut sum: Vec<f32> = vec![];
+        let mut rows = 0;
+        for chunk in logits_chunks {
+            let vocab_size = chunk.dims().last().copied().unwrap_or_default();
+            let chunk = chunk.to_dtype(DType::F32)?.reshape(((), vocab_size))?;
+            rows += chunk.dim(0)?;
+            let chunk_sum = chunk.sum(0)?.to_vec1::<f32>()?;
+            if sum.is_empty() {
+                sum = chunk_sum;
+            } else {
+                sum.iter_mut().zip(chunk_sum).for_each(|(s, c)| *s += c);
+            }
+        }
+        if rows == 0 {
+            pipeline_error::bail!("mistralrs returned no logits for embedding {request_id}");
+        }
+        let embedding = sum.into_iter().map(|s| s / rows as f32).collect();
+        Ok((embedding, tokens.len() as u32))
+    }
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateEmbeddingRequest>,
+        ManyOut<Annotated<NvCreateEmbeddingResponse>>,
+        Error,
+    > for MistralRsEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateEmbeddingRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateEmbeddingResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let inputs = match request.inner.input {
+            async_openai::types::EmbeddingInput::String(text) => vec![text],
+            async_openai::types::EmbeddingInput::StringArray(texts) => texts,
+            _ => pipeline_error::bail!("mistralrs only embeds text, not token IDs"),
+        };
+
+        let mut data = Vec::with_capacity(inputs.len());
+        let mut prompt_tokens = 0;
+        for (index, text) in inputs.into_iter().enumerate() {
+            if ctx.is_stopped() {
+                pipeline_error::bail!("Embedding request cancelled");
+            }
+            let (embedding, tokens) = self.embed(text).await?;
+            prompt_tokens += tokens;
+            data.push(async_openai::types::Embedding {
+                index: index as u32,
+                object: "embedding".to_string(),
+                embedding,
+            });
+        }
+        let response = NvCreateEmbeddingResponse {
+            inner: async_openai::types::CreateEmbeddingResponse {
+                object: "list".to_string(),
+                model: request.inner.model,
+                data,
+                usage: async_openai::types::EmbeddingUsage {
+                    prompt_tokens,
+                    total_tokens: prompt_tokens,
+                },
+            },
+        };
+        let output = stream! {
+            yield Annotated::from_data(response);
         };
         Ok(ResponseStream::new(Box::pin(output), ctx))
     }
@@ -344,6 +529,16 @@ fn to_stop_tokens(t: async_openai::types::Stop) -> StopTokens {
     }
 }
 
+/// response_format to a mistralrs grammar. Any JSON object is the schema allowing any object.
+fn to_constraint(c: OutputConstraint) -> Constraint {
+    match c {
+        OutputConstraint::JsonObject => {
+            Constraint::JsonSchema(serde_json::json!({"type": "object"}))
+        }
+        OutputConstraint::JsonSchema(schema) => Constraint::JsonSchema(schema),
+    }
+}
+
 /// openai logit bias (strings/json) to mistralrs (u32/f32)
 /// I think the input looks like this: {"3721": -100, "17765": 100}
 fn to_logit_bias(lb: HashMap<String, serde_json::Value>) -> HashMap<u32, f32> {
@@ -366,3 +561,24 @@ fn to_logit_bias(lb: HashMap<String, serde_json::Value>) -> HashMap<u32, f32> {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_engine_thread_panic() {
+        let (tx, rx) = channel::<Response>(10);
+        // Like mistral.rs's engine thread dying with the request's sender
+        let engine = std::thread::spawn(move || {
+            let _tx = tx;
+            panic!("CUDA error");
+        });
+        assert!(engine.join().is_err());
+
+        let responses: Vec<_> = chat_stream(1, rx).collect().await;
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].is_error());
+    }
+}
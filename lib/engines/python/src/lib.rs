@@ -14,7 +14,7 @@
 // limitations under the License.
 
 use std::ffi::CStr;
-use std::{env, path::Path, sync::Arc};
+use std::{env, path::Path, pin::Pin, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use dynamo_runtime::pipeline::error as pipeline_error;
@@ -27,53 +27,190 @@ pub use dynamo_runtime::{
     protocols::annotated::Annotated,
     CancellationToken, Error, Result,
 };
+use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
+use pyo3::sync::GILOnceCell;
 use pyo3::types::{IntoPyDict, PyDict};
 use pyo3_async_runtimes::TaskLocals;
 use pythonize::{depythonize, pythonize};
 pub use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc;
+use tokio::runtime::Handle;
 use tokio::sync::oneshot::Sender;
-use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 
 use dynamo_llm::backend::ExecutionContext;
 use dynamo_llm::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
 
-/// Python snippet to import a file as a module
+/// Python snippet to import a file, or a package directory, as a module.
+///
+/// A plain file is run with its directory on `sys.path`, so it can import its siblings. A
+/// package directory (it has an `__init__.py`), or a file inside one, is imported as part of its
+/// package with the directory above the outermost package on `sys.path`, so relative imports work.
 const PY_IMPORT: &CStr = cr#"
+import importlib
+import os
 import runpy
 import sys
-import os
-import functools
-import types
-
-module_dir = os.path.dirname(file_path)
-if module_dir not in sys.path:
-    sys.path.insert(0, module_dir)
 
 sys.argv = sys_argv
-module_dict = runpy.run_path(file_path, run_name='__main__')
-
-# Create a module class with the generate function
-class Module:
-    def __init__(self, module_dict):
-        self.__dict__.update(module_dict)
-        self._generate_func = module_dict['generate']
 
-    async def generate(self, request):
-        async for response in self._generate_func(request):
-            yield response
+path = os.path.abspath(file_path)
+if os.path.isdir(path):
+    directory, module_name = path, None
+else:
+    directory = os.path.dirname(path)
+    module_name = os.path.splitext(os.path.basename(path))[0]
+
+# The outermost package containing the directory, if any
+package_root = None
+parent = directory
+while os.path.isfile(os.path.join(parent, '__init__.py')):
+    package_root = parent
+    if os.path.dirname(parent) == parent:
+        break
+    parent = os.path.dirname(parent)
+
+if package_root is not None:
+    search_path = os.path.dirname(package_root)
+    if search_path not in sys.path:
+        sys.path.insert(0, search_path)
+    parts = os.path.relpath(directory, search_path).split(os.sep)
+    if module_name is not None and module_name != '__init__':
+        parts.append(module_name)
+    module = importlib.import_module('.'.join(parts))
+elif module_name is None:
+    raise ImportError(f"{file_path} is a directory without an __init__.py, it is not a package")
+else:
+    if directory not in sys.path:
+        sys.path.insert(0, directory)
+    module_dict = runpy.run_path(path, run_name='__main__')
+
+    # Create a module class exposing the file's functions as attributes
+    class Module:
+        def __init__(self, module_dict):
+            self.__dict__.update(module_dict)
+
+    module = Module(module_dict)
 
-# Create module instance and store it in globals
-module = Module(module_dict)
 globals()['module'] = module
 "#;
 
+/// Python snippet which runs each request's generator so that it can be cancelled.
+///
+/// A generator with a `context` parameter is passed a `Context`, which it can poll with
+/// `context.is_stopped()` or wait on with `await context.stopped()`. Either way, once the
+/// request is cancelled the generator is interrupted at its next `await` and closed with
+/// `aclose()`, so its `finally` blocks run and resources are released promptly.
+const PY_CANCELLATION: &CStr = cr#"
+import asyncio
+import functools
+import inspect
+
+class Context:
+    def __init__(self, loop):
+        self._loop = loop
+        self._is_stopped = False
+        # Created on the event loop, python < 3.10 binds it to the current loop
+        self._event = None
+
+    def is_stopped(self):
+        return self._is_stopped
+
+    async def stopped(self):
+        if self._event is None:
+            self._event = asyncio.Event()
+        if not self._is_stopped:
+            await self._event.wait()
+
+    def _stop(self):
+        # Called from a tokio thread
+        self._is_stopped = True
+        self._loop.call_soon_threadsafe(self._set)
+
+    def _set(self):
+        if self._event is not None:
+            self._event.set()
+
+@functools.lru_cache(maxsize=None)
+def accepts_context(generate):
+    try:
+        return 'context' in inspect.signature(generate).parameters
+    except (TypeError, ValueError):
+        return False
+
+async def until_stopped(gen, context):
+    stopped = asyncio.ensure_future(context.stopped())
+    try:
+        while True:
+            item = asyncio.ensure_future(gen.__anext__())
+            await asyncio.wait({item, stopped}, return_when=asyncio.FIRST_COMPLETED)
+            if not item.done():
+                item.cancel()
+                try:
+                    await item
+                except BaseException:
+                    pass
+                return
+            try:
+                response = item.result()
+            except StopAsyncIteration:
+                return
+            yield response
+    finally:
+        stopped.cancel()
+        await gen.aclose()
+
+def start(generate, request, loop):
+    context = Context(loop)
+    if accepts_context(generate):
+        gen = generate(request, context=context)
+    else:
+        gen = generate(request)
+    return until_stopped(gen, context), context
+"#;
+
+/// `start` from [`PY_CANCELLATION`], loaded on first use
+static PY_START: GILOnceCell<PyObject> = GILOnceCell::new();
+
+/// The function we call when none is named in [`EngineOptions::entrypoint`]
+pub const DEFAULT_ENTRYPOINT: &str = "generate";
+
+/// How long to wait for the asyncio event loop to start if [`EngineOptions`] doesn't say
+pub const DEFAULT_EVENT_LOOP_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How to run the python engine
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    /// The tokio runtime to run the asyncio event loop thread and the GIL offload tasks on.
+    /// Defaults to the runtime `make_string_engine` / `make_token_engine` are called from.
+    /// Set this when embedding the engine in an application that manages its own runtime.
+    pub runtime: Option<Handle>,
+
+    /// How many times to call `generate` again when it raises before yielding anything, for
+    /// generators making flaky external calls. Once a response has been sent errors are not
+    /// retried, to avoid duplicate output. Defaults to 0, no retries.
+    pub generate_retries: u32,
+
+    /// The name of the async generator function in the python file to call for each request,
+    /// so one file can hold several handlers. Defaults to [`DEFAULT_ENTRYPOINT`].
+    pub entrypoint: Option<String>,
+
+    /// How long to wait for the asyncio event loop to start before giving up on the engine.
+    /// Defaults to [`DEFAULT_EVENT_LOOP_STARTUP_TIMEOUT`].
+    pub event_loop_startup_timeout: Option<Duration>,
+
+    /// Drive at most this many python generators at once, queueing the rest. Unlimited if not
+    /// set.
+    pub max_concurrent: Option<usize>,
+}
+
 /// An engine that takes and returns strings, feeding them to a python written engine
 pub async fn make_string_engine(
     cancel_token: CancellationToken,
     py_file: &Path,
     py_args: Vec<String>,
+    options: EngineOptions,
 ) -> pipeline_error::Result<OpenAIChatCompletionsStreamingEngine> {
     pyo3::prepare_freethreaded_python();
     if let Ok(venv) = env::var("VIRTUAL_ENV") {
@@ -84,7 +221,7 @@ pub async fn make_string_engine(
         });
     }
 
-    let engine = new_engine(cancel_token, py_file, py_args).await?;
+    let engine = new_engine(cancel_token, py_file, py_args, options).await?;
     let engine: OpenAIChatCompletionsStreamingEngine = Arc::new(engine);
     Ok(engine)
 }
@@ -94,6 +231,7 @@ pub async fn make_token_engine(
     cancel_token: CancellationToken,
     py_file: &Path,
     py_args: Vec<String>,
+    options: EngineOptions,
 ) -> pipeline_error::Result<ExecutionContext> {
     pyo3::prepare_freethreaded_python();
     if let Ok(venv) = env::var("VIRTUAL_ENV") {
@@ -104,7 +242,7 @@ pub async fn make_token_engine(
         });
     }
 
-    let engine = new_engine(cancel_token, py_file, py_args).await?;
+    let engine = new_engine(cancel_token, py_file, py_args, options).await?;
     let engine: ExecutionContext = Arc::new(engine);
     Ok(engine)
 }
@@ -114,16 +252,25 @@ pub struct PythonServerStreamingEngine {
     _cancel_token: CancellationToken,
     generator: Arc<PyObject>,
     event_loop: Arc<PyObject>,
+    runtime: Option<Handle>,
+    generate_retries: u32,
+    concurrency: Option<Arc<Semaphore>>,
 }
 
 async fn new_engine(
     cancel_token: CancellationToken,
     py_file: &Path,
     py_args: Vec<String>,
+    options: EngineOptions,
 ) -> anyhow::Result<PythonServerStreamingEngine> {
-    let (tx, rx) = tokio::sync::oneshot::channel();
-    tokio::task::spawn_blocking(move || run_asyncio(tx));
-    let event_loop = rx.await?;
+    let runtime = options.runtime.unwrap_or_else(Handle::current);
+    let entrypoint = options
+        .entrypoint
+        .unwrap_or_else(|| DEFAULT_ENTRYPOINT.to_string());
+    let timeout = options
+        .event_loop_startup_timeout
+        .unwrap_or(DEFAULT_EVENT_LOOP_STARTUP_TIMEOUT);
+    let event_loop = start_event_loop(&runtime, run_asyncio, timeout).await?;
 
     let user_module =
         python_file_to_module(py_file, py_args).with_context(|| py_file.display().to_string())?;
@@ -139,15 +286,25 @@ async fn new_engine(
                 .with_context(|| "Failed calling python engine's initialize(args)")?;
         };
         */
-        user_module
-            .getattr(py, "generate")
-            .with_context(|| "generate")
+        let Ok(generator) = user_module.getattr(py, entrypoint.as_str()) else {
+            anyhow::bail!("{} has no function named '{entrypoint}'", py_file.display());
+        };
+        if !generator.bind(py).is_callable() {
+            anyhow::bail!(
+                "'{entrypoint}' in {} is not callable, it must be an async generator function",
+                py_file.display()
+            );
+        }
+        Ok(generator)
     })?;
-    Ok(PythonServerStreamingEngine::new(
-        cancel_token,
-        Arc::new(generator),
-        event_loop,
-    ))
+    let mut engine =
+        PythonServerStreamingEngine::new(cancel_token, Arc::new(generator), event_loop)
+            .with_runtime(runtime)
+            .with_generate_retries(options.generate_retries);
+    if let Some(max_concurrent) = options.max_concurrent {
+        engine = engine.with_max_concurrent(max_concurrent);
+    }
+    Ok(engine)
 }
 
 impl PythonServerStreamingEngine {
@@ -160,8 +317,52 @@ impl PythonServerStreamingEngine {
             _cancel_token: cancel_token,
             generator,
             event_loop,
+            runtime: None,
+            generate_retries: 0,
+            concurrency: None,
         }
     }
+
+    /// Run the GIL offload and stream processing tasks on this runtime instead of the one
+    /// `generate` is called from.
+    pub fn with_runtime(mut self, runtime: Handle) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    /// Call `generate` again, up to `retries` times, when it raises before its first response
+    pub fn with_generate_retries(mut self, retries: u32) -> Self {
+        self.generate_retries = retries;
+        self
+    }
+
+    /// Run at most `max_concurrent` generators at once. Further requests wait in `generate` for
+    /// one to finish.
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.concurrency = Some(Arc::new(Semaphore::new(max_concurrent)));
+        self
+    }
+
+    fn runtime(&self) -> Handle {
+        self.runtime.clone().unwrap_or_else(Handle::current)
+    }
+}
+
+/// Run `start` on a blocking thread and wait for it to send the event loop. Fails if it panics or
+/// exits without sending it, or takes longer than `timeout`, rather than leaving engine
+/// construction hanging.
+async fn start_event_loop<T: Send + 'static>(
+    runtime: &Handle,
+    start: fn(Sender<T>),
+    timeout: Duration,
+) -> anyhow::Result<T> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    runtime.spawn_blocking(move || start(tx));
+    match tokio::time::timeout(timeout, rx).await {
+        Ok(Ok(event_loop)) => Ok(event_loop),
+        Ok(Err(_)) => anyhow::bail!("python asyncio event loop failed to start"),
+        Err(_) => anyhow::bail!("python asyncio event loop failed to start within {timeout:?}"),
+    }
 }
 
 /// Start asyncio event loop and block on it forever
@@ -233,28 +434,23 @@ where
 
         let generator = self.generator.clone();
         let event_loop = self.event_loop.clone();
-
-        // Acquiring the GIL is similar to acquiring a standard lock/mutex
-        // Performing this in an tokio async task could block the thread for an undefined amount of time
-        // To avoid this, we spawn a blocking task to acquire the GIL and perform the operations needed
-        // while holding the GIL.
-        //
-        // Under low GIL contention, we wouldn't need to do this.
-        // However, under high GIL contention, this can lead to significant performance degradation.
-        //
-        // Since we cannot predict the GIL contention, we will always use the blocking task and pay the
-        // cost. The Python GIL is the gift that keeps on giving -- performance hits...
-        let stream = tokio::task::spawn_blocking(move || {
-            Python::with_gil(|py| {
-                let py_request = pythonize(py, &request)?;
-                let gen = generator.call1(py, (py_request,))?;
-                let locals = TaskLocals::new(event_loop.bind(py).clone());
-                pyo3_async_runtimes::tokio::into_stream_with_locals_v1(locals, gen.into_bound(py))
-            })
-        })
-        .await??;
-
-        let stream = Box::pin(stream);
+        let runtime = self.runtime();
+        let retries = self.generate_retries;
+
+        // Kept as JSON so that `generate` can be called with it again on a retry
+        let request = serde_json::to_value(&request)?;
+        // Held until the generator is done, queueing requests beyond max_concurrent here
+        let permit = match &self.concurrency {
+            Some(concurrency) => Some(concurrency.clone().acquire_owned().await?),
+            None => None,
+        };
+        let (stream, py_context) = start_generator(
+            &runtime,
+            generator.clone(),
+            event_loop.clone(),
+            request.clone(),
+        )
+        .await?;
 
         // process the stream
         // any error thrown in the stream will be caught and complete the processing task
@@ -262,16 +458,31 @@ where
         // the error will be emitted as an annotated error
         let request_id = id.clone();
 
-        tokio::spawn(async move {
+        let offload = runtime.clone();
+        runtime.spawn(async move {
             tracing::debug!(
                 request_id,
                 "starting task to process python async generator stream"
             );
 
             let mut stream = stream;
+            let mut py_context = py_context;
             let mut count = 0;
-
-            while let Some(item) = stream.next().await {
+            let mut attempt = 0;
+            let mut finished = false;
+
+            loop {
+                let item = tokio::select! {
+                    item = stream.next() => item,
+                    _ = ctx.stopped() => {
+                        tracing::debug!(request_id, "request cancelled, stopping the python async generator");
+                        break;
+                    }
+                };
+                let Some(item) = item else {
+                    finished = true;
+                    break;
+                };
                 count += 1;
                 tracing::trace!(
                     request_id,
@@ -279,18 +490,49 @@ where
                     count
                 );
 
+                // Nothing was sent yet, so calling `generate` again can't duplicate output
+                if let Err(err) = &item {
+                    if count == 1 && attempt < retries {
+                        attempt += 1;
+                        tracing::warn!(
+                            request_id,
+                            attempt,
+                            "python async generator raised before its first response, retrying: {err}"
+                        );
+                        match start_generator(
+                            &offload,
+                            generator.clone(),
+                            event_loop.clone(),
+                            request.clone(),
+                        )
+                        .await
+                        {
+                            Ok((retry, retry_context)) => {
+                                stream = retry;
+                                py_context = retry_context;
+                                count = 0;
+                                continue;
+                            }
+                            Err(err) => {
+                                let msg = format!("failed to restart the python async generator: {err}");
+                                let _ = tx.send(Annotated::from_error(msg)).await;
+                                break;
+                            }
+                        }
+                    }
+                }
+
                 let mut done = false;
 
-                let response = match process_item::<Resp>(item).await {
+                let response = match process_item::<Resp>(&offload, item).await {
                     Ok(response) => response,
                     Err(e) => {
                         done = true;
 
                         let msg = match &e {
                             ResponseProcessingError::DeserializeError(e) => {
-                                // tell the python async generator to stop generating
-                                // right now, this is impossible as we are not passing the context to the python async generator
-                                // todo: add task-local context to the python async generator
+                                // tell the python async generator to stop generating, which happens
+                                // once we leave the loop
                                 ctx.stop_generating();
                                 let msg = format!("critical error: invalid response object from python async generator; application-logic-mismatch: {}", e);
                                 msg
@@ -326,10 +568,18 @@ where
                 }
             }
 
+            // The client went away or we gave up on the response, so don't leave the generator
+            // suspended. Wait for it to close, anything it still yields is discarded.
+            if !finished {
+                stop_generator(&offload, py_context).await;
+                while stream.next().await.is_some() {}
+            }
+
             tracing::debug!(
                 request_id,
                 "finished processing python async generator stream"
             );
+            drop(permit);
         });
 
         let stream = ReceiverStream::new(rx);
@@ -338,7 +588,70 @@ where
     }
 }
 
+/// The responses from a python generator
+type PyStream = Pin<Box<dyn Stream<Item = PyResult<PyObject>> + Send>>;
+
+/// Call the python `generate` with `request`, returning the stream of its responses and the
+/// `Context` which cancels it.
+async fn start_generator(
+    runtime: &Handle,
+    generator: Arc<PyObject>,
+    event_loop: Arc<PyObject>,
+    request: serde_json::Value,
+) -> Result<(PyStream, PyObject), Error> {
+    // Acquiring the GIL is similar to acquiring a standard lock/mutex
+    // Performing this in an tokio async task could block the thread for an undefined amount of time
+    // To avoid this, we spawn a blocking task to acquire the GIL and perform the operations needed
+    // while holding the GIL.
+    //
+    // Under low GIL contention, we wouldn't need to do this.
+    // However, under high GIL contention, this can lead to significant performance degradation.
+    //
+    // Since we cannot predict the GIL contention, we will always use the blocking task and pay the
+    // cost. The Python GIL is the gift that keeps on giving -- performance hits...
+    let stream = runtime
+        .spawn_blocking(move || {
+            Python::with_gil(|py| {
+                let py_request = pythonize(py, &request)?;
+                let start = PY_START.get_or_try_init(py, || load_py_start(py))?;
+                let (gen, context): (PyObject, PyObject) = start
+                    .call1(py, (generator.as_ref(), py_request, event_loop.as_ref()))?
+                    .extract(py)?;
+                let locals = TaskLocals::new(event_loop.bind(py).clone());
+                let stream = pyo3_async_runtimes::tokio::into_stream_with_locals_v1(
+                    locals,
+                    gen.into_bound(py),
+                )?;
+                Ok::<_, PyErr>((Box::pin(stream) as PyStream, context))
+            })
+        })
+        .await??;
+    Ok(stream)
+}
+
+fn load_py_start(py: Python<'_>) -> PyResult<PyObject> {
+    let globals = PyDict::new(py);
+    py.run(PY_CANCELLATION, Some(&globals), None)?;
+    let start = globals
+        .get_item("start")?
+        .ok_or_else(|| PyRuntimeError::new_err("PY_CANCELLATION does not define start"))?;
+    Ok(start.unbind())
+}
+
+/// Tell the python generator its request was cancelled. It is interrupted at its next `await`.
+async fn stop_generator(runtime: &Handle, context: PyObject) {
+    let stopped = runtime
+        .spawn_blocking(move || Python::with_gil(|py| context.call_method0(py, "_stop")))
+        .await;
+    match stopped {
+        Ok(Ok(_)) => {}
+        Ok(Err(err)) => tracing::warn!("failed to stop the python async generator: {err}"),
+        Err(err) => tracing::warn!("failed to offload stopping the python async generator: {err}"),
+    }
+}
+
 async fn process_item<Resp>(
+    runtime: &Handle,
     item: Result<Py<PyAny>, PyErr>,
 ) -> Result<Annotated<Resp>, ResponseProcessingError>
 where
@@ -349,12 +662,11 @@ where
         Python::with_gil(|py| e.display(py));
         ResponseProcessingError::PythonException(e.to_string())
     })?;
-    let response = tokio::task::spawn_blocking(move || {
-        Python::with_gil(|py| depythonize::<Resp>(&item.into_bound(py)))
-    })
-    .await
-    .map_err(|e| ResponseProcessingError::OffloadError(e.to_string()))?
-    .map_err(|e| ResponseProcessingError::DeserializeError(e.to_string()))?;
+    let response = runtime
+        .spawn_blocking(move || Python::with_gil(|py| depythonize::<Resp>(&item.into_bound(py))))
+        .await
+        .map_err(|e| ResponseProcessingError::OffloadError(e.to_string()))?
+        .map_err(|e| ResponseProcessingError::DeserializeError(e.to_string()))?;
 
     let response = Annotated::from_data(response);
 
@@ -380,3 +692,42 @@ fn fix_venv(venv: String, py: Python<'_>) -> anyhow::Result<()> {
 fn fix_venv(_venv: String, _py: Python<'_>) -> anyhow::Result<()> {
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_event_loop_start_timeout() {
+        let runtime = Handle::current();
+
+        // Hangs part way through starting
+        fn hangs(_tx: Sender<()>) {
+            std::thread::sleep(Duration::from_millis(500));
+        }
+        let err = start_event_loop(&runtime, hangs, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("python asyncio event loop failed to start within"),
+            "{err}"
+        );
+
+        // Panics, as when asyncio can't be imported
+        fn panics(_tx: Sender<()>) {
+            panic!("No module named 'asyncio'");
+        }
+        let err = start_event_loop(&runtime, panics, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "python asyncio event loop failed to start");
+
+        fn starts(tx: Sender<()>) {
+            let _ = tx.send(());
+        }
+        start_event_loop(&runtime, starts, Duration::from_secs(5))
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+use std::time::Duration;
+
+use dynamo_engine_python::{AsyncEngine, AsyncEngineContextProvider, EngineOptions};
+use tokio_stream::StreamExt;
+
+mod common;
+
+/// Yields forever, writing `closed` when it is closed
+const FOREVER_ENGINE: &str = r#"
+import asyncio
+
+async def generate(request):
+    try:
+        while True:
+            yield chunk(request, "more")
+            await asyncio.sleep(0.01)
+    finally:
+        with open(MARKER, "w") as f:
+            f.write("closed")
+"#;
+
+/// Like `FOREVER_ENGINE`, but takes a `context` and records whether it saw the request stop
+const CONTEXT_ENGINE: &str = r#"
+import asyncio
+
+async def generate(request, context):
+    try:
+        while True:
+            yield chunk(request, "more")
+            await asyncio.sleep(0.01)
+    finally:
+        with open(MARKER, "w") as f:
+            f.write("stopped" if context.is_stopped() else "not stopped")
+"#;
+
+/// Start a request to the engine, read a few responses, cancel it, and return what the
+/// engine wrote to its marker file once it stopped.
+async fn cancel(engine_py: &str) -> String {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("marker");
+    let engine_py = format!("MARKER = {:?}\n{engine_py}", marker.display().to_string());
+    let py_file = common::write_engine(dir.path(), &engine_py);
+
+    let engine = common::load(&py_file, EngineOptions::default())
+        .await
+        .unwrap();
+    let mut stream = engine.generate(common::request()).await.unwrap();
+    for _ in 0..3 {
+        let response = stream.next().await.unwrap();
+        assert!(response.data.is_some(), "{:?}", response.comment);
+    }
+    stream.context().stop_generating();
+
+    tokio::time::timeout(Duration::from_secs(5), wait_for(&marker))
+        .await
+        .expect("python generator kept running after the request was cancelled")
+}
+
+async fn wait_for(marker: &Path) -> String {
+    loop {
+        if let Ok(contents) = std::fs::read_to_string(marker) {
+            if !contents.is_empty() {
+                return contents;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_cancel_closes_generator() {
+    assert_eq!(cancel(FOREVER_ENGINE).await, "closed");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_cancel_sets_context() {
+    assert_eq!(cancel(CONTEXT_ENGINE).await, "stopped");
+}
@@ -0,0 +1,77 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared by the python engine tests. Each test file is its own crate and uses only
+//! some of them.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use dynamo_engine_python::{make_string_engine, AsyncEngine, CancellationToken, EngineOptions};
+use dynamo_llm::types::openai::chat_completions::{
+    NvCreateChatCompletionRequest, OpenAIChatCompletionsStreamingEngine,
+};
+use dynamo_runtime::pipeline::Context;
+use tokio_stream::StreamExt;
+
+/// A python `chunk(request, content)` building one chat completion chunk, for engines to yield
+pub const CHUNK: &str = r#"
+def chunk(request, content):
+    return {
+        "id": "1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": request["model"],
+        "choices": [{"index": 0, "delta": {"role": "assistant", "content": content}}],
+    }
+"#;
+
+/// A one message chat request
+pub fn request() -> Context<NvCreateChatCompletionRequest> {
+    let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": "test",
+        "messages": [{"role": "user", "content": "hi"}],
+    }))
+    .unwrap();
+    Context::new(request)
+}
+
+/// Write `engine_py` to `{dir}/engine.py`, with [`CHUNK`] available to it
+pub fn write_engine(dir: &Path, engine_py: &str) -> PathBuf {
+    let py_file = dir.join("engine.py");
+    std::fs::write(&py_file, format!("{CHUNK}\n{engine_py}")).unwrap();
+    py_file
+}
+
+/// The engine at `path`, or the error loading it with its causes
+pub async fn load(
+    path: &Path,
+    options: EngineOptions,
+) -> Result<OpenAIChatCompletionsStreamingEngine, String> {
+    make_string_engine(CancellationToken::new(), path, vec![], options)
+        .await
+        .map_err(|err| format!("{err:#}"))
+}
+
+/// The content of the engine's first response to [`request`]
+pub async fn first_content(engine: &OpenAIChatCompletionsStreamingEngine) -> String {
+    let mut stream = engine.generate(request()).await.unwrap();
+    let response = stream.next().await.unwrap();
+    response.data.unwrap().inner.choices[0]
+        .delta
+        .content
+        .clone()
+        .unwrap_or_default()
+}
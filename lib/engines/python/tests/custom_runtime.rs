@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use dynamo_engine_python::{AsyncEngine, EngineOptions};
+use tokio_stream::StreamExt;
+
+mod common;
+
+const ENGINE: &str = r#"
+async def generate(request):
+    yield chunk(request, "hello")
+"#;
+
+#[test]
+fn test_engine_on_custom_runtime() {
+    let dir = tempfile::tempdir().unwrap();
+    let py_file = common::write_engine(dir.path(), ENGINE);
+
+    let custom_threads = Arc::new(AtomicUsize::new(0));
+    let custom = {
+        let custom_threads = custom_threads.clone();
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("custom-python-rt")
+            .on_thread_start(move || {
+                custom_threads.fetch_add(1, Ordering::SeqCst);
+            })
+            .enable_all()
+            .build()
+            .unwrap()
+    };
+    // The engine is driven from a different runtime than the one it runs on
+    let caller = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let options = EngineOptions {
+        runtime: Some(custom.handle().clone()),
+        ..Default::default()
+    };
+    let content = caller.block_on(async {
+        let engine = common::load(&py_file, options).await.unwrap();
+        let mut stream = engine.generate(common::request()).await.unwrap();
+        let mut content = String::new();
+        while let Some(response) = stream.next().await {
+            let data = response.data.expect("python engine returned an error");
+            if let Some(c) = &data.inner.choices[0].delta.content {
+                content.push_str(c);
+            }
+        }
+        content
+    });
+
+    assert_eq!(content, "hello");
+    // 2 workers plus the blocking threads for the asyncio loop and the GIL offload
+    assert!(custom_threads.load(Ordering::SeqCst) > 2);
+
+    // The asyncio loop runs forever, don't wait for it
+    custom.shutdown_background();
+}
@@ -0,0 +1,60 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use dynamo_engine_python::EngineOptions;
+
+mod common;
+
+/// Several handlers in one file
+const HANDLERS: &str = r#"
+not_a_function = 42
+
+async def generate(request):
+    yield chunk(request, "generate")
+
+async def my_entry(request):
+    yield chunk(request, "my_entry")
+"#;
+
+/// The content of the first response from calling `entrypoint`, or the error loading it
+async fn run(entrypoint: Option<&str>) -> Result<String, String> {
+    let dir = tempfile::tempdir().unwrap();
+    let py_file = common::write_engine(dir.path(), HANDLERS);
+    let options = EngineOptions {
+        entrypoint: entrypoint.map(|name| name.to_string()),
+        ..Default::default()
+    };
+    let engine = common::load(&py_file, options).await?;
+    Ok(common::first_content(&engine).await)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_default_entrypoint() {
+    assert_eq!(run(None).await, Ok("generate".to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_named_entrypoint() {
+    assert_eq!(run(Some("my_entry")).await, Ok("my_entry".to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_missing_entrypoint() {
+    let err = run(Some("nope")).await.unwrap_err();
+    assert!(err.contains("no function named 'nope'"), "{err}");
+
+    let err = run(Some("not_a_function")).await.unwrap_err();
+    assert!(err.contains("not callable"), "{err}");
+}
@@ -0,0 +1,88 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use dynamo_engine_python::{AsyncEngine, EngineOptions};
+use tokio_stream::StreamExt;
+
+mod common;
+
+/// Raises on the first call, like a flaky external call, and works after that
+const FLAKY_ENGINE: &str = r#"
+calls = 0
+
+async def generate(request):
+    global calls
+    calls += 1
+    if calls == 1:
+        raise ConnectionError("upstream unavailable")
+    yield chunk(request, f"call {calls}")
+"#;
+
+/// Raises after its first response, every time
+const FAILS_MIDWAY_ENGINE: &str = r#"
+calls = 0
+
+async def generate(request):
+    global calls
+    calls += 1
+    yield chunk(request, f"call {calls}")
+    raise ConnectionError("upstream went away")
+"#;
+
+/// The content of each response from the engine, or the error
+async fn run(engine_py: &str, generate_retries: u32) -> Vec<Result<String, String>> {
+    let dir = tempfile::tempdir().unwrap();
+    let py_file = common::write_engine(dir.path(), engine_py);
+
+    let options = EngineOptions {
+        generate_retries,
+        ..Default::default()
+    };
+    let engine = common::load(&py_file, options).await.unwrap();
+    let mut stream = engine.generate(common::request()).await.unwrap();
+    let mut responses = Vec::new();
+    while let Some(response) = stream.next().await {
+        match response.data {
+            Some(data) => responses.push(Ok(data.inner.choices[0]
+                .delta
+                .content
+                .clone()
+                .unwrap_or_default())),
+            None => responses.push(Err(response.comment.unwrap_or_default().join(", "))),
+        }
+    }
+    responses
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_retry_before_first_response() {
+    let responses = run(FLAKY_ENGINE, 1).await;
+    assert_eq!(responses, vec![Ok("call 2".to_string())]);
+
+    // Without retries the error reaches the client
+    let responses = run(FLAKY_ENGINE, 0).await;
+    assert_eq!(responses.len(), 1);
+    let err = responses[0].as_ref().unwrap_err();
+    assert!(err.contains("upstream unavailable"), "{err}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_no_retry_after_first_response() {
+    let responses = run(FAILS_MIDWAY_ENGINE, 3).await;
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0], Ok("call 1".to_string()));
+    let err = responses[1].as_ref().unwrap_err();
+    assert!(err.contains("upstream went away"), "{err}");
+}
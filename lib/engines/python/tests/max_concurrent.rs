@@ -0,0 +1,67 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use dynamo_engine_python::{AsyncEngine, EngineOptions};
+use tokio_stream::StreamExt;
+
+use common::request;
+
+mod common;
+
+/// How long each request holds its slot
+const SLOW: Duration = Duration::from_millis(500);
+
+/// Takes a while before its only response
+const SLOW_ENGINE: &str = r#"
+import asyncio
+
+async def generate(request):
+    await asyncio.sleep(0.5)
+    yield chunk(request, "done")
+"#;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_concurrent_queues_extra_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let py_file = common::write_engine(dir.path(), SLOW_ENGINE);
+
+    let options = EngineOptions {
+        max_concurrent: Some(2),
+        ..Default::default()
+    };
+    let engine = common::load(&py_file, options).await.unwrap();
+
+    // Both fit, so they start straight away
+    let start = Instant::now();
+    let first = engine.generate(request()).await.unwrap();
+    let second = engine.generate(request()).await.unwrap();
+    assert!(start.elapsed() < SLOW, "{:?}", start.elapsed());
+
+    // The third waits for one of them to finish
+    let third = engine.generate(request()).await.unwrap();
+    assert!(start.elapsed() >= SLOW, "{:?}", start.elapsed());
+
+    for stream in [first, second, third] {
+        let responses: Vec<_> = stream.collect().await;
+        assert_eq!(responses.len(), 1);
+        let content = responses[0].data.as_ref().unwrap().inner.choices[0]
+            .delta
+            .content
+            .clone();
+        assert_eq!(content.as_deref(), Some("done"));
+    }
+}
@@ -0,0 +1,75 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use dynamo_engine_python::EngineOptions;
+
+mod common;
+
+const INIT: &str = r#"
+from .helper import chunk
+
+async def generate(request):
+    yield chunk(request, f"package {__name__}")
+"#;
+
+const SERVER: &str = r#"
+from .helper import chunk
+
+async def generate(request):
+    yield chunk(request, f"module {__name__}")
+"#;
+
+/// Lay out `{name}/{__init__,helper,server}.py` in `dir`. Tests share one interpreter, and so
+/// `sys.modules`, which is why each uses its own package name.
+fn write_package(dir: &Path, name: &str) -> std::path::PathBuf {
+    let package = dir.join(name);
+    std::fs::create_dir(&package).unwrap();
+    std::fs::write(package.join("__init__.py"), INIT).unwrap();
+    std::fs::write(package.join("helper.py"), common::CHUNK).unwrap();
+    std::fs::write(package.join("server.py"), SERVER).unwrap();
+    package
+}
+
+/// The content of the first response from the engine at `path`, or the error loading it
+async fn run(path: &Path) -> Result<String, String> {
+    let engine = common::load(path, EngineOptions::default()).await?;
+    Ok(common::first_content(&engine).await)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_package_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = write_package(dir.path(), "engine_dir");
+    assert_eq!(run(&package).await, Ok("package engine_dir".to_string()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_module_in_package() {
+    let dir = tempfile::tempdir().unwrap();
+    let package = write_package(dir.path(), "engine_module");
+    assert_eq!(
+        run(&package.join("server.py")).await,
+        Ok("module engine_module.server".to_string())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_directory_not_a_package() {
+    let dir = tempfile::tempdir().unwrap();
+    let err = run(dir.path()).await.unwrap_err();
+    assert!(err.contains("not a package"), "{err}");
+}
@@ -19,7 +19,7 @@ use std::{
     os::fd::{FromRawFd as _, RawFd},
     path::{Path, PathBuf},
     process::Stdio,
-    sync::Arc,
+    sync::{Arc, Once},
     time::Duration,
     vec::IntoIter,
 };
@@ -309,6 +309,10 @@ pub async fn start(
             "Need at least as many GPUs as nodes. Pass --tensor-parallel-size >= --num-nodes."
         );
     }
+    if node_conf.num_nodes > 1 {
+        // torch distributed gives us no way to check the others agree, at least say what we expect
+        tracing::info!("Multi-node topology: {node_conf}, {tp_size} GPUs in total");
+    }
     let tp_size_per_node = tp_size / node_conf.num_nodes;
     let tp_rank_start = tp_size_per_node * node_conf.node_rank;
     let tp_rank_end = tp_size_per_node * (node_conf.node_rank + 1);
@@ -591,6 +595,7 @@ async fn input_loop(
     active_requests: Arc<tokio::sync::Mutex<HashMap<RequestID, ActiveRequest>>>,
     mut rx: tokio::sync::mpsc::Receiver<WorkRequest>,
 ) {
+    let seed_ignored = Once::new();
     loop {
         let work_request = tokio::select! {
             _ = cancel_token.cancelled() => {
@@ -617,6 +622,9 @@ async fn input_loop(
             .unwrap_or(0.0)
             .into();
         tracing::trace!("Received work request: {request_id}");
+        if work_request.request.sampling_options.seed.is_some() {
+            dynamo_llm::engines::warn_seed_ignored(&seed_ignored, "sglang");
+        }
 
         // Parts that don't change
         let (py_request_id, sampling_params) = Python::with_gil(|py| {
@@ -627,6 +635,11 @@ async fn input_loop(
                 // sglang defaults this to 128
                 sp_kwargs.push(("max_new_tokens", py_max_tokens));
             }
+            let sampling_options = &work_request.request.sampling_options;
+            for (name, value) in sampling_options.penalty_kwargs() {
+                let py_value: PyObject = value.into_pyobject(py).unwrap().into();
+                sp_kwargs.push((name, py_value));
+            }
             let sp_kwargs = sp_kwargs.into_py_dict(py).unwrap();
             let sampling_params = py_imports
                 .sampling_params_type
@@ -55,10 +55,10 @@ pub async fn make_leader_engine(
     kv_metrics_publisher: Option<Arc<KvMetricsPublisher>>,
 ) -> pipeline_error::Result<(ExecutionContext, impl Future<Output = ()>)> {
     let ray_obj = if node_conf.num_nodes > 1 {
-        let r = ray::start_leader(node_conf.leader_addr.parse()?)?;
+        tracing::info!("Multi-node topology: {node_conf}");
+        let r = ray::start_leader(node_conf.leader_addr.parse()?, node_conf.num_nodes)?;
         tracing::info!("Leader waiting for {} total nodes", node_conf.num_nodes);
-        r.wait_for(cancel_token.clone(), node_conf.num_nodes)
-            .await?;
+        r.wait_for(cancel_token.clone(), &node_conf).await?;
         tracing::info!("All nodes registered");
         Some(r)
     } else {
@@ -94,9 +94,10 @@ pub async fn start_follower(
     cancel_token: CancellationToken,
     node_conf: MultiNodeConfig,
 ) -> pipeline_error::Result<StopFuture> {
-    let r = ray::start_follower(node_conf.leader_addr.parse()?)?;
+    tracing::info!("Multi-node topology: {node_conf}");
+    let r = ray::start_follower(node_conf.leader_addr.parse()?, node_conf.num_nodes)?;
     tracing::info!("Follower waiting for {} total nodes", node_conf.num_nodes);
-    r.wait_for(cancel_token, node_conf.num_nodes).await?;
+    r.wait_for(cancel_token, &node_conf).await?;
     tracing::info!("All nodes registered");
 
     Ok(StopFuture {
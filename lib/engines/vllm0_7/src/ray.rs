@@ -23,6 +23,7 @@ use tokio::io::AsyncBufReadExt;
 use tokio::select;
 use tokio::time;
 
+use dynamo_llm::engines::MultiNodeConfig;
 use dynamo_runtime::CancellationToken;
 
 /// Default is 16 seconds, we make it a bit shorter
@@ -33,6 +34,10 @@ const RAY_STOP_TIMEOUT_SECS: u32 = 10;
 /// can take some time.
 const RAY_WAIT_SECS: u32 = 60 * 5;
 
+/// Each node starts ray with one unit of the custom resource `dynamo_num_nodes_<num_nodes>`, so
+/// the leader can see in `ray status` how many nodes every node expects.
+const NUM_NODES_RESOURCE: &str = "dynamo_num_nodes_";
+
 #[derive(Debug, Error)]
 pub enum RayError {
     #[error("Failed to execute Ray command: {0}")]
@@ -49,6 +54,9 @@ pub enum RayError {
 
     #[error("Operation cancelled")]
     Cancelled,
+
+    #[error("{0}")]
+    TopologyMismatch(String),
 }
 
 #[derive(Debug, PartialEq)]
@@ -56,6 +64,8 @@ pub struct RayStatus {
     pub active_nodes: Vec<String>,
     pub pending_nodes_count: usize,
     pub recent_failures_count: usize,
+    /// The `num_nodes` each node was started with, from the `dynamo_num_nodes_*` resources
+    pub reported_num_nodes: Vec<u32>,
 }
 
 pub struct Ray {
@@ -63,7 +73,7 @@ pub struct Ray {
     leader_address: SocketAddrV4,
 }
 
-pub fn start_leader(leader_address: SocketAddrV4) -> Result<Ray, RayError> {
+pub fn start_leader(leader_address: SocketAddrV4, num_nodes: u32) -> Result<Ray, RayError> {
     let ip = leader_address.ip().to_string();
     let port = leader_address.port().to_string();
 
@@ -75,6 +85,7 @@ pub fn start_leader(leader_address: SocketAddrV4) -> Result<Ray, RayError> {
         "--log-style=record",
         &format!("--node-ip-address={}", ip),
         &format!("--port={}", port),
+        &num_nodes_resource_arg(num_nodes),
     ]);
 
     cmd.stdout(Stdio::piped());
@@ -107,11 +118,15 @@ pub fn start_leader(leader_address: SocketAddrV4) -> Result<Ray, RayError> {
     Ok(Ray { leader_address })
 }
 
-pub fn start_follower(leader_address: SocketAddrV4) -> Result<Ray, RayError> {
+pub fn start_follower(leader_address: SocketAddrV4, num_nodes: u32) -> Result<Ray, RayError> {
     let address = leader_address.to_string();
 
     let mut cmd = Command::new("ray");
-    cmd.args(["start", &format!("--address={address}")]);
+    cmd.args([
+        "start",
+        &format!("--address={address}"),
+        &num_nodes_resource_arg(num_nodes),
+    ]);
 
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
@@ -158,7 +173,7 @@ impl Ray {
     pub async fn wait_for(
         &self,
         cancel_token: CancellationToken,
-        num_nodes: u32,
+        node_conf: &MultiNodeConfig,
     ) -> Result<(), RayError> {
         let timeout = time::sleep(Duration::from_secs(RAY_WAIT_SECS as u64));
 
@@ -169,16 +184,19 @@ impl Ray {
             _ = timeout => {
                 Err(RayError::WaitTimeout)
             }
-            result = self.wait_for_nodes(num_nodes) => {
+            result = self.wait_for_nodes(node_conf) => {
                 result
             }
         }
     }
 
-    async fn wait_for_nodes(&self, num_nodes: u32) -> Result<(), RayError> {
+    async fn wait_for_nodes(&self, node_conf: &MultiNodeConfig) -> Result<(), RayError> {
         loop {
             let status = self.status()?;
-            if status.active_nodes.len() as u32 == num_nodes {
+            node_conf
+                .check_reported_num_nodes(&status.reported_num_nodes)
+                .map_err(|err| RayError::TopologyMismatch(err.to_string()))?;
+            if status.active_nodes.len() as u32 == node_conf.num_nodes {
                 return Ok(());
             }
             time::sleep(Duration::from_millis(100)).await;
@@ -226,11 +244,16 @@ impl Ray {
     }
 }
 
+fn num_nodes_resource_arg(num_nodes: u32) -> String {
+    format!(r#"--resources={{"{NUM_NODES_RESOURCE}{num_nodes}": 1}}"#)
+}
+
 /// Parse the output of "ray status" command into a RayStatus struct
 fn parse_ray_status(output: &str) -> Option<RayStatus> {
     let mut active_nodes = Vec::new();
     let mut pending_nodes_count = 0;
     let mut recent_failures_count = 0;
+    let mut reported_num_nodes = Vec::new();
 
     // Flags to track which section we're in
     let mut in_active_section = false;
@@ -240,6 +263,12 @@ fn parse_ray_status(output: &str) -> Option<RayStatus> {
     // Regex to match node IDs
     let node_regex = Regex::new(r"(\d+)\s+(node_[a-f0-9]+)").unwrap();
     let num_regex = Regex::new(r"(\d+)").unwrap();
+    // e.g. ` 0.0/2.0 dynamo_num_nodes_2`, two nodes started with num_nodes 2
+    let resource_regex = Regex::new(&format!(
+        r"^[\d.]+/(\d+)(\.\d+)?\s+{NUM_NODES_RESOURCE}(\d+)$"
+    ))
+    .unwrap();
+    let mut in_resources_section = false;
 
     for line in output.lines() {
         let trimmed = line.trim();
@@ -261,10 +290,21 @@ fn parse_ray_status(output: &str) -> Option<RayStatus> {
             continue;
         } else if trimmed.starts_with("Resources") {
             // We've reached the end of the node status section
-            break;
+            in_active_section = false;
+            in_pending_section = false;
+            in_failures_section = false;
+            in_resources_section = true;
+            continue;
         }
 
-        if in_active_section {
+        if in_resources_section {
+            if let Some(captures) = resource_regex.captures(trimmed) {
+                let nodes = captures[1].parse::<usize>().unwrap_or_default();
+                if let Ok(num_nodes) = captures[3].parse::<u32>() {
+                    reported_num_nodes.extend(std::iter::repeat_n(num_nodes, nodes));
+                }
+            }
+        } else if in_active_section {
             if let Some(captures) = node_regex.captures(trimmed) {
                 if let Some(node_id) = captures.get(2) {
                     active_nodes.push(node_id.as_str().to_string());
@@ -291,10 +331,12 @@ fn parse_ray_status(output: &str) -> Option<RayStatus> {
         }
     }
 
+    reported_num_nodes.sort_unstable();
     Some(RayStatus {
         active_nodes,
         pending_nodes_count,
         recent_failures_count,
+        reported_num_nodes,
     })
 }
 
@@ -334,6 +376,7 @@ Demands:
             ],
             pending_nodes_count: 0,
             recent_failures_count: 0,
+            reported_num_nodes: vec![],
         };
 
         let result = parse_ray_status(sample_output);
@@ -368,6 +411,7 @@ Usage:
             ],
             pending_nodes_count: 5,   // 2 + 3
             recent_failures_count: 5, // 1 + 4
+            reported_num_nodes: vec![],
         };
 
         let result = parse_ray_status(sample_output_with_pending);
@@ -383,4 +427,47 @@ Usage:
         assert!(result.is_some());
         assert_eq!(result.unwrap().active_nodes.len(), 0);
     }
+
+    /// The follower was started with a different num_nodes than the leader
+    #[test]
+    fn test_parse_ray_status_mismatched_num_nodes() {
+        let sample_output = r#"======== Autoscaler status: 2025-03-04 13:13:59.104771 ========
+Node status
+---------------------------------------------------------------
+Active:
+ 1 node_b09a7440bd0987680f97c35206b2475251907d0c928fdd0f52b1b38f
+ 1 node_035ea3b640e13f3603d3debd97de8c569ed8c8b10e19ce00ea4fd070
+Pending:
+ (no pending nodes)
+Recent failures:
+ (no failures)
+
+Resources
+---------------------------------------------------------------
+Usage:
+ 0.0/256.0 CPU
+ 0.0/1.0 dynamo_num_nodes_2
+ 0.0/1.0 dynamo_num_nodes_3
+ 0.0/16.0 GPU
+ 0B/1.58TiB memory
+ 0B/372.53GiB object_store_memory
+
+Demands:
+ (no resource demands)
+"#;
+
+        let status = parse_ray_status(sample_output).unwrap();
+        assert_eq!(status.active_nodes.len(), 2);
+        assert_eq!(status.reported_num_nodes, vec![2, 3]);
+
+        let leader = MultiNodeConfig {
+            num_nodes: 2,
+            node_rank: 0,
+            leader_addr: "10.0.0.1:6379".to_string(),
+        };
+        let err = leader
+            .check_reported_num_nodes(&status.reported_num_nodes)
+            .unwrap_err();
+        assert!(err.to_string().contains("--num-nodes [3]"), "{err}");
+    }
 }
@@ -658,6 +658,15 @@ async fn input_loop(
                 // vllm defaults this to 16
                 sp_kwargs.push(("max_tokens", py_max_tokens));
             }
+            let sampling_options = &work_request.request.sampling_options;
+            for (name, value) in sampling_options.penalty_kwargs() {
+                let py_value: PyObject = value.into_pyobject(py).unwrap().into();
+                sp_kwargs.push((name, py_value));
+            }
+            if let Some(seed) = sampling_options.seed {
+                let py_seed: PyObject = seed.into_pyobject(py).unwrap().into();
+                sp_kwargs.push(("seed", py_seed));
+            }
             let sp_kwargs = sp_kwargs.into_py_dict(py).unwrap();
             let sampling_params = py_imports
                 .sample_params_type
@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -182,6 +183,19 @@ impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Er
                 // vllm defaults this to 16
                 sp_kwargs.push(("max_tokens", py_max_tokens));
             }
+            let sampling_options = &request.sampling_options;
+            for (name, value) in sampling_options.penalty_kwargs() {
+                let py_value: PyObject = value.into_pyobject(py).unwrap().into();
+                sp_kwargs.push((name, py_value));
+            }
+            if let Some(seed) = sampling_options.seed {
+                let py_seed: PyObject = seed.into_pyobject(py).unwrap().into();
+                sp_kwargs.push(("seed", py_seed));
+            }
+            if let Some(logprobs) = sampling_options.logprobs {
+                let py_logprobs: PyObject = logprobs.into_pyobject(py).unwrap().into();
+                sp_kwargs.push(("logprobs", py_logprobs));
+            }
             let sp_kwargs = sp_kwargs.into_py_dict(py).unwrap();
             let sampling_params = self.sampling_params.call(py, (), Some(&sp_kwargs)).unwrap();
 
@@ -342,6 +356,9 @@ async fn run_vllm_worker(
     tensor_parallel_size: u32,
     extra_engine_args: Option<PathBuf>,
 ) -> anyhow::Result<()> {
+    if node_conf.num_nodes > 1 {
+        tracing::info!("Multi-node topology: {node_conf}");
+    }
     let model_path_str = model_path.display().to_string();
     let extra_engine_args_str = &extra_engine_args
         .map(|p| p.display().to_string())
@@ -443,11 +460,17 @@ pub struct CompletionOutput {
     pub text: String,
     pub token_ids: Vec<u32>,
     pub cumulative_logprob: Option<f64>,
-    pub logprobs: Option<Vec<f64>>,
+    /// For each new token, the most likely tokens by id, always including the chosen one
+    pub logprobs: Option<Vec<HashMap<u32, Logprob>>>,
     pub finish_reason: Option<String>,
     pub stop_reason: Option<String>,
 }
 
+#[derive(Debug, Clone, Deserialize, FromPyObject)]
+pub struct Logprob {
+    pub logprob: f64,
+}
+
 #[derive(Debug, Clone, Deserialize, FromPyObject)]
 pub struct RequestMetrics {
     pub arrival_time: f64,
@@ -497,12 +520,21 @@ impl From<RequestOutput> for LLMEngineOutput {
                     FinishReason::Error(s)
                 }
             });
+        // The chosen token's score, the alternatives have nowhere to go yet
+        let log_probs = out.logprobs.map(|logprobs| {
+            out.token_ids
+                .iter()
+                .zip(logprobs)
+                .filter_map(|(token_id, mut top)| top.remove(token_id))
+                .map(|logprob| logprob.logprob)
+                .collect()
+        });
         LLMEngineOutput {
             token_ids: out.token_ids,
             tokens: None,
             text: None,
             cum_log_probs: out.cumulative_logprob,
-            log_probs: out.logprobs,
+            log_probs,
             finish_reason,
         }
     }
@@ -45,13 +45,16 @@ use dynamo_runtime::{
 use crate::protocols::{
     common::{
         llm_backend::{BackendInput, BackendOutput, FinishReason, LLMEngineOutput},
-        StopConditions,
+        StopCause, StopConditions,
     },
     TokenIdType,
 };
-use crate::tokenizers::{DecodeStream, HuggingFaceTokenizer, Tokenizer};
+use crate::tokenizers::{DecodeStream, HuggingFaceTokenizer, StreamGranularity, Tokenizer};
 use tokenizers::Tokenizer as HfTokenizer;
 
+/// Sent just before the response which ends generation, with the [`StopCause`] as its comment
+pub const ANNOTATION_STOP_CAUSE: &str = "stop_cause";
+
 /// Represents the output stream from the execution engine
 pub type ExecutionOutputStream = Annotated<LLMEngineOutput>;
 
@@ -61,8 +64,9 @@ pub type ExecutionContext = ServerStreamingEngine<BackendInput, ExecutionOutputS
 /// Backend handles resource management and orchestrates LLM execution
 #[allow(dead_code)]
 pub struct Backend {
-    pub tokenizer: Tokenizer,     // Handles token encoding/decoding
-    validate_engine_decode: bool, // Enable validation of engine decoding
+    pub tokenizer: Tokenizer,              // Handles token encoding/decoding
+    validate_engine_decode: bool,          // Enable validation of engine decoding
+    stream_granularity: StreamGranularity, // How decoded text is split into chunks
 }
 
 /// Internal state for managing token decoding and stream processing
@@ -71,27 +75,47 @@ struct DecoderUnfoldState {
     stream: ManyOut<ExecutionOutputStream>,
     decoder: Decoder,
     validate_engine_decode: bool,
+    max_tokens: Option<u32>,
+    // number of tokens the engine generated so far, whether or not we decode them
+    generated_tokens: u32,
 }
 
 impl Backend {
     pub async fn from_tokenizer(tokenizer: HfTokenizer) -> Result<Arc<Self>> {
+        Self::from_tokenizer_with_granularity(tokenizer, StreamGranularity::default()).await
+    }
+
+    /// As [`Backend::from_tokenizer`], streaming the text at `stream_granularity`
+    pub async fn from_tokenizer_with_granularity(
+        tokenizer: HfTokenizer,
+        stream_granularity: StreamGranularity,
+    ) -> Result<Arc<Self>> {
         let tokenizer = HuggingFaceTokenizer::from_tokenizer(tokenizer);
         let tokenizer = Tokenizer::from(Arc::new(tokenizer));
 
         Ok(Arc::new(Self {
             tokenizer,
             validate_engine_decode: false,
+            stream_granularity,
         }))
     }
 
     pub async fn from_mdc(mdc: ModelDeploymentCard) -> Result<Arc<Self>> {
+        Self::from_mdc_with_granularity(mdc, StreamGranularity::default()).await
+    }
+
+    /// As [`Backend::from_mdc`], streaming the text at `stream_granularity`
+    pub async fn from_mdc_with_granularity(
+        mdc: ModelDeploymentCard,
+        stream_granularity: StreamGranularity,
+    ) -> Result<Arc<Self>> {
         let tokenizer = match &mdc.tokenizer {
             TokenizerKind::HfTokenizerJson(file) => {
                 HfTokenizer::from_file(file).map_err(Error::msg)?
             }
             TokenizerKind::GGUF(t) => *t.clone(),
         };
-        Self::from_tokenizer(tokenizer).await
+        Self::from_tokenizer_with_granularity(tokenizer, stream_granularity).await
     }
 
     fn decoder(
@@ -99,12 +123,19 @@ impl Backend {
         stream: ManyOut<ExecutionOutputStream>,
         stop_conditions: StopConditions,
     ) -> DecoderUnfoldState {
-        let decoder = Decoder::new(self.tokenizer.decode_stream(false), stop_conditions);
+        let max_tokens = stop_conditions.max_tokens;
+        let decoder = Decoder::new(
+            self.tokenizer
+                .decode_stream_with_granularity(false, self.stream_granularity),
+            stop_conditions,
+        );
 
         DecoderUnfoldState {
             stream,
             decoder,
             validate_engine_decode: self.validate_engine_decode,
+            max_tokens,
+            generated_tokens: 0,
         }
     }
 }
@@ -137,13 +168,15 @@ impl
 
                     // events are pass thru
                     if output.is_event() || output.data.is_none() {
-                        return Some((output, state));
+                        return Some((vec![output], state));
                     }
 
                     // if we have a data field without an event, then we might need to update the data
                     if let Some(data) = &output.data {
+                        state.generated_tokens += data.token_ids.len() as u32;
                         if data.text.is_some() && !state.validate_engine_decode {
-                            return Some((output, state));
+                            let stop_cause = state.stop_cause(None, data.finish_reason.as_ref());
+                            return Some((with_stop_cause(output, stop_cause), state));
                         }
                     }
 
@@ -161,6 +194,9 @@ impl
                         None => None,
                     };
 
+                    let stop_cause = state
+                        .stop_cause(result.stop_trigger.as_ref(), data.finish_reason.as_ref());
+
                     if data.finish_reason.is_none() && finish_reason.is_some() {
                         tracing::debug!(
                             ?result.stop_trigger,
@@ -196,12 +232,13 @@ impl
 
                     output.data = Some(data);
 
-                    Some((output, state))
+                    Some((with_stop_cause(output, stop_cause), state))
                 }
 
                 None => None,
             }
-        });
+        })
+        .flat_map(stream::iter);
 
         // convert stream of processed Annotated<LLMEngineOutput> to Annotated<BackendOutput>
         //let mdcsum = self.mdcsum.clone();
@@ -223,6 +260,41 @@ impl
     }
 }
 
+impl DecoderUnfoldState {
+    /// Why generation ended, if this response ends it: a stop condition the decoder found, or
+    /// else the engine's finish reason
+    fn stop_cause(
+        &self,
+        stop_trigger: Option<&StopTrigger>,
+        finish_reason: Option<&FinishReason>,
+    ) -> Option<StopCause> {
+        match (stop_trigger, finish_reason) {
+            (Some(StopTrigger::MaxTokensLimit), _) => Some(StopCause::Length),
+            (Some(_), _) => Some(StopCause::Stop),
+            (None, Some(FinishReason::EoS | FinishReason::Stop)) => Some(StopCause::Stop),
+            (None, Some(FinishReason::Length)) => match self.max_tokens {
+                Some(max_tokens) if self.generated_tokens >= max_tokens => Some(StopCause::Length),
+                _ => Some(StopCause::ServerCap),
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The output, preceded by a [`ANNOTATION_STOP_CAUSE`] annotation if it ends generation. The
+/// annotation goes first because readers stop at the response with the finish reason.
+fn with_stop_cause(
+    output: ExecutionOutputStream,
+    stop_cause: Option<StopCause>,
+) -> Vec<ExecutionOutputStream> {
+    match stop_cause
+        .and_then(|cause| Annotated::from_annotation(ANNOTATION_STOP_CAUSE, &cause).ok())
+    {
+        Some(annotation) => vec![annotation, output],
+        None => vec![output],
+    }
+}
+
 // todo - add visible stop conditions
 // visible_stop_ids: HashSet<TokenIdType>,
 // visible_stop_sequences: Vec<String>,
@@ -13,47 +13,1162 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::env;
-use std::sync::Arc;
 use std::sync::LazyLock;
+use std::sync::{Arc, Mutex, Once};
 use std::time::Duration;
 
 use async_stream::stream;
 use async_trait::async_trait;
+use futures::StreamExt;
+use tokio::sync::mpsc;
 
-use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
-use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
-use dynamo_runtime::protocols::annotated::Annotated;
+use dynamo_runtime::engine::{
+    AsyncEngine, AsyncEngineContext, AsyncEngineContextProvider, Data, ResponseStream,
+};
+use dynamo_runtime::pipeline::{
+    Context, Error, ManyOut, PipelineError, ServerStreamingEngine, SingleIn,
+};
+use dynamo_runtime::protocols::annotated::{Annotated, AnnotationsProvider};
+
+use crate::backend::{ExecutionContext, ANNOTATION_STOP_CAUSE};
+use crate::http::service::error::HttpError;
+use crate::preprocessor::BackendInput;
+use crate::protocols::common::llm_backend::LLMEngineOutput;
+use crate::protocols::common::{OutputConstraint, StopCause};
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+    NvCreateChatCompletionStreamResponse,
+};
+use crate::protocols::openai::completions::{
+    prompt_to_string, CompletionChoice, CompletionRequest, CompletionResponse,
+};
+use crate::protocols::openai::CompletionUsage;
+use crate::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+use crate::types::openai::completions::OpenAICompletionsStreamingEngine;
+
+//
+// The engines are each in their own crate under `lib/engines`
+//
+
+pub mod openai;
+pub mod warmup_cache;
+
+#[derive(Debug, Clone)]
+pub struct MultiNodeConfig {
+    /// How many nodes / hosts we are using
+    pub num_nodes: u32,
+    /// Unique consecutive integer to identify this node
+    pub node_rank: u32,
+    /// host:port of head / control node
+    pub leader_addr: String,
+}
+
+impl MultiNodeConfig {
+    /// Check the nodes in the cluster agree with us on how many nodes there are. `reported` has
+    /// the `num_nodes` of each node which joined, as far as the engine's framework lets us see it.
+    /// A node started with a different `--num-nodes` would otherwise leave the cluster waiting
+    /// for nodes which never come.
+    pub fn check_reported_num_nodes(&self, reported: &[u32]) -> anyhow::Result<()> {
+        let mismatched = reported
+            .iter()
+            .filter(|num_nodes| **num_nodes != self.num_nodes)
+            .collect::<Vec<_>>();
+        if !mismatched.is_empty() {
+            anyhow::bail!(
+                "Multi-node topology mismatch: this node expects {} nodes, but {} of the {} nodes \
+                 which joined were started with --num-nodes {:?}. Pass the same --num-nodes to \
+                 every node.",
+                self.num_nodes,
+                mismatched.len(),
+                reported.len(),
+                mismatched,
+            );
+        }
+        if reported.len() > self.num_nodes as usize {
+            anyhow::bail!(
+                "Multi-node topology mismatch: this node expects {} nodes, but {} joined. Check \
+                 the nodes of another deployment aren't using the same --leader-addr.",
+                self.num_nodes,
+                reported.len(),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for MultiNodeConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "node {} of {} nodes, leader at '{}'",
+            self.node_rank, self.num_nodes, self.leader_addr
+        )
+    }
+}
+
+impl Default for MultiNodeConfig {
+    fn default() -> Self {
+        MultiNodeConfig {
+            num_nodes: 1,
+            node_rank: 0,
+            leader_addr: "".to_string(),
+        }
+    }
+}
+
+/// A `CUDA_VISIBLE_DEVICES` value selecting `gpus_per_node` consecutive GPUs from
+/// `base_gpu_id` upwards. For engines such as vllm which have no base GPU setting.
+pub fn cuda_visible_devices(base_gpu_id: u32, gpus_per_node: u32) -> String {
+    (base_gpu_id..base_gpu_id + gpus_per_node.max(1))
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+//
+// Aggregation
+//
+
+/// Request annotation (`nvext.annotations`) asking for the whole response as a single chunk
+/// instead of a stream of deltas. The equivalent of `stream: false` over HTTP, for callers on the
+/// network.
+pub const ANNOTATION_AGGREGATE: &str = "aggregate";
+
+/// Engine which gives requests annotated with [`ANNOTATION_AGGREGATE`] their response from
+/// `inner` as one chunk. Other requests are streamed as usual.
+struct AggregatingEngine {
+    inner: OpenAIChatCompletionsStreamingEngine,
+}
+pub fn make_aggregating_engine(
+    inner: OpenAIChatCompletionsStreamingEngine,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(AggregatingEngine { inner })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for AggregatingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        if !request.has_annotation(ANNOTATION_AGGREGATE) {
+            return self.inner.generate(request).await;
+        }
+        let stream = self.inner.generate(request).await?;
+        let ctx = stream.context();
+        let output = stream! {
+            match NvCreateChatCompletionResponse::from_annotated_stream(Box::pin(stream)).await {
+                Ok(response) => yield Annotated::from_data(response.into()),
+                Err(err) => yield Annotated::from_error(err),
+            }
+        };
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+//
+// Pacing
+//
+
+/// Engine which limits the output of `inner` to at most `max_tps` tokens per second, however fast
+/// `inner` produces them. Responses are held back rather than buffered, so a slow reader also
+/// slows down reading from `inner`.
+struct PacedEngine<E> {
+    inner: E,
+    max_tps: u32,
+}
+
+/// Pace an engine which does its own pre-processing. Each chunk with content counts as a token.
+pub fn make_paced_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+    max_tps: u32,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(PacedEngine { inner, max_tps })
+}
+
+/// Pace an engine which takes pre-processed requests
+pub fn make_paced_engine_core(inner: ExecutionContext, max_tps: u32) -> ExecutionContext {
+    Arc::new(PacedEngine { inner, max_tps })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for PacedEngine<OpenAIChatCompletionsStreamingEngine>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let stream = self.inner.generate(request).await?;
+        Ok(paced(stream, self.max_tps, |response| {
+            let has_content = response.data.as_ref().is_some_and(|data| {
+                data.inner
+                    .choices
+                    .iter()
+                    .any(|choice| choice.delta.content.as_ref().is_some_and(|c| !c.is_empty()))
+            });
+            has_content as usize
+        }))
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for PacedEngine<ExecutionContext>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let stream = self.inner.generate(request).await?;
+        Ok(paced(stream, self.max_tps, |output| {
+            output.data.as_ref().map_or(0, |data| data.token_ids.len())
+        }))
+    }
+}
+
+/// Delay each item of `stream` so that at most `max_tps` tokens per second come out, as counted
+/// by `tokens`. Items without tokens (errors, the final finish reason) are not delayed.
+fn paced<T: Send + Sync + 'static>(
+    mut stream: ManyOut<T>,
+    max_tps: u32,
+    tokens: fn(&T) -> usize,
+) -> ManyOut<T> {
+    let ctx = stream.context();
+    let per_token = Duration::from_secs(1) / max_tps.max(1);
+    let output = stream! {
+        let mut next = tokio::time::Instant::now();
+        while let Some(item) = stream.next().await {
+            let count = tokens(&item) as u32;
+            if count > 0 {
+                tokio::time::sleep_until(next).await;
+                // Don't let an engine which was slow for a while burst to catch up
+                next = next.max(tokio::time::Instant::now()) + per_token * count;
+            }
+            yield item;
+        }
+    };
+    ResponseStream::new(Box::pin(output), ctx)
+}
+
+//
+// max_tokens
+//
+
+/// Limits on `max_tokens`, applied before requests reach the engine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaxTokensLimits {
+    /// `max_tokens` for requests which don't set it
+    pub default: Option<u32>,
+
+    /// The most tokens a request may ask for. Larger requests are lowered to this, and requests
+    /// which don't set `max_tokens` get at most this many even without a `default`.
+    pub max: Option<u32>,
+
+    /// Reject requests asking for more than `max` with a 400, instead of lowering them
+    pub reject_over_max: bool,
+}
+
+impl MaxTokensLimits {
+    /// Do these limits change any request
+    pub fn is_set(&self) -> bool {
+        self.default.is_some() || self.max.is_some()
+    }
+
+    /// The `max_tokens` the engine should see for a request asking for `requested`
+    pub fn apply(&self, requested: Option<u32>) -> anyhow::Result<Option<u32>> {
+        match (requested, self.max) {
+            (Some(requested), Some(max)) if requested > max => {
+                if self.reject_over_max {
+                    return Err(HttpError {
+                        code: 400,
+                        message: format!(
+                            "max_tokens is {requested}, but this server allows at most {max}"
+                        ),
+                    }
+                    .into());
+                }
+                Ok(Some(max))
+            }
+            (Some(requested), _) => Ok(Some(requested)),
+            (None, Some(max)) => Ok(Some(self.default.map_or(max, |default| default.min(max)))),
+            (None, None) => Ok(self.default),
+        }
+    }
+}
+
+/// Engine which applies [`MaxTokensLimits`] to each request before passing it to `inner`
+struct MaxTokensEngine<E> {
+    inner: E,
+    limits: MaxTokensLimits,
+}
+
+/// Limit `max_tokens` for an engine which does its own pre-processing
+pub fn make_max_tokens_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+    limits: MaxTokensLimits,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(MaxTokensEngine { inner, limits })
+}
+
+/// Limit `max_tokens` for an engine which takes pre-processed requests
+pub fn make_max_tokens_engine_core(
+    inner: ExecutionContext,
+    limits: MaxTokensLimits,
+) -> ExecutionContext {
+    Arc::new(MaxTokensEngine { inner, limits })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for MaxTokensEngine<OpenAIChatCompletionsStreamingEngine>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (mut request, context) = request.into_parts();
+        // allow deprecated because max_tokens
+        #[allow(deprecated)]
+        let lowered = {
+            let requested = request
+                .inner
+                .max_completion_tokens
+                .or(request.inner.max_tokens);
+            let max_tokens = self.limits.apply(requested)?;
+            request.inner.max_completion_tokens = max_tokens;
+            // Engines read either, so they must agree
+            if request.inner.max_tokens.is_some() {
+                request.inner.max_tokens = max_tokens;
+            }
+            requested.is_some() && max_tokens != requested
+        };
+        let stream = self.inner.generate(context.map(|_| request)).await?;
+        if !lowered {
+            return Ok(stream);
+        }
+
+        // The engine's `length` is our cap, not the client's max_tokens. There is no backend
+        // decode loop to tell the difference, so say so the way it would.
+        let ctx = stream.context();
+        let output = stream.flat_map(|response| {
+            let at_cap = response.data.as_ref().is_some_and(|data| {
+                data.inner.choices.iter().any(|choice| {
+                    matches!(
+                        choice.finish_reason,
+                        Some(async_openai::types::FinishReason::Length)
+                    )
+                })
+            });
+            let stop_cause = at_cap
+                .then(|| Annotated::from_annotation(ANNOTATION_STOP_CAUSE, &StopCause::ServerCap))
+                .and_then(Result::ok);
+            futures::stream::iter(stop_cause.into_iter().chain(std::iter::once(response)))
+        });
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for MaxTokensEngine<ExecutionContext>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let (mut request, context) = request.into_parts();
+        // The backend's decode loop still has the client's max_tokens, so an engine stopping at
+        // our lower cap is reported as `server_cap`
+        request.stop_conditions.max_tokens =
+            self.limits.apply(request.stop_conditions.max_tokens)?;
+        self.inner.generate(context.map(|_| request)).await
+    }
+}
+
+//
+// response_format
+//
+
+/// Engine which can't constrain its output, so it rejects requests with `response_format`
+/// `json_object` or `json_schema` rather than ignore them
+struct UnconstrainedEngine<E> {
+    inner: E,
+}
+
+/// Reject requests with `response_format` `json_object` or `json_schema`, for an engine which
+/// does its own pre-processing
+pub fn make_unconstrained_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(UnconstrainedEngine { inner })
+}
+
+/// Reject requests with `response_format` `json_object` or `json_schema`, for an engine which
+/// takes pre-processed requests
+pub fn make_unconstrained_engine_core(inner: ExecutionContext) -> ExecutionContext {
+    Arc::new(UnconstrainedEngine { inner })
+}
+
+fn reject_output_constraint(constraint: &OutputConstraint) -> anyhow::Error {
+    let format = match constraint {
+        OutputConstraint::JsonObject => "json_object",
+        OutputConstraint::JsonSchema(_) => "json_schema",
+    };
+    HttpError {
+        code: 400,
+        message: format!(
+            "This model's engine can't constrain its output, so it does not support \
+             `response_format: {format}`. Remove it from the request."
+        ),
+    }
+    .into()
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for UnconstrainedEngine<OpenAIChatCompletionsStreamingEngine>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        if let Some(constraint) = request
+            .inner
+            .response_format
+            .as_ref()
+            .and_then(OutputConstraint::from_response_format)
+        {
+            return Err(reject_output_constraint(&constraint));
+        }
+        self.inner.generate(request).await
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for UnconstrainedEngine<ExecutionContext>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        if let Some(constraint) = &request.output_constraint {
+            return Err(reject_output_constraint(constraint));
+        }
+        self.inner.generate(request).await
+    }
+}
+
+//
+// Panics
+//
+
+/// Engine which turns a panic in `inner` into an error for the request that caused it, so one
+/// bad request doesn't take the whole process down. A panic before the first response fails the
+/// request, a panic while streaming ends the stream with an error. Panics on threads or tasks
+/// the engine starts itself are not seen here, those engines must report them on the stream.
+struct PanicSafeEngine<E> {
+    inner: E,
+}
+
+/// Catch panics in an engine which does its own pre-processing
+pub fn make_panic_safe_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(PanicSafeEngine { inner })
+}
+
+/// Catch panics in an engine which takes pre-processed requests
+pub fn make_panic_safe_engine_core(inner: ExecutionContext) -> ExecutionContext {
+    Arc::new(PanicSafeEngine { inner })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for PanicSafeEngine<OpenAIChatCompletionsStreamingEngine>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        panic_safe_generate(&self.inner, request).await
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for PanicSafeEngine<ExecutionContext>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        panic_safe_generate(&self.inner, request).await
+    }
+}
+
+async fn panic_safe_generate<Req: Data, Resp: Data>(
+    inner: &Arc<dyn AsyncEngine<SingleIn<Req>, ManyOut<Annotated<Resp>>, Error>>,
+    request: SingleIn<Req>,
+) -> Result<ManyOut<Annotated<Resp>>, Error> {
+    use futures::FutureExt;
+    use std::panic::AssertUnwindSafe;
+
+    let request_id = request.id().to_string();
+    let mut stream = match AssertUnwindSafe(inner.generate(request))
+        .catch_unwind()
+        .await
+    {
+        Ok(result) => result?,
+        Err(payload) => {
+            let message = panic_message(payload.as_ref());
+            tracing::error!(
+                request_id,
+                "Engine panicked starting the request: {message}"
+            );
+            anyhow::bail!("The engine failed on this request: {message}");
+        }
+    };
+    let ctx = stream.context();
+    let stream_ctx = ctx.clone();
+    let output = stream! {
+        loop {
+            match AssertUnwindSafe(stream.next()).catch_unwind().await {
+                Ok(Some(response)) => yield response,
+                Ok(None) => break,
+                Err(payload) => {
+                    let message = panic_message(payload.as_ref());
+                    tracing::error!(request_id, "Engine panicked during generation: {message}");
+                    stream_ctx.stop_generating();
+                    yield Annotated::from_error(format!(
+                        "The engine failed on this request: {message}"
+                    ));
+                    break;
+                }
+            }
+        }
+    };
+    Ok(ResponseStream::new(Box::pin(output), ctx))
+}
+
+/// The message a panic was raised with, if it has one
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+//
+// Seed
+//
+
+/// Engine which gives requests which don't set a `seed` this one, for reproducible sampling
+struct SeedEngine<E> {
+    inner: E,
+    seed: i64,
+}
+
+/// Default the seed for an engine which does its own pre-processing
+pub fn make_seed_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+    seed: i64,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(SeedEngine { inner, seed })
+}
+
+/// Default the seed for an engine which takes pre-processed requests
+pub fn make_seed_engine_core(inner: ExecutionContext, seed: i64) -> ExecutionContext {
+    Arc::new(SeedEngine { inner, seed })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for SeedEngine<OpenAIChatCompletionsStreamingEngine>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (mut request, context) = request.into_parts();
+        request.inner.seed.get_or_insert(self.seed);
+        self.inner.generate(context.map(|_| request)).await
+    }
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for SeedEngine<ExecutionContext>
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let (mut request, context) = request.into_parts();
+        request.sampling_options.seed.get_or_insert(self.seed);
+        self.inner.generate(context.map(|_| request)).await
+    }
+}
+
+/// For engines which can't seed their sampling: say the first time a request asks for a seed that
+/// it is ignored, rather than on every request. Each engine has its own `warned`.
+pub fn warn_seed_ignored(warned: &Once, engine: &str) {
+    warned.call_once(|| {
+        tracing::warn!("The {engine} engine does not support seeding, ignoring the seed");
+    });
+}
+
+//
+// Coalescing
+//
+
+/// Engine which runs identical deterministic requests only once while the first of them is in
+/// flight, and streams the one response to each of them. It is not a cache: a request arriving
+/// after the first one finished runs again.
+struct CoalescingEngine<Req: Data, R: Data> {
+    inner: ServerStreamingEngine<Req, Annotated<R>>,
+    /// What identifies requests which may share a response, None for requests which may not
+    key: fn(&Req) -> Option<String>,
+    flights: Arc<Flights<R>>,
+}
+
+/// Coalesce requests to an engine which does its own pre-processing
+pub fn make_coalescing_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(CoalescingEngine {
+        inner,
+        key: |request: &NvCreateChatCompletionRequest| {
+            let deterministic =
+                request.inner.temperature == Some(0.0) && request.inner.n.unwrap_or(1) == 1;
+            deterministic.then(|| serde_json::to_string(request).ok())?
+        },
+        flights: Arc::new(Flights::default()),
+    })
+}
+
+/// Coalesce requests to an engine which takes pre-processed requests
+pub fn make_coalescing_engine_core(inner: ExecutionContext) -> ExecutionContext {
+    Arc::new(CoalescingEngine {
+        inner,
+        key: |request: &BackendInput| {
+            let sampling = &request.sampling_options;
+            let deterministic = sampling.temperature == Some(0.0) && sampling.n.unwrap_or(1) == 1;
+            deterministic.then(|| serde_json::to_string(request).ok())?
+        },
+        flights: Arc::new(Flights::default()),
+    })
+}
+
+#[async_trait]
+impl<Req: Data, R: Data + Clone> AsyncEngine<SingleIn<Req>, ManyOut<Annotated<R>>, Error>
+    for CoalescingEngine<Req, R>
+{
+    async fn generate(&self, request: SingleIn<Req>) -> Result<ManyOut<Annotated<R>>, Error> {
+        let Some(key) = (self.key)(&request) else {
+            return self.inner.generate(request).await;
+        };
+        let (request, context) = request.into_parts();
+        let ctx = context.context();
+        let (flight, receiver, is_first) = self.flights.join(&key);
+        if is_first {
+            // The run has a context of its own, so that one of the clients going away doesn't
+            // stop it for the others
+            let run = Context::with_id(request, ctx.id().to_string());
+            match self.inner.generate(run).await {
+                Ok(stream) => {
+                    tokio::spawn(fan_out(self.flights.clone(), key, flight, stream));
+                }
+                Err(err) => {
+                    flight
+                        .lock()
+                        .unwrap()
+                        .send(Annotated::from_error(format!("{err:#}")));
+                    self.flights.finish(&key, &flight);
+                    return Err(err);
+                }
+            }
+        } else {
+            tracing::debug!(
+                request_id = ctx.id(),
+                "Sharing the response of an identical request in flight"
+            );
+        }
+        let stream = tokio_stream::wrappers::UnboundedReceiverStream::new(receiver);
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Send everything `stream` produces to the requests waiting on `flight`
+async fn fan_out<R: Data + Clone>(
+    flights: Arc<Flights<R>>,
+    key: String,
+    flight: Arc<Mutex<Flight<R>>>,
+    mut stream: ManyOut<Annotated<R>>,
+) {
+    while let Some(item) = stream.next().await {
+        if !flight.lock().unwrap().send(item) {
+            // Every client went away
+            stream.context().stop_generating();
+            break;
+        }
+    }
+    flights.finish(&key, &flight);
+}
+
+/// The runs in flight, by request key
+struct Flights<R>(Mutex<HashMap<String, Arc<Mutex<Flight<R>>>>>);
+
+impl<R> Default for Flights<R> {
+    fn default() -> Self {
+        Flights(Mutex::new(HashMap::new()))
+    }
+}
+
+impl<R: Clone> Flights<R> {
+    /// Wait on the run for `key`, or start one if there is none. The bool is true if we started
+    /// it, and so must now call the engine.
+    fn join(
+        &self,
+        key: &str,
+    ) -> (
+        Arc<Mutex<Flight<R>>>,
+        mpsc::UnboundedReceiver<Annotated<R>>,
+        bool,
+    ) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut flights = self.0.lock().unwrap();
+        if let Some(flight) = flights.get(key) {
+            let mut joined = flight.lock().unwrap();
+            if !joined.done {
+                // Catch up with what the others already have
+                for item in &joined.sent {
+                    let _ = sender.send(item.clone());
+                }
+                joined.waiting.push(sender);
+                return (flight.clone(), receiver, false);
+            }
+        }
+        let flight = Arc::new(Mutex::new(Flight {
+            sent: Vec::new(),
+            waiting: vec![sender],
+            done: false,
+        }));
+        flights.insert(key.to_string(), flight.clone());
+        (flight, receiver, true)
+    }
 
-use crate::backend::ExecutionContext;
-use crate::preprocessor::BackendInput;
-use crate::protocols::common::llm_backend::LLMEngineOutput;
-use crate::protocols::openai::chat_completions::{
-    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
-};
-use crate::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+    /// Close `flight` to new requests and end the streams of those waiting on it
+    fn finish(&self, key: &str, flight: &Arc<Mutex<Flight<R>>>) {
+        let mut flights = self.0.lock().unwrap();
+        if flights.get(key).is_some_and(|f| Arc::ptr_eq(f, flight)) {
+            flights.remove(key);
+        }
+        let mut flight = flight.lock().unwrap();
+        flight.done = true;
+        flight.waiting.clear();
+        flight.sent.clear();
+    }
+}
+
+/// One run of the engine, and the requests waiting on it
+struct Flight<R> {
+    /// Everything sent so far, for requests which join late
+    sent: Vec<Annotated<R>>,
+    waiting: Vec<mpsc::UnboundedSender<Annotated<R>>>,
+    /// Requests may no longer join
+    done: bool,
+}
+
+impl<R: Clone> Flight<R> {
+    /// Send `item` to every request waiting. False if none are left.
+    fn send(&mut self, item: Annotated<R>) -> bool {
+        self.waiting
+            .retain(|sender| sender.send(item.clone()).is_ok());
+        self.sent.push(item);
+        if self.waiting.is_empty() {
+            self.done = true;
+        }
+        !self.done
+    }
+}
 
 //
-// The engines are each in their own crate under `lib/engines`
+// Timeout
 //
 
-#[derive(Debug, Clone)]
-pub struct MultiNodeConfig {
-    /// How many nodes / hosts we are using
-    pub num_nodes: u32,
-    /// Unique consecutive integer to identify this node
-    pub node_rank: u32,
-    /// host:port of head / control node
-    pub leader_addr: String,
+/// End `stream` with a "request timed out" error, and stop the engine, if the engine goes
+/// `timeout` without sending anything. The clock starts again with every response, so a long
+/// generation which keeps producing tokens is never cut short, only a stalled one.
+pub fn with_inter_token_timeout<T: Data>(
+    mut stream: ManyOut<Annotated<T>>,
+    timeout: Duration,
+) -> ManyOut<Annotated<T>> {
+    let ctx = stream.context();
+    let stream_ctx = ctx.clone();
+    let output = stream! {
+        loop {
+            match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(Some(response)) => yield response,
+                Ok(None) => break,
+                Err(_) => {
+                    tracing::warn!(
+                        request_id = stream_ctx.id(),
+                        "No response from the engine in {timeout:?}, stopping the request"
+                    );
+                    stream_ctx.stop_generating();
+                    yield Annotated::from_error("request timed out".to_string());
+                    break;
+                }
+            }
+        }
+    };
+    ResponseStream::new(Box::pin(output), ctx)
 }
 
-impl Default for MultiNodeConfig {
-    fn default() -> Self {
-        MultiNodeConfig {
-            num_nodes: 1,
-            node_rank: 0,
-            leader_addr: "".to_string(),
+//
+// Prompt prefix and suffix
+//
+
+/// Engine which puts fixed text around the content of the last user message before `inner` sees
+/// it. For engines which do their own pre-processing, where we can't reach the rendered prompt.
+struct PromptWrappingEngine {
+    inner: OpenAIChatCompletionsStreamingEngine,
+    prefix: String,
+    suffix: String,
+}
+
+pub fn make_prompt_wrapping_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+    prefix: Option<String>,
+    suffix: Option<String>,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(PromptWrappingEngine {
+        inner,
+        prefix: prefix.unwrap_or_default(),
+        suffix: suffix.unwrap_or_default(),
+    })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for PromptWrappingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (mut request, context) = request.into_parts();
+        wrap_last_user_message(&mut request, &self.prefix, &self.suffix);
+        self.inner.generate(context.map(|_| request)).await
+    }
+}
+
+/// Put `prefix` and `suffix` around the content of the request's last user message. Content in
+/// parts gets them as text parts of their own.
+pub fn wrap_last_user_message(
+    request: &mut NvCreateChatCompletionRequest,
+    prefix: &str,
+    suffix: &str,
+) {
+    use async_openai::types::{
+        ChatCompletionRequestMessage, ChatCompletionRequestMessageContentPartText,
+        ChatCompletionRequestUserMessageContent, ChatCompletionRequestUserMessageContentPart,
+    };
+    for message in request.inner.messages.iter_mut().rev() {
+        let ChatCompletionRequestMessage::User(user_message) = message else {
+            continue;
+        };
+        match &mut user_message.content {
+            ChatCompletionRequestUserMessageContent::Text(text) => {
+                *text = format!("{prefix}{text}{suffix}");
+            }
+            ChatCompletionRequestUserMessageContent::Array(parts) => {
+                let text_part = |text: &str| {
+                    ChatCompletionRequestUserMessageContentPart::Text(
+                        ChatCompletionRequestMessageContentPartText {
+                            text: text.to_string(),
+                        },
+                    )
+                };
+                if !prefix.is_empty() {
+                    parts.insert(0, text_part(prefix));
+                }
+                if !suffix.is_empty() {
+                    parts.push(text_part(suffix));
+                }
+            }
+        }
+        return;
+    }
+}
+
+//
+// Completions
+//
+
+/// First wait before retrying a request [`make_retrying_engine_full`] couldn't send. It doubles
+/// with every retry, up to [`RETRY_MAX_DELAY`].
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Longest wait between two retries of a request
+pub const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Engine which retries requests `inner` failed to send because of the transport, for example
+/// because the remote worker went away, waiting longer each time. Nothing has been generated
+/// yet when those fail, so it's safe to try again. Other errors are returned straight away.
+struct RetryingEngine {
+    inner: OpenAIChatCompletionsStreamingEngine,
+    max_retries: u32,
+    base_delay: Duration,
+}
+
+/// Retry requests `inner` couldn't send up to `max_retries` times. A dynamic client's list of
+/// endpoints follows etcd, so a restarted or rescheduled worker is found again in between.
+pub fn make_retrying_engine_full(
+    inner: OpenAIChatCompletionsStreamingEngine,
+    max_retries: u32,
+) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(RetryingEngine {
+        inner,
+        max_retries,
+        base_delay: RETRY_BASE_DELAY,
+    })
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for RetryingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.into_parts();
+        let request_id = context.id().to_string();
+        let caller = context.context();
+        // The first attempt keeps the caller's context, retries get a new one with the same id
+        let mut attempt = context.map(|_| request.clone());
+        let mut delay = self.base_delay;
+        for retry in 1..=self.max_retries {
+            match self.inner.generate(attempt).await {
+                Err(err) if is_transport_error(&err) => {
+                    tracing::warn!(
+                        request_id,
+                        retry,
+                        max_retries = self.max_retries,
+                        "Could not reach the engine, retrying in {delay:?}: {err:#}"
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = caller.stopped() => {
+                            return Err(err.context("Request stopped before it could be retried"));
+                        }
+                    }
+                    delay = (delay * 2).min(RETRY_MAX_DELAY);
+                    attempt = Context::with_id(request.clone(), request_id.clone());
+                }
+                Ok(stream) if retry > 1 => return Ok(linked(stream, caller)),
+                result => return result,
+            }
+        }
+        let stream = self.inner.generate(attempt).await?;
+        Ok(if self.max_retries > 0 {
+            linked(stream, caller)
+        } else {
+            stream
+        })
+    }
+}
+
+/// Pass a stop or kill of the caller's context on to the context of a retry, which has its own,
+/// for as long as the retry's responses are read.
+fn linked<T: Data>(stream: ManyOut<T>, caller: Arc<dyn AsyncEngineContext>) -> ManyOut<T> {
+    let ctx = stream.context();
+    let retry = ctx.clone();
+    let output = stream! {
+        let mut stream = stream;
+        let (mut stopped, mut killed) = (false, false);
+        loop {
+            let response = tokio::select! {
+                response = stream.next() => response,
+                _ = caller.stopped(), if !stopped => {
+                    stopped = true;
+                    retry.stop();
+                    continue;
+                }
+                _ = caller.killed(), if !killed => {
+                    killed = true;
+                    retry.kill();
+                    continue;
+                }
+            };
+            match response {
+                Some(response) => yield response,
+                None => break,
+            }
         }
+    };
+    ResponseStream::new(Box::pin(output), ctx)
+}
+
+/// Did the request fail before it reached the engine? No endpoint being up, no one answering
+/// on the request subject, or the response stream never connecting.
+fn is_transport_error(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<PipelineError>(),
+        Some(
+            PipelineError::NoEndpoints(_)
+                | PipelineError::ControlPlaneRequestError(_)
+                | PipelineError::ConnectionFailed(_)
+                | PipelineError::DetatchedStreamReceiver
+        )
+    )
+}
+
+/// Engine which serves completions requests from a chat engine, for engines which only
+/// understand chat. The prompt is sent as a single user message, so the chat engine's prompt
+/// template is applied to it.
+struct ChatAsCompletionsEngine {
+    inner: OpenAIChatCompletionsStreamingEngine,
+}
+pub fn make_completions_engine(
+    inner: OpenAIChatCompletionsStreamingEngine,
+) -> OpenAICompletionsStreamingEngine {
+    Arc::new(ChatAsCompletionsEngine { inner })
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionResponse>>, Error>
+    for ChatAsCompletionsEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<CompletionRequest>,
+    ) -> Result<ManyOut<Annotated<CompletionResponse>>, Error> {
+        let (request, context) = request.into_parts();
+        let request = context.map(|_| chat_from_completion(request));
+        let stream = self.inner.generate(request).await?;
+        let ctx = stream.context();
+        let stream =
+            stream.map(|response| response.map_data(|data| Ok(completion_from_chat(data))));
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// The chat request equivalent to a completions request. `echo`, `logprobs`, `best_of` and
+/// `suffix` have no chat equivalent and are dropped.
+fn chat_from_completion(request: CompletionRequest) -> NvCreateChatCompletionRequest {
+    let request_inner = request.inner;
+    let message = async_openai::types::ChatCompletionRequestMessage::User(
+        async_openai::types::ChatCompletionRequestUserMessage {
+            content: async_openai::types::ChatCompletionRequestUserMessageContent::Text(
+                prompt_to_string(&request_inner.prompt),
+            ),
+            name: None,
+        },
+    );
+    let inner = async_openai::types::CreateChatCompletionRequest {
+        messages: vec![message],
+        model: request_inner.model,
+        max_completion_tokens: request_inner.max_tokens,
+        temperature: request_inner.temperature,
+        top_p: request_inner.top_p,
+        n: request_inner.n,
+        stream: request_inner.stream,
+        stream_options: request_inner.stream_options,
+        stop: request_inner.stop,
+        presence_penalty: request_inner.presence_penalty,
+        frequency_penalty: request_inner.frequency_penalty,
+        logit_bias: request_inner.logit_bias,
+        user: request_inner.user,
+        seed: request_inner.seed,
+        ..Default::default()
+    };
+    NvCreateChatCompletionRequest {
+        inner,
+        nvext: request.nvext,
+        service_tier: None,
+        stop_token_ids: None,
+    }
+}
+
+/// The completions chunk equivalent to a chat chunk
+fn completion_from_chat(response: NvCreateChatCompletionStreamResponse) -> CompletionResponse {
+    let response = response.inner;
+    let choices = response
+        .choices
+        .into_iter()
+        .map(|choice| CompletionChoice {
+            text: choice.delta.content.unwrap_or_default(),
+            index: choice.index as u64,
+            finish_reason: choice.finish_reason.map(|reason| {
+                match reason {
+                    async_openai::types::FinishReason::Stop => "stop",
+                    async_openai::types::FinishReason::Length => "length",
+                    async_openai::types::FinishReason::ToolCalls => "tool_calls",
+                    async_openai::types::FinishReason::ContentFilter => "content_filter",
+                    async_openai::types::FinishReason::FunctionCall => "function_call",
+                }
+                .to_string()
+            }),
+            logprobs: None,
+        })
+        .collect();
+    CompletionResponse {
+        id: response.id,
+        choices,
+        created: response.created as u64,
+        model: response.model,
+        object: "text_completion".to_string(),
+        usage: response.usage.map(|usage| CompletionUsage {
+            completion_tokens: usage.completion_tokens as i32,
+            prompt_tokens: usage.prompt_tokens as i32,
+            total_tokens: usage.total_tokens as i32,
+            completion_tokens_details: None,
+            prompt_tokens_details: None,
+        }),
+        system_fingerprint: response.system_fingerprint,
     }
 }
 
@@ -61,7 +1176,7 @@ impl Default for MultiNodeConfig {
 // Example echo engines
 //
 
-/// How long to sleep between echoed tokens.
+/// How long to sleep between echoed tokens, the default for [`EchoDelays`].
 /// Default is 10ms which gives us 100 tok/s.
 /// Can be configured via the DYN_TOKEN_ECHO_DELAY_MS environment variable.
 pub static TOKEN_ECHO_DELAY: LazyLock<Duration> = LazyLock::new(|| {
@@ -75,12 +1190,51 @@ pub static TOKEN_ECHO_DELAY: LazyLock<Duration> = LazyLock::new(|| {
     Duration::from_millis(delay_ms)
 });
 
+/// How long the echo engines wait before each echoed token. Setting them makes the echo engines
+/// a deterministic stand-in for a slow model, to test time to first token and timeout handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EchoDelays {
+    /// Before the first token, as if processing the prompt
+    pub first_token: Duration,
+    /// Before every later token
+    pub between_tokens: Duration,
+}
+
+impl Default for EchoDelays {
+    fn default() -> Self {
+        EchoDelays {
+            first_token: *TOKEN_ECHO_DELAY,
+            between_tokens: *TOKEN_ECHO_DELAY,
+        }
+    }
+}
+
+impl EchoDelays {
+    /// The delay before the `index`th token, counting from 0
+    fn before(&self, index: usize) -> Duration {
+        if index == 0 {
+            self.first_token
+        } else {
+            self.between_tokens
+        }
+    }
+}
+
 /// Engine that accepts pre-processed requests and echos the tokens back as the response
 /// The response will include the full prompt template.
 /// Useful for testing pre-processing.
-struct EchoEngineCore {}
+struct EchoEngineCore {
+    delays: EchoDelays,
+    seed_ignored: Once,
+}
 pub fn make_engine_core() -> ExecutionContext {
-    Arc::new(EchoEngineCore {})
+    make_engine_core_with_delays(EchoDelays::default())
+}
+pub fn make_engine_core_with_delays(delays: EchoDelays) -> ExecutionContext {
+    Arc::new(EchoEngineCore {
+        delays,
+        seed_ignored: Once::new(),
+    })
 }
 
 #[async_trait]
@@ -93,10 +1247,14 @@ impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Er
     ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
         let (request, context) = incoming_request.into_parts();
         let ctx = context.context();
+        let delays = self.delays;
+        if request.sampling_options.seed.is_some() {
+            warn_seed_ignored(&self.seed_ignored, "echo_core");
+        }
 
         let output = stream! {
-            for tok in request.token_ids {
-                tokio::time::sleep(*TOKEN_ECHO_DELAY).await;
+            for (i, tok) in request.token_ids.into_iter().enumerate() {
+                tokio::time::sleep(delays.before(i)).await;
                 yield delta_core(tok);
             }
             yield Annotated::from_data(LLMEngineOutput::stop());
@@ -119,9 +1277,18 @@ fn delta_core(tok: u32) -> Annotated<LLMEngineOutput> {
 
 /// Engine that accepts un-preprocessed requests and echos the prompt back as the response
 /// Useful for testing ingress such as service-http.
-struct EchoEngineFull {}
+struct EchoEngineFull {
+    delays: EchoDelays,
+    seed_ignored: Once,
+}
 pub fn make_engine_full() -> OpenAIChatCompletionsStreamingEngine {
-    Arc::new(EchoEngineFull {})
+    make_engine_full_with_delays(EchoDelays::default())
+}
+pub fn make_engine_full_with_delays(delays: EchoDelays) -> OpenAIChatCompletionsStreamingEngine {
+    Arc::new(EchoEngineFull {
+        delays,
+        seed_ignored: Once::new(),
+    })
 }
 
 #[async_trait]
@@ -137,6 +1304,9 @@ impl
         incoming_request: SingleIn<NvCreateChatCompletionRequest>,
     ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
         let (request, context) = incoming_request.transfer(());
+        if request.inner.seed.is_some() {
+            warn_seed_ignored(&self.seed_ignored, "echo_full");
+        }
         let deltas = request.response_generator();
         let ctx = context.context();
         let req = request.inner.messages.into_iter().next_back().unwrap();
@@ -153,11 +1323,12 @@ impl
             _ => anyhow::bail!("Invalid request type, expected User message"),
         };
 
+        let delays = self.delays;
         let output = stream! {
             let mut id = 1;
-            for c in prompt.chars() {
+            for (i, c) in prompt.chars().enumerate() {
                 // we are returning characters not tokens, so there will be some postprocessing overhead
-                tokio::time::sleep(*TOKEN_ECHO_DELAY).await;
+                tokio::time::sleep(delays.before(i)).await;
                 let inner = deltas.create_choice(0, Some(c.to_string()), None, None);
                 let response = NvCreateChatCompletionStreamResponse {
                     inner,
@@ -176,3 +1347,702 @@ impl
         Ok(ResponseStream::new(Box::pin(output), ctx))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cuda_visible_devices() {
+        assert_eq!(cuda_visible_devices(2, 2), "2,3");
+        assert_eq!(cuda_visible_devices(0, 1), "0");
+        assert_eq!(cuda_visible_devices(4, 0), "4");
+    }
+
+    #[test]
+    fn test_check_reported_num_nodes() {
+        let conf = MultiNodeConfig {
+            num_nodes: 2,
+            node_rank: 0,
+            leader_addr: "10.0.0.1:6379".to_string(),
+        };
+        conf.check_reported_num_nodes(&[2, 2]).unwrap();
+        // Still waiting for the follower
+        conf.check_reported_num_nodes(&[2]).unwrap();
+
+        // The follower was started with --num-nodes 3
+        let err = conf.check_reported_num_nodes(&[2, 3]).unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("expects 2 nodes"), "{err}");
+        assert!(err.contains("[3]"), "{err}");
+
+        let err = conf.check_reported_num_nodes(&[2, 2, 2]).unwrap_err();
+        assert!(err.to_string().contains("but 3 joined"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_annotation() {
+        use dynamo_runtime::pipeline::Context;
+
+        let engine = make_aggregating_engine(make_engine_full());
+        let request = |annotations: serde_json::Value| {
+            let request: NvCreateChatCompletionRequest =
+                serde_json::from_value(serde_json::json!({
+                    "model": "echo",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "nvext": {"annotations": annotations},
+                }))
+                .unwrap();
+            Context::new(request)
+        };
+
+        // One chunk per character, then the finish reason
+        let stream = engine
+            .generate(request(serde_json::json!([])))
+            .await
+            .unwrap();
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 6);
+
+        let stream = engine
+            .generate(request(serde_json::json!([ANNOTATION_AGGREGATE])))
+            .await
+            .unwrap();
+        let responses = stream.collect::<Vec<_>>().await;
+        assert_eq!(responses.len(), 1);
+        let response = responses[0].data.as_ref().unwrap();
+        let choice = &response.inner.choices[0];
+        assert_eq!(choice.delta.content.as_deref(), Some("hello"));
+        assert_eq!(
+            choice.finish_reason,
+            Some(async_openai::types::FinishReason::Stop)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prompt_wrapping_engine() {
+        use dynamo_runtime::pipeline::Context;
+
+        let engine = make_prompt_wrapping_engine_full(
+            make_engine_full(),
+            Some("<<".to_string()),
+            Some(">>".to_string()),
+        );
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "echo",
+            "messages": [
+                {"role": "user", "content": "first"},
+                {"role": "assistant", "content": "ok"},
+                {"role": "user", "content": "hello"},
+            ],
+        }))
+        .unwrap();
+
+        // The echo engine sends back the last user message, as it received it
+        let stream = engine.generate(Context::new(request)).await.unwrap();
+        let text: String = stream
+            .filter_map(|response| async move { response.data })
+            .flat_map(|data| futures::stream::iter(data.inner.choices))
+            .filter_map(|choice| async move { choice.delta.content })
+            .collect()
+            .await;
+        assert_eq!(text, "<<hello>>");
+    }
+
+    #[tokio::test]
+    async fn test_unconstrained_engine() {
+        use dynamo_runtime::pipeline::Context;
+
+        let engine = make_unconstrained_engine_full(make_engine_full());
+        let request = |response_format: serde_json::Value| {
+            let request: NvCreateChatCompletionRequest =
+                serde_json::from_value(serde_json::json!({
+                    "model": "echo",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "response_format": response_format,
+                }))
+                .unwrap();
+            Context::new(request)
+        };
+
+        let Err(err) = engine
+            .generate(request(serde_json::json!({"type": "json_object"})))
+            .await
+        else {
+            panic!("json_object should be rejected");
+        };
+        let err = err.downcast::<HttpError>().unwrap();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("json_object"), "{}", err.message);
+
+        // Plain text is what the engine does anyway
+        engine
+            .generate(request(serde_json::json!({"type": "text"})))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_wrap_last_user_message_parts() {
+        let mut request: NvCreateChatCompletionRequest =
+            serde_json::from_value(serde_json::json!({
+                "model": "echo",
+                "messages": [
+                    {"role": "user", "content": [{"type": "text", "text": "hello"}]},
+                    {"role": "system", "content": "Be brief."},
+                ],
+            }))
+            .unwrap();
+        wrap_last_user_message(&mut request, "", "!");
+
+        let request = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            request["messages"][0]["content"],
+            serde_json::json!([
+                {"type": "text", "text": "hello"},
+                {"type": "text", "text": "!"},
+            ])
+        );
+        assert_eq!(request["messages"][1]["content"], "Be brief.");
+    }
+
+    /// Fails the first `failures` requests with `error`, then echoes
+    struct FlakyEngine {
+        failures: u32,
+        error: fn() -> Error,
+        calls: std::sync::atomic::AtomicU32,
+    }
+
+    #[async_trait]
+    impl
+        AsyncEngine<
+            SingleIn<NvCreateChatCompletionRequest>,
+            ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+            Error,
+        > for FlakyEngine
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<NvCreateChatCompletionRequest>,
+        ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+            let calls = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if calls < self.failures {
+                return Err((self.error)());
+            }
+            make_engine_full().generate(request).await
+        }
+    }
+
+    fn no_endpoints() -> Error {
+        PipelineError::NoEndpoints("ns/components/backend/generate".to_string()).into()
+    }
+
+    fn chat_request(content: &str) -> NvCreateChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "echo",
+            "messages": [{"role": "user", "content": content}],
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retrying_engine() {
+        let run = |failures: u32, error: fn() -> Error, max_retries: u32| async move {
+            let flaky = Arc::new(FlakyEngine {
+                failures,
+                error,
+                calls: Default::default(),
+            });
+            let engine = RetryingEngine {
+                inner: flaky.clone(),
+                max_retries,
+                base_delay: Duration::from_millis(1),
+            };
+            let result = engine
+                .generate(Context::with_id(chat_request("hi"), "req-1".to_string()))
+                .await
+                .map(|stream| stream.context().id().to_string());
+            (
+                result,
+                flaky.calls.load(std::sync::atomic::Ordering::SeqCst),
+            )
+        };
+
+        // The worker came back before we ran out of retries. It keeps the request id.
+        let (result, calls) = run(2, no_endpoints, 3).await;
+        assert_eq!(result.unwrap(), "req-1");
+        assert_eq!(calls, 3);
+
+        // It didn't
+        let (result, calls) = run(5, no_endpoints, 3).await;
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<PipelineError>(),
+            Some(PipelineError::NoEndpoints(_))
+        ));
+        assert_eq!(calls, 4);
+
+        // Retrying is off by default
+        let (result, calls) = run(1, no_endpoints, 0).await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        // No one answered on the request subject
+        let no_responders = || -> Error {
+            PipelineError::ControlPlaneRequestError("no responders".to_string()).into()
+        };
+        let (result, calls) = run(1, no_responders, 3).await;
+        assert!(result.is_ok());
+        assert_eq!(calls, 2);
+
+        // Not a transport problem, so not retried
+        let (result, calls) = run(1, || anyhow::anyhow!("invalid request"), 3).await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        // Only the pipeline's own errors count, not what an error happens to say
+        let (result, calls) = run(1, || anyhow::anyhow!("model load timed out"), 3).await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retrying_engine_stopped() {
+        let flaky = Arc::new(FlakyEngine {
+            failures: u32::MAX,
+            error: no_endpoints,
+            calls: Default::default(),
+        });
+        let engine = Arc::new(RetryingEngine {
+            inner: flaky.clone(),
+            max_retries: 100,
+            base_delay: Duration::from_secs(60),
+        });
+        let request = Context::new(chat_request("hi"));
+        let caller = request.context();
+        let generate = tokio::spawn({
+            let engine = engine.clone();
+            async move { engine.generate(request).await.map(|_| ()) }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Stopping the request ends the wait for the next retry
+        caller.stop_generating();
+        let result = tokio::time::timeout(Duration::from_secs(5), generate)
+            .await
+            .expect("retry loop kept going after the caller stopped")
+            .unwrap();
+        assert!(result.unwrap_err().to_string().contains("stopped"));
+        assert_eq!(flaky.calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retrying_engine_passes_stop_to_retry() {
+        let engine = RetryingEngine {
+            inner: Arc::new(FlakyEngine {
+                failures: 1,
+                error: no_endpoints,
+                calls: Default::default(),
+            }),
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+        };
+        let request = Context::new(chat_request(&"x".repeat(1000)));
+        let caller = request.context();
+        let mut stream = engine.generate(request).await.unwrap();
+        let retry = stream.context();
+        assert!(!retry.is_stopped());
+
+        // The retry has a context of its own, the caller's stop reaches it through the stream
+        caller.stop_generating();
+        while !retry.is_stopped() {
+            if stream.next().await.is_none() {
+                break;
+            }
+        }
+        assert!(retry.is_stopped());
+    }
+
+    #[tokio::test]
+    async fn test_completions_engine() {
+        use dynamo_runtime::pipeline::Context;
+
+        let engine = make_completions_engine(make_engine_full());
+        // echo and logprobs have no chat equivalent, but are accepted
+        let request: CompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "echo",
+            "prompt": "hello",
+            "echo": true,
+            "logprobs": 1,
+        }))
+        .unwrap();
+
+        let stream = engine.generate(Context::new(request)).await.unwrap();
+        let responses = stream
+            .map(|response| response.data.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        let text: String = responses
+            .iter()
+            .flat_map(|response| response.choices.iter())
+            .map(|choice| choice.text.as_str())
+            .collect();
+        assert_eq!(text, "hello");
+        let last = responses.last().unwrap();
+        assert_eq!(last.object, "text_completion");
+        assert_eq!(last.choices[0].finish_reason.as_deref(), Some("stop"));
+    }
+
+    #[tokio::test]
+    async fn test_paced_engine() {
+        use dynamo_runtime::pipeline::Context;
+        use std::time::Instant;
+
+        const MAX_TPS: u32 = 20;
+        // Echoed back one character per chunk, 10ms apart, so 100 tokens per second unpaced
+        let prompt = "abcdefghijklmnopqrstu";
+        let request = || {
+            let request: NvCreateChatCompletionRequest =
+                serde_json::from_value(serde_json::json!({
+                    "model": "echo",
+                    "messages": [{"role": "user", "content": prompt}],
+                }))
+                .unwrap();
+            Context::new(request)
+        };
+
+        let engine = make_paced_engine_full(make_engine_full(), MAX_TPS);
+        let start = Instant::now();
+        let stream = engine.generate(request()).await.unwrap();
+        let responses = stream.collect::<Vec<_>>().await;
+        let elapsed = start.elapsed();
+
+        // Nothing dropped or merged
+        assert_eq!(responses.len(), prompt.len() + 1);
+        // The first token goes out right away, then one every 1/MAX_TPS seconds
+        let expected = Duration::from_secs(1) * (prompt.len() as u32 - 1) / MAX_TPS;
+        assert!(elapsed >= expected, "{elapsed:?} < {expected:?}");
+        assert!(
+            elapsed < expected + Duration::from_millis(300),
+            "{elapsed:?} is too slow for {expected:?}"
+        );
+    }
+
+    #[test]
+    fn test_max_tokens_limits() {
+        let limits = MaxTokensLimits {
+            default: Some(100),
+            max: Some(1000),
+            reject_over_max: false,
+        };
+        assert_eq!(limits.apply(None).unwrap(), Some(100));
+        assert_eq!(limits.apply(Some(10)).unwrap(), Some(10));
+        assert_eq!(limits.apply(Some(5000)).unwrap(), Some(1000));
+
+        // The cap applies to requests which don't ask, and to the default
+        let cap_only = MaxTokensLimits {
+            default: None,
+            ..limits
+        };
+        assert_eq!(cap_only.apply(None).unwrap(), Some(1000));
+        let big_default = MaxTokensLimits {
+            default: Some(2000),
+            ..limits
+        };
+        assert_eq!(big_default.apply(None).unwrap(), Some(1000));
+
+        assert_eq!(MaxTokensLimits::default().apply(None).unwrap(), None);
+        assert!(!MaxTokensLimits::default().is_set());
+
+        let strict = MaxTokensLimits {
+            reject_over_max: true,
+            ..limits
+        };
+        assert_eq!(strict.apply(Some(1000)).unwrap(), Some(1000));
+        let err = strict.apply(Some(1001)).unwrap_err();
+        assert_eq!(err.downcast_ref::<HttpError>().unwrap().code, 400);
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_engine_server_cap() {
+        use dynamo_runtime::pipeline::Context;
+
+        /// Always runs out of tokens
+        struct LengthEngine;
+
+        #[async_trait]
+        impl
+            AsyncEngine<
+                SingleIn<NvCreateChatCompletionRequest>,
+                ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+                Error,
+            > for LengthEngine
+        {
+            async fn generate(
+                &self,
+                request: SingleIn<NvCreateChatCompletionRequest>,
+            ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error>
+            {
+                let (request, context) = request.transfer(());
+                let deltas = request.response_generator();
+                let output = [
+                    deltas.create_choice(0, Some("a".to_string()), None, None),
+                    deltas.create_choice(
+                        0,
+                        None,
+                        Some(async_openai::types::FinishReason::Length),
+                        None,
+                    ),
+                ]
+                .map(|inner| Annotated::from_data(NvCreateChatCompletionStreamResponse { inner }));
+                Ok(ResponseStream::new(
+                    Box::pin(futures::stream::iter(output)),
+                    context.context(),
+                ))
+            }
+        }
+
+        let engine = make_max_tokens_engine_full(
+            Arc::new(LengthEngine),
+            MaxTokensLimits {
+                max: Some(10),
+                ..Default::default()
+            },
+        );
+        let stop_causes = |max_tokens: u32| {
+            let engine = engine.clone();
+            async move {
+                let request: NvCreateChatCompletionRequest =
+                    serde_json::from_value(serde_json::json!({
+                        "model": "length",
+                        "messages": [{"role": "user", "content": "hello"}],
+                        "max_tokens": max_tokens,
+                    }))
+                    .unwrap();
+                let stream = engine.generate(Context::new(request)).await.unwrap();
+                let responses: Vec<_> = stream.collect().await;
+                responses
+                    .iter()
+                    .position(|response| response.event.as_deref() == Some(ANNOTATION_STOP_CAUSE))
+                    .map(|position| {
+                        // Ahead of the response which ends generation
+                        assert!(responses[position + 1].data.is_some());
+                        let comment = responses[position].comment.as_ref().unwrap();
+                        serde_json::from_str::<StopCause>(&comment[0]).unwrap()
+                    })
+            }
+        };
+
+        // Lowered to our cap, so the engine stopping there is ours
+        assert_eq!(stop_causes(100).await, Some(StopCause::ServerCap));
+        // Within the cap, the engine's length is the client's max_tokens
+        assert_eq!(stop_causes(5).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_seed_engine() {
+        use crate::protocols::common::{SamplingOptions, StopConditions};
+        use dynamo_runtime::pipeline::Context;
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        /// Samples random tokens, seeded from the request if it has a seed
+        struct SamplingEngine;
+
+        #[async_trait]
+        impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+            for SamplingEngine
+        {
+            async fn generate(
+                &self,
+                request: SingleIn<BackendInput>,
+            ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+                let (request, context) = request.into_parts();
+                let mut rng = match request.sampling_options.seed {
+                    Some(seed) => StdRng::seed_from_u64(seed as u64),
+                    None => StdRng::from_os_rng(),
+                };
+                let output = (0..16)
+                    .map(|_| delta_core(rng.random_range(0..32_000)))
+                    .collect::<Vec<_>>();
+                Ok(ResponseStream::new(
+                    Box::pin(futures::stream::iter(output)),
+                    context.context(),
+                ))
+            }
+        }
+
+        let engine = make_seed_engine_core(Arc::new(SamplingEngine), 42);
+        let generate = |seed: Option<i64>| {
+            let request = BackendInput::builder()
+                .token_ids(vec![1, 2, 3])
+                .stop_conditions(StopConditions::default())
+                .sampling_options(SamplingOptions {
+                    seed,
+                    temperature: Some(0.8),
+                    ..Default::default()
+                })
+                .build()
+                .unwrap();
+            let engine = engine.clone();
+            async move {
+                let stream = engine.generate(Context::new(request)).await.unwrap();
+                stream
+                    .flat_map(|output| futures::stream::iter(output.data.unwrap().token_ids))
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+
+        // The engine's seed makes runs reproducible
+        let first = generate(None).await;
+        assert_eq!(first, generate(None).await);
+        assert_eq!(first, generate(Some(42)).await);
+
+        // A request's own seed wins
+        let own_seed = generate(Some(7)).await;
+        assert_ne!(first, own_seed);
+        assert_eq!(own_seed, generate(Some(7)).await);
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_engine() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Counts the requests reaching the echo engine
+        struct CountingEngine {
+            inner: OpenAIChatCompletionsStreamingEngine,
+            runs: Arc<AtomicUsize>,
+        }
+
+        #[async_trait]
+        impl
+            AsyncEngine<
+                SingleIn<NvCreateChatCompletionRequest>,
+                ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+                Error,
+            > for CountingEngine
+        {
+            async fn generate(
+                &self,
+                request: SingleIn<NvCreateChatCompletionRequest>,
+            ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error>
+            {
+                self.runs.fetch_add(1, Ordering::SeqCst);
+                self.inner.generate(request).await
+            }
+        }
+
+        let runs = Arc::new(AtomicUsize::new(0));
+        let engine = make_coalescing_engine_full(Arc::new(CountingEngine {
+            inner: make_engine_full(),
+            runs: runs.clone(),
+        }));
+        // Echoed back one character every 10ms, so still in flight when the second one arrives
+        let generate = |temperature: f32| {
+            let request: NvCreateChatCompletionRequest =
+                serde_json::from_value(serde_json::json!({
+                    "model": "echo",
+                    "messages": [{"role": "user", "content": "hello"}],
+                    "temperature": temperature,
+                }))
+                .unwrap();
+            let engine = engine.clone();
+            async move {
+                let stream = engine.generate(Context::new(request)).await.unwrap();
+                stream
+                    .filter_map(|response| async move { response.data })
+                    .filter_map(|data| async move { data.inner.choices[0].delta.content.clone() })
+                    .collect::<String>()
+                    .await
+            }
+        };
+
+        let (first, second) = tokio::join!(generate(0.0), generate(0.0));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(first, "hello");
+        assert_eq!(second, "hello");
+
+        // The first one finished, so this runs again
+        assert_eq!(generate(0.0).await, "hello");
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        // Sampled requests each get a response of their own
+        tokio::join!(generate(0.7), generate(0.7));
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_echo_delays() {
+        use dynamo_runtime::pipeline::Context;
+        use std::time::Instant;
+
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "echo",
+            "messages": [{"role": "user", "content": "abcde"}],
+        }))
+        .unwrap();
+        let delays = EchoDelays {
+            first_token: Duration::from_millis(200),
+            between_tokens: Duration::from_millis(50),
+        };
+
+        let start = Instant::now();
+        let mut stream = make_engine_full_with_delays(delays)
+            .generate(Context::new(request))
+            .await
+            .unwrap();
+        stream.next().await.unwrap();
+        let first_token = start.elapsed();
+        let rest = stream.collect::<Vec<_>>().await;
+        let total = start.elapsed();
+
+        // the four other characters, then the finish reason
+        assert_eq!(rest.len(), 5);
+        assert!(first_token >= delays.first_token, "{first_token:?}");
+        assert!(first_token < Duration::from_millis(250), "{first_token:?}");
+        let expected = delays.first_token + delays.between_tokens * 4;
+        assert!(total >= expected, "{total:?} < {expected:?}");
+        assert!(
+            total < expected + Duration::from_millis(100),
+            "{total:?} is too slow for {expected:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_inter_token_timeout() {
+        use dynamo_runtime::pipeline::Context;
+
+        // Echoed back one character per chunk, 10ms apart, so it takes longer than the timeout
+        // in total but never stalls for it
+        let prompt = "abcdefghijklmnopqrstu";
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "echo",
+            "messages": [{"role": "user", "content": prompt}],
+        }))
+        .unwrap();
+        let stream = make_engine_full()
+            .generate(Context::new(request))
+            .await
+            .unwrap();
+        let responses = with_inter_token_timeout(stream, Duration::from_millis(100))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(responses.len(), prompt.len() + 1);
+        assert!(responses.iter().all(|response| !response.is_error()));
+
+        // One response, then nothing
+        let ctx = Context::new(()).context();
+        let stalled = futures::stream::iter([Annotated::from_data("first".to_string())])
+            .chain(futures::stream::pending());
+        let stream = ResponseStream::new(Box::pin(stalled), ctx.clone());
+        let responses = with_inter_token_timeout(stream, Duration::from_millis(50))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[0].data.as_deref(), Some("first"));
+        assert!(responses[1].is_error());
+        assert_eq!(
+            responses[1].comment,
+            Some(vec!["request timed out".to_string()])
+        );
+        assert!(ctx.is_stopped());
+    }
+}
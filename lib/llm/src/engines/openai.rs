@@ -0,0 +1,205 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Engine which sends chat completions requests on to an OpenAI compatible HTTP server, such as
+//! vllm's own server, TGI, or OpenAI itself, and streams its response back. It lets dynamo front
+//! engines it doesn't run.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_stream::stream;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures::StreamExt;
+use tokio_util::codec::Decoder;
+
+use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+
+use crate::protocols::codec::{Message, SseLineCodec};
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+};
+use crate::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+
+struct OpenAIEngine {
+    /// Shared by all requests, it holds the connection pool
+    client: reqwest::Client,
+    /// `{base_url}/v1/chat/completions`
+    url: String,
+    api_key: Option<String>,
+}
+
+/// Connections to the server kept open between requests, by default
+pub const DEFAULT_UPSTREAM_POOL_SIZE: usize = 32;
+
+/// How long an unused connection to the server is kept open, by default
+pub const DEFAULT_UPSTREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// The connections the engine keeps open to the server, so requests don't each pay for a new one
+/// (and a TLS handshake, for hosted servers).
+#[derive(Debug, Clone, Copy)]
+pub struct UpstreamPool {
+    /// Most connections kept open while idle. More are opened when there are more requests at
+    /// once, then closed when those finish. 0 opens a new connection for every request.
+    pub max_idle: usize,
+
+    /// Close connections which have been idle for this long
+    pub idle_timeout: Duration,
+}
+
+impl Default for UpstreamPool {
+    fn default() -> Self {
+        UpstreamPool {
+            max_idle: DEFAULT_UPSTREAM_POOL_SIZE,
+            idle_timeout: DEFAULT_UPSTREAM_IDLE_TIMEOUT,
+        }
+    }
+}
+
+/// An engine for the server at `base_url`, e.g. `http://localhost:8000` for vllm or
+/// `https://api.openai.com`. The `api_key`, if any, is sent as a bearer token.
+pub fn make_engine(
+    base_url: &str,
+    api_key: Option<String>,
+) -> anyhow::Result<OpenAIChatCompletionsStreamingEngine> {
+    make_engine_with_pool(base_url, api_key, UpstreamPool::default())
+}
+
+/// [`make_engine`] with its own connection pool settings
+pub fn make_engine_with_pool(
+    base_url: &str,
+    api_key: Option<String>,
+    pool: UpstreamPool,
+) -> anyhow::Result<OpenAIChatCompletionsStreamingEngine> {
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(pool.max_idle)
+        .pool_idle_timeout(pool.idle_timeout)
+        .build()?;
+    let url = format!("{}/v1/chat/completions", base_url.trim_end_matches('/'));
+    Ok(Arc::new(OpenAIEngine {
+        client,
+        url,
+        api_key,
+    }))
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for OpenAIEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (mut request, context) = request.into_parts();
+        let ctx = context.context();
+        request.inner.stream = Some(true);
+        // Our extensions, which other servers would reject or ignore
+        request.nvext = None;
+
+        let mut upstream = self.client.post(&self.url).json(&request);
+        if let Some(api_key) = &self.api_key {
+            upstream = upstream.bearer_auth(api_key);
+        }
+        let url = self.url.clone();
+        let stream_ctx = ctx.clone();
+        let output = stream! {
+            let response = match upstream.send().await {
+                Ok(response) => response,
+                Err(err) => {
+                    yield Annotated::from_error(format!("Request to {url} failed: {err}"));
+                    return;
+                }
+            };
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                yield Annotated::from_error(format!("{url} returned {status}: {body}"));
+                return;
+            }
+
+            let mut body = response.bytes_stream();
+            let mut codec = SseLineCodec::new();
+            let mut buffer = BytesMut::new();
+            loop {
+                let chunk = tokio::select! {
+                    chunk = body.next() => chunk,
+                    // Dropping the response closes the connection, which stops the server
+                    _ = stream_ctx.stopped() => break,
+                };
+                match chunk {
+                    Some(Ok(bytes)) => buffer.extend_from_slice(&bytes),
+                    Some(Err(err)) => {
+                        yield Annotated::from_error(format!("Reading the response from {url}: {err}"));
+                        return;
+                    }
+                    None => break,
+                }
+                loop {
+                    match codec.decode(&mut buffer) {
+                        Ok(Some(message)) => {
+                            if let Some(response) = response_from_message(message) {
+                                yield response;
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(err) => {
+                            yield Annotated::from_error(format!("Invalid response from {url}: {err}"));
+                            return;
+                        }
+                    }
+                }
+            }
+            if let Ok(Some(message)) = codec.decode_eof(&mut buffer) {
+                if let Some(response) = response_from_message(message) {
+                    yield response;
+                }
+            }
+        };
+        Ok(ResponseStream::new(Box::pin(output), ctx))
+    }
+}
+
+/// The response in one SSE message from the server. None for messages without one, such as
+/// keep-alive comments.
+fn response_from_message(
+    message: Message,
+) -> Option<Annotated<NvCreateChatCompletionStreamResponse>> {
+    if message.data.is_none() && message.event.as_deref() != Some("error") {
+        return None;
+    }
+    // OpenAI reports errors part way through as `data: {"error": {"message": ...}}`
+    if let Some(error) = message
+        .data
+        .as_deref()
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .and_then(|data| data.get("error").cloned())
+    {
+        let error = error
+            .get("message")
+            .and_then(|message| message.as_str())
+            .map(|message| message.to_string())
+            .unwrap_or_else(|| error.to_string());
+        return Some(Annotated::from_error(error));
+    }
+    Some(Annotated::try_from(message).unwrap_or_else(Annotated::from_error))
+}
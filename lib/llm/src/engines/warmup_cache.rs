@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! On-disk cache for engine warmup artifacts, such as compiled kernels and captured CUDA graphs,
+//! so that restarting with the same model and configuration skips that work.
+//!
+//! Each model and configuration gets a directory named after a hash of the two. When the
+//! configuration changes the hash changes, and the model's directories for older configurations
+//! are deleted.
+
+use std::path::{Path, PathBuf};
+
+use dynamo_runtime::slug::Slug;
+use serde::Serialize;
+
+/// Written once the artifacts are complete, so a crash during warmup doesn't leave a cache that
+/// looks usable.
+const WARM_MARKER: &str = ".warm";
+
+/// Hex characters of the configuration hash kept in the directory name
+const HASH_LEN: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct WarmupCache {
+    dir: PathBuf,
+}
+
+impl WarmupCache {
+    /// The cache for `model` run with `config`, in a directory under `root`. Created if needed.
+    pub fn open<C: Serialize>(root: &Path, model: &str, config: &C) -> anyhow::Result<Self> {
+        let config = serde_json::to_string(config)?;
+        let hash = blake3::hash(format!("{model}\n{config}").as_bytes()).to_hex();
+        // Slugs never contain '-', so this can't match another model's directories
+        let prefix = format!("{}-", Slug::from_string(model));
+        let name = format!("{prefix}{}", &hash[..HASH_LEN]);
+
+        std::fs::create_dir_all(root)?;
+        for entry in std::fs::read_dir(root)? {
+            let entry = entry?;
+            let entry_name = entry.file_name().to_string_lossy().to_string();
+            let is_older_config = entry_name != name
+                && entry_name
+                    .strip_prefix(&prefix)
+                    .is_some_and(|hash| hash.len() == HASH_LEN);
+            if is_older_config && entry.path().is_dir() {
+                tracing::info!(
+                    "Configuration changed, removing warmup cache {}",
+                    entry.path().display()
+                );
+                std::fs::remove_dir_all(entry.path())?;
+            }
+        }
+
+        let dir = root.join(name);
+        std::fs::create_dir_all(&dir)?;
+        Ok(WarmupCache { dir })
+    }
+
+    /// Where the engine should keep its artifacts
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A previous run completed warmup with this model and configuration
+    pub fn is_warm(&self) -> bool {
+        self.dir.join(WARM_MARKER).exists()
+    }
+
+    /// Call once the engine has finished warming up and written its artifacts
+    pub fn mark_warm(&self) -> anyhow::Result<()> {
+        std::fs::write(self.dir.join(WARM_MARKER), b"")?;
+        Ok(())
+    }
+
+    /// Environment variables which send the torch, triton and vllm compilation caches into
+    /// this cache. Set them before starting the engine.
+    pub fn env_vars(&self) -> Vec<(&'static str, PathBuf)> {
+        vec![
+            ("VLLM_CACHE_ROOT", self.dir.join("vllm")),
+            ("TORCHINDUCTOR_CACHE_DIR", self.dir.join("torchinductor")),
+            ("TRITON_CACHE_DIR", self.dir.join("triton")),
+        ]
+    }
+}
@@ -101,10 +101,12 @@ impl Content {
                     .to_string()
                     .context("Model metadata should have declared an architecture")
                     .and_then(GGUFArchitecture::from_value)
-                    .unwrap(),
+                    .map_err(candle_core::Error::msg)?,
             );
         }
-        let arch = arch.expect("GGUF files must specify `general.architecture`");
+        let Some(arch) = arch else {
+            candle_core::bail!("GGUF files must specify `general.architecture`");
+        };
 
         let mut all_metadata = HashMap::new();
         for content in &contents {
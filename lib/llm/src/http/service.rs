@@ -32,10 +32,20 @@
 
 mod openai;
 
+pub mod admin;
+pub mod debug;
 pub mod discovery;
 pub mod error;
+pub mod health;
+pub mod listener;
 pub mod metrics;
+pub mod metrics_export;
+pub mod response_cache;
+pub mod sampling_profiles;
+pub mod scheduler;
 pub mod service_v2;
+pub mod tee;
+pub mod version;
 
 // #[cfg(feature = "py3")]
 // pub mod py3;
@@ -45,15 +55,37 @@ pub use axum;
 pub use error::ServiceHttpError;
 pub use metrics::Metrics;
 
+use crate::model_card::model::ModelDeploymentCard;
+use crate::preprocessor::OpenAIPreprocessor;
 use crate::types::openai::{
     chat_completions::OpenAIChatCompletionsStreamingEngine,
-    completions::OpenAICompletionsStreamingEngine,
+    completions::OpenAICompletionsStreamingEngine, embeddings::OpenAIEmbeddingsEngine,
 };
+use admin::ActiveRequests;
+use response_cache::ResponseCache;
+use sampling_profiles::SamplingProfiles;
+use scheduler::{PriorityScheduler, SchedulerPermit};
+use serde::Serialize;
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
+use tee::TeeEvent;
+use tokio::sync::mpsc;
+
+/// How often to check whether in-flight requests have finished while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Default time between `event: prefill` events for clients which ask for them
+pub const DEFAULT_PREFILL_PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Default cap on the `n` request parameter: one choice per request, unless the engine is known
+/// to support more
+pub const DEFAULT_MAX_N: u8 = 1;
 
 #[derive(Clone)]
 pub struct ModelManager {
@@ -68,7 +100,15 @@ impl Default for ModelManager {
 
 impl ModelManager {
     pub fn new() -> Self {
-        let state = Arc::new(DeploymentState::new());
+        let state = Arc::new(DeploymentState::new(None));
+        Self { state }
+    }
+
+    /// At most `max_concurrent_requests` requests are sent to the engines at once, the others
+    /// wait in a priority queue.
+    pub fn with_max_concurrent_requests(max_concurrent_requests: usize) -> Self {
+        let scheduler = PriorityScheduler::new(max_concurrent_requests);
+        let state = Arc::new(DeploymentState::new(Some(scheduler)));
         Self { state }
     }
 
@@ -88,6 +128,12 @@ impl ModelManager {
                 .lock()
                 .unwrap()
                 .contains(model)
+            || self
+                .state
+                .embeddings_engines
+                .lock()
+                .unwrap()
+                .contains(model)
     }
 
     pub fn list_chat_completions_models(&self) -> Vec<String> {
@@ -98,6 +144,10 @@ impl ModelManager {
         self.state.completion_engines.lock().unwrap().list()
     }
 
+    pub fn list_embeddings_models(&self) -> Vec<String> {
+        self.state.embeddings_engines.lock().unwrap().list()
+    }
+
     pub fn add_completions_model(
         &self,
         model: &str,
@@ -116,26 +166,290 @@ impl ModelManager {
         clients.add(model, engine)
     }
 
+    pub fn add_embeddings_model(
+        &self,
+        model: &str,
+        engine: OpenAIEmbeddingsEngine,
+    ) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.embeddings_engines.lock().unwrap();
+        clients.add(model, engine)
+    }
+
+    /// Serve `model` with `engine` to clients which send `X-Engine: <engine_name>`, for example
+    /// to compare two backends. The first engine added for a model also serves the requests
+    /// without the header.
+    pub fn add_named_completions_model(
+        &self,
+        model: &str,
+        engine_name: &str,
+        engine: OpenAICompletionsStreamingEngine,
+    ) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.completion_engines.lock().unwrap();
+        clients.add_named(model, engine_name, engine)
+    }
+
+    /// See [`ModelManager::add_named_completions_model`]
+    pub fn add_named_chat_completions_model(
+        &self,
+        model: &str,
+        engine_name: &str,
+        engine: OpenAIChatCompletionsStreamingEngine,
+    ) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.chat_completion_engines.lock().unwrap();
+        clients.add_named(model, engine_name, engine)
+    }
+
     pub fn remove_completions_model(&self, model: &str) -> Result<(), ServiceHttpError> {
         let mut clients = self.state.completion_engines.lock().unwrap();
-        clients.remove(model)
+        clients.remove(model)?;
+        drop(clients);
+        self.forget_model_if_gone(model);
+        Ok(())
     }
 
     pub fn remove_chat_completions_model(&self, model: &str) -> Result<(), ServiceHttpError> {
         let mut clients = self.state.chat_completion_engines.lock().unwrap();
-        clients.remove(model)
+        clients.remove(model)?;
+        drop(clients);
+        self.forget_model_if_gone(model);
+        Ok(())
+    }
+
+    pub fn remove_embeddings_model(&self, model: &str) -> Result<(), ServiceHttpError> {
+        let mut clients = self.state.embeddings_engines.lock().unwrap();
+        clients.remove(model)?;
+        drop(clients);
+        self.forget_model_if_gone(model);
+        Ok(())
+    }
+
+    /// Details reported for this model in `/v1/models`
+    pub fn set_model_metadata(&self, model: &str, metadata: ModelMetadata) {
+        self.state
+            .model_metadata
+            .lock()
+            .unwrap()
+            .models
+            .insert(model.to_string(), metadata);
+    }
+
+    /// Details reported in `/v1/models` for models without their own metadata, for example
+    /// models discovered at runtime. Its `default_max_tokens` applies to models which don't
+    /// have their own.
+    pub fn set_default_model_metadata(&self, metadata: ModelMetadata) {
+        self.state.model_metadata.lock().unwrap().default = metadata;
+    }
+
+    /// `max_tokens` for requests to this model which don't set it, in place of the server-wide
+    /// default
+    pub fn set_model_default_max_tokens(&self, model: &str, max_tokens: u32) {
+        let mut metadata = self.state.model_metadata.lock().unwrap();
+        let default = metadata.default.clone();
+        metadata
+            .models
+            .entry(model.to_string())
+            .or_insert(default)
+            .default_max_tokens = Some(max_tokens);
+    }
+
+    /// Requests for this model are admitted ahead of requests for lower priority models when the
+    /// number of concurrent requests is limited. Added to the request's own `service_tier`
+    /// priority. Models default to 0.
+    pub fn set_model_priority(&self, model: &str, priority: i32) {
+        self.state
+            .model_priorities
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), priority);
+    }
+
+    /// The pre-processor in front of this model's engine, if we do the pre-processing. Used to
+    /// count prompt tokens in `/v1/tokenize-count`.
+    pub fn set_model_preprocessor(&self, model: &str, preprocessor: Arc<OpenAIPreprocessor>) {
+        self.state
+            .preprocessors
+            .lock()
+            .unwrap()
+            .insert(model.to_string(), preprocessor);
+    }
+
+    /// Report this `system_fingerprint` in every response, in place of the engine's, so clients
+    /// can pin to a known backend revision.
+    pub fn set_system_fingerprint(&self, system_fingerprint: Option<String>) {
+        *self.state.system_fingerprint.lock().unwrap() = system_fingerprint;
+    }
+
+    /// Accept numbers given as strings, and integers given as floats, in chat completions
+    /// requests, see `NvCreateChatCompletionRequest::from_lenient_json`
+    pub fn set_lenient_numbers(&self, lenient: bool) {
+        self.state.lenient_numbers.store(lenient, Ordering::Relaxed);
+    }
+
+    /// Serve requests naming a model we don't have with the one we do, when we serve only one
+    /// model of that kind. Otherwise they get a 404 `model_not_found`.
+    pub fn set_allow_any_model(&self, allow: bool) {
+        self.state.allow_any_model.store(allow, Ordering::Relaxed);
+    }
+
+    /// Repair trailing commas and unclosed strings, arrays and objects in the output of chat
+    /// requests with `response_format` `json_object` or `json_schema`. Non-streaming responses
+    /// get the repaired content, streaming ones a `json_repair` annotation at the end.
+    pub fn set_repair_json(&self, repair: bool) {
+        self.state.repair_json.store(repair, Ordering::Relaxed);
+    }
+
+    /// Fail requests whose engine ends the stream without a finish reason. Otherwise they are
+    /// given `finish_reason: stop`, and if the engine sent no chunks at all, an empty one with a
+    /// `warning` annotation before it.
+    pub fn set_strict_finish_reason(&self, strict: bool) {
+        self.state
+            .strict_finish_reason
+            .store(strict, Ordering::Relaxed);
+    }
+
+    /// End requests whose engine sends nothing for `timeout` with an error, and stop them. See
+    /// [`crate::engines::with_inter_token_timeout`].
+    pub fn set_request_timeout(&self, timeout: Option<Duration>) {
+        *self.state.request_timeout.lock().unwrap() = timeout;
+    }
+
+    /// Send streamed chunks with more than `max_chars` characters of content as several events.
+    /// None or 0 sends them as the engine made them.
+    pub fn set_max_chunk_chars(&self, max_chars: Option<usize>) {
+        *self.state.max_chunk_chars.lock().unwrap() = max_chars.filter(|max_chars| *max_chars > 0);
+    }
+
+    /// Reject requests asking for more than `max_n` choices with a 400
+    pub fn set_max_n(&self, max_n: u8) {
+        self.state.max_n.store(max_n, Ordering::Relaxed);
+    }
+
+    /// Copy the responses of requests with `X-Tee: true` to `sink`. Without a sink the header is
+    /// ignored.
+    pub fn set_tee_sink(&self, sink: Option<mpsc::Sender<TeeEvent>>) {
+        *self.state.tee_sink.lock().unwrap() = sink;
+    }
+
+    /// Profiles which requests can pick with `X-Sampling-Profile: <name>`, replacing any set
+    /// before
+    pub fn set_sampling_profiles(&self, profiles: SamplingProfiles) {
+        *self.state.sampling_profiles.lock().unwrap() = profiles;
+    }
+
+    /// Return the prompt's token ids to requests with `X-Debug-Token-Ids: true`
+    pub fn set_debug_token_ids(&self, enabled: bool) {
+        self.state.debug_token_ids.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Keep the responses to this many greedy, non-streaming chat completions requests, see
+    /// [`ResponseCache`]. 0 disables the cache.
+    pub fn set_response_cache_size(&self, size: usize) {
+        self.state.response_cache.set_capacity(size);
+    }
+
+    /// How often streaming requests with `X-Prefill-Progress: true` get an `event: prefill` while
+    /// waiting for the first response
+    pub fn set_prefill_progress_interval(&self, interval: Duration) {
+        *self.state.prefill_progress_interval.lock().unwrap() = interval;
+    }
+
+    /// Parse request bodies as JSON whatever their `Content-Type`, instead of rejecting anything
+    /// but `application/json` with a 415.
+    pub fn set_lenient_content_type(&self, lenient: bool) {
+        self.state
+            .lenient_content_type
+            .store(lenient, Ordering::Relaxed);
+    }
+
+    fn forget_model_if_gone(&self, model: &str) {
+        if !self.has_model_any(model) {
+            self.state
+                .model_metadata
+                .lock()
+                .unwrap()
+                .models
+                .remove(model);
+            self.state.preprocessors.lock().unwrap().remove(model);
+        }
     }
 
     /// Get the Prometheus [`Metrics`] object which tracks request counts and inflight requests
     pub fn metrics(&self) -> Arc<Metrics> {
         self.state.metrics.clone()
     }
+
+    /// The requests in flight, listed by `/admin/requests`
+    pub fn active_requests(&self) -> ActiveRequests {
+        self.state.active_requests.clone()
+    }
+}
+
+/// Model details for clients, listed in `/v1/models`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ModelMetadata {
+    /// Maximum number of tokens, prompt plus output, the model handles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<usize>,
+
+    /// Maximum number of tokens the model will generate for one request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<usize>,
+
+    /// `max_tokens` for requests which don't set it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_max_tokens: Option<u32>,
+}
+
+impl ModelMetadata {
+    /// The context length of the model described by this card
+    pub async fn from_card(card: &ModelDeploymentCard) -> anyhow::Result<Self> {
+        let model_info = card.model_info.get_model_info().await?;
+        Ok(ModelMetadata {
+            context_length: Some(model_info.max_position_embeddings()),
+            max_output_tokens: None,
+            default_max_tokens: None,
+        })
+    }
+
+    /// Use the fields set in `other` in place of ours
+    pub fn merge(self, other: ModelMetadata) -> Self {
+        ModelMetadata {
+            context_length: other.context_length.or(self.context_length),
+            max_output_tokens: other.max_output_tokens.or(self.max_output_tokens),
+            default_max_tokens: other.default_max_tokens.or(self.default_max_tokens),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ModelMetadataMap {
+    default: ModelMetadata,
+    models: HashMap<String, ModelMetadata>,
+}
+
+impl ModelMetadataMap {
+    fn get(&self, model: &str) -> ModelMetadata {
+        self.models
+            .get(model)
+            .cloned()
+            .unwrap_or_else(|| self.default.clone())
+    }
+
+    /// The model's own default `max_tokens`, or the server-wide one
+    fn default_max_tokens(&self, model: &str) -> Option<u32> {
+        self.models
+            .get(model)
+            .and_then(|metadata| metadata.default_max_tokens)
+            .or(self.default.default_max_tokens)
+    }
 }
 
 struct ModelEngines<E> {
     /// Optional default model name
     default: Option<String>,
     engines: HashMap<String, E>,
+    /// Engines for a model which clients pick by name with the `X-Engine` header
+    named: HashMap<String, HashMap<String, E>>,
 }
 
 impl<E> Default for ModelEngines<E> {
@@ -143,6 +457,7 @@ impl<E> Default for ModelEngines<E> {
         Self {
             default: None,
             engines: HashMap::new(),
+            named: HashMap::new(),
         }
     }
 }
@@ -166,10 +481,33 @@ impl<E> ModelEngines<E> {
         Ok(())
     }
 
+    /// The first engine added for a model also serves the requests which don't name one
+    fn add_named(
+        &mut self,
+        model: &str,
+        engine_name: &str,
+        engine: E,
+    ) -> Result<(), ServiceHttpError>
+    where
+        E: Clone,
+    {
+        let named = self.named.entry(model.to_string()).or_default();
+        if named.contains_key(engine_name) {
+            return Err(ServiceHttpError::EngineAlreadyExists(
+                model.to_string(),
+                engine_name.to_string(),
+            ));
+        }
+        named.insert(engine_name.to_string(), engine.clone());
+        self.engines.entry(model.to_string()).or_insert(engine);
+        Ok(())
+    }
+
     fn remove(&mut self, model: &str) -> Result<(), ServiceHttpError> {
         if self.engines.remove(model).is_none() {
             return Err(ServiceHttpError::ModelNotFound(model.to_string()));
         }
+        self.named.remove(model);
         Ok(())
     }
 
@@ -177,10 +515,33 @@ impl<E> ModelEngines<E> {
         self.engines.get(model)
     }
 
+    fn get_named(&self, model: &str, engine_name: &str) -> Option<&E> {
+        self.named.get(model)?.get(engine_name)
+    }
+
+    /// The names clients can pick `model`'s engines by, sorted
+    fn engine_names(&self, model: &str) -> Vec<String> {
+        let mut names = self
+            .named
+            .get(model)
+            .map(|named| named.keys().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
     fn contains(&self, model: &str) -> bool {
         self.engines.contains_key(model)
     }
 
+    /// The model's name if there is exactly one
+    fn only_model(&self) -> Option<&str> {
+        match self.engines.len() {
+            1 => self.engines.keys().next().map(|model| model.as_str()),
+            _ => None,
+        }
+    }
+
     fn list(&self) -> Vec<String> {
         self.engines.keys().map(|k| k.to_owned()).collect()
     }
@@ -191,30 +552,134 @@ impl<E> ModelEngines<E> {
 pub struct DeploymentState {
     completion_engines: Arc<Mutex<ModelEngines<OpenAICompletionsStreamingEngine>>>,
     chat_completion_engines: Arc<Mutex<ModelEngines<OpenAIChatCompletionsStreamingEngine>>>,
+    embeddings_engines: Arc<Mutex<ModelEngines<OpenAIEmbeddingsEngine>>>,
     metrics: Arc<Metrics>,
+    active_requests: ActiveRequests,
     sse_keep_alive: Option<Duration>,
+    scheduler: Option<Arc<PriorityScheduler>>,
+    model_metadata: Mutex<ModelMetadataMap>,
+    model_priorities: Mutex<HashMap<String, i32>>,
+    preprocessors: Mutex<HashMap<String, Arc<OpenAIPreprocessor>>>,
+    system_fingerprint: Mutex<Option<String>>,
+    lenient_content_type: AtomicBool,
+    lenient_numbers: AtomicBool,
+    strict_finish_reason: AtomicBool,
+    allow_any_model: AtomicBool,
+    repair_json: AtomicBool,
+    max_n: AtomicU8,
+    request_timeout: Mutex<Option<Duration>>,
+    max_chunk_chars: Mutex<Option<usize>>,
+    debug_token_ids: AtomicBool,
+    tee_sink: Mutex<Option<mpsc::Sender<TeeEvent>>>,
+    sampling_profiles: Mutex<SamplingProfiles>,
+    response_cache: ResponseCache,
+    draining: AtomicBool,
+    prefill_progress_interval: Mutex<Duration>,
 }
 
 impl DeploymentState {
-    fn new() -> Self {
+    fn new(scheduler: Option<Arc<PriorityScheduler>>) -> Self {
         Self {
             completion_engines: Arc::new(Mutex::new(ModelEngines::default())),
             chat_completion_engines: Arc::new(Mutex::new(ModelEngines::default())),
+            embeddings_engines: Arc::new(Mutex::new(ModelEngines::default())),
             metrics: Arc::new(Metrics::default()),
+            active_requests: ActiveRequests::default(),
             sse_keep_alive: None,
+            scheduler,
+            model_metadata: Mutex::new(ModelMetadataMap::default()),
+            model_priorities: Mutex::new(HashMap::new()),
+            preprocessors: Mutex::new(HashMap::new()),
+            system_fingerprint: Mutex::new(None),
+            lenient_content_type: AtomicBool::new(false),
+            lenient_numbers: AtomicBool::new(false),
+            strict_finish_reason: AtomicBool::new(false),
+            allow_any_model: AtomicBool::new(false),
+            repair_json: AtomicBool::new(false),
+            max_n: AtomicU8::new(DEFAULT_MAX_N),
+            request_timeout: Mutex::new(None),
+            max_chunk_chars: Mutex::new(None),
+            debug_token_ids: AtomicBool::new(false),
+            tee_sink: Mutex::new(None),
+            sampling_profiles: Mutex::new(SamplingProfiles::new()),
+            response_cache: ResponseCache::new(0),
+            draining: AtomicBool::new(false),
+            prefill_progress_interval: Mutex::new(DEFAULT_PREFILL_PROGRESS_INTERVAL),
         }
     }
 
-    fn get_completions_engine(
-        &self,
-        model: &str,
-    ) -> Result<OpenAICompletionsStreamingEngine, ServiceHttpError> {
-        self.completion_engines
+    /// Refuse new requests, and wait up to `timeout` for the in-flight ones to finish
+    async fn drain(&self, timeout: Duration) {
+        self.draining.store(true, Ordering::Relaxed);
+        let in_flight = self.metrics.get_inflight_total();
+        if in_flight == 0 {
+            return;
+        }
+        tracing::info!("Draining {in_flight} in-flight requests");
+        let finished = async {
+            while self.metrics.get_inflight_total() > 0 {
+                tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+            }
+        };
+        if tokio::time::timeout(timeout, finished).await.is_err() {
+            tracing::warn!(
+                "{} requests still in flight after draining for {timeout:?}, stopping anyway",
+                self.metrics.get_inflight_total()
+            );
+        }
+    }
+
+    /// With `allow_any_model`, send requests for a model we don't serve to the one we do, if
+    /// there is only one. `model` becomes its name.
+    fn substitute_model<E>(&self, engines: &Mutex<ModelEngines<E>>, model: &mut String) {
+        if !self.allow_any_model.load(Ordering::Relaxed) {
+            return;
+        }
+        let engines = engines.lock().unwrap();
+        if engines.contains(model) {
+            return;
+        }
+        if let Some(only) = engines.only_model() {
+            tracing::trace!(
+                requested = model.as_str(),
+                served = only,
+                "Substituting model"
+            );
+            *model = only.to_string();
+        }
+    }
+
+    fn default_max_tokens(&self, model: &str) -> Option<u32> {
+        self.model_metadata
+            .lock()
+            .unwrap()
+            .default_max_tokens(model)
+    }
+
+    fn model_priority(&self, model: &str) -> i32 {
+        self.model_priorities
             .lock()
             .unwrap()
             .get(model)
-            .cloned()
-            .ok_or(ServiceHttpError::ModelNotFound(model.to_string()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn prefill_progress_interval(&self) -> Duration {
+        *self.prefill_progress_interval.lock().unwrap()
+    }
+
+    fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Wait until the request may run, if the number of concurrent requests is limited.
+    /// The request runs until the permit is dropped.
+    async fn admit(&self, priority: i32) -> Option<SchedulerPermit> {
+        match &self.scheduler {
+            Some(scheduler) => Some(scheduler.acquire(priority).await),
+            None => None,
+        }
     }
 
     fn get_chat_completions_engine(
@@ -228,6 +693,10 @@ impl DeploymentState {
             .cloned()
             .ok_or(ServiceHttpError::ModelNotFound(model.to_string()))
     }
+
+    fn get_preprocessor(&self, model: &str) -> Option<Arc<OpenAIPreprocessor>> {
+        self.preprocessors.lock().unwrap().get(model).cloned()
+    }
 }
 
 /// Documentation for a route
@@ -0,0 +1,286 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Endpoints for operators, guarded by an API key sent as `Authorization: Bearer <key>`.
+//!
+//! `GET /admin/config` returns the configuration the process resolved at startup, so it's possible
+//! to confirm what a running deployment actually parsed. Values of fields which look like secrets
+//! (`*_key`, `*_token`, `*_secret`, `*_password`) are replaced with `"<redacted>"`.
+//!
+//! `GET /admin/requests` lists the requests in flight, oldest first, with how long they have been
+//! running and how many tokens they produced so far. Useful to find stuck requests.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    routing::get,
+    Json, Router,
+};
+
+use dynamo_runtime::engine::{AsyncEngineContextProvider, Data, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::Serialize;
+
+use super::RouteDoc;
+
+/// Replaces the values of secret fields
+pub const REDACTED: &str = "<redacted>";
+
+/// Field name parts which mark the value as a secret
+const SECRET_WORDS: &[&str] = &["key", "token", "secret", "password"];
+
+/// The configuration reported by `/admin/config`. Cheap to clone, clones share state.
+#[derive(Clone, Default)]
+pub struct ResolvedConfig {
+    state: Arc<Mutex<serde_json::Value>>,
+}
+
+impl ResolvedConfig {
+    /// Set the configuration to report. It is stored redacted.
+    pub fn set(&self, mut config: serde_json::Value) {
+        redact_secrets(&mut config);
+        *self.state.lock().unwrap() = config;
+    }
+
+    pub fn get(&self) -> serde_json::Value {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// Replace the value of every field whose name looks like a secret, at any depth. A field is a
+/// secret if one of the `_` or `-` separated words in its name is "key", "token", "secret" or
+/// "password", so `admin_api_key` is redacted but `max_tokens` isn't. Unset (null) fields are
+/// left alone, so it's still visible that they weren't set.
+pub fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                if is_secret(name) && !value.is_null() {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+pub(crate) fn is_secret(name: &str) -> bool {
+    name.split(['_', '-'])
+        .any(|word| SECRET_WORDS.contains(&word.to_ascii_lowercase().as_str()))
+}
+
+/// The requests currently being served. Cheap to clone, clones share state.
+#[derive(Clone, Default)]
+pub struct ActiveRequests {
+    requests: Arc<Mutex<HashMap<String, ActiveEntry>>>,
+}
+
+struct ActiveEntry {
+    model: String,
+    started: Instant,
+    tokens: Arc<AtomicU64>,
+}
+
+/// A request in flight, as listed by `/admin/requests`
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRequest {
+    pub id: String,
+    pub model: String,
+    pub elapsed_ms: u64,
+    /// Responses with data sent so far, one per token for most engines
+    pub tokens: u64,
+}
+
+impl ActiveRequests {
+    /// Track a request until the returned guard is dropped
+    pub fn register(&self, id: &str, model: &str) -> ActiveRequestGuard {
+        let tokens = Arc::new(AtomicU64::new(0));
+        self.requests.lock().unwrap().insert(
+            id.to_string(),
+            ActiveEntry {
+                model: model.to_string(),
+                started: Instant::now(),
+                tokens: tokens.clone(),
+            },
+        );
+        ActiveRequestGuard {
+            requests: self.clone(),
+            id: id.to_string(),
+            tokens,
+        }
+    }
+
+    /// The requests in flight, oldest first
+    pub fn list(&self) -> Vec<ActiveRequest> {
+        let requests = self.requests.lock().unwrap();
+        let mut active = requests
+            .iter()
+            .map(|(id, entry)| ActiveRequest {
+                id: id.clone(),
+                model: entry.model.clone(),
+                elapsed_ms: entry.started.elapsed().as_millis() as u64,
+                tokens: entry.tokens.load(Ordering::Relaxed),
+            })
+            .collect::<Vec<_>>();
+        active.sort_by(|a, b| b.elapsed_ms.cmp(&a.elapsed_ms).then(a.id.cmp(&b.id)));
+        active
+    }
+}
+
+/// RAII object for a request listed in [`ActiveRequests`], it's removed when this is dropped
+pub struct ActiveRequestGuard {
+    requests: ActiveRequests,
+    id: String,
+    tokens: Arc<AtomicU64>,
+}
+
+impl ActiveRequestGuard {
+    /// Count the responses of `stream` as they go by. The request stays listed until the stream
+    /// is dropped.
+    pub fn track<T: Data>(self, stream: ManyOut<Annotated<T>>) -> ManyOut<Annotated<T>> {
+        let ctx = stream.context();
+        let stream = stream.inspect(move |response| {
+            if response.data.is_some() {
+                self.tokens.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        ResponseStream::new(Box::pin(stream), ctx)
+    }
+}
+
+impl Drop for ActiveRequestGuard {
+    fn drop(&mut self) {
+        self.requests.requests.lock().unwrap().remove(&self.id);
+    }
+}
+
+#[derive(Clone)]
+struct AdminState {
+    config: ResolvedConfig,
+    api_key: Arc<String>,
+}
+
+#[derive(Clone)]
+struct RequestsState {
+    requests: ActiveRequests,
+    api_key: Arc<String>,
+}
+
+/// Create an Axum [`Router`] for the resolved configuration, readable with `api_key`.
+/// If no path is provided, the default path is `/admin/config`
+pub fn config_router(
+    config: ResolvedConfig,
+    api_key: String,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/admin/config".to_string());
+    let doc = RouteDoc::new(axum::http::Method::GET, &path);
+    let state = AdminState {
+        config,
+        api_key: Arc::new(api_key),
+    };
+    let router = Router::new()
+        .route(&path, get(config_handler))
+        .with_state(state);
+    (vec![doc], router)
+}
+
+/// Create an Axum [`Router`] listing the requests in flight, readable with `api_key`.
+/// If no path is provided, the default path is `/admin/requests`
+pub fn requests_router(
+    requests: ActiveRequests,
+    api_key: String,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/admin/requests".to_string());
+    let doc = RouteDoc::new(axum::http::Method::GET, &path);
+    let state = RequestsState {
+        requests,
+        api_key: Arc::new(api_key),
+    };
+    let router = Router::new()
+        .route(&path, get(requests_handler))
+        .with_state(state);
+    (vec![doc], router)
+}
+
+async fn config_handler(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    check_api_key(&headers, &state.api_key)?;
+    Ok(Json(state.config.get()))
+}
+
+async fn requests_handler(
+    State(state): State<RequestsState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ActiveRequest>>, StatusCode> {
+    check_api_key(&headers, &state.api_key)?;
+    Ok(Json(state.requests.list()))
+}
+
+/// 401 unless the request presents `api_key` as `Authorization: Bearer <key>`
+fn check_api_key(headers: &HeaderMap, api_key: &str) -> Result<(), StatusCode> {
+    let presented = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(key) if constant_time_eq(key.as_bytes(), api_key.as_bytes()) => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Compare without returning early, so response times don't reveal how much of the key matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        let mut config = serde_json::json!({
+            "model_name": "foo",
+            "admin_api_key": "hunter2",
+            "max_tokens": 10,
+            "hf_token": null,
+            "nested": [{"db-password": "p"}],
+        });
+        redact_secrets(&mut config);
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "model_name": "foo",
+                "admin_api_key": REDACTED,
+                "max_tokens": 10,
+                "hf_token": null,
+                "nested": [{"db-password": REDACTED}],
+            })
+        );
+    }
+}
@@ -0,0 +1,63 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `GET /debug/headers` returns the headers of the request as the server received them, to find
+//! out what proxies and load balancers in front of it add, drop or rewrite.
+//!
+//! Credentials are not echoed: the values of `Authorization`, `Proxy-Authorization`, `Cookie`
+//! and of headers whose name looks like a secret (`X-Api-Key`, ...) are replaced with
+//! `"<redacted>"`.
+
+use std::collections::BTreeMap;
+
+use axum::{http::HeaderMap, routing::get, Json, Router};
+
+use super::admin::{is_secret, REDACTED};
+use super::RouteDoc;
+
+/// Headers carrying credentials under a name [`is_secret`] doesn't recognize
+const CREDENTIAL_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+/// Create an Axum [`Router`] echoing the request headers.
+/// If no path is provided, the default path is `/debug/headers`
+pub fn headers_router(path: Option<String>) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/debug/headers".to_string());
+    let doc = RouteDoc::new(axum::http::Method::GET, &path);
+    let router = Router::new().route(&path, get(headers_handler));
+    (vec![doc], router)
+}
+
+async fn headers_handler(headers: HeaderMap) -> Json<BTreeMap<String, String>> {
+    Json(echo_headers(&headers))
+}
+
+/// Header names, lowercase, to their value. Repeated headers are joined with `, `.
+fn echo_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut echoed = BTreeMap::new();
+    for name in headers.keys() {
+        let value = if CREDENTIAL_HEADERS.contains(&name.as_str()) || is_secret(name.as_str()) {
+            REDACTED.to_string()
+        } else {
+            headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        echoed.insert(name.to_string(), value);
+    }
+    echoed
+}
@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
@@ -20,7 +21,6 @@ use tokio::sync::mpsc::Receiver;
 
 use dynamo_runtime::{
     protocols::{self, annotated::Annotated},
-    raise,
     transports::etcd::{KeyValue, WatchEvent},
     DistributedRuntime,
 };
@@ -31,6 +31,7 @@ use crate::protocols::openai::chat_completions::{
     NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
 };
 use crate::protocols::openai::completions::{CompletionRequest, CompletionResponse};
+use crate::protocols::openai::embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse};
 use tracing;
 /// [ModelEntry] is a struct that contains the information for the HTTP service to discover models
 /// from the etcd cluster.
@@ -46,18 +47,29 @@ pub struct ModelEntry {
 
     /// Specifies whether the model is a chat or completion model.s
     pub model_type: ModelType,
+
+    /// `max_tokens` for requests which don't set it. The HTTP service's default if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_max_tokens: Option<u32>,
 }
 
 pub struct ModelWatchState {
     pub prefix: String,
-    pub model_type: ModelType,
     pub manager: ModelManager,
     pub drt: DistributedRuntime,
+    /// Only add models served from this namespace. For when `prefix` is shared with other
+    /// namespaces, see [`dynamo_runtime::component::Namespace::service_name_prefix`].
+    pub namespace: Option<String>,
 }
 
+/// Adds and removes the models of every type registered under `state.prefix`, so one etcd watch
+/// serves them all
 pub async fn model_watcher(state: Arc<ModelWatchState>, mut events_rx: Receiver<WatchEvent>) {
     tracing::debug!("model watcher started");
 
+    // The model each key registers, so the model goes when its last key does
+    let mut keys: HashMap<String, (String, ModelType)> = HashMap::new();
+
     while let Some(event) = events_rx.recv().await {
         match event {
             WatchEvent::Put(kv) => {
@@ -78,27 +90,38 @@ pub async fn model_watcher(state: Arc<ModelWatchState>, mut events_rx: Receiver<
                         continue;
                     }
                 };
-                if state.manager.has_model_any(&model_entry.name) {
+                if let Some(namespace) = &state.namespace {
+                    if &model_entry.endpoint.namespace != namespace {
+                        tracing::trace!(key, "model from another namespace");
+                        continue;
+                    }
+                }
+                let model = (model_entry.name.clone(), model_entry.model_type);
+                if keys.values().any(|existing| existing == &model) {
                     tracing::trace!(
                         service_name = model_entry.name,
                         "New endpoint for existing model"
                     );
+                    keys.insert(key.to_string(), model);
                     continue;
                 }
 
+                let key = key.to_string();
                 match handle_put(model_entry, state.clone()).await {
                     Ok((model_name, model_type)) => {
                         tracing::info!("added {} model: {}", model_type, model_name);
+                        keys.insert(key, (model_name, model_type));
                     }
                     Err(e) => {
                         tracing::error!("error adding model: {}", e);
                     }
                 }
             }
-            WatchEvent::Delete(kv) => match handle_delete(&kv, state.clone()).await {
-                Ok((model_name, model_type)) => {
+            WatchEvent::Delete(kv) => match handle_delete(&kv, &mut keys, state.clone()).await {
+                Ok(Some((model_name, model_type))) => {
                     tracing::info!("removed {} model: {}", model_type, model_name);
                 }
+                Ok(None) => {}
                 Err(e) => {
                     tracing::error!("error removing model: {}", e);
                 }
@@ -107,21 +130,31 @@ pub async fn model_watcher(state: Arc<ModelWatchState>, mut events_rx: Receiver<
     }
 }
 
+/// Remove the model once none of its keys are left. None if the key wasn't one of ours, e.g. a
+/// model from another namespace, or other instances still serve the model.
 async fn handle_delete(
     kv: &KeyValue,
+    keys: &mut HashMap<String, (String, ModelType)>,
     state: Arc<ModelWatchState>,
-) -> anyhow::Result<(&str, ModelType)> {
+) -> anyhow::Result<Option<(String, ModelType)>> {
     let key = kv.key_str()?;
+    let Some(model) = keys.remove(key) else {
+        return Ok(None);
+    };
+    let (model_name, model_type) = model.clone();
+    if keys.values().any(|existing| existing == &model) {
+        tracing::debug!(key, model_name, "instance removed, model still served");
+        return Ok(None);
+    }
     tracing::debug!(key, "removing model");
 
-    let model_name = key.trim_start_matches(&state.prefix);
-
-    match state.model_type {
-        ModelType::Chat => state.manager.remove_chat_completions_model(model_name)?,
-        ModelType::Completion => state.manager.remove_completions_model(model_name)?,
+    match model_type {
+        ModelType::Chat => state.manager.remove_chat_completions_model(&model_name)?,
+        ModelType::Completion => state.manager.remove_completions_model(&model_name)?,
+        ModelType::Embedding => state.manager.remove_embeddings_model(&model_name)?,
     };
 
-    Ok((model_name, state.model_type))
+    Ok(Some((model_name, model_type)))
 }
 
 // Handles a PUT event from etcd, this usually means adding a new model to the list of served
@@ -132,15 +165,7 @@ async fn handle_put(
     model_entry: ModelEntry,
     state: Arc<ModelWatchState>,
 ) -> anyhow::Result<(String, ModelType)> {
-    if model_entry.model_type != state.model_type {
-        raise!(
-            "model type mismatch: {} != {}",
-            model_entry.model_type,
-            state.model_type
-        );
-    }
-
-    match state.model_type {
+    match model_entry.model_type {
         ModelType::Chat => {
             let client = state
                 .drt
@@ -165,7 +190,195 @@ async fn handle_put(
                 .manager
                 .add_completions_model(&model_entry.name, Arc::new(client))?;
         }
+        ModelType::Embedding => {
+            let client = state
+                .drt
+                .namespace(model_entry.endpoint.namespace)?
+                .component(model_entry.endpoint.component)?
+                .endpoint(model_entry.endpoint.name)
+                .client::<NvCreateEmbeddingRequest, Annotated<NvCreateEmbeddingResponse>>()
+                .await?;
+            state
+                .manager
+                .add_embeddings_model(&model_entry.name, Arc::new(client))?;
+        }
+    }
+
+    if let Some(max_tokens) = model_entry.default_max_tokens {
+        state
+            .manager
+            .set_model_default_max_tokens(&model_entry.name, max_tokens);
+    }
+
+    Ok((model_entry.name, model_entry.model_type))
+}
+
+#[cfg(feature = "integration")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dynamo_runtime::{transports::etcd, Runtime};
+    use std::time::Duration;
+
+    const NAMESPACE: &str = "discovery-test";
+
+    /// Run a model watcher for `NAMESPACE` on a prefix of its own. Needs etcd and NATS.
+    async fn watch(prefix: &str) -> (ModelManager, etcd::Client) {
+        let rt = Runtime::from_current().unwrap();
+        let drt = DistributedRuntime::from_settings(rt).await.unwrap();
+        let etcd_client = drt.etcd_client().unwrap();
+        let manager = ModelManager::new();
+        let state = Arc::new(ModelWatchState {
+            prefix: prefix.to_string(),
+            manager: manager.clone(),
+            drt,
+            namespace: Some(NAMESPACE.to_string()),
+        });
+        let (_prefix, _watcher, receiver) = etcd_client
+            .kv_get_and_watch_prefix(prefix)
+            .await
+            .unwrap()
+            .dissolve();
+        tokio::spawn(model_watcher(state, receiver));
+        (manager, etcd_client)
     }
 
-    Ok((model_entry.name, state.model_type))
+    async fn put(etcd_client: &etcd::Client, key: &str, name: &str, namespace: &str) {
+        put_typed(etcd_client, key, name, namespace, ModelType::Chat).await
+    }
+
+    async fn put_typed(
+        etcd_client: &etcd::Client,
+        key: &str,
+        name: &str,
+        namespace: &str,
+        model_type: ModelType,
+    ) {
+        let entry = ModelEntry {
+            name: name.to_string(),
+            endpoint: protocols::Endpoint {
+                namespace: namespace.to_string(),
+                component: "backend".to_string(),
+                name: "generate".to_string(),
+                instance_id: None,
+            },
+            model_type,
+            default_max_tokens: None,
+        };
+        etcd_client
+            .kv_put(key, serde_json::to_vec(&entry).unwrap(), None)
+            .await
+            .unwrap();
+    }
+
+    async fn delete(etcd_client: &etcd::Client, key: &str) {
+        etcd_client
+            .etcd_client()
+            .kv_client()
+            .delete(key, None)
+            .await
+            .unwrap();
+    }
+
+    /// Wait for the watcher to add, or remove, `name`
+    async fn wait_for(manager: &ModelManager, name: &str, present: bool) {
+        let wait = async {
+            while manager.has_model_any(name) != present {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        if tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .is_err()
+        {
+            panic!("{name} present: {}", !present);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_model_from_another_namespace_ignored() {
+        let prefix = format!("discovery-test-{}-namespace", std::process::id());
+        let (manager, etcd_client) = watch(&prefix).await;
+
+        put(
+            &etcd_client,
+            &format!("{prefix}/other"),
+            "other",
+            "elsewhere",
+        )
+        .await;
+        // events arrive in order, once this one is added the other was seen
+        put(&etcd_client, &format!("{prefix}/ours"), "ours", NAMESPACE).await;
+        wait_for(&manager, "ours", true).await;
+        assert!(!manager.has_model_any("other"));
+
+        delete(&etcd_client, &format!("{prefix}/other")).await;
+        delete(&etcd_client, &format!("{prefix}/ours")).await;
+    }
+
+    #[tokio::test]
+    async fn test_model_kept_until_last_instance_deleted() {
+        let prefix = format!("discovery-test-{}-instances", std::process::id());
+        let (manager, etcd_client) = watch(&prefix).await;
+
+        put(&etcd_client, &format!("{prefix}/a"), "model", NAMESPACE).await;
+        put(&etcd_client, &format!("{prefix}/b"), "model", NAMESPACE).await;
+        wait_for(&manager, "model", true).await;
+
+        delete(&etcd_client, &format!("{prefix}/a")).await;
+        // as above, once this is added the delete was seen
+        put(
+            &etcd_client,
+            &format!("{prefix}/marker"),
+            "marker",
+            NAMESPACE,
+        )
+        .await;
+        wait_for(&manager, "marker", true).await;
+        assert!(manager.has_model_any("model"));
+
+        delete(&etcd_client, &format!("{prefix}/b")).await;
+        wait_for(&manager, "model", false).await;
+
+        delete(&etcd_client, &format!("{prefix}/marker")).await;
+    }
+
+    #[tokio::test]
+    async fn test_one_watch_serves_every_model_type() {
+        let prefix = format!("discovery-test-{}-types", std::process::id());
+        let (manager, etcd_client) = watch(&prefix).await;
+
+        put(&etcd_client, &format!("{prefix}/chat"), "model", NAMESPACE).await;
+        put_typed(
+            &etcd_client,
+            &format!("{prefix}/embedding"),
+            "model",
+            NAMESPACE,
+            ModelType::Embedding,
+        )
+        .await;
+        let wait = async {
+            while manager.list_embeddings_models().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap();
+        assert_eq!(manager.list_chat_completions_models(), vec!["model"]);
+
+        // the chat model goes, the embedding model with the same name stays
+        delete(&etcd_client, &format!("{prefix}/chat")).await;
+        let wait = async {
+            while !manager.list_chat_completions_models().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap();
+        assert_eq!(manager.list_embeddings_models(), vec!["model"]);
+
+        delete(&etcd_client, &format!("{prefix}/embedding")).await;
+    }
 }
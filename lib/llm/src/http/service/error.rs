@@ -13,6 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -22,6 +23,9 @@ pub enum ServiceHttpError {
 
     #[error("Model already exists: {0}")]
     ModelAlreadyExists(String),
+
+    #[error("Model {0} already has an engine named {1}")]
+    EngineAlreadyExists(String, String),
 }
 
 /// Implementation of the Completion Engines served by the HTTP service should
@@ -33,3 +37,35 @@ pub struct HttpError {
     pub code: u16,
     pub message: String,
 }
+
+/// One problem with a request, and the request field it is about if there is one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>,
+    pub message: String,
+}
+
+/// Every problem found validating a request, so the client can fix them all in one go
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub errors: Vec<FieldError>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<&str> = self.errors.iter().map(|e| e.message.as_str()).collect();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+impl ValidationError {
+    /// As a 400 [`HttpError`] listing every message. The field errors stay reachable with
+    /// `downcast_ref::<ValidationError>()`, which is how the HTTP service reports them.
+    pub fn into_http_error(self) -> anyhow::Error {
+        let message = self.to_string();
+        anyhow::Error::new(self).context(HttpError { code: 400, message })
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Startup progress, for orchestrators probing a server that is still loading its model.
+//!
+//! `GET /health/startup` returns 200 with `"status": "ready"` once the model is served, and until
+//! then 503 with what is happening, e.g.
+//! `{"status": "starting", "stage": "loading engine", "message": "loading engine"}`.
+//! None of the engines report how far through loading they are, so the stage is all there is.
+
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::State, http::StatusCode, routing::get, Json, Router};
+use serde::Serialize;
+
+use super::RouteDoc;
+
+/// Shared view of how far the server is through startup. Cheap to clone, clones share state.
+#[derive(Clone)]
+pub struct StartupProgress {
+    state: Arc<Mutex<StartupStatus>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StartupStatus {
+    /// "starting" or "ready"
+    pub status: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+
+    pub message: String,
+}
+
+impl StartupProgress {
+    /// Progress for a server which is already ready to serve
+    pub fn ready() -> Self {
+        let progress = Self::starting();
+        progress.set_ready();
+        progress
+    }
+
+    /// Progress for a server which is still loading, with no details yet
+    pub fn starting() -> Self {
+        StartupProgress {
+            state: Arc::new(Mutex::new(StartupStatus {
+                status: "starting",
+                stage: None,
+                message: "starting".to_string(),
+            })),
+        }
+    }
+
+    /// Report the current stage of startup
+    pub fn update(&self, stage: impl Into<String>) {
+        let stage = stage.into();
+        *self.state.lock().unwrap() = StartupStatus {
+            status: "starting",
+            message: stage.clone(),
+            stage: Some(stage),
+        };
+    }
+
+    /// The model is loaded and being served
+    pub fn set_ready(&self) {
+        *self.state.lock().unwrap() = StartupStatus {
+            status: "ready",
+            stage: None,
+            message: "ready".to_string(),
+        };
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().unwrap().status == "ready"
+    }
+
+    pub fn status(&self) -> StartupStatus {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// Create an Axum [`Router`] for the startup probe.
+/// If no path is provided, the default path is `/health/startup`
+pub fn startup_router(progress: StartupProgress, path: Option<String>) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/health/startup".to_string());
+    let doc = RouteDoc::new(axum::http::Method::GET, &path);
+    let router = Router::new()
+        .route(&path, get(startup_handler))
+        .with_state(progress);
+    (vec![doc], router)
+}
+
+async fn startup_handler(
+    State(progress): State<StartupProgress>,
+) -> (StatusCode, Json<StartupStatus>) {
+    let status = progress.status();
+    let code = if status.status == "ready" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(status))
+}
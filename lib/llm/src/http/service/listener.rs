@@ -0,0 +1,284 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TCP listener for the HTTP service with a cap on open connections and TCP keep-alive, and the
+//! loop serving HTTP/1.1 and HTTP/2 on it.
+
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Sent to connections over the limit before closing them
+const SERVICE_UNAVAILABLE: &[u8] =
+    b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+pub struct ServiceListener {
+    inner: TcpListener,
+    connections: Option<Arc<Semaphore>>,
+    keepalive: Option<Duration>,
+}
+
+impl ServiceListener {
+    /// `max_connections`: connections accepted beyond this many open ones are answered with a
+    /// 503 and closed. Unlimited if None.
+    ///
+    /// `keepalive`: enable TCP keep-alive probes once a connection has been idle this long, so
+    /// connections to clients which went away are closed. Off if None.
+    pub fn new(
+        inner: TcpListener,
+        max_connections: Option<usize>,
+        keepalive: Option<Duration>,
+    ) -> Self {
+        ServiceListener {
+            inner,
+            connections: max_connections.map(|n| Arc::new(Semaphore::new(n))),
+            keepalive,
+        }
+    }
+
+    /// The next connection within the limit. Errors accepting one connection, which is the
+    /// client's problem, are skipped. Any other error is the listener's, and is returned.
+    pub async fn accept(&mut self) -> io::Result<(Connection, SocketAddr)> {
+        loop {
+            let (stream, addr) = match self.inner.accept().await {
+                Ok(conn) => conn,
+                Err(err) if is_connection_error(&err) => {
+                    tracing::debug!(%err, "Failed to accept a connection");
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            let permit = match &self.connections {
+                Some(connections) => match connections.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        tracing::warn!(%addr, "Too many open connections, rejecting");
+                        // Best effort, the client may not have sent its request yet
+                        let _ = stream.try_write(SERVICE_UNAVAILABLE);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            // Responses are streamed in small writes, send each one as soon as it's written.
+            // Otherwise the final chunk and `[DONE]` can sit in the kernel waiting for an ACK.
+            // There is no Unix socket listener to do the same for, and it wouldn't need it:
+            // Nagle's algorithm and delayed ACKs are TCP only.
+            if let Err(err) = stream.set_nodelay(true) {
+                tracing::warn!(%addr, %err, "Failed to set TCP_NODELAY");
+            }
+            if let Some(time) = self.keepalive {
+                let keepalive = socket2::TcpKeepalive::new().with_time(time);
+                if let Err(err) = socket2::SockRef::from(&stream).set_tcp_keepalive(&keepalive) {
+                    tracing::warn!(%addr, %err, "Failed to enable TCP keep-alive");
+                }
+            }
+            return Ok((
+                Connection {
+                    stream,
+                    _permit: permit,
+                },
+                addr,
+            ));
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+/// The client reset or gave up on the connection before we accepted it
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+/// Which HTTP versions clients may use. HTTP/2 is cleartext (h2c) with prior knowledge, there is
+/// no upgrade from HTTP/1.1.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HttpVersion {
+    /// HTTP/1.1 only
+    #[serde(rename = "1")]
+    Http1,
+
+    /// HTTP/2 only
+    #[serde(rename = "2")]
+    Http2,
+
+    /// Whichever the client speaks, told apart by the HTTP/2 connection preface
+    #[default]
+    Auto,
+}
+
+impl FromStr for HttpVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "1" | "1.1" => Ok(HttpVersion::Http1),
+            "2" => Ok(HttpVersion::Http2),
+            "auto" => Ok(HttpVersion::Auto),
+            _ => anyhow::bail!("Invalid HTTP version '{s}', expected 1, 2 or auto"),
+        }
+    }
+}
+
+/// Serve `router` on the connections from `listener` until `shutdown` completes, or the listener
+/// fails, then wait for the open connections to finish their requests. `shutdown` resolves to how
+/// long they get; connections still open after that are closed, dropping their requests.
+pub async fn serve(
+    mut listener: ServiceListener,
+    router: axum::Router,
+    http_version: HttpVersion,
+    shutdown: impl Future<Output = Duration>,
+) -> io::Result<()> {
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let builder = match http_version {
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_only(),
+        HttpVersion::Auto => builder,
+    };
+    let graceful = GracefulShutdown::new();
+    let close = CancellationToken::new();
+    tokio::pin!(shutdown);
+
+    let (result, close_timeout) = loop {
+        let (io, addr) = tokio::select! {
+            conn = listener.accept() => match conn {
+                Ok(conn) => conn,
+                // the service is failing, don't wait on its connections
+                Err(err) => break (Err(err), Duration::ZERO),
+            },
+            close_timeout = &mut shutdown => break (Ok(()), close_timeout),
+        };
+        let service = TowerToHyperService::new(router.clone());
+        let conn = builder
+            .serve_connection_with_upgrades(TokioIo::new(io), service)
+            .into_owned();
+        let conn = graceful.watch(conn);
+        let close = close.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                result = conn => {
+                    if let Err(err) = result {
+                        tracing::debug!(%addr, %err, "Connection closed with an error");
+                    }
+                }
+                // dropping the connection drops the streams of its requests, stopping them
+                _ = close.cancelled() => tracing::debug!(%addr, "Connection closed on shutdown"),
+            }
+        });
+    };
+
+    // stop accepting, and let hyper close idle connections
+    drop(listener);
+    if tokio::time::timeout(close_timeout, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Closing the connections still open after {close_timeout:?}");
+        close.cancel();
+    }
+    result
+}
+
+/// An accepted connection. Holds its place in the connection limit until dropped.
+pub struct Connection {
+    stream: TcpStream,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_accepted_connections_are_nodelay() {
+        let inner = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = inner.local_addr().unwrap();
+        let mut listener = ServiceListener::new(inner, None, None);
+
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (conn, _) = listener.accept().await.unwrap();
+        assert!(conn.stream.nodelay().unwrap());
+    }
+}
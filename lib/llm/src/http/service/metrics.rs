@@ -14,8 +14,13 @@
 // limitations under the License.
 
 use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Router};
-use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts};
-use std::{sync::Arc, time::Instant};
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 pub use prometheus::Registry;
 
@@ -37,6 +42,9 @@ pub struct Metrics {
     request_counter: IntCounterVec,
     inflight_gauge: IntGaugeVec,
     request_duration: HistogramVec,
+    time_to_first_token: HistogramVec,
+    inter_token_latency: HistogramVec,
+    output_tokens: IntCounterVec,
 }
 
 /// RAII object for inflight gauge and request counters
@@ -60,6 +68,9 @@ pub enum Endpoint {
 
     /// OAI Chat Completions
     ChatCompletions,
+
+    /// OAI Embeddings
+    Embeddings,
 }
 
 /// Metrics for the HTTP service
@@ -89,6 +100,12 @@ impl Metrics {
     /// - `{prefix}_http_service_requests_total` - IntCounterVec for the total number of requests processed
     /// - `{prefix}_http_service_inflight_requests` - IntGaugeVec for the number of inflight requests
     /// - `{prefix}_http_service_request_duration_seconds` - HistogramVec for the duration of requests
+    /// - `{prefix}_http_service_time_to_first_token_seconds` - HistogramVec for the time from
+    ///   receiving a request to its first response
+    /// - `{prefix}_http_service_inter_token_latency_seconds` - HistogramVec for the average time
+    ///   between a request's tokens
+    /// - `{prefix}_http_service_output_tokens_total` - IntCounterVec for the number of generated
+    ///   tokens
     pub fn new(prefix: &str) -> Self {
         let request_counter = IntCounterVec::new(
             Opts::new(
@@ -120,10 +137,46 @@ impl Metrics {
         )
         .unwrap();
 
+        let time_to_first_token = HistogramVec::new(
+            HistogramOpts::new(
+                format!("{}_http_service_time_to_first_token_seconds", prefix),
+                "Time from receiving a request to its first response",
+            )
+            .buckets(vec![
+                0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+            ]),
+            &["model"],
+        )
+        .unwrap();
+
+        let inter_token_latency = HistogramVec::new(
+            HistogramOpts::new(
+                format!("{}_http_service_inter_token_latency_seconds", prefix),
+                "Average time between the tokens of a request",
+            )
+            .buckets(vec![
+                0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+            ]),
+            &["model"],
+        )
+        .unwrap();
+
+        let output_tokens = IntCounterVec::new(
+            Opts::new(
+                format!("{}_http_service_output_tokens_total", prefix),
+                "Total number of generated tokens",
+            ),
+            &["model"],
+        )
+        .unwrap();
+
         Metrics {
             request_counter,
             inflight_gauge,
             request_duration,
+            time_to_first_token,
+            inter_token_latency,
+            output_tokens,
         }
     }
 
@@ -176,6 +229,58 @@ impl Metrics {
         self.inflight_gauge.with_label_values(&[model]).get()
     }
 
+    /// Get the number of inflight requests across all models
+    pub fn get_inflight_total(&self) -> i64 {
+        self.inflight_gauge
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| metric.get_gauge().get_value() as i64)
+            .sum()
+    }
+
+    /// Get the number of finished requests across all models, endpoints and request types
+    pub fn get_request_total(&self) -> u64 {
+        self.sum_request_counter(|_| true)
+    }
+
+    /// Get the number of failed requests across all models, endpoints and request types
+    pub fn get_error_total(&self) -> u64 {
+        self.sum_request_counter(|status| status == REQUEST_STATUS_ERROR)
+    }
+
+    fn sum_request_counter(&self, status_matches: impl Fn(&str) -> bool) -> u64 {
+        self.request_counter
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .filter(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|l| l.get_name() == "status" && status_matches(l.get_value()))
+            })
+            .map(|metric| metric.get_counter().get_value() as u64)
+            .sum()
+    }
+
+    /// Get the average duration of finished requests across all models, None if there were none
+    pub fn get_average_duration(&self) -> Option<Duration> {
+        let (sum, count) = self
+            .request_duration
+            .collect()
+            .iter()
+            .flat_map(|family| family.get_metric())
+            .map(|metric| metric.get_histogram())
+            .fold((0.0, 0), |(sum, count), histogram| {
+                (
+                    sum + histogram.get_sample_sum(),
+                    count + histogram.get_sample_count(),
+                )
+            });
+        (count > 0).then(|| Duration::from_secs_f64(sum / count as f64))
+    }
+
     fn inc_inflight_gauge(&self, model: &str) {
         self.inflight_gauge.with_label_values(&[model]).inc()
     }
@@ -184,10 +289,43 @@ impl Metrics {
         self.inflight_gauge.with_label_values(&[model]).dec()
     }
 
+    /// Get the number of tokens generated for the given model
+    pub fn get_output_tokens(&self, model: &str) -> u64 {
+        self.output_tokens.with_label_values(&[model]).get()
+    }
+
+    /// Record how a finished request generated: `time_to_first_token` if it got a response at
+    /// all, `decode` the time from the first response to the last, and `output_tokens` how many
+    /// tokens it got.
+    pub(crate) fn observe_generation(
+        &self,
+        model: &str,
+        time_to_first_token: Option<Duration>,
+        decode: Duration,
+        output_tokens: u64,
+    ) {
+        if let Some(ttft) = time_to_first_token {
+            self.time_to_first_token
+                .with_label_values(&[model])
+                .observe(ttft.as_secs_f64());
+        }
+        if output_tokens > 1 {
+            self.inter_token_latency
+                .with_label_values(&[model])
+                .observe(decode.as_secs_f64() / (output_tokens - 1) as f64);
+        }
+        self.output_tokens
+            .with_label_values(&[model])
+            .inc_by(output_tokens);
+    }
+
     pub fn register(&self, registry: &Registry) -> Result<(), prometheus::Error> {
         registry.register(Box::new(self.request_counter.clone()))?;
         registry.register(Box::new(self.inflight_gauge.clone()))?;
         registry.register(Box::new(self.request_duration.clone()))?;
+        registry.register(Box::new(self.time_to_first_token.clone()))?;
+        registry.register(Box::new(self.inter_token_latency.clone()))?;
+        registry.register(Box::new(self.output_tokens.clone()))?;
         Ok(())
     }
 }
@@ -276,6 +414,7 @@ impl std::fmt::Display for Endpoint {
         match self {
             Endpoint::Completions => write!(f, "completions"),
             Endpoint::ChatCompletions => write!(f, "chat_completions"),
+            Endpoint::Embeddings => write!(f, "embeddings"),
         }
     }
 }
@@ -285,6 +424,7 @@ impl Endpoint {
         match self {
             Endpoint::Completions => "completions",
             Endpoint::ChatCompletions => "chat_completions",
+            Endpoint::Embeddings => "embeddings",
         }
     }
 }
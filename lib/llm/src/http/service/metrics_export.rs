@@ -0,0 +1,383 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Push the HTTP service metrics to an OTLP collector or a StatsD server.
+//!
+//! The metrics are the ones registered in the prometheus [`Registry`] served on `/metrics`. They
+//! are gathered and pushed on an interval, so both ways of collecting them see the same values.
+//!
+//! - OTLP uses the HTTP/JSON protocol, counters and histograms are sent as cumulative.
+//! - StatsD uses UDP with DogStatsD style tags. Counters are sent as the increase since the
+//!   previous push, gauges as is, histograms as `<name>_count` and `<name>_sum` gauges.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use prometheus::proto::{MetricFamily, MetricType};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{net::UdpSocket, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+
+use super::metrics::Registry;
+
+/// How often metrics are pushed if not configured
+pub const DEFAULT_EXPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Largest StatsD datagram we send, to stay under common network MTUs
+const STATSD_MAX_PACKET: usize = 1400;
+
+/// Where to push metrics to. Parsed from `otlp=<url>` or `statsd=<host:port>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsExport {
+    /// An OTLP/HTTP collector, e.g. `http://localhost:4318`. `/v1/metrics` is added if the URL
+    /// doesn't already end with it.
+    Otlp(String),
+
+    /// A StatsD server, e.g. `localhost:8125`. A leading `udp://` is allowed.
+    Statsd(String),
+}
+
+impl FromStr for MetricsExport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let Some((kind, target)) = s.split_once('=') else {
+            anyhow::bail!("Invalid metrics export '{s}', expected otlp=<url> or statsd=<url>");
+        };
+        if target.is_empty() {
+            anyhow::bail!("Metrics export '{s}' is missing the URL");
+        }
+        match kind {
+            "otlp" => {
+                let target = target.trim_end_matches('/');
+                let url = if target.ends_with("/v1/metrics") {
+                    target.to_string()
+                } else {
+                    format!("{target}/v1/metrics")
+                };
+                Ok(MetricsExport::Otlp(url))
+            }
+            "statsd" => Ok(MetricsExport::Statsd(
+                target.trim_start_matches("udp://").to_string(),
+            )),
+            _ => anyhow::bail!("Unknown metrics export '{kind}', expected otlp or statsd"),
+        }
+    }
+}
+
+impl fmt::Display for MetricsExport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsExport::Otlp(url) => write!(f, "otlp={url}"),
+            MetricsExport::Statsd(addr) => write!(f, "statsd={addr}"),
+        }
+    }
+}
+
+/// Push the metrics in `registry` to `export` every `interval` until cancelled. A final push is
+/// made on cancellation so short lived processes still report.
+pub fn spawn(
+    registry: Registry,
+    export: MetricsExport,
+    interval: Duration,
+    cancel_token: CancellationToken,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut exporter = match Exporter::new(export.clone()).await {
+            Ok(exporter) => exporter,
+            Err(err) => {
+                tracing::error!(%export, %err, "Failed to start metrics export");
+                return;
+            }
+        };
+        tracing::info!(%export, "Pushing metrics every {interval:?}");
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        // The first tick completes immediately, there is nothing to push yet
+        ticker.tick().await;
+        loop {
+            let stopping = tokio::select! {
+                _ = ticker.tick() => false,
+                _ = cancel_token.cancelled() => true,
+            };
+            if let Err(err) = exporter.push(&registry.gather()).await {
+                tracing::warn!(%export, %err, "Failed to push metrics");
+            }
+            if stopping {
+                break;
+            }
+        }
+    })
+}
+
+enum Exporter {
+    Otlp {
+        url: String,
+        client: reqwest::Client,
+        start_time: u128,
+    },
+    Statsd {
+        socket: UdpSocket,
+        /// Counter values at the previous push, keyed by name and tags
+        previous: HashMap<String, f64>,
+    },
+}
+
+impl Exporter {
+    async fn new(export: MetricsExport) -> anyhow::Result<Self> {
+        match export {
+            MetricsExport::Otlp(url) => Ok(Exporter::Otlp {
+                url,
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(10))
+                    .build()?,
+                start_time: unix_nanos(),
+            }),
+            MetricsExport::Statsd(addr) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(&addr).await?;
+                Ok(Exporter::Statsd {
+                    socket,
+                    previous: HashMap::new(),
+                })
+            }
+        }
+    }
+
+    async fn push(&mut self, families: &[MetricFamily]) -> anyhow::Result<()> {
+        match self {
+            Exporter::Otlp {
+                url,
+                client,
+                start_time,
+            } => {
+                let body = otlp_json(families, *start_time, unix_nanos());
+                client
+                    .post(url.as_str())
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Exporter::Statsd { socket, previous } => {
+                let mut packet = String::new();
+                for line in statsd_lines(families, previous) {
+                    if !packet.is_empty() && packet.len() + line.len() + 1 > STATSD_MAX_PACKET {
+                        socket.send(packet.as_bytes()).await?;
+                        packet.clear();
+                    }
+                    if !packet.is_empty() {
+                        packet.push('\n');
+                    }
+                    packet.push_str(&line);
+                }
+                if !packet.is_empty() {
+                    socket.send(packet.as_bytes()).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// StatsD lines for the gathered metrics. `previous` holds the counter values at the last push
+/// and is updated.
+fn statsd_lines(families: &[MetricFamily], previous: &mut HashMap<String, f64>) -> Vec<String> {
+    let mut lines = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let tags = metric
+                .get_label()
+                .iter()
+                .map(|l| format!("{}:{}", l.get_name(), l.get_value()))
+                .collect::<Vec<_>>()
+                .join(",");
+            let tags = if tags.is_empty() {
+                tags
+            } else {
+                format!("|#{tags}")
+            };
+            match family.get_field_type() {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().get_value();
+                    let key = format!("{name}{tags}");
+                    let last = previous.insert(key, value).unwrap_or_default();
+                    let delta = value - last;
+                    if delta > 0.0 {
+                        lines.push(format!("{name}:{delta}|c{tags}"));
+                    }
+                }
+                MetricType::GAUGE => {
+                    let value = metric.get_gauge().get_value();
+                    lines.push(format!("{name}:{value}|g{tags}"));
+                }
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    lines.push(format!(
+                        "{name}_count:{}|g{tags}",
+                        histogram.get_sample_count()
+                    ));
+                    lines.push(format!("{name}_sum:{}|g{tags}", histogram.get_sample_sum()));
+                }
+                _ => {}
+            }
+        }
+    }
+    lines
+}
+
+/// An OTLP `ExportMetricsServiceRequest` in its JSON encoding
+fn otlp_json(families: &[MetricFamily], start_time: u128, now: u128) -> Value {
+    let start_time = start_time.to_string();
+    let now = now.to_string();
+    let mut metrics = Vec::new();
+    for family in families {
+        let points = family.get_metric().iter().map(|metric| {
+            let attributes = metric
+                .get_label()
+                .iter()
+                .map(|l| json!({"key": l.get_name(), "value": {"stringValue": l.get_value()}}))
+                .collect::<Vec<_>>();
+            (metric, attributes)
+        });
+        let data = match family.get_field_type() {
+            MetricType::COUNTER => {
+                let points = points
+                    .map(|(metric, attributes)| {
+                        json!({
+                            "attributes": attributes,
+                            "startTimeUnixNano": start_time,
+                            "timeUnixNano": now,
+                            "asDouble": metric.get_counter().get_value(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({"sum": {
+                    "dataPoints": points,
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                }})
+            }
+            MetricType::GAUGE => {
+                let points = points
+                    .map(|(metric, attributes)| {
+                        json!({
+                            "attributes": attributes,
+                            "timeUnixNano": now,
+                            "asDouble": metric.get_gauge().get_value(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({"gauge": {"dataPoints": points}})
+            }
+            MetricType::HISTOGRAM => {
+                let points = points
+                    .map(|(metric, attributes)| {
+                        let histogram = metric.get_histogram();
+                        // Prometheus buckets are cumulative, OTLP bucket counts are not
+                        let mut bounds = Vec::new();
+                        let mut counts = Vec::new();
+                        let mut below = 0;
+                        for bucket in histogram.get_bucket() {
+                            if bucket.get_upper_bound().is_finite() {
+                                bounds.push(bucket.get_upper_bound());
+                                counts.push((bucket.get_cumulative_count() - below).to_string());
+                                below = bucket.get_cumulative_count();
+                            }
+                        }
+                        counts.push((histogram.get_sample_count() - below).to_string());
+                        json!({
+                            "attributes": attributes,
+                            "startTimeUnixNano": start_time,
+                            "timeUnixNano": now,
+                            "count": histogram.get_sample_count().to_string(),
+                            "sum": histogram.get_sample_sum(),
+                            "bucketCounts": counts,
+                            "explicitBounds": bounds,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({"histogram": {
+                    "dataPoints": points,
+                    "aggregationTemporality": 2,
+                }})
+            }
+            _ => continue,
+        };
+        let mut metric = json!({
+            "name": family.get_name(),
+            "description": family.get_help(),
+        });
+        if let (Some(metric), Some(data)) = (metric.as_object_mut(), data.as_object()) {
+            metric.extend(data.clone());
+        }
+        metrics.push(metric);
+    }
+    json!({
+        "resourceMetrics": [{
+            "resource": {"attributes": [
+                {"key": "service.name", "value": {"stringValue": "dynamo"}},
+            ]},
+            "scopeMetrics": [{
+                "scope": {"name": "dynamo-llm"},
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_metrics_export() {
+        assert_eq!(
+            "otlp=http://collector:4318"
+                .parse::<MetricsExport>()
+                .unwrap(),
+            MetricsExport::Otlp("http://collector:4318/v1/metrics".to_string())
+        );
+        assert_eq!(
+            "otlp=http://collector:4318/v1/metrics/"
+                .parse::<MetricsExport>()
+                .unwrap(),
+            MetricsExport::Otlp("http://collector:4318/v1/metrics".to_string())
+        );
+        assert_eq!(
+            "statsd=udp://localhost:8125"
+                .parse::<MetricsExport>()
+                .unwrap(),
+            MetricsExport::Statsd("localhost:8125".to_string())
+        );
+        assert!("prometheus=http://x".parse::<MetricsExport>().is_err());
+        assert!("statsd".parse::<MetricsExport>().is_err());
+        assert!("statsd=".parse::<MetricsExport>().is_err());
+    }
+}
@@ -14,8 +14,10 @@
 // limitations under the License.
 
 use axum::{
-    extract::State,
-    http::StatusCode,
+    body::Bytes,
+    extract::{rejection::JsonRejection, FromRequest, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{
         sse::{Event, KeepAlive, Sse},
         IntoResponse, Response,
@@ -28,40 +30,143 @@ use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     pin::Pin,
-    sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{atomic::Ordering, Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio_stream::wrappers::ReceiverStream;
 
-use super::DeploymentState;
 use super::{
-    error::HttpError,
-    metrics::{Endpoint, InflightGuard},
+    error::{FieldError, HttpError, ValidationError},
+    metrics::{Endpoint, InflightGuard, Metrics},
+    sampling_profiles::SamplingProfile,
+    scheduler::SchedulerPermit,
     RouteDoc,
 };
+use super::{DeploymentState, ModelEngines, ModelMetadata};
 
+use crate::backend::ANNOTATION_STOP_CAUSE;
+use crate::preprocessor::prompt::OAIChatLikeRequest;
+use crate::protocols::common::StopCause;
 use crate::protocols::openai::{
-    chat_completions::NvCreateChatCompletionResponse, completions::CompletionResponse,
+    chat_completions::{
+        repair_json, JsonRepair, NvCreateChatCompletionResponse,
+        NvCreateChatCompletionStreamResponse,
+    },
+    completions::{CompletionChoice, CompletionResponse},
+    embeddings::NvCreateEmbeddingResponse,
+    nvext::NvExtProvider,
 };
 use crate::types::{
-    openai::{chat_completions::NvCreateChatCompletionRequest, completions::CompletionRequest},
+    openai::{
+        chat_completions::NvCreateChatCompletionRequest, completions::CompletionRequest,
+        embeddings::NvCreateEmbeddingRequest,
+    },
     Annotated,
 };
 
-use dynamo_runtime::pipeline::{AsyncEngineContext, Context};
+use dynamo_runtime::engine::{Data, DataStream, ResponseStream};
+use dynamo_runtime::pipeline::{AsyncEngineContext, Context, ManyOut};
+use dynamo_runtime::protocols::annotated::AnnotationsProvider;
+
+/// Standard header, see https://www.w3.org/TR/server-timing/
+const SERVER_TIMING: HeaderName = HeaderName::from_static("server-timing");
+
+/// `Retry-After` for requests refused because the service is shutting down
+const DRAINING_RETRY_AFTER_SECS: u64 = 1;
+
+/// Streaming requests with this annotation get the time they spent queued, in prefill and in
+/// decode as a `latency` annotation at the end of the stream
+const ANNOTATION_LATENCY: &str = "latency";
+
+/// With `--repair-json`, streamed responses to JSON requests whose content isn't valid JSON end
+/// with this annotation: per choice, the repaired content, or `null` if it couldn't be repaired
+const ANNOTATION_JSON_REPAIR: &str = "json_repair";
+
+/// With `--repair-json`, non-streaming responses to JSON requests whose content wasn't valid
+/// JSON say `repaired`, or `failed` when the content is sent as generated
+const X_JSON_REPAIR: HeaderName = HeaderName::from_static("x-json-repair");
+
+/// Sent before the `stop` chunk made up for an engine which sent no output at all
+const ANNOTATION_WARNING: &str = "warning";
+
+/// Streaming requests with `X-Prefill-Progress: true` get `event: prefill` events while they
+/// wait for the first response
+const PREFILL_PROGRESS: HeaderName = HeaderName::from_static("x-prefill-progress");
+
+/// Requests with `X-Debug-Token-Ids: true` get their prompt's token ids back in this response
+/// header, as a JSON array, when the service allows it
+const DEBUG_TOKEN_IDS: HeaderName = HeaderName::from_static("x-debug-token-ids");
+
+/// Requests with `X-Tee: true` also have their response copied to the service's tee sink, see
+/// [`super::ModelManager::set_tee_sink`]
+const X_TEE: HeaderName = HeaderName::from_static("x-tee");
+
+/// `HIT` when the response came from the response cache, `MISS` when it was generated and cached
+const X_CACHE: HeaderName = HeaderName::from_static("x-cache");
+
+/// Pick one of the model's named engines, see
+/// [`super::ModelManager::add_named_chat_completions_model`]
+const X_ENGINE: HeaderName = HeaderName::from_static("x-engine");
+
+/// Requests with `X-Sampling-Profile: <name>` get the named profile's sampling parameters for
+/// the ones they don't set, see [`super::sampling_profiles`]
+const X_SAMPLING_PROFILE: HeaderName = HeaderName::from_static("x-sampling-profile");
 
 #[derive(Serialize, Deserialize)]
 pub(crate) struct ErrorResponse {
-    error: String,
+    error: ErrorBody,
+}
+
+/// Most errors are a message. Some are in OpenAI's shape, which its client libraries turn into
+/// specific exceptions, e.g. `openai.NotFoundError` for an unknown model.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ErrorBody {
+    Message(String),
+    /// A request that failed validation, with each of its problems in `errors`
+    Validation {
+        message: String,
+        #[serde(rename = "type")]
+        error_type: String,
+        errors: Vec<FieldError>,
+    },
+    OpenAI {
+        message: String,
+        #[serde(rename = "type")]
+        error_type: String,
+        param: Option<String>,
+        code: Option<String>,
+    },
+}
+
+impl From<String> for ErrorBody {
+    fn from(message: String) -> Self {
+        ErrorBody::Message(message)
+    }
+}
+
+impl ErrorBody {
+    pub fn message(&self) -> &str {
+        match self {
+            ErrorBody::Message(message)
+            | ErrorBody::Validation { message, .. }
+            | ErrorBody::OpenAI { message, .. } => message,
+        }
+    }
 }
 
 impl ErrorResponse {
-    /// Not Found Error
-    pub fn model_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    /// Not Found Error, as OpenAI reports it
+    pub fn model_not_found(model: &str) -> (StatusCode, Json<ErrorResponse>) {
         (
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
-                error: "Model not found".to_string(),
+                error: ErrorBody::OpenAI {
+                    message: format!("The model `{model}` does not exist"),
+                    error_type: "invalid_request_error".to_string(),
+                    param: Some("model".to_string()),
+                    code: Some("model_not_found".to_string()),
+                },
             }),
         )
     }
@@ -72,7 +177,7 @@ impl ErrorResponse {
         (
             StatusCode::SERVICE_UNAVAILABLE,
             Json(ErrorResponse {
-                error: "Service is not ready".to_string(),
+                error: "Service is not ready".to_string().into(),
             }),
         )
     }
@@ -86,7 +191,7 @@ impl ErrorResponse {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: msg.to_string(),
+                error: msg.to_string().into(),
             }),
         )
     }
@@ -96,19 +201,41 @@ impl ErrorResponse {
     /// If successful, it will return the [`HttpError`] as an [`ErrorResponse::internal_server_error`]
     /// with the details of the error.
     pub fn from_anyhow(err: anyhow::Error, alt_msg: &str) -> (StatusCode, Json<ErrorResponse>) {
+        if let Some(validation) = err.downcast_ref::<ValidationError>() {
+            return ErrorResponse::validation_error(validation);
+        }
         match err.downcast::<HttpError>() {
             Ok(http_error) => ErrorResponse::from_http_error(http_error),
             Err(err) => ErrorResponse::internal_server_error(&format!("{alt_msg}: {err}")),
         }
     }
 
+    /// Bad Request, with every field error so clients can point at each problem
+    pub fn validation_error(err: &ValidationError) -> (StatusCode, Json<ErrorResponse>) {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: ErrorBody::Validation {
+                    message: err.to_string(),
+                    error_type: "invalid_request_error".to_string(),
+                    errors: err.errors.clone(),
+                },
+            }),
+        )
+    }
+
     /// Implementers should only be able to throw 400-499 errors.
     pub fn from_http_error(err: HttpError) -> (StatusCode, Json<ErrorResponse>) {
         if err.code < 400 || err.code >= 500 {
             return ErrorResponse::internal_server_error(&err.message);
         }
         match StatusCode::from_u16(err.code) {
-            Ok(code) => (code, Json(ErrorResponse { error: err.message })),
+            Ok(code) => (
+                code,
+                Json(ErrorResponse {
+                    error: err.message.into(),
+                }),
+            ),
             Err(_) => ErrorResponse::internal_server_error(&err.message),
         }
     }
@@ -116,10 +243,90 @@ impl ErrorResponse {
 
 impl From<HttpError> for ErrorResponse {
     fn from(err: HttpError) -> Self {
-        ErrorResponse { error: err.message }
+        ErrorResponse {
+            error: err.message.into(),
+        }
+    }
+}
+
+/// A JSON request body. Like [`Json`], but if the service accepts any content type the body is
+/// parsed as JSON whatever the `Content-Type` header says, and if it accepts lenient numbers the
+/// request type may coerce them.
+struct RequestJson<T>(T);
+
+/// Request bodies which can be parsed more leniently than their `Deserialize` implementation
+trait FromLenientJson: serde::de::DeserializeOwned {
+    fn from_lenient_json(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+}
+
+impl FromLenientJson for NvCreateChatCompletionRequest {
+    fn from_lenient_json(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        NvCreateChatCompletionRequest::from_lenient_json(value)
     }
 }
 
+impl FromLenientJson for CompletionRequest {}
+
+impl<T: FromLenientJson> FromRequest<Arc<DeploymentState>> for RequestJson<T> {
+    type Rejection = (StatusCode, Json<ErrorResponse>);
+
+    async fn from_request(
+        req: Request,
+        state: &Arc<DeploymentState>,
+    ) -> Result<Self, Self::Rejection> {
+        let result = if state.lenient_numbers.load(Ordering::Relaxed) {
+            json_body::<serde_json::Value>(req, state)
+                .await
+                .map(|value| T::from_lenient_json(value))
+        } else {
+            json_body::<T>(req, state).await.map(Ok)
+        };
+        match result {
+            Ok(Ok(value)) => Ok(RequestJson(value)),
+            Ok(Err(err)) => Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorResponse {
+                    error: format!(
+                        "Failed to deserialize the JSON body into the target type: {err}"
+                    )
+                    .into(),
+                }),
+            )),
+            Err(JsonRejection::MissingJsonContentType(_)) => Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(ErrorResponse {
+                    error: "Expected request with `Content-Type: application/json`"
+                        .to_string()
+                        .into(),
+                }),
+            )),
+            Err(err) => Err((
+                err.status(),
+                Json(ErrorResponse {
+                    error: err.body_text().into(),
+                }),
+            )),
+        }
+    }
+}
+
+/// The request body parsed as JSON, checking the `Content-Type` unless the service is lenient
+/// about it
+async fn json_body<V: serde::de::DeserializeOwned>(
+    req: Request,
+    state: &Arc<DeploymentState>,
+) -> Result<V, JsonRejection> {
+    let Json(value) = if state.lenient_content_type.load(Ordering::Relaxed) {
+        let bytes = Bytes::from_request(req, state).await?;
+        Json::from_bytes(&bytes)?
+    } else {
+        Json::from_request(req, state).await?
+    };
+    Ok(value)
+}
+
 /// OpenAI Completions Request Handler
 ///
 /// This method will handle the incoming request for the `/v1/completions endpoint`. The endpoint is a "source"
@@ -131,16 +338,30 @@ impl From<HttpError> for ErrorResponse {
 #[tracing::instrument(skip_all)]
 async fn completions(
     State(state): State<Arc<DeploymentState>>,
-    Json(request): Json<CompletionRequest>,
+    headers: HeaderMap,
+    RequestJson(mut request): RequestJson<CompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     // return a 503 if the service is not ready
     check_ready(&state)?;
+    check_n(&state, request.inner.n)?;
+    state.substitute_model(&state.completion_engines, &mut request.inner.model);
+    if let Some(profile) = sampling_profile(&state, &headers)? {
+        profile.apply_to_completion(&mut request.inner);
+    }
+
+    let mut timing = ServerTiming::new(request.inner.max_tokens);
+    if request.inner.max_tokens.is_none() {
+        request.inner.max_tokens = state.default_max_tokens(&request.inner.model);
+    }
+    let token_ids = debug_token_ids(&state, &headers, &request.inner.model, &request).await;
+
+    let created = unix_now();
 
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
+    let annotate_latency = request.has_annotation(ANNOTATION_LATENCY);
 
-    // todo - decide on default
-    let streaming = request.inner.stream.unwrap_or(false);
+    let streaming = resolve_streaming(request.inner.stream, &headers);
 
     // update the request to always stream
     let inner = async_openai::types::CreateCompletionRequest {
@@ -148,19 +369,25 @@ async fn completions(
         ..request.inner
     };
 
-    let request = CompletionRequest { inner, nvext: None };
+    let request = CompletionRequest {
+        inner,
+        nvext: request.nvext,
+    };
 
     // todo - make the protocols be optional for model name
     // todo - when optional, if none, apply a default
     let model = &request.inner.model;
 
     // todo - error handling should be more robust
-    let engine = state
-        .get_completions_engine(model)
-        .map_err(|_| ErrorResponse::model_not_found())?;
+    let engine = select_engine(&state.completion_engines.lock().unwrap(), model, &headers)?;
+
+    // wait for a slot if the number of concurrent requests is limited
+    let permit = state.admit(state.model_priority(model)).await;
+    timing.admitted();
 
     // this will increment the inflight gauge for the model
     let mut inflight = state.create_inflight_guard(model, Endpoint::Completions, streaming);
+    let model = model.clone();
 
     // setup context
     // todo - inherit request_id from distributed trace details
@@ -171,6 +398,16 @@ async fn completions(
         .generate(request)
         .await
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    timing.engine_started();
+    let stream = state.with_request_timeout(stream);
+    let stream = state.default_finish_reason(stream, &model);
+    let stream = with_created(stream, created);
+    let stream = state.override_system_fingerprint(stream);
+    let stream = state.tee(stream, &headers, &request_id, &model);
+    let stream = state
+        .active_requests
+        .register(&request_id, &model)
+        .track(stream);
 
     // capture the context to cancel the stream if the client disconnects
     let ctx = stream.context();
@@ -179,8 +416,21 @@ async fn completions(
     // note - we might do this as part of the post processing set to make it more generic
 
     if streaming {
-        let stream = stream.map(|response| Event::try_from(EventConverter::from(response)));
-        let stream = monitor_for_disconnects(stream.boxed(), ctx, inflight).await;
+        let stream = timing.tap_latency(
+            stream,
+            request_id,
+            model,
+            annotate_latency,
+            state.metrics.clone(),
+        );
+        let stream = state.split_large_chunks(stream);
+        let stream = sse_events(stream);
+        let stream = if wants_prefill_progress(&headers) {
+            with_prefill_progress(stream, state.prefill_progress_interval()).boxed()
+        } else {
+            stream.boxed()
+        };
+        let stream = monitor_for_disconnects(stream, ctx, inflight, permit).await;
 
         let mut sse_stream = Sse::new(stream);
 
@@ -188,9 +438,13 @@ async fn completions(
             sse_stream = sse_stream.keep_alive(KeepAlive::default().interval(keep_alive));
         }
 
-        Ok(sse_stream.into_response())
+        let mut response = sse_stream.into_response();
+        if let Some(token_ids) = token_ids {
+            response.headers_mut().insert(DEBUG_TOKEN_IDS, token_ids);
+        }
+        Ok(response)
     } else {
-        let response = CompletionResponse::from_annotated_stream(stream.into())
+        let response = CompletionResponse::from_annotated_stream(timing.tap(stream))
             .await
             .map_err(|e| {
                 tracing::error!(
@@ -202,7 +456,18 @@ async fn completions(
             })?;
 
         inflight.mark_ok();
-        Ok(Json(response).into_response())
+        timing
+            .latency()
+            .log(&request_id, &model, timing.stop_cause());
+        timing.record_metrics(&state.metrics, &model);
+        let mut response = Json(response).into_response();
+        response
+            .headers_mut()
+            .insert(SERVER_TIMING, timing.header_value());
+        if let Some(token_ids) = token_ids {
+            response.headers_mut().insert(DEBUG_TOKEN_IDS, token_ids);
+        }
+        Ok(response)
     }
 }
 
@@ -217,16 +482,43 @@ async fn completions(
 #[tracing::instrument(skip_all)]
 async fn chat_completions(
     State(state): State<Arc<DeploymentState>>,
-    Json(request): Json<NvCreateChatCompletionRequest>,
+    headers: HeaderMap,
+    RequestJson(mut request): RequestJson<NvCreateChatCompletionRequest>,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     // return a 503 if the service is not ready
     check_ready(&state)?;
+    check_n(&state, request.inner.n)?;
+    state.substitute_model(&state.chat_completion_engines, &mut request.inner.model);
+    if let Some(profile) = sampling_profile(&state, &headers)? {
+        profile.apply_to_chat(&mut request.inner);
+    }
+
+    #[allow(deprecated)]
+    let mut timing = ServerTiming::new(
+        request
+            .inner
+            .max_completion_tokens
+            .or(request.inner.max_tokens),
+    );
+    #[allow(deprecated)]
+    if request.inner.max_completion_tokens.is_none() && request.inner.max_tokens.is_none() {
+        request.inner.max_completion_tokens = state.default_max_tokens(&request.inner.model);
+    }
+    let token_ids = debug_token_ids(&state, &headers, &request.inner.model, &request).await;
+
+    let created = unix_now();
 
     // todo - extract distributed tracing id and context id from headers
     let request_id = uuid::Uuid::new_v4().to_string();
+    let annotate_latency = request.has_annotation(ANNOTATION_LATENCY);
+    let repair = state.repair_json.load(Ordering::Relaxed) && wants_json(&request);
+    let include_usage = request
+        .inner
+        .stream_options
+        .as_ref()
+        .is_some_and(|options| options.include_usage);
 
-    // todo - decide on default
-    let streaming = request.inner.stream.unwrap_or(false);
+    let streaming = resolve_streaming(request.inner.stream, &headers);
 
     // update the request to always stream
     let inner_request = async_openai::types::CreateChatCompletionRequest {
@@ -235,7 +527,9 @@ async fn chat_completions(
     };
     let request = NvCreateChatCompletionRequest {
         inner: inner_request,
-        nvext: None,
+        nvext: request.nvext,
+        service_tier: request.service_tier,
+        stop_token_ids: request.stop_token_ids,
     };
 
     // todo - make the protocols be optional for model name
@@ -245,12 +539,43 @@ async fn chat_completions(
     // todo - determine the proper error code for when a request model is not present
     tracing::trace!("Getting chat completions engine for model: {}", model);
 
-    let engine = state
-        .get_chat_completions_engine(model)
-        .map_err(|_| ErrorResponse::model_not_found())?;
+    let engine = select_engine(
+        &state.chat_completion_engines.lock().unwrap(),
+        model,
+        &headers,
+    )?;
+
+    // greedy non-streaming requests may have been answered before, by the default engine
+    let cache_key =
+        if streaming || headers.contains_key(X_ENGINE) || header_is_true(&headers, X_TEE) {
+            None
+        } else {
+            state.response_cache.key(&request)
+        };
+    if let Some(mut cached) = cache_key.and_then(|key| state.response_cache.get(&key)) {
+        // a hit is still a request, it just doesn't wait for an engine
+        let mut inflight = state.create_inflight_guard(model, Endpoint::ChatCompletions, streaming);
+        // and it's a new response, not the one the cache was filled from
+        cached.inner.id = format!("chatcmpl-{request_id}");
+        cached.inner.created = created as u32;
+        inflight.mark_ok();
+        let mut response = Json(cached).into_response();
+        response
+            .headers_mut()
+            .insert(X_CACHE, HeaderValue::from_static("HIT"));
+        return Ok(response);
+    }
+
+    // wait for a slot if the number of concurrent requests is limited, high priority models
+    // first, flex tier last within a model
+    let priority =
+        state.model_priority(model) + request.service_tier.unwrap_or_default().priority();
+    let permit = state.admit(priority).await;
+    timing.admitted();
 
     // this will increment the inflight gauge for the model
     let mut inflight = state.create_inflight_guard(model, Endpoint::ChatCompletions, streaming);
+    let model = model.clone();
 
     // setup context
     // todo - inherit request_id from distributed trace details
@@ -263,6 +588,16 @@ async fn chat_completions(
         .generate(request)
         .await
         .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate completions"))?;
+    timing.engine_started();
+    let stream = state.with_request_timeout(stream);
+    let stream = state.default_finish_reason(stream, &model);
+    let stream = with_created(stream, created);
+    let stream = state.override_system_fingerprint(stream);
+    let stream = state.tee(stream, &headers, &request_id, &model);
+    let stream = state
+        .active_requests
+        .register(&request_id, &model)
+        .track(stream);
 
     // capture the context to cancel the stream if the client disconnects
     let ctx = stream.context();
@@ -271,8 +606,31 @@ async fn chat_completions(
     // note - we might do this as part of the post processing set to make it more generic
 
     if streaming {
-        let stream = stream.map(|response| Event::try_from(EventConverter::from(response)));
-        let stream = monitor_for_disconnects(stream.boxed(), ctx, inflight).await;
+        let stream = if include_usage {
+            with_usage_chunk(stream)
+        } else {
+            stream
+        };
+        let stream = timing.tap_latency(
+            stream,
+            request_id,
+            model,
+            annotate_latency,
+            state.metrics.clone(),
+        );
+        let stream = if repair {
+            with_json_repair(stream)
+        } else {
+            stream
+        };
+        let stream = state.split_large_chunks(stream);
+        let stream = sse_events(stream);
+        let stream = if wants_prefill_progress(&headers) {
+            with_prefill_progress(stream, state.prefill_progress_interval()).boxed()
+        } else {
+            stream.boxed()
+        };
+        let stream = monitor_for_disconnects(stream, ctx, inflight, permit).await;
 
         let mut sse_stream = Sse::new(stream);
 
@@ -280,27 +638,980 @@ async fn chat_completions(
             sse_stream = sse_stream.keep_alive(KeepAlive::default().interval(keep_alive));
         }
 
-        Ok(sse_stream.into_response())
+        let mut response = sse_stream.into_response();
+        if let Some(token_ids) = token_ids {
+            response.headers_mut().insert(DEBUG_TOKEN_IDS, token_ids);
+        }
+        Ok(response)
     } else {
-        let response = NvCreateChatCompletionResponse::from_annotated_stream(stream.into())
+        let mut response =
+            NvCreateChatCompletionResponse::from_annotated_stream(timing.tap(stream))
+                .await
+                .map_err(|e| {
+                    tracing::error!(
+                        request_id,
+                        "Failed to fold chat completions stream for: {:?}",
+                        e
+                    );
+                    ErrorResponse::internal_server_error(&format!(
+                        "Failed to fold chat completions stream: {}",
+                        e
+                    ))
+                })?;
+        let json_repair = if repair {
+            repair_response_json(&mut response)
+        } else {
+            None
+        };
+
+        inflight.mark_ok();
+        timing
+            .latency()
+            .log(&request_id, &model, timing.stop_cause());
+        timing.record_metrics(&state.metrics, &model);
+        if let Some(key) = cache_key {
+            state.response_cache.insert(key, response.clone());
+        }
+        let mut response = Json(response).into_response();
+        response
+            .headers_mut()
+            .insert(SERVER_TIMING, timing.header_value());
+        if let Some(token_ids) = token_ids {
+            response.headers_mut().insert(DEBUG_TOKEN_IDS, token_ids);
+        }
+        if cache_key.is_some() {
+            response
+                .headers_mut()
+                .insert(X_CACHE, HeaderValue::from_static("MISS"));
+        }
+        if let Some(json_repair) = json_repair {
+            response
+                .headers_mut()
+                .insert(X_JSON_REPAIR, HeaderValue::from_static(json_repair));
+        }
+        Ok(response)
+    }
+}
+
+/// For `stream_options: {"include_usage": true}`, take the usage off the chunks and send it at the
+/// end in a chunk of its own with no choices, as OpenAI does. The token counts are the engine's:
+/// the preprocessor's for core engines, the remote's for proxies and remote workers. Engines
+/// which don't count completion tokens get one per chunk with content instead.
+fn with_usage_chunk(
+    mut stream: ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+) -> ManyOut<Annotated<NvCreateChatCompletionStreamResponse>> {
+    let ctx = stream.context();
+    let stream_ctx = ctx.clone();
+    let output = async_stream::stream! {
+        let mut reported = None;
+        let mut content_chunks = 0;
+        let mut last = None;
+        let mut failed = false;
+        while let Some(mut response) = stream.next().await {
+            failed |= response.is_error();
+            if let Some(data) = response.data.as_mut() {
+                if let Some(usage) = data.inner.usage.take() {
+                    reported = Some(usage);
+                }
+                // A server we proxy sends its own usage chunk, ours replaces it
+                if data.inner.choices.is_empty() {
+                    continue;
+                }
+                let has_content = data.inner.choices.iter().any(|choice| {
+                    choice.delta.content.as_ref().is_some_and(|content| !content.is_empty())
+                });
+                content_chunks += has_content as u32;
+                last = Some(data.clone());
+            }
+            yield response;
+        }
+        if failed || stream_ctx.is_stopped() {
+            return;
+        }
+        let Some(mut chunk) = last else {
+            return;
+        };
+        let mut usage = reported.unwrap_or(async_openai::types::CompletionUsage {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+            prompt_tokens_details: None,
+            completion_tokens_details: None,
+        });
+        if usage.completion_tokens == 0 {
+            usage.completion_tokens = content_chunks;
+        }
+        usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+        chunk.inner.choices.clear();
+        chunk.inner.usage = Some(usage);
+        yield Annotated::from_data(chunk);
+    };
+    ResponseStream::new(Box::pin(output), ctx)
+}
+
+/// Whether the request asks for JSON output, `response_format: json_object` or `json_schema`
+fn wants_json(request: &NvCreateChatCompletionRequest) -> bool {
+    use async_openai::types::ResponseFormat;
+    matches!(
+        request.inner.response_format,
+        Some(ResponseFormat::JsonObject | ResponseFormat::JsonSchema { .. })
+    )
+}
+
+/// Repair the content of the response's choices which isn't valid JSON. `repaired` if that
+/// worked for all of them, `failed` if any is left as generated, None if they were all valid.
+fn repair_response_json(response: &mut NvCreateChatCompletionResponse) -> Option<&'static str> {
+    let mut outcome = None;
+    for choice in response.inner.choices.iter_mut() {
+        let Some(content) = choice.message.content.as_mut() else {
+            continue;
+        };
+        match repair_json(content) {
+            JsonRepair::Valid => {}
+            JsonRepair::Repaired(repaired) => {
+                *content = repaired;
+                outcome = outcome.or(Some("repaired"));
+            }
+            JsonRepair::Failed => {
+                tracing::warn!(index = choice.index, "Could not repair the JSON output");
+                outcome = Some("failed");
+            }
+        }
+    }
+    outcome
+}
+
+/// The [`ANNOTATION_JSON_REPAIR`] for one choice
+#[derive(Serialize)]
+struct JsonRepairAnnotation {
+    index: u32,
+    repaired: Option<String>,
+}
+
+/// Follow the stream with a [`ANNOTATION_JSON_REPAIR`] annotation if the content it streamed
+/// isn't valid JSON. It's too late to change the content by then.
+fn with_json_repair(
+    stream: ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+) -> ManyOut<Annotated<NvCreateChatCompletionStreamResponse>> {
+    let ctx = stream.context();
+    let content: Arc<Mutex<HashMap<u32, String>>> = Default::default();
+    let collected = content.clone();
+    let stream = stream.inspect(move |response| {
+        let Some(data) = &response.data else {
+            return;
+        };
+        let mut collected = collected.lock().unwrap();
+        for choice in &data.inner.choices {
+            if let Some(text) = &choice.delta.content {
+                collected.entry(choice.index).or_default().push_str(text);
+            }
+        }
+    });
+    let annotation = futures::stream::once(async move {
+        let mut repairs = std::mem::take(&mut *content.lock().unwrap())
+            .into_iter()
+            .filter_map(|(index, text)| match repair_json(&text) {
+                JsonRepair::Valid => None,
+                JsonRepair::Repaired(repaired) => Some(JsonRepairAnnotation {
+                    index,
+                    repaired: Some(repaired),
+                }),
+                JsonRepair::Failed => {
+                    tracing::warn!(index, "Could not repair the JSON output");
+                    Some(JsonRepairAnnotation {
+                        index,
+                        repaired: None,
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
+        if repairs.is_empty() {
+            return None;
+        }
+        repairs.sort_by_key(|repair| repair.index);
+        Annotated::from_annotation(ANNOTATION_JSON_REPAIR, &repairs).ok()
+    })
+    .filter_map(futures::future::ready);
+    ResponseStream::new(Box::pin(stream.chain(annotation)), ctx)
+}
+
+/// OpenAI Embeddings Request Handler
+///
+/// This method will handle the incoming request for the /v1/embeddings endpoint. The engine's
+/// responses are combined into one, with an embedding per input in input order.
+#[tracing::instrument(skip_all)]
+async fn embeddings(
+    State(state): State<Arc<DeploymentState>>,
+    headers: HeaderMap,
+    RequestJson(mut request): RequestJson<NvCreateEmbeddingRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    // return a 503 if the service is not ready
+    check_ready(&state)?;
+    state.substitute_model(&state.embeddings_engines, &mut request.inner.model);
+
+    // todo - extract distributed tracing id and context id from headers
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let inputs = request.input_count();
+    let model = request.inner.model.clone();
+
+    let engine = select_engine(&state.embeddings_engines.lock().unwrap(), &model, &headers)?;
+
+    // wait for a slot if the number of concurrent requests is limited
+    let permit = state.admit(state.model_priority(&model)).await;
+
+    // this will increment the inflight gauge for the model
+    let mut inflight = state.create_inflight_guard(&model, Endpoint::Embeddings, false);
+
+    let request = Context::with_id(request, request_id.clone());
+    let stream = engine
+        .generate(request)
+        .await
+        .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to generate embeddings"))?;
+    let response = NvCreateEmbeddingResponse::from_annotated_stream(Box::pin(stream))
+        .await
+        .map_err(|e| {
+            tracing::error!(request_id, "Failed to fold embeddings stream: {:?}", e);
+            ErrorResponse::internal_server_error(&format!("Failed to fold embeddings stream: {e}"))
+        })?;
+    drop(permit);
+
+    if response.inner.data.len() != inputs {
+        return Err(ErrorResponse::internal_server_error(&format!(
+            "Engine returned {} embeddings for {inputs} inputs",
+            response.inner.data.len()
+        )));
+    }
+
+    inflight.mark_ok();
+    Ok(Json(response).into_response())
+}
+
+/// Prompt Token Count Handler
+///
+/// Counts the tokens in the prompt of a chat completions request, without generating anything.
+/// The request goes through the same prompt template and tokenizer as `/v1/chat/completions`, so
+/// this is only available for models whose requests we pre-process.
+#[tracing::instrument(skip_all)]
+async fn tokenize_count(
+    State(state): State<Arc<DeploymentState>>,
+    RequestJson(request): RequestJson<NvCreateChatCompletionRequest>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    check_ready(&state)?;
+
+    let model = request.inner.model.clone();
+    state
+        .get_chat_completions_engine(&model)
+        .map_err(|_| ErrorResponse::model_not_found(&model))?;
+    let Some(preprocessor) = state.get_preprocessor(&model) else {
+        return Err(ErrorResponse::from_http_error(HttpError {
+            code: 400,
+            message: format!(
+                "Token counts are not available for model '{model}', its prompts are templated \
+                 and tokenized by the engine"
+            ),
+        }));
+    };
+
+    let prompt_tokens =
+        tokio::task::spawn_blocking(move || preprocessor.count_prompt_tokens(&request))
             .await
             .map_err(|e| {
-                tracing::error!(
-                    request_id,
-                    "Failed to fold chat completions stream for: {:?}",
-                    e
-                );
-                ErrorResponse::internal_server_error(&format!(
-                    "Failed to fold chat completions stream: {}",
-                    e
-                ))
-            })?;
+                ErrorResponse::internal_server_error(&format!("Failed to count tokens: {e}"))
+            })?
+            .map_err(|e| ErrorResponse::from_anyhow(e, "Failed to count tokens"))?;
+
+    Ok(Json(TokenizeCount {
+        model,
+        prompt_tokens,
+    })
+    .into_response())
+}
 
-        inflight.mark_ok();
-        Ok(Json(response).into_response())
+#[derive(Serialize)]
+struct TokenizeCount {
+    model: String,
+    prompt_tokens: usize,
+}
+
+/// Time spent in each phase of a non-streaming request, returned in the `Server-Timing` header so
+/// that it shows up in browser devtools.
+///
+/// - `queue`: request received until it was admitted, see `max_concurrent_requests`.
+/// - `preprocess`: request received until the engine accepted it. For engines where we do the
+///   pre-processing that includes prompt templating and tokenization.
+/// - `ttft`: engine accepted the request until the first response.
+/// - `generate`: first response until the stream completed.
+#[derive(Clone)]
+struct ServerTiming {
+    start: Instant,
+    admitted: Option<Instant>,
+    engine_start: Option<Instant>,
+    first_response: Arc<OnceLock<Instant>>,
+
+    /// The `max_tokens` the client asked for, None if we filled in the default
+    requested_max_tokens: Option<u32>,
+    finish: Arc<Mutex<Finish>>,
+}
+
+/// How the stream said generation ended
+#[derive(Default)]
+struct Finish {
+    /// From the backend's decode loop, engines which don't run behind it only send the reason
+    cause: Option<StopCause>,
+    reason: Option<String>,
+    completion_tokens: Option<u32>,
+    /// Responses with generated text, the token count for engines which don't report usage
+    content_responses: u32,
+}
+
+/// Why generation ended, for the completion log. The backend's [`StopCause::Length`] is a
+/// [`StopCause::ServerCap`] if the client didn't ask for a `max_tokens`, as we filled it in.
+fn classify_stop_cause(
+    cause: Option<StopCause>,
+    finish_reason: Option<&str>,
+    requested_max_tokens: Option<u32>,
+) -> Option<StopCause> {
+    let cause = cause.or_else(|| StopCause::from_finish_reason(finish_reason?))?;
+    match (cause, requested_max_tokens) {
+        (StopCause::Length, None) => Some(StopCause::ServerCap),
+        (cause, _) => Some(cause),
     }
 }
 
+/// Responses which say why generation ended
+trait ReportsFinish {
+    fn record_finish(&self, finish: &mut Finish);
+}
+
+impl ReportsFinish for NvCreateChatCompletionStreamResponse {
+    fn record_finish(&self, finish: &mut Finish) {
+        use async_openai::types::FinishReason;
+        let has_content = self.inner.choices.iter().any(|choice| {
+            choice
+                .delta
+                .content
+                .as_ref()
+                .is_some_and(|content| !content.is_empty())
+        });
+        finish.content_responses += has_content as u32;
+        if let Some(reason) = self.inner.choices.iter().find_map(|c| c.finish_reason) {
+            let reason = match reason {
+                FinishReason::Stop => "stop",
+                FinishReason::Length => "length",
+                FinishReason::ToolCalls => "tool_calls",
+                FinishReason::ContentFilter => "content_filter",
+                FinishReason::FunctionCall => "function_call",
+            };
+            finish.reason = Some(reason.to_string());
+        }
+        if let Some(usage) = &self.inner.usage {
+            finish.completion_tokens = Some(usage.completion_tokens);
+        }
+    }
+}
+
+impl ReportsFinish for CompletionResponse {
+    fn record_finish(&self, finish: &mut Finish) {
+        let has_content = self.choices.iter().any(|choice| !choice.text.is_empty());
+        finish.content_responses += has_content as u32;
+        if let Some(reason) = self.choices.iter().find_map(|c| c.finish_reason.clone()) {
+            finish.reason = Some(reason);
+        }
+        if let Some(usage) = &self.usage {
+            finish.completion_tokens = Some(usage.completion_tokens.max(0) as u32);
+        }
+    }
+}
+
+/// How long a request spent in each phase, for the completion log and the `latency` annotation.
+/// The phases add up to the total.
+#[derive(Debug, Serialize)]
+struct LatencyBreakdown {
+    /// Request received until it was admitted
+    queue_ms: f64,
+
+    /// Admitted until the first response: pre-processing and the engine's prefill
+    prefill_ms: f64,
+
+    /// First response until the stream completed
+    decode_ms: f64,
+
+    total_ms: f64,
+}
+
+impl LatencyBreakdown {
+    fn log(&self, request_id: &str, model: &str, stop_cause: Option<StopCause>) {
+        tracing::info!(
+            request_id,
+            model,
+            stop_cause = stop_cause.map(|cause| cause.as_str()),
+            queue_ms = self.queue_ms,
+            prefill_ms = self.prefill_ms,
+            decode_ms = self.decode_ms,
+            total_ms = self.total_ms,
+            "Request completed"
+        );
+    }
+}
+
+impl ServerTiming {
+    fn new(requested_max_tokens: Option<u32>) -> Self {
+        ServerTiming {
+            start: Instant::now(),
+            admitted: None,
+            engine_start: None,
+            first_response: Arc::new(OnceLock::new()),
+            requested_max_tokens,
+            finish: Arc::new(Mutex::new(Finish::default())),
+        }
+    }
+
+    fn admitted(&mut self) {
+        self.admitted = Some(Instant::now());
+    }
+
+    fn engine_started(&mut self) {
+        self.engine_start = Some(Instant::now());
+    }
+
+    /// Call once the stream is complete
+    fn latency(&self) -> LatencyBreakdown {
+        let end = Instant::now();
+        let admitted = self.admitted.unwrap_or(self.start);
+        let first_response = self.first_response.get().copied().unwrap_or(end);
+        LatencyBreakdown {
+            queue_ms: ms(self.start, admitted),
+            prefill_ms: ms(admitted, first_response),
+            decode_ms: ms(first_response, end),
+            total_ms: ms(self.start, end),
+        }
+    }
+
+    /// Record when the first response arrives, and when the stream completes log the latency
+    /// breakdown and record the metrics. If `annotate` the latency breakdown is also sent as a
+    /// `latency` annotation at the end of the stream.
+    fn tap_latency<T: Data + ReportsFinish>(
+        &self,
+        stream: ManyOut<Annotated<T>>,
+        request_id: String,
+        model: String,
+        annotate: bool,
+        metrics: Arc<Metrics>,
+    ) -> ManyOut<Annotated<T>> {
+        let ctx = stream.context();
+        let timing = self.clone();
+        let latency = futures::stream::once(async move {
+            let latency = timing.latency();
+            latency.log(&request_id, &model, timing.stop_cause());
+            timing.record_metrics(&metrics, &model);
+            if annotate {
+                Annotated::from_annotation(ANNOTATION_LATENCY, &latency).ok()
+            } else {
+                None
+            }
+        })
+        .filter_map(futures::future::ready);
+        ResponseStream::new(Box::pin(self.tap(stream).chain(latency)), ctx)
+    }
+
+    /// Record when the first response arrives, and how generation ended
+    fn tap<T: Data + ReportsFinish>(
+        &self,
+        stream: ManyOut<Annotated<T>>,
+    ) -> DataStream<Annotated<T>> {
+        let first_response = self.first_response.clone();
+        let finish = self.finish.clone();
+        Box::pin(stream.filter(move |response| {
+            first_response.get_or_init(Instant::now);
+            if let Some(data) = &response.data {
+                data.record_finish(&mut finish.lock().unwrap());
+            }
+            // the backend's stop cause is for us, not the client
+            let is_stop_cause = response.event.as_deref() == Some(ANNOTATION_STOP_CAUSE);
+            if is_stop_cause {
+                finish.lock().unwrap().cause = response
+                    .comment
+                    .as_ref()
+                    .and_then(|comment| comment.first())
+                    .and_then(|cause| serde_json::from_str(cause).ok());
+            }
+            futures::future::ready(!is_stop_cause)
+        }))
+    }
+
+    /// Call once the stream is complete
+    fn record_metrics(&self, metrics: &Metrics, model: &str) {
+        let end = Instant::now();
+        let first_response = self.first_response.get().copied();
+        let finish = self.finish.lock().unwrap();
+        metrics.observe_generation(
+            model,
+            first_response.map(|first| first.saturating_duration_since(self.start)),
+            first_response.map_or(Duration::ZERO, |first| end.saturating_duration_since(first)),
+            finish
+                .completion_tokens
+                .unwrap_or(finish.content_responses)
+                .into(),
+        );
+    }
+
+    /// Call once the stream is complete
+    fn stop_cause(&self) -> Option<StopCause> {
+        let finish = self.finish.lock().unwrap();
+        classify_stop_cause(
+            finish.cause,
+            finish.reason.as_deref(),
+            self.requested_max_tokens,
+        )
+    }
+
+    /// Call once the stream is complete
+    fn header_value(&self) -> HeaderValue {
+        let end = Instant::now();
+        let admitted = self.admitted.unwrap_or(self.start);
+        let engine_start = self.engine_start.unwrap_or(end);
+        let first_response = self.first_response.get().copied().unwrap_or(end);
+        let value = format!(
+            "queue;dur={:.3}, preprocess;dur={:.3}, ttft;dur={:.3}, generate;dur={:.3}",
+            ms(self.start, admitted),
+            ms(self.start, engine_start),
+            ms(engine_start, first_response),
+            ms(first_response, end),
+        );
+        // Only ASCII, always valid
+        HeaderValue::from_str(&value).unwrap()
+    }
+}
+
+/// Milliseconds between two instants
+fn ms(from: Instant, to: Instant) -> f64 {
+    to.saturating_duration_since(from).as_secs_f64() * 1000.0
+}
+
+/// The engine named by the `X-Engine` header, or the model's default engine without the header
+fn select_engine<E: Clone>(
+    engines: &ModelEngines<E>,
+    model: &str,
+    headers: &HeaderMap,
+) -> Result<E, (StatusCode, Json<ErrorResponse>)> {
+    let Some(engine_name) = headers.get(X_ENGINE) else {
+        return engines
+            .get(model)
+            .cloned()
+            .ok_or_else(|| ErrorResponse::model_not_found(model));
+    };
+    let engine_name = engine_name.to_str().unwrap_or_default();
+    if let Some(engine) = engines.get_named(model, engine_name) {
+        return Ok(engine.clone());
+    }
+    if !engines.contains(model) {
+        return Err(ErrorResponse::model_not_found(model));
+    }
+    let available = engines.engine_names(model);
+    let message = if available.is_empty() {
+        format!(
+            "Unknown engine '{engine_name}', model '{model}' has a single engine. Send the \
+             request without X-Engine."
+        )
+    } else {
+        format!(
+            "Unknown engine '{engine_name}' for model '{model}', available engines: {}",
+            available.join(", ")
+        )
+    };
+    Err(ErrorResponse::from_http_error(HttpError {
+        code: 400,
+        message,
+    }))
+}
+
+/// Responses which report the `system_fingerprint` of the backend
+trait SystemFingerprint {
+    fn set_system_fingerprint(&mut self, system_fingerprint: String);
+}
+
+impl SystemFingerprint for NvCreateChatCompletionStreamResponse {
+    fn set_system_fingerprint(&mut self, system_fingerprint: String) {
+        self.inner.system_fingerprint = Some(system_fingerprint);
+    }
+}
+
+impl SystemFingerprint for CompletionResponse {
+    fn set_system_fingerprint(&mut self, system_fingerprint: String) {
+        self.system_fingerprint = Some(system_fingerprint);
+    }
+}
+
+/// The `created` timestamp of a response
+trait Created {
+    fn set_created(&mut self, created: u64);
+}
+
+impl Created for NvCreateChatCompletionStreamResponse {
+    fn set_created(&mut self, created: u64) {
+        self.inner.created = created as u32;
+    }
+}
+
+impl Created for CompletionResponse {
+    fn set_created(&mut self, created: u64) {
+        self.created = created;
+    }
+}
+
+/// Give every chunk of a response the same `created`, the time the request was received.
+/// Engines may otherwise stamp each chunk as it is made, which would also leave the aggregated
+/// response with the time of its first chunk.
+fn with_created<T: Created + Data>(
+    stream: ManyOut<Annotated<T>>,
+    created: u64,
+) -> ManyOut<Annotated<T>> {
+    let ctx = stream.context();
+    let stream = stream.map(move |mut response| {
+        if let Some(data) = response.data.as_mut() {
+            data.set_created(created);
+        }
+        response
+    });
+    ResponseStream::new(Box::pin(stream), ctx)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+impl DeploymentState {
+    /// Replace the engine's `system_fingerprint` with the configured one, if there is one
+    fn override_system_fingerprint<T: SystemFingerprint + Data>(
+        &self,
+        stream: ManyOut<Annotated<T>>,
+    ) -> ManyOut<Annotated<T>> {
+        let Some(system_fingerprint) = self.system_fingerprint.lock().unwrap().clone() else {
+            return stream;
+        };
+        let ctx = stream.context();
+        let stream = stream.map(move |mut response| {
+            if let Some(data) = response.data.as_mut() {
+                data.set_system_fingerprint(system_fingerprint.clone());
+            }
+            response
+        });
+        ResponseStream::new(Box::pin(stream), ctx)
+    }
+}
+
+/// Responses which say why generation ended, so a missing reason can be filled in
+trait DefaultFinishReason: Clone {
+    fn has_finish_reason(&self) -> bool;
+
+    /// This chunk with the content removed and `finish_reason` set to `stop`
+    fn stop_chunk(self) -> Self;
+
+    /// A chunk with no content and `finish_reason` `stop`, for engines which sent no chunks
+    fn empty_stop_chunk(model: &str) -> Self;
+}
+
+impl DefaultFinishReason for NvCreateChatCompletionStreamResponse {
+    fn has_finish_reason(&self) -> bool {
+        self.inner.choices.iter().any(|c| c.finish_reason.is_some())
+    }
+
+    fn stop_chunk(mut self) -> Self {
+        // usage was already reported, if at all
+        self.inner.usage = None;
+        for choice in self.inner.choices.iter_mut() {
+            choice.delta.role = None;
+            choice.delta.content = None;
+            choice.delta.tool_calls = None;
+            choice.delta.refusal = None;
+            choice.logprobs = None;
+            choice.finish_reason = Some(async_openai::types::FinishReason::Stop);
+        }
+        self
+    }
+
+    #[allow(deprecated)]
+    fn empty_stop_chunk(model: &str) -> Self {
+        let delta = async_openai::types::ChatCompletionStreamResponseDelta {
+            role: Some(async_openai::types::Role::Assistant),
+            content: Some(String::new()),
+            tool_calls: None,
+            function_call: None,
+            refusal: None,
+        };
+        NvCreateChatCompletionStreamResponse {
+            inner: async_openai::types::CreateChatCompletionStreamResponse {
+                id: format!("chatcmpl-{}", uuid::Uuid::new_v4()),
+                object: "chat.completion.chunk".to_string(),
+                // set by `with_created`
+                created: 0,
+                model: model.to_string(),
+                system_fingerprint: None,
+                service_tier: None,
+                choices: vec![async_openai::types::ChatChoiceStream {
+                    index: 0,
+                    delta,
+                    finish_reason: Some(async_openai::types::FinishReason::Stop),
+                    logprobs: None,
+                }],
+                usage: None,
+            },
+        }
+    }
+}
+
+impl DefaultFinishReason for CompletionResponse {
+    fn has_finish_reason(&self) -> bool {
+        self.choices.iter().any(|c| c.finish_reason.is_some())
+    }
+
+    fn stop_chunk(mut self) -> Self {
+        self.usage = None;
+        for choice in self.choices.iter_mut() {
+            choice.text = String::new();
+            choice.logprobs = None;
+            choice.finish_reason = Some("stop".to_string());
+        }
+        self
+    }
+
+    fn empty_stop_chunk(model: &str) -> Self {
+        CompletionResponse {
+            id: format!("cmpl-{}", uuid::Uuid::new_v4()),
+            object: "text_completion".to_string(),
+            // set by `with_created`
+            created: 0,
+            model: model.to_string(),
+            system_fingerprint: None,
+            choices: vec![CompletionChoice {
+                text: String::new(),
+                index: 0,
+                finish_reason: Some("stop".to_string()),
+                logprobs: None,
+            }],
+            usage: None,
+        }
+    }
+}
+
+impl DeploymentState {
+    /// With a request timeout, end streams whose engine stalls with an error. Streaming clients
+    /// get the responses so far then an error event; non-streaming clients get a 500, and none
+    /// of the partial response.
+    fn with_request_timeout<T: Data>(
+        &self,
+        stream: ManyOut<Annotated<T>>,
+    ) -> ManyOut<Annotated<T>> {
+        match *self.request_timeout.lock().unwrap() {
+            Some(timeout) => crate::engines::with_inter_token_timeout(stream, timeout),
+            None => stream,
+        }
+    }
+
+    /// If the engine ends the stream without a finish reason, add a last chunk with
+    /// `finish_reason: stop`, or with `--strict-finish-reason` an error. Streams which end in an
+    /// error, or because the request was stopped, are left as they are.
+    fn default_finish_reason<T: DefaultFinishReason + Data>(
+        &self,
+        mut stream: ManyOut<Annotated<T>>,
+        model: &str,
+    ) -> ManyOut<Annotated<T>> {
+        let strict = self.strict_finish_reason.load(Ordering::Relaxed);
+        let ctx = stream.context();
+        let stream_ctx = ctx.clone();
+        let model = model.to_string();
+        let output = async_stream::stream! {
+            let mut last = None;
+            let mut finished = false;
+            while let Some(response) = stream.next().await {
+                finished |= response.is_error();
+                if let Some(data) = response.data.as_ref() {
+                    finished |= data.has_finish_reason();
+                    if !finished {
+                        last = Some(data.clone());
+                    }
+                }
+                yield response;
+            }
+            if finished || stream_ctx.is_stopped() {
+                return;
+            }
+            let Some(last) = last else {
+                // Not a single chunk. Without one clients wait for content that isn't coming.
+                tracing::warn!(request_id = stream_ctx.id(), "Engine sent no output");
+                if strict {
+                    yield Annotated::from_error("engine ended the stream without any output".to_string());
+                } else {
+                    if let Ok(warning) = Annotated::from_annotation(ANNOTATION_WARNING, &"engine sent no output") {
+                        yield warning;
+                    }
+                    yield Annotated::from_data(T::empty_stop_chunk(&model));
+                }
+                return;
+            };
+            if strict {
+                tracing::warn!(request_id = stream_ctx.id(), "Engine did not set a finish reason");
+                yield Annotated::from_error("engine ended the stream without a finish reason".to_string());
+            } else {
+                yield Annotated::from_data(last.stop_chunk());
+            }
+        };
+        ResponseStream::new(Box::pin(output), ctx)
+    }
+}
+
+/// Responses whose content can be sent in several pieces
+trait SplitContent: Sized {
+    /// This chunk as chunks of at most `max_chars` characters of content each, which concatenate
+    /// to the original. The first piece keeps the role, the last the finish reason and usage.
+    /// Chunks with several choices or logprobs are left whole.
+    fn split_content(self, max_chars: usize) -> Vec<Self>;
+}
+
+/// `text` in pieces of at most `max_chars` characters, never splitting a character
+fn split_chars(text: &str, max_chars: usize) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .chunks(max_chars)
+        .map(|piece| piece.iter().collect())
+        .collect()
+}
+
+impl SplitContent for NvCreateChatCompletionStreamResponse {
+    fn split_content(self, max_chars: usize) -> Vec<Self> {
+        let pieces = match self.inner.choices.as_slice() {
+            [choice] if choice.logprobs.is_none() => match choice.delta.content.as_deref() {
+                Some(content) => split_chars(content, max_chars),
+                None => vec![],
+            },
+            _ => vec![],
+        };
+        if pieces.len() <= 1 {
+            return vec![self];
+        }
+        let last = pieces.len() - 1;
+        pieces
+            .into_iter()
+            .enumerate()
+            .map(|(i, piece)| {
+                let mut chunk = self.clone();
+                let choice = &mut chunk.inner.choices[0];
+                choice.delta.content = Some(piece);
+                if i > 0 {
+                    choice.delta.role = None;
+                    choice.delta.tool_calls = None;
+                    choice.delta.refusal = None;
+                }
+                if i < last {
+                    choice.finish_reason = None;
+                    chunk.inner.usage = None;
+                }
+                chunk
+            })
+            .collect()
+    }
+}
+
+impl SplitContent for CompletionResponse {
+    fn split_content(self, max_chars: usize) -> Vec<Self> {
+        let pieces = match self.choices.as_slice() {
+            [choice] if choice.logprobs.is_none() => split_chars(&choice.text, max_chars),
+            _ => vec![],
+        };
+        if pieces.len() <= 1 {
+            return vec![self];
+        }
+        let last = pieces.len() - 1;
+        pieces
+            .into_iter()
+            .enumerate()
+            .map(|(i, piece)| {
+                let mut chunk = self.clone();
+                chunk.choices[0].text = piece;
+                if i < last {
+                    chunk.choices[0].finish_reason = None;
+                    chunk.usage = None;
+                }
+                chunk
+            })
+            .collect()
+    }
+}
+
+impl DeploymentState {
+    /// With `max_chunk_chars`, send chunks with more content than that as several SSE events,
+    /// for clients with small buffers
+    fn split_large_chunks<T: SplitContent + Data>(
+        &self,
+        stream: ManyOut<Annotated<T>>,
+    ) -> ManyOut<Annotated<T>> {
+        let Some(max_chars) = *self.max_chunk_chars.lock().unwrap() else {
+            return stream;
+        };
+        let ctx = stream.context();
+        let stream = stream.flat_map(move |mut response| {
+            let responses = match response.data.take() {
+                Some(data) => data
+                    .split_content(max_chars)
+                    .into_iter()
+                    .map(|data| Annotated {
+                        data: Some(data),
+                        ..response.clone()
+                    })
+                    .collect(),
+                None => vec![response],
+            };
+            futures::stream::iter(responses)
+        });
+        ResponseStream::new(Box::pin(stream), ctx)
+    }
+}
+
+impl DeploymentState {
+    /// Copy the response to the tee sink, if the request asks for it and there is one
+    fn tee<T: Serialize + Data>(
+        &self,
+        stream: ManyOut<Annotated<T>>,
+        headers: &HeaderMap,
+        request_id: &str,
+        model: &str,
+    ) -> ManyOut<Annotated<T>> {
+        if !header_is_true(headers, X_TEE) {
+            return stream;
+        }
+        match self.tee_sink.lock().unwrap().clone() {
+            Some(sink) => super::tee::tee(stream, sink, request_id.to_string(), model.to_string()),
+            None => {
+                tracing::debug!("Ignoring X-Tee, there is no tee sink");
+                stream
+            }
+        }
+    }
+}
+
+/// Once the service is shutting down, new requests get a 503 with `Retry-After`, so the client
+/// tries another instance, while the ones already in flight finish.
+async fn reject_while_draining(
+    State(state): State<Arc<DeploymentState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.is_draining() {
+        return next.run(request).await;
+    }
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, DRAINING_RETRY_AFTER_SECS.to_string())],
+        Json(ErrorResponse {
+            error: "Service is shutting down".to_string().into(),
+        }),
+    )
+        .into_response()
+}
+
 // todo - abstract this to the top level lib.rs to be reused
 // todo - move the service_observer to its own state/arc
 fn check_ready(_state: &Arc<DeploymentState>) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
@@ -310,6 +1621,50 @@ fn check_ready(_state: &Arc<DeploymentState>) -> Result<(), (StatusCode, Json<Er
     Ok(())
 }
 
+/// The profile named by the `X-Sampling-Profile` header, if there is one
+fn sampling_profile(
+    state: &DeploymentState,
+    headers: &HeaderMap,
+) -> Result<Option<SamplingProfile>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(name) = headers.get(X_SAMPLING_PROFILE) else {
+        return Ok(None);
+    };
+    let name = name.to_str().unwrap_or_default();
+    let profiles = state.sampling_profiles.lock().unwrap();
+    if let Some(profile) = profiles.get(name) {
+        return Ok(Some(profile.clone()));
+    }
+    let mut available = profiles.keys().map(String::as_str).collect::<Vec<_>>();
+    available.sort_unstable();
+    let message = if available.is_empty() {
+        format!("Unknown sampling profile '{name}', this server has none")
+    } else {
+        format!(
+            "Unknown sampling profile '{name}', available profiles: {}",
+            available.join(", ")
+        )
+    };
+    Err(ErrorResponse::from_http_error(HttpError {
+        code: 400,
+        message,
+    }))
+}
+
+/// Reject requests asking for more choices than `--max-n` allows
+fn check_n(
+    state: &DeploymentState,
+    n: Option<u8>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let max_n = state.max_n.load(Ordering::Relaxed);
+    match n {
+        Some(n) if n > max_n => Err(ErrorResponse::from_http_error(HttpError {
+            code: 400,
+            message: format!("n must be at most {max_n}, got {n}"),
+        })),
+        _ => Ok(()),
+    }
+}
+
 /// list models handler, non-standard format
 async fn list_models_custom(
     State(state): State<Arc<DeploymentState>>,
@@ -335,8 +1690,18 @@ async fn list_models_custom(
         .cloned()
         .collect::<Vec<String>>();
 
+    let embeddings_models = state
+        .embeddings_engines
+        .lock()
+        .unwrap()
+        .engines
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+
     models.insert("chat_completion_models", chat_models);
     models.insert("completion_models", completion_models);
+    models.insert("embeddings_models", embeddings_models);
 
     Ok(Json(models).into_response())
 }
@@ -350,7 +1715,7 @@ async fn list_models_custom(
 ///      "id": "model-id-0",
 ///      "object": "model",
 ///      "created": 1686935002,
-///      "owned_by": "organization-owner"
+///      "owned_by": "dynemo"
 ///    },
 ///    ]
 /// }
@@ -359,10 +1724,7 @@ async fn list_models_openai(
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     check_ready(&state)?;
 
-    let created = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
+    let created = unix_now();
     let mut data = Vec::new();
 
     let models: HashSet<String> = state
@@ -372,17 +1734,21 @@ async fn list_models_openai(
         .engines
         .keys()
         .chain(state.completion_engines.lock().unwrap().engines.keys())
+        .chain(state.embeddings_engines.lock().unwrap().engines.keys())
         .cloned()
         .collect();
 
+    let metadata = state.model_metadata.lock().unwrap();
     for model_id in models {
         data.push(ModelListing {
-            id: model_id.clone(),
-            object: "object",
+            metadata: metadata.get(&model_id),
+            id: model_id,
+            object: "model",
             created,                        // Where would this come from? The GGUF?
-            owned_by: "nvidia".to_string(), // Get organization from GGUF
+            owned_by: "dynemo".to_string(), // Get organization from GGUF
         });
     }
+    drop(metadata);
 
     let out = ListModelOpenAI {
         object: "list",
@@ -400,9 +1766,11 @@ struct ListModelOpenAI {
 #[derive(Serialize)]
 struct ModelListing {
     id: String,
-    object: &'static str, // always "object"
+    object: &'static str, // always "model"
     created: u64,         //  Seconds since epoch
     owned_by: String,
+    #[serde(flatten)]
+    metadata: ModelMetadata,
 }
 
 /// This method will consume a stream of SSE events and forward them to a new stream defined by a tokio channel.
@@ -417,16 +1785,23 @@ async fn monitor_for_disconnects(
     >,
     context: Arc<dyn AsyncEngineContext>,
     inflight: InflightGuard,
+    permit: Option<SchedulerPermit>,
 ) -> ReceiverStream<Result<Event, axum::Error>> {
     let (tx, rx) = tokio::sync::mpsc::channel(8);
 
     tokio::spawn(async move {
+        // hold the scheduler slot until the stream is done
+        let _permit = permit;
         let mut inflight = inflight;
         let mut stream = stream;
+        let mut failed = false;
         while let Some(event) = stream.next().await {
             let event = match event {
                 Ok(event) => Ok(event),
-                Err(err) => Ok(Event::default().event("error").comment(err.to_string())),
+                Err(err) => {
+                    failed = true;
+                    Ok(Event::default().event("error").comment(err.to_string()))
+                }
             };
 
             if (tx.send(event).await).is_err() {
@@ -434,11 +1809,20 @@ async fn monitor_for_disconnects(
                 context.stop_generating();
                 break;
             }
+
+            if failed {
+                // the engine failed mid-stream, the client has the final chunk and the error
+                context.stop_generating();
+                break;
+            }
         }
+        // done with the engine, so the request is no longer listed as active by the time the
+        // client sees the end of the stream
+        drop(stream);
 
-        // the stream completed successfully - mark as ok
-        // this will increment the request counter with an "success" status
-        if tx.send(Ok(Event::default().data("[DONE]"))).await.is_ok() {
+        // close the stream cleanly even on error, so the client knows it has everything
+        // only a completed stream is counted with a "success" status
+        if tx.send(Ok(Event::default().data("[DONE]"))).await.is_ok() && !failed {
             inflight.mark_ok();
         }
     });
@@ -446,6 +1830,172 @@ async fn monitor_for_disconnects(
     ReceiverStream::new(rx)
 }
 
+/// Convert the engine responses into SSE events.
+///
+/// If the engine fails after it has started responding, the client first gets a final chunk like
+/// the last one it saw but with `finish_reason: "error"`, so the partial response is closed out
+/// properly, then the error.
+fn sse_events<T: Serialize + Clone + Data>(
+    stream: ManyOut<Annotated<T>>,
+) -> impl Stream<Item = Result<Event, axum::Error>> + Send {
+    stream
+        .scan(None, |last_data: &mut Option<T>, response| {
+            let mut events = Vec::with_capacity(2);
+            if response.is_error() {
+                if let Some(last) = last_data.take() {
+                    events.push(
+                        error_chunk(&last).and_then(|chunk| Event::default().json_data(chunk)),
+                    );
+                }
+            } else if response.data.is_some() {
+                last_data.clone_from(&response.data);
+            }
+            events.push(Event::try_from(EventConverter::from(response)));
+            futures::future::ready(Some(futures::stream::iter(events)))
+        })
+        .flatten()
+}
+
+/// Whether to stream the response. The request's `stream` field always decides: an `Accept`
+/// header which contradicts it, e.g. `Accept: application/json` with `stream: true`, is ignored
+/// with a warning.
+fn resolve_streaming(stream: Option<bool>, headers: &HeaderMap) -> bool {
+    let streaming = stream.unwrap_or(false);
+    let media_type = if streaming {
+        "text/event-stream"
+    } else {
+        "application/json"
+    };
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    if let Some(accept) = accept {
+        if !accepts(accept, media_type) {
+            tracing::warn!(
+                accept,
+                stream = streaming,
+                "Accept header contradicts the request's stream field, responding with {media_type}"
+            );
+        }
+    }
+    streaming
+}
+
+/// Whether an `Accept` header value allows `media_type`, by name or by a wildcard
+fn accepts(accept: &str, media_type: &str) -> bool {
+    let kind = media_type.split('/').next().unwrap_or_default();
+    accept.split(',').any(|range| {
+        let range = range.split(';').next().unwrap_or_default().trim();
+        range == "*/*"
+            || range.eq_ignore_ascii_case(media_type)
+            || range
+                .strip_suffix("/*")
+                .is_some_and(|range| range.eq_ignore_ascii_case(kind))
+    })
+}
+
+/// Whether the client asked for `event: prefill` events while waiting for the first response
+fn wants_prefill_progress(headers: &HeaderMap) -> bool {
+    header_is_true(headers, PREFILL_PROGRESS)
+}
+
+/// Whether an opt-in header is set to `true` or `1`
+fn header_is_true(headers: &HeaderMap, name: HeaderName) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("true") || value == "1")
+}
+
+/// The value of the `X-Debug-Token-Ids` response header: the request's prompt token ids, if the
+/// client asked for them and the service allows it. Only models whose requests we pre-process
+/// have any, other engines tokenize for themselves.
+async fn debug_token_ids<R>(
+    state: &DeploymentState,
+    headers: &HeaderMap,
+    model: &str,
+    request: &R,
+) -> Option<HeaderValue>
+where
+    R: OAIChatLikeRequest + NvExtProvider + Clone + Send + 'static,
+{
+    if !header_is_true(headers, DEBUG_TOKEN_IDS) {
+        return None;
+    }
+    if !state.debug_token_ids.load(Ordering::Relaxed) {
+        tracing::debug!("Ignoring X-Debug-Token-Ids, it is not enabled");
+        return None;
+    }
+    let preprocessor = state.get_preprocessor(model)?;
+    let request = request.clone();
+    let token_ids =
+        tokio::task::spawn_blocking(move || preprocessor.prompt_token_ids(&request)).await;
+    match token_ids {
+        Ok(Ok(token_ids)) => HeaderValue::from_str(&serde_json::to_string(&token_ids).ok()?).ok(),
+        Ok(Err(err)) => {
+            tracing::warn!("Failed to tokenize prompt for X-Debug-Token-Ids: {err}");
+            None
+        }
+        Err(err) => {
+            tracing::warn!("Failed to tokenize prompt for X-Debug-Token-Ids: {err}");
+            None
+        }
+    }
+}
+
+/// Until the engine sends its first response, which for a long prompt can take many seconds of
+/// prefill, send an `event: prefill` every `interval` so the client can tell the request is being
+/// worked on. The event's data is `{"elapsed_ms": <time since the request started>}`.
+fn with_prefill_progress(
+    stream: impl Stream<Item = Result<Event, axum::Error>> + Send + 'static,
+    interval: Duration,
+) -> impl Stream<Item = Result<Event, axum::Error>> + Send {
+    let started = Instant::now();
+    async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        loop {
+            match tokio::time::timeout(interval, stream.next()).await {
+                Ok(Some(event)) => {
+                    yield event;
+                    break;
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    let elapsed_ms = started.elapsed().as_millis() as u64;
+                    yield Event::default()
+                        .event("prefill")
+                        .json_data(serde_json::json!({ "elapsed_ms": elapsed_ms }));
+                }
+            }
+        }
+        while let Some(event) = stream.next().await {
+            yield event;
+        }
+    }
+}
+
+/// A chat or completions chunk with the content removed and `finish_reason` set to `error`
+fn error_chunk<T: Serialize>(last: &T) -> Result<serde_json::Value, axum::Error> {
+    let mut chunk = serde_json::to_value(last).map_err(axum::Error::new)?;
+    if let Some(obj) = chunk.as_object_mut() {
+        // usage was already reported, if at all
+        obj.remove("usage");
+    }
+    if let Some(choices) = chunk.get_mut("choices").and_then(|c| c.as_array_mut()) {
+        for choice in choices.iter_mut().filter_map(|c| c.as_object_mut()) {
+            if choice.contains_key("delta") {
+                choice.insert("delta".to_string(), serde_json::json!({}));
+            }
+            if choice.contains_key("text") {
+                choice.insert("text".to_string(), serde_json::json!(""));
+            }
+            choice.insert("logprobs".to_string(), serde_json::Value::Null);
+            choice.insert("finish_reason".to_string(), serde_json::json!("error"));
+        }
+    }
+    Ok(chunk)
+}
+
 struct EventConverter<T>(Annotated<T>);
 
 impl<T> From<Annotated<T>> for EventConverter<T> {
@@ -500,6 +2050,10 @@ pub fn completions_router(
     let doc = RouteDoc::new(axum::http::Method::POST, &path);
     let router = Router::new()
         .route(&path, post(completions))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_while_draining,
+        ))
         .with_state(state);
     (vec![doc], router)
 }
@@ -511,9 +2065,53 @@ pub fn chat_completions_router(
     path: Option<String>,
 ) -> (Vec<RouteDoc>, Router) {
     let path = path.unwrap_or("/v1/chat/completions".to_string());
+    let docs = vec![
+        RouteDoc::new(axum::http::Method::POST, &path),
+        RouteDoc::new(axum::http::Method::HEAD, &path),
+    ];
+    let router = Router::new()
+        .route(&path, post(chat_completions).head(route_available))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_while_draining,
+        ))
+        .with_state(state);
+    (docs, router)
+}
+
+/// Create an Axum [`Router`] for the OpenAI API Embeddings endpoint
+/// If not path is provided, the default path is `/v1/embeddings`
+pub fn embeddings_router(
+    state: Arc<DeploymentState>,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/v1/embeddings".to_string());
+    let doc = RouteDoc::new(axum::http::Method::POST, &path);
+    let router = Router::new()
+        .route(&path, post(embeddings))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_while_draining,
+        ))
+        .with_state(state);
+    (vec![doc], router)
+}
+
+/// Load balancers probe with `HEAD`. Say the route is served, without calling an engine.
+async fn route_available() -> StatusCode {
+    StatusCode::OK
+}
+
+/// Create an Axum [`Router`] for counting prompt tokens
+/// If not path is provided, the default path is `/v1/tokenize-count`
+pub fn tokenize_count_router(
+    state: Arc<DeploymentState>,
+    path: Option<String>,
+) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/v1/tokenize-count".to_string());
     let doc = RouteDoc::new(axum::http::Method::POST, &path);
     let router = Router::new()
-        .route(&path, post(chat_completions))
+        .route(&path, post(tokenize_count))
         .with_state(state);
     (vec![doc], router)
 }
@@ -563,7 +2161,50 @@ mod tests {
         let err = http_error_from_engine(400).unwrap_err();
         let (status, response) = ErrorResponse::from_anyhow(err, BACKUP_ERROR_MESSAGE);
         assert_eq!(status, StatusCode::BAD_REQUEST);
-        assert_eq!(response.error, "custom error message");
+        assert_eq!(response.error.message(), "custom error message");
+    }
+
+    #[test]
+    fn test_validation_error_response_from_anyhow() {
+        let err = ValidationError {
+            errors: vec![
+                FieldError {
+                    param: Some("temperature".to_string()),
+                    message: "temperature is too high".to_string(),
+                },
+                FieldError {
+                    param: None,
+                    message: "logprobs are not supported".to_string(),
+                },
+            ],
+        }
+        .into_http_error();
+        // still a plain 400 for callers which only know HttpError
+        assert_eq!(err.downcast_ref::<HttpError>().unwrap().code, 400);
+
+        let (status, response) = ErrorResponse::from_anyhow(err, BACKUP_ERROR_MESSAGE);
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            response.error.message(),
+            "temperature is too high; logprobs are not supported"
+        );
+        let body = serde_json::to_value(&response.0).unwrap();
+        assert_eq!(
+            body["error"],
+            serde_json::json!({
+                "message": "temperature is too high; logprobs are not supported",
+                "type": "invalid_request_error",
+                "errors": [
+                    {"param": "temperature", "message": "temperature is too high"},
+                    {"message": "logprobs are not supported"},
+                ],
+            })
+        );
+        // and clients read it back
+        let response: ErrorResponse = serde_json::from_value(body).unwrap();
+        assert!(
+            matches!(response.error, ErrorBody::Validation { errors, .. } if errors.len() == 2)
+        );
     }
 
     #[test]
@@ -571,17 +2212,66 @@ mod tests {
         let err = http_error_from_engine(399).unwrap_err();
         let (status, response) = ErrorResponse::from_anyhow(err, BACKUP_ERROR_MESSAGE);
         assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
-        assert_eq!(response.error, "custom error message");
+        assert_eq!(response.error.message(), "custom error message");
 
         let err = http_error_from_engine(500).unwrap_err();
         let (status, response) = ErrorResponse::from_anyhow(err, BACKUP_ERROR_MESSAGE);
         assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
-        assert_eq!(response.error, "custom error message");
+        assert_eq!(response.error.message(), "custom error message");
 
         let err = http_error_from_engine(501).unwrap_err();
         let (status, response) = ErrorResponse::from_anyhow(err, BACKUP_ERROR_MESSAGE);
         assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
-        assert_eq!(response.error, "custom error message");
+        assert_eq!(response.error.message(), "custom error message");
+    }
+
+    #[test]
+    fn test_stop_cause() {
+        // The backend's cause, as the client sees it
+        assert_eq!(
+            classify_stop_cause(Some(StopCause::Length), Some("length"), Some(16)),
+            Some(StopCause::Length)
+        );
+        assert_eq!(
+            classify_stop_cause(Some(StopCause::ServerCap), Some("length"), Some(16)),
+            Some(StopCause::ServerCap)
+        );
+        // Our default max_tokens
+        assert_eq!(
+            classify_stop_cause(Some(StopCause::Length), Some("length"), None),
+            Some(StopCause::ServerCap)
+        );
+        assert_eq!(
+            classify_stop_cause(Some(StopCause::Stop), Some("stop"), Some(16)),
+            Some(StopCause::Stop)
+        );
+        // Engines without the backend only send a finish reason
+        assert_eq!(
+            classify_stop_cause(None, Some("length"), Some(16)),
+            Some(StopCause::Length)
+        );
+        assert_eq!(
+            classify_stop_cause(None, Some("eos"), None),
+            Some(StopCause::Stop)
+        );
+        assert_eq!(
+            classify_stop_cause(None, Some("content_filter"), None),
+            None
+        );
+        assert_eq!(classify_stop_cause(None, None, None), None);
+    }
+
+    #[test]
+    fn test_accepts() {
+        assert!(accepts("application/json", "application/json"));
+        assert!(accepts("*/*", "text/event-stream"));
+        assert!(accepts(
+            "text/*;q=0.5, application/json",
+            "text/event-stream"
+        ));
+        assert!(accepts("Text/Event-Stream", "text/event-stream"));
+        assert!(!accepts("application/json", "text/event-stream"));
+        assert!(!accepts("text/event-stream", "application/json"));
     }
 
     #[test]
@@ -590,7 +2280,7 @@ mod tests {
         let (status, response) = ErrorResponse::from_anyhow(err, BACKUP_ERROR_MESSAGE);
         assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
         assert_eq!(
-            response.error,
+            response.error.message(),
             format!(
                 "{}: {}",
                 BACKUP_ERROR_MESSAGE,
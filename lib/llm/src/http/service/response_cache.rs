@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cache of responses to greedy (`temperature: 0`), non-streaming chat completions requests, so
+//! that repeating one doesn't run the engine again.
+//!
+//! Responses served from the cache have the header `X-Cache: HIT`, responses which were generated
+//! and stored have `X-Cache: MISS`. Requests which can't be cached have neither. The oldest entry
+//! is evicted first once the cache is full.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionResponse,
+};
+
+/// Identifies a request in the [`ResponseCache`]
+pub type CacheKey = blake3::Hash;
+
+pub struct ResponseCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    capacity: usize,
+    entries: HashMap<CacheKey, NvCreateChatCompletionResponse>,
+    order: VecDeque<CacheKey>,
+}
+
+impl ResponseCache {
+    /// A cache holding up to `capacity` responses. 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        ResponseCache {
+            state: Mutex::new(CacheState {
+                capacity,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// Change how many responses the cache holds, evicting the oldest ones if it shrinks
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.capacity = capacity;
+        state.evict();
+    }
+
+    /// The key for `request`, or None if the cache is disabled or the request's output isn't
+    /// deterministic. `request` must already be in the form sent to the engine.
+    pub fn key(&self, request: &NvCreateChatCompletionRequest) -> Option<CacheKey> {
+        if self.state.lock().unwrap().capacity == 0 || request.inner.temperature != Some(0.0) {
+            return None;
+        }
+        let request = serde_json::to_vec(request).ok()?;
+        Some(blake3::hash(&request))
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<NvCreateChatCompletionResponse> {
+        self.state.lock().unwrap().entries.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: CacheKey, response: NvCreateChatCompletionResponse) {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.insert(key, response).is_none() {
+            state.order.push_back(key);
+        }
+        state.evict();
+    }
+}
+
+impl CacheState {
+    fn evict(&mut self) {
+        while self.order.len() > self.capacity {
+            if let Some(key) = self.order.pop_front() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(content: &str, temperature: f32) -> NvCreateChatCompletionRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": content}],
+            "temperature": temperature,
+        }))
+        .unwrap()
+    }
+
+    fn response(content: &str) -> NvCreateChatCompletionResponse {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "foo",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": content},
+                "finish_reason": "stop",
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let cache = ResponseCache::new(2);
+        assert!(cache.key(&request("a", 0.7)).is_none());
+
+        let a = cache.key(&request("a", 0.0)).unwrap();
+        let b = cache.key(&request("b", 0.0)).unwrap();
+        let c = cache.key(&request("c", 0.0)).unwrap();
+        assert_eq!(a, cache.key(&request("a", 0.0)).unwrap());
+        assert_ne!(a, b);
+
+        cache.insert(a, response("a"));
+        cache.insert(b, response("b"));
+        assert!(cache.get(&a).is_some());
+
+        // The oldest entry goes first
+        cache.insert(c, response("c"));
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&b).is_some());
+        assert!(cache.get(&c).is_some());
+
+        cache.set_capacity(0);
+        assert!(cache.get(&c).is_none());
+        assert!(cache.key(&request("a", 0.0)).is_none());
+    }
+}
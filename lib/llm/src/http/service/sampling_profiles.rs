@@ -0,0 +1,96 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named sets of sampling parameters, such as `creative` or `precise`, which clients pick with
+//! the `X-Sampling-Profile` header instead of setting each parameter.
+//!
+//! A profile only fills in what the request leaves out, so fields set in the request win.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Profiles by name
+pub type SamplingProfiles = HashMap<String, SamplingProfile>;
+
+/// Sampling parameters for requests which don't set them
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SamplingProfile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+}
+
+impl SamplingProfile {
+    pub fn apply_to_chat(&self, request: &mut async_openai::types::CreateChatCompletionRequest) {
+        fill(&mut request.temperature, self.temperature);
+        fill(&mut request.top_p, self.top_p);
+        fill(&mut request.frequency_penalty, self.frequency_penalty);
+        fill(&mut request.presence_penalty, self.presence_penalty);
+    }
+
+    pub fn apply_to_completion(&self, request: &mut async_openai::types::CreateCompletionRequest) {
+        fill(&mut request.temperature, self.temperature);
+        fill(&mut request.top_p, self.top_p);
+        fill(&mut request.frequency_penalty, self.frequency_penalty);
+        fill(&mut request.presence_penalty, self.presence_penalty);
+    }
+}
+
+fn fill(field: &mut Option<f32>, default: Option<f32>) {
+    if field.is_none() {
+        *field = default;
+    }
+}
+
+/// Read profiles from a JSON file, for example
+/// `{"creative": {"temperature": 1.1, "top_p": 0.95}, "precise": {"temperature": 0.2}}`
+pub fn load(path: &Path) -> anyhow::Result<SamplingProfiles> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed reading sampling profiles from {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Invalid sampling profiles in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_rejects_unknown_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("profiles.json");
+
+        std::fs::write(&path, r#"{"precise": {"temperature": 0.2}}"#).unwrap();
+        let profiles = load(&path).unwrap();
+        assert_eq!(profiles["precise"].temperature, Some(0.2));
+        assert_eq!(profiles["precise"].top_p, None);
+
+        // A typo would otherwise silently do nothing
+        std::fs::write(&path, r#"{"precise": {"temprature": 0.2}}"#).unwrap();
+        assert!(load(&path).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Admission control for the HTTP service.
+//!
+//! The [`PriorityScheduler`] limits how many requests are sent to the engines at once. Requests
+//! over the limit wait in a queue and are admitted highest priority first, then in arrival order.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+pub struct PriorityScheduler {
+    max_concurrent: usize,
+    state: Mutex<SchedulerState>,
+}
+
+#[derive(Default)]
+struct SchedulerState {
+    running: usize,
+    waiting: BinaryHeap<Waiter>,
+    next_seq: u64,
+}
+
+struct Waiter {
+    priority: i32,
+    seq: u64,
+    tx: oneshot::Sender<SchedulerPermit>,
+}
+
+impl Ord for Waiter {
+    // Highest priority first, then lowest sequence number (first to arrive)
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+/// A running slot. The slot is released, and handed to the next waiting request, on drop.
+pub struct SchedulerPermit {
+    scheduler: Option<Arc<PriorityScheduler>>,
+}
+
+impl Drop for SchedulerPermit {
+    fn drop(&mut self) {
+        if let Some(scheduler) = self.scheduler.take() {
+            scheduler.release();
+        }
+    }
+}
+
+impl PriorityScheduler {
+    pub fn new(max_concurrent: usize) -> Arc<Self> {
+        Arc::new(PriorityScheduler {
+            max_concurrent: max_concurrent.max(1),
+            state: Mutex::new(SchedulerState::default()),
+        })
+    }
+
+    /// Wait for a running slot. Higher `priority` requests are admitted first.
+    pub async fn acquire(self: &Arc<Self>, priority: i32) -> SchedulerPermit {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < self.max_concurrent && state.waiting.is_empty() {
+                state.running += 1;
+                return SchedulerPermit {
+                    scheduler: Some(self.clone()),
+                };
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiter { priority, seq, tx });
+            rx
+        };
+        // If this future is dropped after the permit was sent, the permit is dropped with the
+        // channel and the slot passes to the next waiter.
+        match rx.await {
+            Ok(permit) => permit,
+            Err(_) => unreachable!("Scheduler dropped a waiter without admitting it"),
+        }
+    }
+
+    /// How many requests are waiting for a slot
+    pub fn waiting(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    /// How many requests hold a slot
+    pub fn running(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    fn release(self: Arc<Self>) {
+        loop {
+            let waiter = {
+                let mut state = self.state.lock().unwrap();
+                match state.waiting.pop() {
+                    Some(waiter) => waiter,
+                    None => {
+                        state.running -= 1;
+                        return;
+                    }
+                }
+            };
+            // The slot passes straight to the waiter, so `running` doesn't change
+            let permit = SchedulerPermit {
+                scheduler: Some(self.clone()),
+            };
+            match waiter.tx.send(permit) {
+                Ok(()) => return,
+                Err(mut permit) => {
+                    // That request went away while waiting, try the next one
+                    permit.scheduler = None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_flex_request_waits_behind_default() {
+        use crate::protocols::openai::ServiceTier;
+
+        let scheduler = PriorityScheduler::new(1);
+        let running = scheduler.acquire(0).await;
+
+        let (order_tx, mut order_rx) = tokio::sync::mpsc::unbounded_channel();
+        let tiers = [
+            ("flex", ServiceTier::Flex),
+            ("default", ServiceTier::Default),
+        ];
+        for (i, (name, tier)) in tiers.into_iter().enumerate() {
+            let scheduler_task = scheduler.clone();
+            let order_tx = order_tx.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler_task.acquire(tier.priority()).await;
+                order_tx.send(name).unwrap();
+            });
+            // Make sure flex is enqueued first
+            while scheduler.waiting() <= i {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        assert_eq!(scheduler.waiting(), 2);
+
+        drop(running);
+        assert_eq!(order_rx.recv().await, Some("default"));
+        assert_eq!(order_rx.recv().await, Some("flex"));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_passes_slot_on() {
+        let scheduler = PriorityScheduler::new(1);
+        let running = scheduler.acquire(0).await;
+
+        let cancelled = tokio::spawn({
+            let scheduler = scheduler.clone();
+            async move { scheduler.acquire(10).await }
+        });
+        while scheduler.waiting() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        cancelled.abort();
+        let _ = cancelled.await;
+
+        drop(running);
+        let _permit = scheduler.acquire(0).await;
+        assert_eq!(scheduler.running(), 1);
+    }
+}
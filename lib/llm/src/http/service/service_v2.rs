@@ -13,19 +13,72 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::admin::{self, ResolvedConfig};
+use super::health::{self, StartupProgress};
+use super::listener::{self, HttpVersion, ServiceListener};
 use super::metrics;
+use super::metrics_export::{self, MetricsExport};
+use super::sampling_profiles::SamplingProfiles;
+use super::tee::TeeEvent;
+use super::version::{self, ServiceVersion};
 use super::ModelManager;
-use anyhow::Result;
+use anyhow::{Context as _, Result};
 use derive_builder::Builder;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
+/// Default for [`HttpServiceConfig::drain_timeout`]
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// After the drain, how long connections have at least to send the responses they're writing
+const MIN_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 pub struct HttpService {
     models: ModelManager,
     router: axum::Router,
     port: u16,
     host: String,
+    registry: metrics::Registry,
+    metrics_export: Vec<MetricsExport>,
+    metrics_export_interval: Duration,
+    metrics_port: Option<u16>,
+    max_connections: Option<usize>,
+    keepalive: Option<Duration>,
+    http_version: HttpVersion,
+    drain_timeout: Duration,
+    startup: StartupProgress,
+    resolved_config: ResolvedConfig,
+    version: ServiceVersion,
+    started: Instant,
+}
+
+/// What a service did in its lifetime, logged once it has shut down
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShutdownSummary {
+    /// Requests which finished, successfully or not
+    pub requests: u64,
+    pub errors: u64,
+    /// None if there were no requests
+    pub average_latency: Option<Duration>,
+    pub uptime: Duration,
+}
+
+impl std::fmt::Display for ShutdownSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "served {} requests, {} errors",
+            self.requests, self.errors
+        )?;
+        if let Some(latency) = self.average_latency {
+            write!(f, ", average latency {latency:.3?}")?;
+        }
+        write!(f, ", up {:.0?}", self.uptime)
+    }
 }
 
 #[derive(Clone, Builder)]
@@ -44,6 +97,127 @@ pub struct HttpServiceConfig {
 
     #[builder(default = "true")]
     enable_cmpl_endpoints: bool,
+
+    #[builder(default = "true")]
+    enable_embeddings_endpoints: bool,
+
+    /// Queue requests beyond this many in flight. Unlimited if not set.
+    #[builder(default, setter(strip_option))]
+    max_concurrent_requests: Option<usize>,
+
+    /// Scheduling priority by model name, higher first. See
+    /// [`ModelManager::set_model_priority`]. Only matters with `max_concurrent_requests`.
+    #[builder(default)]
+    model_priorities: HashMap<String, i32>,
+
+    /// Also push the `/metrics` metrics to these OTLP collectors / StatsD servers
+    #[builder(default)]
+    metrics_export: Vec<MetricsExport>,
+
+    #[builder(default = "metrics_export::DEFAULT_EXPORT_INTERVAL")]
+    metrics_export_interval: Duration,
+
+    /// Also serve `/metrics` on this port, on a listener of its own, so scrapers don't need
+    /// access to the client facing port
+    #[builder(default, setter(strip_option))]
+    metrics_port: Option<u16>,
+
+    /// Connections beyond this many open ones get a 503 and are closed. Unlimited if not set.
+    #[builder(default, setter(strip_option))]
+    max_connections: Option<usize>,
+
+    /// Enable TCP keep-alive on client connections after this much idle time. Off if not set.
+    #[builder(default, setter(strip_option))]
+    keepalive: Option<Duration>,
+
+    /// Serve HTTP/1.1, HTTP/2 (h2c), or whichever each client speaks
+    #[builder(default)]
+    http_version: HttpVersion,
+
+    /// On shutdown, how long to wait for in-flight requests to finish. New requests get a 503
+    /// meanwhile. Connections still streaming after that are closed.
+    #[builder(default = "DEFAULT_DRAIN_TIMEOUT")]
+    drain_timeout: Duration,
+
+    /// The service will be started before the models are loaded. `/health/startup` reports
+    /// "starting" until [`StartupProgress::set_ready`] is called.
+    #[builder(default = "false")]
+    serve_before_ready: bool,
+
+    /// Report this `system_fingerprint` in all responses instead of the engine's
+    #[builder(default, setter(strip_option, into))]
+    system_fingerprint: Option<String>,
+
+    /// Parse request bodies as JSON whatever their `Content-Type` says
+    #[builder(default = "false")]
+    lenient_content_type: bool,
+
+    /// Accept numbers given as strings, and integers given as floats, in chat requests
+    #[builder(default = "false")]
+    lenient_numbers: bool,
+
+    /// Fail requests whose engine ends the stream without a finish reason, instead of reporting
+    /// `stop` for them
+    #[builder(default = "false")]
+    strict_finish_reason: bool,
+
+    /// Serve requests for unknown models with the only model, see
+    /// [`ModelManager::set_allow_any_model`]
+    #[builder(default = "false")]
+    allow_any_model: bool,
+
+    /// Repair the output of JSON requests which isn't valid JSON, see
+    /// [`ModelManager::set_repair_json`]
+    #[builder(default = "false")]
+    repair_json: bool,
+
+    /// Fail requests whose engine sends nothing for this long, see
+    /// [`ModelManager::set_request_timeout`]. No timeout if not set.
+    #[builder(default, setter(strip_option))]
+    request_timeout: Option<Duration>,
+
+    /// Split streamed chunks with more than this many characters of content into several
+    /// events, see [`ModelManager::set_max_chunk_chars`]
+    #[builder(default, setter(strip_option))]
+    max_chunk_chars: Option<usize>,
+
+    /// Reject requests asking for more than this many choices (`n`) with a 400
+    #[builder(default = "super::DEFAULT_MAX_N")]
+    max_n: u8,
+
+    /// Let clients ask for their prompt's token ids with `X-Debug-Token-Ids: true`. Off by
+    /// default as it gives away how prompts are templated.
+    #[builder(default = "false")]
+    debug_token_ids: bool,
+
+    /// Copy the responses of requests with `X-Tee: true` here, for debugging. A
+    /// [`super::tee::TeeRecorder`] writes them to a file.
+    #[builder(default, setter(strip_option))]
+    tee_sink: Option<mpsc::Sender<TeeEvent>>,
+
+    /// Sampling parameter presets which requests can pick with `X-Sampling-Profile: <name>`
+    #[builder(default)]
+    sampling_profiles: SamplingProfiles,
+
+    /// Answer repeated greedy (`temperature: 0`), non-streaming chat completions requests from a
+    /// cache of this many responses. 0, the default, disables the cache.
+    #[builder(default = "0")]
+    response_cache_size: usize,
+
+    /// Time between `event: prefill` events for streaming requests which ask for them with
+    /// `X-Prefill-Progress: true`
+    #[builder(default = "super::DEFAULT_PREFILL_PROGRESS_INTERVAL")]
+    prefill_progress_interval: Duration,
+
+    /// Serve `GET /admin/config` and `GET /admin/requests` to clients presenting this key. Not
+    /// served if not set.
+    #[builder(default, setter(strip_option, into))]
+    admin_api_key: Option<String>,
+
+    /// Serve `GET /debug/headers`, which echoes the request headers with credentials redacted,
+    /// to check what reaches the server through proxies
+    #[builder(default = "false")]
+    debug_echo_headers: bool,
 }
 
 impl HttpService {
@@ -55,6 +229,32 @@ impl HttpService {
         &self.models
     }
 
+    /// Report model loading progress here, see `serve_before_ready`
+    pub fn startup_progress(&self) -> &StartupProgress {
+        &self.startup
+    }
+
+    /// The configuration reported by `/admin/config`, see `admin_api_key`
+    pub fn resolved_config(&self) -> &ResolvedConfig {
+        &self.resolved_config
+    }
+
+    /// The build information reported by `/version`
+    pub fn version(&self) -> &ServiceVersion {
+        &self.version
+    }
+
+    /// Totals from the metrics counters since the service was built
+    pub fn shutdown_summary(&self) -> ShutdownSummary {
+        let metrics = self.models.metrics();
+        ShutdownSummary {
+            requests: metrics.get_request_total(),
+            errors: metrics.get_error_total(),
+            average_latency: metrics.get_average_duration(),
+            uptime: self.started.elapsed(),
+        }
+    }
+
     pub async fn spawn(&self, cancel_token: CancellationToken) -> JoinHandle<Result<()>> {
         let this = self.clone();
         tokio::spawn(async move { this.run(cancel_token).await })
@@ -66,35 +266,144 @@ impl HttpService {
 
         let listener = tokio::net::TcpListener::bind(address.as_str())
             .await
-            .unwrap_or_else(|_| panic!("could not bind to address: {address}"));
+            .with_context(|| format!("could not bind to address: {address}"))?;
+        let listener = ServiceListener::new(listener, self.max_connections, self.keepalive);
 
         let router = self.router.clone();
         let observer = cancel_token.child_token();
 
-        axum::serve(listener, router)
-            .with_graceful_shutdown(observer.cancelled_owned())
-            .await
-            .inspect_err(|_| cancel_token.cancel())?;
+        // Stopped after the main listener, so the drain can be watched
+        let metrics_token = CancellationToken::new();
+        let metrics_listener = match self.metrics_port {
+            Some(port) => Some(
+                self.spawn_metrics_listener(port, metrics_token.clone())
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let exporters_token = cancel_token.child_token();
+        let exporters = self
+            .metrics_export
+            .iter()
+            .map(|export| {
+                metrics_export::spawn(
+                    self.registry.clone(),
+                    export.clone(),
+                    self.metrics_export_interval,
+                    exporters_token.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Keep serving, with 503s for new requests, until the in-flight ones finish. The
+        // connections get what is left of the drain timeout to finish sending their responses.
+        let state = self.models.state();
+        let drain_timeout = self.drain_timeout;
+        let shutdown = async move {
+            observer.cancelled().await;
+            let started = Instant::now();
+            state.drain(drain_timeout).await;
+            drain_timeout
+                .saturating_sub(started.elapsed())
+                .max(MIN_CLOSE_TIMEOUT)
+        };
 
+        let served = listener::serve(listener, router, self.http_version, shutdown).await;
+
+        metrics_token.cancel();
+        let metrics_served = match metrics_listener {
+            Some(metrics_listener) => metrics_listener.await?,
+            None => Ok(()),
+        };
+
+        // let the exporters make a final push
+        exporters_token.cancel();
+        for exporter in exporters {
+            let _ = exporter.await;
+        }
+
+        let summary = self.shutdown_summary();
+        tracing::info!(
+            requests = summary.requests,
+            errors = summary.errors,
+            average_latency_ms = summary.average_latency.map(|d| d.as_millis() as u64),
+            uptime_secs = summary.uptime.as_secs(),
+            "HTTP service shut down: {summary}"
+        );
+
+        served.with_context(|| format!("HTTP service on {address} failed"))?;
+        metrics_served.context("metrics listener failed")?;
         Ok(())
     }
+
+    /// Serve `/metrics`, and only that, on `port`
+    async fn spawn_metrics_listener(
+        &self,
+        port: u16,
+        cancel_token: CancellationToken,
+    ) -> Result<JoinHandle<std::io::Result<()>>> {
+        let address = format!("{}:{}", self.host, port);
+        tracing::info!(address, "Serving metrics on: {address}");
+        let listener = tokio::net::TcpListener::bind(address.as_str())
+            .await
+            .with_context(|| format!("could not bind metrics listener to address: {address}"))?;
+        let listener = ServiceListener::new(listener, None, None);
+        let (_, router) = metrics::router(self.registry.clone(), None);
+        Ok(tokio::spawn(async move {
+            listener::serve(listener, router, HttpVersion::default(), async move {
+                cancel_token.cancelled().await;
+                MIN_CLOSE_TIMEOUT
+            })
+            .await
+        }))
+    }
 }
 
 impl HttpServiceConfigBuilder {
     pub fn build(self) -> Result<HttpService, anyhow::Error> {
         let config = self.build_internal()?;
 
-        let model_manager = ModelManager::new();
+        let model_manager = match config.max_concurrent_requests {
+            Some(limit) => ModelManager::with_max_concurrent_requests(limit),
+            None => ModelManager::new(),
+        };
 
         // enable prometheus metrics
         let registry = metrics::Registry::new();
         model_manager.metrics().register(&registry)?;
+        model_manager.set_system_fingerprint(config.system_fingerprint);
+        model_manager.set_lenient_content_type(config.lenient_content_type);
+        model_manager.set_lenient_numbers(config.lenient_numbers);
+        model_manager.set_strict_finish_reason(config.strict_finish_reason);
+        model_manager.set_allow_any_model(config.allow_any_model);
+        model_manager.set_repair_json(config.repair_json);
+        model_manager.set_max_n(config.max_n);
+        model_manager.set_request_timeout(config.request_timeout);
+        model_manager.set_max_chunk_chars(config.max_chunk_chars);
+        model_manager.set_debug_token_ids(config.debug_token_ids);
+        model_manager.set_sampling_profiles(config.sampling_profiles);
+        model_manager.set_tee_sink(config.tee_sink);
+        model_manager.set_response_cache_size(config.response_cache_size);
+        model_manager.set_prefill_progress_interval(config.prefill_progress_interval);
+        for (model, priority) in &config.model_priorities {
+            model_manager.set_model_priority(model, *priority);
+        }
 
         let mut router = axum::Router::new();
         let mut all_docs = Vec::new();
 
+        let startup = if config.serve_before_ready {
+            StartupProgress::starting()
+        } else {
+            StartupProgress::ready()
+        };
+
+        let version = ServiceVersion::default();
         let mut routes = vec![
-            metrics::router(registry, None),
+            metrics::router(registry.clone(), None),
+            health::startup_router(startup.clone(), None),
+            version::version_router(version.clone(), None),
             super::openai::list_models_router(model_manager.state(), None),
         ];
 
@@ -103,6 +412,28 @@ impl HttpServiceConfigBuilder {
                 model_manager.state(),
                 None,
             ));
+            routes.push(super::openai::tokenize_count_router(
+                model_manager.state(),
+                None,
+            ));
+        }
+
+        let resolved_config = ResolvedConfig::default();
+        if let Some(api_key) = config.admin_api_key {
+            routes.push(admin::config_router(
+                resolved_config.clone(),
+                api_key.clone(),
+                None,
+            ));
+            routes.push(admin::requests_router(
+                model_manager.active_requests(),
+                api_key,
+                None,
+            ));
+        }
+
+        if config.debug_echo_headers {
+            routes.push(super::debug::headers_router(None));
         }
 
         if config.enable_cmpl_endpoints {
@@ -112,6 +443,13 @@ impl HttpServiceConfigBuilder {
             ));
         }
 
+        if config.enable_embeddings_endpoints {
+            routes.push(super::openai::embeddings_router(
+                model_manager.state(),
+                None,
+            ));
+        }
+
         // for (route_docs, route) in routes.into_iter().chain(self.routes.into_iter()) {
         //     router = router.merge(route);
         //     all_docs.extend(route_docs);
@@ -127,6 +465,18 @@ impl HttpServiceConfigBuilder {
             router,
             port: config.port,
             host: config.host,
+            registry,
+            metrics_export: config.metrics_export,
+            metrics_export_interval: config.metrics_export_interval,
+            metrics_port: config.metrics_port,
+            max_connections: config.max_connections,
+            keepalive: config.keepalive,
+            http_version: config.http_version,
+            drain_timeout: config.drain_timeout,
+            startup,
+            resolved_config,
+            version,
+            started: Instant::now(),
         })
     }
 }
@@ -0,0 +1,74 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copy the response stream of requests which ask for it to a second sink, for debugging.
+//!
+//! The client is never held up by the sink: chunks the sink can't take right away are dropped,
+//! with a warning.
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::recorder::Recorder;
+use crate::types::Annotated;
+use dynamo_runtime::engine::{AsyncEngineContextProvider, Data, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+
+/// A chunk of a response, as sent to the tee sink
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TeeEvent {
+    pub request_id: String,
+    pub model: String,
+    /// The chunk as the client got it, before SSE framing
+    pub chunk: serde_json::Value,
+}
+
+/// Writes tee'd chunks to a JSONL file, see [`Recorder`]
+pub type TeeRecorder = Recorder<TeeEvent>;
+
+/// Send a copy of every chunk of `stream` to `sink`, as well as passing it on
+pub(crate) fn tee<T: Serialize + Data>(
+    stream: ManyOut<Annotated<T>>,
+    sink: mpsc::Sender<TeeEvent>,
+    request_id: String,
+    model: String,
+) -> ManyOut<Annotated<T>> {
+    let ctx = stream.context();
+    let mut dropped = 0;
+    let stream = stream.map(move |response| {
+        let chunk = match serde_json::to_value(&response) {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                tracing::warn!(request_id, %err, "Failed to serialize chunk for the tee sink");
+                return response;
+            }
+        };
+        let event = TeeEvent {
+            request_id: request_id.clone(),
+            model: model.clone(),
+            chunk,
+        };
+        if sink.try_send(event).is_err() {
+            // only the first, a slow sink would otherwise log every chunk
+            if dropped == 0 {
+                tracing::warn!(request_id, "Tee sink is not keeping up, dropping chunks");
+            }
+            dropped += 1;
+        }
+        response
+    });
+    ResponseStream::new(Box::pin(stream), ctx)
+}
@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Build information, for support tickets.
+//!
+//! `GET /version` returns the dynamo version and git commit the server was built from, the
+//! optional features compiled in, and the engine serving requests, e.g.
+//! `{"version": "0.1.1", "git_sha": "1f98fe9c0d2b", "features": ["mistralrs", "vllm"], "engine": "vllm"}`.
+
+use std::sync::{Arc, Mutex};
+
+use axum::{extract::State, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+
+use super::RouteDoc;
+
+/// What `GET /version` reports
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    pub version: String,
+
+    /// The commit built from, if it was built from a git checkout
+    pub git_sha: Option<String>,
+
+    /// Optional features compiled in, such as the engines
+    pub features: Vec<String>,
+
+    /// The engine serving requests, once known
+    pub engine: Option<String>,
+}
+
+impl Default for VersionInfo {
+    fn default() -> Self {
+        VersionInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_sha: None,
+            features: vec![],
+            engine: None,
+        }
+    }
+}
+
+/// The build information reported by `/version`. Cheap to clone, clones share state.
+#[derive(Clone, Default)]
+pub struct ServiceVersion {
+    state: Arc<Mutex<VersionInfo>>,
+}
+
+impl ServiceVersion {
+    pub fn set(&self, info: VersionInfo) {
+        *self.state.lock().unwrap() = info;
+    }
+
+    pub fn get(&self) -> VersionInfo {
+        self.state.lock().unwrap().clone()
+    }
+}
+
+/// Create an Axum [`Router`] for the build information.
+/// If no path is provided, the default path is `/version`
+pub fn version_router(version: ServiceVersion, path: Option<String>) -> (Vec<RouteDoc>, Router) {
+    let path = path.unwrap_or("/version".to_string());
+    let doc = RouteDoc::new(axum::http::Method::GET, &path);
+    let router = Router::new()
+        .route(&path, get(version_handler))
+        .with_state(version);
+    (vec![doc], router)
+}
+
+async fn version_handler(State(version): State<ServiceVersion>) -> Json<VersionInfo> {
+    Json(version.get())
+}
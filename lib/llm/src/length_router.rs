@@ -0,0 +1,84 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Route chat requests by prompt length, for deployments where one set of workers is tuned for
+//! short prompts and another for long ones.
+
+use async_trait::async_trait;
+
+use dynamo_runtime::engine::AsyncEngine;
+use dynamo_runtime::pipeline::{Error, ManyOut, SingleIn};
+use dynamo_runtime::protocols::annotated::Annotated;
+
+use crate::preprocessor::estimate_prompt_tokens;
+use crate::protocols::openai::chat_completions::{
+    NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+};
+use crate::types::openai::chat_completions::OpenAIChatCompletionsStreamingEngine;
+
+/// Sends requests whose prompt is estimated at more than `threshold` tokens to the `long` engine,
+/// everything else to the `short` engine.
+pub struct PromptLengthRouter {
+    threshold: usize,
+    short: OpenAIChatCompletionsStreamingEngine,
+    long: OpenAIChatCompletionsStreamingEngine,
+}
+
+impl PromptLengthRouter {
+    pub fn new(
+        threshold: usize,
+        short: OpenAIChatCompletionsStreamingEngine,
+        long: OpenAIChatCompletionsStreamingEngine,
+    ) -> Self {
+        PromptLengthRouter {
+            threshold,
+            short,
+            long,
+        }
+    }
+
+    fn select(
+        &self,
+        request: &NvCreateChatCompletionRequest,
+    ) -> &OpenAIChatCompletionsStreamingEngine {
+        let estimate = estimate_prompt_tokens(request);
+        if estimate > self.threshold {
+            tracing::trace!(
+                estimate,
+                threshold = self.threshold,
+                "Routing to long prompt engine"
+            );
+            &self.long
+        } else {
+            &self.short
+        }
+    }
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for PromptLengthRouter
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        self.select(&request).generate(request).await
+    }
+}
@@ -25,6 +25,7 @@ pub mod engines;
 pub mod gguf;
 pub mod http;
 pub mod kv_router;
+pub mod length_router;
 pub mod model_card;
 pub mod model_type;
 pub mod preprocessor;
@@ -13,5 +13,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod archive;
 pub mod create;
 pub mod model;
+pub mod weights;
@@ -0,0 +1,191 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Model directories distributed as a `.tar.gz`, `.tgz` or `.zip` archive.
+//!
+//! The archive is unpacked once into a cache directory and the extracted model directory is
+//! used from then on, so the card loader and engines never see the archive itself.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+
+/// Written into the extraction directory once all entries are unpacked
+const COMPLETE_MARKER: &str = ".dynamo-extracted";
+
+const TAR_GZ_EXTENSIONS: [&str; 2] = [".tar.gz", ".tgz"];
+const ZIP_EXTENSION: &str = ".zip";
+
+/// Is this path an archive format we know how to unpack?
+pub fn is_archive(path: &Path) -> bool {
+    archive_stem(path).is_some()
+}
+
+/// The archive file name without its archive extension, e.g. `TinyLlama.tar.gz` -> `TinyLlama`.
+pub fn archive_stem(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    TAR_GZ_EXTENSIONS
+        .iter()
+        .chain(std::iter::once(&ZIP_EXTENSION))
+        .find_map(|ext| name.strip_suffix(ext))
+        .filter(|stem| !stem.is_empty())
+        .map(|stem| stem.to_string())
+}
+
+/// Where archives are unpacked if the caller doesn't have a preference.
+/// `$XDG_CACHE_HOME/dynamo/models`, falling back to `$HOME/.cache/dynamo/models`.
+pub fn default_cache_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join("dynamo").join("models")
+}
+
+/// Unpack a model archive into `cache_dir` and return the model directory.
+///
+/// The extraction directory is keyed by the archive's path, size and modification time, so a
+/// second call with the same archive re-uses the earlier extraction. If the archive contains a
+/// single top level directory that directory is returned, otherwise the extraction root.
+///
+/// Entries which would be written outside of the extraction directory (absolute paths, `..`
+/// components) and tar link entries are rejected.
+pub fn extract(archive: &Path, cache_dir: &Path) -> anyhow::Result<PathBuf> {
+    let Some(stem) = archive_stem(archive) else {
+        anyhow::bail!(
+            "Unsupported archive {}. Expected .tar.gz, .tgz or .zip",
+            archive.display()
+        );
+    };
+    let meta = fs::metadata(archive).with_context(|| archive.display().to_string())?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let key = blake3::hash(
+        format!(
+            "{}:{}:{modified}",
+            archive.canonicalize()?.display(),
+            meta.len()
+        )
+        .as_bytes(),
+    )
+    .to_hex();
+    let dest = cache_dir.join(format!("{stem}-{}", &key[..16]));
+
+    if !dest.join(COMPLETE_MARKER).exists() {
+        if dest.exists() {
+            // A previous extraction was interrupted
+            fs::remove_dir_all(&dest).with_context(|| dest.display().to_string())?;
+        }
+        fs::create_dir_all(&dest).with_context(|| dest.display().to_string())?;
+        tracing::info!("Extracting {} to {}", archive.display(), dest.display());
+        let result = if archive.to_string_lossy().ends_with(ZIP_EXTENSION) {
+            unpack_zip(archive, &dest)
+        } else {
+            unpack_tar_gz(archive, &dest)
+        };
+        if let Err(err) = result {
+            let _ = fs::remove_dir_all(&dest);
+            return Err(err.context(format!("Failed extracting {}", archive.display())));
+        }
+        File::create(dest.join(COMPLETE_MARKER))?;
+    }
+
+    model_root(&dest)
+}
+
+/// [`extract`] into the [`default_cache_dir`], on a blocking thread
+pub async fn unpack(archive: &Path) -> anyhow::Result<PathBuf> {
+    let archive = archive.to_path_buf();
+    let cache_dir = default_cache_dir();
+    tokio::task::spawn_blocking(move || extract(&archive, &cache_dir)).await?
+}
+
+fn unpack_tar_gz(archive: &Path, dest: &Path) -> anyhow::Result<()> {
+    let gz = flate2::read::GzDecoder::new(File::open(archive)?);
+    let mut tar = tar::Archive::new(gz);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        let name = entry.path()?.into_owned();
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            anyhow::bail!("Archive contains a link entry: {}", name.display());
+        }
+        let out = dest.join(safe_relative_path(&name)?);
+        if entry_type.is_dir() {
+            fs::create_dir_all(&out)?;
+        } else if entry_type.is_file() {
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&out)?;
+        }
+    }
+    Ok(())
+}
+
+fn unpack_zip(archive: &Path, dest: &Path) -> anyhow::Result<()> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+    for i in 0..zip.len() {
+        // zip symlinks are regular entries holding the link target, so they are written out as
+        // plain files and cannot point outside `dest`.
+        let mut file = zip.by_index(i)?;
+        let out = dest.join(safe_relative_path(Path::new(file.name()))?);
+        if file.is_dir() {
+            fs::create_dir_all(&out)?;
+        } else {
+            if let Some(parent) = out.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file, &mut File::create(&out)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reject any archive entry that could escape the extraction directory
+fn safe_relative_path(name: &Path) -> anyhow::Result<PathBuf> {
+    let mut out = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(c) => out.push(c),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                anyhow::bail!(
+                    "Archive entry '{}' would be extracted outside of the target directory",
+                    name.display()
+                );
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Archives usually wrap the model in a single directory, use that if so
+fn model_root(dest: &Path) -> anyhow::Result<PathBuf> {
+    let mut entries = fs::read_dir(dest)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name() != COMPLETE_MARKER)
+        .collect::<Vec<_>>();
+    if entries.len() == 1 && entries[0].path().is_dir() {
+        return Ok(entries.remove(0).path());
+    }
+    Ok(dest.to_path_buf())
+}
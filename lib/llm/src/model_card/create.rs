@@ -15,12 +15,18 @@
 
 use std::collections::HashMap;
 
+use crate::model_card::archive;
 use crate::model_card::model::ModelDeploymentCard;
 use anyhow::{Context, Result};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::model_card::model::{ModelInfoType, PromptFormatterArtifact, TokenizerKind};
+use crate::model_card::model::{
+    check_gguf_metadata, load_gguf, ModelInfoType, PromptFormatterArtifact, TokenizerKind,
+};
+
+/// Newer Hugging Face repos ship the chat template as a separate file
+const CHAT_TEMPLATE_FILE: &str = "chat_template.jinja";
 
 impl ModelDeploymentCard {
     /// Creates a ModelDeploymentCard from a local directory path.
@@ -29,9 +35,18 @@ impl ModelDeploymentCard {
     /// - config.json: Model configuration in HuggingFace format
     /// - tokenizer.json: Tokenizer configuration in HuggingFace format
     /// - tokenizer_config.json: Optional prompt formatter configuration
+    /// - chat_template.jinja: Optional chat template
+    ///
+    /// The chat template is taken from, in order of priority:
+    /// - an explicit template, see [`ModelDeploymentCard::set_chat_template`] and
+    ///   [`ModelDeploymentCard::set_chat_template_file`]
+    /// - chat_template.jinja
+    /// - the `chat_template` field of tokenizer_config.json
     ///
     /// # Arguments
-    /// * `local_root_dir` - Path to the local model directory
+    /// * `local_root_dir` - Path to the local model directory, or to a `.tar.gz`, `.tgz` or
+    ///   `.zip` archive of one. Archives are unpacked into [`archive::default_cache_dir`] and
+    ///   named after the archive file.
     ///
     /// # Errors
     /// Returns an error if:
@@ -42,22 +57,59 @@ impl ModelDeploymentCard {
         local_root_dir: impl AsRef<Path>,
         model_name: Option<&str>,
     ) -> anyhow::Result<Self> {
-        let local_root_dir = local_root_dir.as_ref();
-        check_valid_local_repo_path(local_root_dir)?;
-        let repo_id = local_root_dir
-            .canonicalize()?
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Path contains invalid Unicode"))?
-            .to_string();
-        let model_name = model_name.unwrap_or(
+        Self::from_local_path_with_tokenizer(local_root_dir, None, model_name).await
+    }
+
+    /// Like [`ModelDeploymentCard::from_local_path`], but if `tokenizer_dir` is given the
+    /// tokenizer files (tokenizer.json, tokenizer_config.json, chat_template.jinja) are taken
+    /// from there instead, and the model directory doesn't need to have any.
+    pub async fn from_local_path_with_tokenizer(
+        local_root_dir: impl AsRef<Path>,
+        tokenizer_dir: Option<&Path>,
+        model_name: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let mut local_root_dir = local_root_dir.as_ref().to_path_buf();
+        let mut archive_name = None;
+        if local_root_dir.is_file() && archive::is_archive(&local_root_dir) {
+            archive_name = archive::archive_stem(&local_root_dir);
+            local_root_dir = archive::unpack(&local_root_dir).await?;
+        }
+        let repo_id = local_repo_id(&local_root_dir)?;
+        let tokenizer_repo_id = match tokenizer_dir {
+            Some(tokenizer_dir) => local_repo_id(tokenizer_dir)?,
+            None => repo_id.clone(),
+        };
+        let model_name = model_name.or(archive_name.as_deref()).unwrap_or(
             local_root_dir
                 .file_name()
                 .and_then(|n| n.to_str())
                 .ok_or_else(|| anyhow::anyhow!("Invalid model directory name"))?,
         );
-        Self::from_repo(&repo_id, model_name).await
+        Self::from_repos(&repo_id, &tokenizer_repo_id, model_name).await
     }
 
+    /// Use the tokenizer files in `tokenizer_dir` instead of the model's own, for example an HF
+    /// repo's tokenizer for GGUF weights. The prompt formatter and chat template are replaced
+    /// only if the directory has them.
+    pub async fn set_tokenizer_dir(&mut self, tokenizer_dir: &Path) -> anyhow::Result<()> {
+        let artifacts = TokenizerArtifacts::from_repo(&local_repo_id(tokenizer_dir)?).await?;
+        self.tokenizer = artifacts.tokenizer;
+        if artifacts.prompt_formatter.is_some() {
+            self.prompt_formatter = artifacts.prompt_formatter;
+        }
+        if artifacts.chat_template_file.is_some() {
+            self.chat_template_file = artifacts.chat_template_file;
+        }
+        Ok(())
+    }
+
+    /// Creates a ModelDeploymentCard from a single GGUF file, so it doesn't need a separate
+    /// HuggingFace checkout. The tokenizer, chat template, context length and bos / eos tokens
+    /// all come from the GGUF's metadata.
+    ///
+    /// # Errors
+    /// Returns an error if the file isn't a GGUF, or its metadata is missing any of the keys we
+    /// need. The error lists them all.
     pub async fn from_gguf(gguf_file: &Path, model_name: Option<&str>) -> anyhow::Result<Self> {
         let model_name = model_name.map(|s| s.to_string()).or_else(|| {
             gguf_file
@@ -72,12 +124,27 @@ impl ModelDeploymentCard {
                 gguf_file.display()
             );
         };
+        let content = load_gguf(gguf_file)?;
+        check_gguf_metadata(&content).with_context(|| gguf_file.display().to_string())?;
+        if !content
+            .get_metadata()
+            .contains_key("tokenizer.chat_template")
+        {
+            tracing::warn!(
+                "{} has no chat template (tokenizer.chat_template), one must be set separately",
+                gguf_file.display()
+            );
+        }
+
         Ok(Self {
             display_name: model_name.to_string(),
             service_name: model_name.to_string(),
             model_info: ModelInfoType::GGUF(gguf_file.to_path_buf()),
             tokenizer: TokenizerKind::from_gguf(gguf_file)?,
             prompt_formatter: Some(PromptFormatterArtifact::GGUF(gguf_file.to_path_buf())),
+            chat_template_file: None,
+            chat_template: None,
+            tool_call_format: None,
             prompt_context: None, // TODO - auto-detect prompt context
             revision: 0,
             last_published: None,
@@ -94,12 +161,25 @@ impl ModelDeploymentCard {
     }
 
     pub async fn from_repo(repo_id: &str, model_name: &str) -> anyhow::Result<Self> {
+        Self::from_repos(repo_id, repo_id, model_name).await
+    }
+
+    /// The model's configuration from `repo_id`, its tokenizer files from `tokenizer_repo_id`
+    async fn from_repos(
+        repo_id: &str,
+        tokenizer_repo_id: &str,
+        model_name: &str,
+    ) -> anyhow::Result<Self> {
+        let artifacts = TokenizerArtifacts::from_repo(tokenizer_repo_id).await?;
         Ok(Self {
             display_name: model_name.to_string(),
             service_name: model_name.to_string(),
             model_info: ModelInfoType::from_repo(repo_id).await?,
-            tokenizer: TokenizerKind::from_repo(repo_id).await?,
-            prompt_formatter: PromptFormatterArtifact::from_repo(repo_id).await?,
+            tokenizer: artifacts.tokenizer,
+            prompt_formatter: artifacts.prompt_formatter,
+            chat_template_file: artifacts.chat_template_file,
+            chat_template: None,
+            tool_call_format: None,
             prompt_context: None, // TODO - auto-detect prompt context
             revision: 0,
             last_published: None,
@@ -108,6 +188,41 @@ impl ModelDeploymentCard {
     }
 }
 
+/// The files of a repo which say how to tokenize and format prompts
+struct TokenizerArtifacts {
+    tokenizer: TokenizerKind,
+    prompt_formatter: Option<PromptFormatterArtifact>,
+    chat_template_file: Option<PathBuf>,
+}
+
+impl TokenizerArtifacts {
+    async fn from_repo(repo_id: &str) -> anyhow::Result<Self> {
+        let prompt_formatter = PromptFormatterArtifact::from_repo(repo_id).await?;
+        let chat_template_file = check_for_file(repo_id, CHAT_TEMPLATE_FILE)
+            .await
+            .ok()
+            .map(PathBuf::from);
+        match (&chat_template_file, &prompt_formatter) {
+            (Some(path), _) => {
+                tracing::debug!("Using chat template from {}", path.display());
+            }
+            (None, Some(PromptFormatterArtifact::HfTokenizerConfigJson(file)))
+                if tokenizer_config_has_chat_template(file) =>
+            {
+                tracing::debug!("Using chat template from {file}");
+            }
+            _ => {
+                tracing::debug!("No chat template found in {repo_id}");
+            }
+        }
+        Ok(Self {
+            tokenizer: TokenizerKind::from_repo(repo_id).await?,
+            prompt_formatter,
+            chat_template_file,
+        })
+    }
+}
+
 impl ModelInfoType {
     pub async fn from_repo(repo_id: &str) -> Result<Self> {
         Self::try_is_hf_repo(repo_id)
@@ -153,6 +268,14 @@ impl TokenizerKind {
     }
 }
 
+/// Does this tokenizer_config.json carry a chat template? Some repos only have it there.
+fn tokenizer_config_has_chat_template(path: &str) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(&s).ok())
+        .is_some_and(|config| config.get("chat_template").is_some_and(|t| !t.is_null()))
+}
+
 /// Checks if the provided path contains the expected file.
 async fn check_for_file(repo_id: &str, file: &str) -> anyhow::Result<String> {
     let mut files = check_for_files(repo_id, vec![file.to_string()]).await?;
@@ -186,6 +309,16 @@ async fn check_for_files(repo_id: &str, files: Vec<String>) -> Result<HashMap<St
     Ok(found_files)
 }
 
+/// The repo id of a local directory: its canonical path
+fn local_repo_id(path: &Path) -> Result<String> {
+    check_valid_local_repo_path(path)?;
+    Ok(path
+        .canonicalize()?
+        .to_str()
+        .ok_or_else(|| anyhow::anyhow!("Path contains invalid Unicode"))?
+        .to_string())
+}
+
 /// Checks if the provided path is a valid local repository path.
 ///
 /// # Arguments
@@ -39,6 +39,7 @@ use serde::{Deserialize, Serialize};
 use tokenizers::Tokenizer as HfTokenizer;
 
 use crate::gguf::{Content, ContentConfig};
+use crate::preprocessor::tools::ToolCallFormat;
 use crate::protocols::TokenIdType;
 
 pub const BUCKET_NAME: &str = "mdc";
@@ -112,6 +113,21 @@ pub struct ModelDeploymentCard {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub prompt_formatter: Option<PromptFormatterArtifact>,
 
+    /// Jinja chat template file. Takes precedence over any template in `prompt_formatter`.
+    /// Either the repo's `chat_template.jinja` or set with `set_chat_template_file`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template_file: Option<PathBuf>,
+
+    /// Jinja chat template given inline with `set_chat_template`. Takes precedence over
+    /// `chat_template_file`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_template: Option<String>,
+
+    /// How the model writes tool calls, so we can turn them into `tool_calls` in the response.
+    /// None leaves them in the content. Set with `set_tool_call_format`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_format: Option<ToolCallFormat>,
+
     /// Prompt Formatter Config
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub prompt_context: Option<Vec<PromptContextMixin>>,
@@ -146,6 +162,26 @@ impl ModelDeploymentCard {
         self.service_name = service_name.to_string();
     }
 
+    /// Use this Jinja chat template instead of the one from the model repo
+    pub fn set_chat_template_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        if !path.is_file() {
+            anyhow::bail!("Chat template file not found: {}", path.display());
+        }
+        self.chat_template_file = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Use this Jinja chat template, given as the template itself, instead of the one from the
+    /// model repo
+    pub fn set_chat_template(&mut self, template: &str) {
+        self.chat_template = Some(template.to_string());
+    }
+
+    /// Parse tool calls out of the model's output in this format
+    pub fn set_tool_call_format(&mut self, format: ToolCallFormat) {
+        self.tool_call_format = Some(format);
+    }
+
     /// How often we should check if a model deployment card expired because it's workers are gone
     pub fn expiry_check_period() -> Duration {
         match CARD_MAX_AGE.to_std() {
@@ -274,6 +310,7 @@ impl HFConfig {
     }
     fn from_gguf(gguf_file: &Path) -> Result<Arc<dyn ModelInfo>> {
         let content = load_gguf(gguf_file)?;
+        check_gguf_metadata(&content).with_context(|| gguf_file.display().to_string())?;
         let model_config_metadata: ContentConfig = (&content).into();
         let num_hidden_layers =
             content.get_metadata()[&format!("{}.block_count", content.arch())].to_u32()? as usize;
@@ -339,7 +376,39 @@ impl TokenizerKind {
     }
 }
 
-fn load_gguf(gguf_file: &Path) -> anyhow::Result<Content> {
+/// GGUF metadata keys a card is built from. `{arch}` is the model's `general.architecture`.
+const GGUF_REQUIRED_KEYS: &[&str] = &[
+    "tokenizer.ggml.model",
+    "tokenizer.ggml.tokens",
+    "tokenizer.ggml.bos_token_id",
+    "tokenizer.ggml.eos_token_id",
+    "{arch}.context_length",
+    "{arch}.embedding_length",
+    "{arch}.block_count",
+    "{arch}.attention.head_count",
+    "{arch}.attention.head_count_kv",
+];
+
+/// Check the GGUF has everything we read from it, naming all the missing keys at once rather
+/// than failing on the first one when the card is used.
+pub(crate) fn check_gguf_metadata(content: &Content) -> anyhow::Result<()> {
+    let arch = content.arch().to_string();
+    let metadata = content.get_metadata();
+    let missing: Vec<String> = GGUF_REQUIRED_KEYS
+        .iter()
+        .map(|key| key.replace("{arch}", &arch))
+        .filter(|key| !metadata.contains_key(key))
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "GGUF metadata is missing required keys: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn load_gguf(gguf_file: &Path) -> anyhow::Result<Content> {
     let filename = gguf_file.display().to_string();
     let mut f = File::open(gguf_file).with_context(|| filename.clone())?;
     // vec because GGUF can be split into multiple files (shards)
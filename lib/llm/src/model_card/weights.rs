@@ -0,0 +1,109 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Locating the safetensors weights in a Hugging Face model directory.
+//!
+//! Small models keep their weights in a single `model.safetensors`. Larger ones split them into
+//! shards listed in `model.safetensors.index.json`, whose `weight_map` says which shard holds each
+//! tensor. The index is the source of truth: directories often hold other `.safetensors` files,
+//! such as a `consolidated.safetensors` copy of the same weights, which must not be loaded too.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// Weights of an unsharded model
+pub const SAFETENSORS_FILE: &str = "model.safetensors";
+
+/// Which shard holds each tensor of a sharded model
+pub const SAFETENSORS_INDEX_FILE: &str = "model.safetensors.index.json";
+
+#[derive(Debug, Deserialize)]
+struct SafetensorsIndex {
+    /// Tensor name to shard file name, relative to the index
+    weight_map: HashMap<String, String>,
+}
+
+/// The safetensors files holding the model's weights, in shard order. Errors if the directory has
+/// neither an index nor a `model.safetensors`, or if the index lists a shard which is missing.
+pub fn safetensors_files(model_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let index_path = model_dir.join(SAFETENSORS_INDEX_FILE);
+    if !index_path.exists() {
+        let single = model_dir.join(SAFETENSORS_FILE);
+        if !single.exists() {
+            anyhow::bail!(
+                "{} has neither {SAFETENSORS_INDEX_FILE} nor {SAFETENSORS_FILE}",
+                model_dir.display()
+            );
+        }
+        return Ok(vec![single]);
+    }
+
+    let index = std::fs::read_to_string(&index_path)
+        .with_context(|| format!("Reading {}", index_path.display()))?;
+    let index: SafetensorsIndex = serde_json::from_str(&index)
+        .with_context(|| format!("Parsing {}", index_path.display()))?;
+    // Shard names are numbered, e.g. model-00001-of-00002.safetensors, so this sorts them in order
+    let shards: BTreeSet<&String> = index.weight_map.values().collect();
+    if shards.is_empty() {
+        anyhow::bail!("{} lists no weights", index_path.display());
+    }
+    shards
+        .into_iter()
+        .map(|shard| {
+            let path = model_dir.join(shard);
+            if !path.exists() {
+                anyhow::bail!(
+                    "{} lists shard {shard}, which is missing",
+                    index_path.display()
+                );
+            }
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Fills the empty directory `dest` with symlinks to everything in `model_dir` except its
+/// safetensors files, plus the given `shards`. Engines which load every `.safetensors` file they
+/// find in a model directory can be pointed at `dest` so they load exactly those shards.
+pub fn link_shards(model_dir: &Path, shards: &[PathBuf], dest: &Path) -> anyhow::Result<()> {
+    let model_dir = model_dir
+        .canonicalize()
+        .with_context(|| model_dir.display().to_string())?;
+    for entry in std::fs::read_dir(&model_dir).with_context(|| model_dir.display().to_string())? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "safetensors") {
+            continue;
+        }
+        symlink(&path, &dest.join(entry.file_name()))?;
+    }
+    for shard in shards {
+        let Some(name) = shard.file_name() else {
+            anyhow::bail!("Invalid shard path {}", shard.display());
+        };
+        let shard = shard
+            .canonicalize()
+            .with_context(|| shard.display().to_string())?;
+        symlink(&shard, &dest.join(name))?;
+    }
+    Ok(())
+}
+
+fn symlink(original: &Path, link: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+        .with_context(|| format!("Linking {} to {}", link.display(), original.display()))
+}
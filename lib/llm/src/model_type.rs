@@ -20,6 +20,7 @@ use strum::Display;
 pub enum ModelType {
     Chat,
     Completion,
+    Embedding,
 }
 
 impl ModelType {
@@ -27,10 +28,11 @@ impl ModelType {
         match self {
             Self::Chat => "chat",
             Self::Completion => "completion",
+            Self::Embedding => "embedding",
         }
     }
 
     pub fn all() -> Vec<Self> {
-        vec![Self::Chat, Self::Completion]
+        vec![Self::Chat, Self::Completion, Self::Embedding]
     }
 }
@@ -32,8 +32,10 @@ use prompt::OAIPromptFormatter;
 use std::{collections::HashMap, sync::Arc};
 use tracing;
 
+use crate::http::service::error::{FieldError, HttpError, ValidationError};
 use crate::model_card::model::{ModelDeploymentCard, ModelInfo, TokenizerKind};
-use crate::preprocessor::prompt::OAIChatLikeRequest;
+use crate::preprocessor::prompt::{MergedSystemMessages, OAIChatLikeRequest};
+use crate::preprocessor::tools::{Tool, ToolCallFormat, ToolCallingMatcher, ToolChoice, ToolType};
 use crate::tokenizers::Encoding;
 
 use dynamo_runtime::engine::{AsyncEngine, AsyncEngineContextProvider, ResponseStream};
@@ -43,13 +45,14 @@ use dynamo_runtime::pipeline::{
 use dynamo_runtime::protocols::annotated::{Annotated, AnnotationsProvider};
 
 use crate::protocols::{
-    common::{SamplingOptionsProvider, StopConditionsProvider},
+    common::{FinishReason, SamplingOptionsProvider, StopConditionsProvider},
     openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
         completions::{CompletionRequest, CompletionResponse},
         nvext::NvExtProvider,
         DeltaGeneratorExt,
     },
+    TokenIdType,
 };
 use crate::tokenizers::{traits::Tokenizer, HuggingFaceTokenizer};
 
@@ -60,15 +63,188 @@ pub use crate::protocols::common::llm_backend::{BackendInput, BackendOutput};
 pub const ANNOTATION_FORMATTED_PROMPT: &str = "formatted_prompt";
 pub const ANNOTATION_TOKEN_IDS: &str = "token_ids";
 
+/// Average characters per token for English text, good enough for an estimate
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens the prompt template adds around each message, for the role and the delimiters.
+/// Llama 3 uses five, ChatML four.
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Cheap estimate of how many tokens a chat request's messages will use, without applying the
+/// prompt template or running the tokenizer. Useful for routing before we know which backend, and
+/// hence which tokenizer, will handle the request.
+pub fn estimate_prompt_tokens(request: &NvCreateChatCompletionRequest) -> usize {
+    /// Characters of text in a message's `content`, either a string or an array of parts
+    fn content_len(message: &serde_json::Value) -> usize {
+        match message.get("content") {
+            Some(serde_json::Value::String(s)) => s.chars().count(),
+            Some(serde_json::Value::Array(parts)) => parts
+                .iter()
+                .filter_map(|part| part.get("text").and_then(|text| text.as_str()))
+                .map(|text| text.chars().count())
+                .sum(),
+            _ => 0,
+        }
+    }
+    let messages = match serde_json::to_value(&request.inner.messages) {
+        Ok(serde_json::Value::Array(messages)) => messages,
+        _ => return 0,
+    };
+    let chars: usize = messages.iter().map(content_len).sum();
+    chars.div_ceil(CHARS_PER_TOKEN) + messages.len() * TOKENS_PER_MESSAGE
+}
+
+/// Default for [`PreprocessorOptions::max_stop_sequences`]. OpenAI allows 4.
+pub const DEFAULT_MAX_STOP_SEQUENCES: usize = 8;
+
+/// Server-wide settings which change how requests are pre-processed
+#[derive(Debug, Clone)]
+pub struct PreprocessorOptions {
+    /// Honor `nvext.ignore_eos` on requests. When false the model's EOS tokens always stop
+    /// generation, whatever the request says.
+    pub allow_disable_eos: bool,
+
+    /// Reject requests with more `stop` sequences than this. Every sequence is checked after
+    /// every token, so many of them slow down decoding.
+    pub max_stop_sequences: usize,
+
+    /// `min_tokens` for requests which don't set it: stop sequences and EOS are ignored until
+    /// this many tokens were generated, so they can't cut the output short.
+    pub min_tokens: Option<u32>,
+
+    /// `max_tokens` for requests which don't set it. Applied before `max_total_tokens`, which
+    /// may lower it further.
+    pub default_max_tokens: Option<u32>,
+
+    /// Concatenate a request's system messages into one before rendering the prompt template.
+    /// Some templates fail on more than one.
+    pub merge_system_messages: bool,
+
+    /// Text put before the rendered prompt, e.g. for guardrails. Unlike a system message the
+    /// prompt template never sees it.
+    pub prompt_prefix: Option<String>,
+
+    /// Text put after the rendered prompt
+    pub prompt_suffix: Option<String>,
+
+    /// Most tokens a request may use, prompt plus completion. `max_tokens` is lowered to what
+    /// the prompt leaves, and requests whose prompt alone doesn't fit are rejected.
+    pub max_total_tokens: Option<usize>,
+
+    /// What the engine behind this pre-processor declares it can do
+    pub capabilities: EngineCapabilities,
+
+    /// Serve completions requests asking for `logprobs` without them if the engine doesn't
+    /// report scores, instead of rejecting them with a 400. For clients which always send it.
+    pub ignore_unsupported_logprobs: bool,
+}
+
+/// Request features an engine may or may not support. Requests using an unsupported feature
+/// are rejected before they reach the engine.
+#[derive(Debug, Clone, Default)]
+pub struct EngineCapabilities {
+    /// Requests may combine `tools` with `response_format: json_schema`. Most engines constrain
+    /// the output to the schema, which leaves no way to emit a tool call.
+    pub tools_with_json_schema: bool,
+
+    /// The engine reports the log probability of each token it generates, which completions
+    /// requests with `logprobs` need
+    pub logprobs: bool,
+}
+
+impl Default for PreprocessorOptions {
+    fn default() -> Self {
+        PreprocessorOptions {
+            allow_disable_eos: false,
+            max_stop_sequences: DEFAULT_MAX_STOP_SEQUENCES,
+            min_tokens: None,
+            default_max_tokens: None,
+            merge_system_messages: false,
+            prompt_prefix: None,
+            prompt_suffix: None,
+            max_total_tokens: None,
+            capabilities: EngineCapabilities::default(),
+            ignore_unsupported_logprobs: false,
+        }
+    }
+}
+
+/// Collects the client errors in a request, so they are all reported together
+#[derive(Default)]
+struct Validation {
+    errors: Vec<FieldError>,
+}
+
+impl Validation {
+    /// Notes a [`ValidationError`]'s field errors, or a 400 [`HttpError`] as an error about
+    /// `param`, and returns None. Anything else is returned as an error.
+    fn check<T>(&mut self, param: Option<&str>, result: Result<T>) -> Result<Option<T>> {
+        let err = match result {
+            Ok(value) => return Ok(Some(value)),
+            Err(err) => err,
+        };
+        let err = match err.downcast::<ValidationError>() {
+            Ok(err) => {
+                self.errors.extend(err.errors);
+                return Ok(None);
+            }
+            Err(err) => err,
+        };
+        match err.downcast::<HttpError>() {
+            Ok(err) if err.code == 400 => {
+                self.errors.push(FieldError {
+                    param: param.map(str::to_string),
+                    message: err.message,
+                });
+                Ok(None)
+            }
+            Ok(err) => Err(err.into()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// A single 400 with every error noted, see [`ValidationError::into_http_error`]
+    fn finish(self) -> Result<()> {
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(ValidationError {
+            errors: self.errors,
+        }
+        .into_http_error())
+    }
+}
+
+/// Reject the request if it has no messages, there would be nothing to respond to
+fn check_has_messages<R: OAIChatLikeRequest>(request: &R) -> Result<()> {
+    if request.messages().len() == Some(0) {
+        return Err(HttpError {
+            code: 400,
+            message: "messages must contain at least one message".to_string(),
+        })?;
+    }
+    Ok(())
+}
+
 pub struct OpenAIPreprocessor {
     mdcsum: String,
     formatter: Arc<dyn OAIPromptFormatter>,
     tokenizer: Arc<dyn Tokenizer>,
     model_info: Arc<dyn ModelInfo>,
+    options: PreprocessorOptions,
+    /// From the model deployment card
+    tool_call_format: Option<ToolCallFormat>,
 }
 
 impl OpenAIPreprocessor {
     pub async fn new(mdc: ModelDeploymentCard) -> Result<Arc<Self>> {
+        Self::new_with_options(mdc, PreprocessorOptions::default()).await
+    }
+
+    pub async fn new_with_options(
+        mdc: ModelDeploymentCard,
+        options: PreprocessorOptions,
+    ) -> Result<Arc<Self>> {
         let formatter = PromptFormatter::from_mdc(mdc.clone()).await?;
         let PromptFormatter::OAI(formatter) = formatter;
 
@@ -89,6 +265,8 @@ impl OpenAIPreprocessor {
             tokenizer,
             model_info,
             mdcsum,
+            options,
+            tool_call_format: mdc.tool_call_format,
         }))
     }
 
@@ -116,22 +294,35 @@ impl OpenAIPreprocessor {
         let mut annotations = HashMap::new();
         let mut builder = BackendInput::builder();
 
-        let use_raw_prompt = request
-            .nvext()
-            .is_some_and(|ext| ext.use_raw_prompt.unwrap_or(false));
-
-        let formatted_prompt = if use_raw_prompt {
-            match request.raw_prompt() {
-                Some(prompt) => prompt,
-                None => {
-                    tracing::warn!("Raw prompt requested but not available");
-                    self.formatter.render(request)?
+        // Report every problem with the request at once, so the client can fix them in one go
+        let mut validation = Validation::default();
+        validation.check(None, self.check_capabilities(request))?;
+        validation.check(Some("messages"), check_has_messages(request))?;
+        // Out of range sampling options are the client's mistake, reported per option
+        let sampling_options = validation.check(
+            None,
+            request.extract_sampling_options().map_err(|err| {
+                if err.is::<ValidationError>() {
+                    return err;
                 }
-            }
-        } else {
-            self.formatter.render(request)?
-        };
-
+                anyhow::Error::from(HttpError {
+                    code: 400,
+                    message: err.to_string(),
+                })
+            }),
+        )?;
+        let mut stop_conditions = request.extract_stop_conditions()?;
+        validation.check(
+            Some("stop"),
+            self.check_stop_sequences(stop_conditions.stop.as_deref()),
+        )?;
+        validation.check(
+            Some("stop_token_ids"),
+            self.check_stop_token_ids(stop_conditions.stop_token_ids_hidden.as_deref()),
+        )?;
+        validation.finish()?;
+
+        let formatted_prompt = self.format_prompt(request)?;
         let encoding = tokio::task::block_in_place(|| self.tokenizer.encode(&formatted_prompt))?;
 
         if request.has_annotation(ANNOTATION_FORMATTED_PROMPT) {
@@ -145,33 +336,271 @@ impl OpenAIPreprocessor {
             );
         }
 
-        let mut stop_conditions = request.extract_stop_conditions()?;
-        if let Some(stop_tokens) = &mut stop_conditions.stop_token_ids_hidden {
-            for eos_token in self.model_info.eos_token_ids() {
-                if !stop_tokens.contains(&eos_token) {
-                    stop_tokens.push(eos_token);
-                }
-            }
-        } else {
-            stop_conditions.stop_token_ids_hidden = Some(self.model_info.eos_token_ids());
+        stop_conditions.min_tokens = stop_conditions.min_tokens.or(self.options.min_tokens);
+        stop_conditions.max_tokens = stop_conditions
+            .max_tokens
+            .or(self.options.default_max_tokens);
+        if stop_conditions.ignore_eos.unwrap_or(false) && !self.options.allow_disable_eos {
+            tracing::warn!("Request asked to ignore EOS but disabling EOS is not allowed");
+            stop_conditions.ignore_eos = None;
         }
+        stop_conditions.merge_eos_token_ids(&self.model_info.eos_token_ids());
 
         // apply ignore eos if not already set
         stop_conditions.apply_ignore_eos();
 
+        let tool_tokens = self.unrendered_tool_tokens(request, &formatted_prompt)?;
+        stop_conditions.max_tokens = self.fit_total_tokens(
+            encoding.token_ids.len() + tool_tokens,
+            stop_conditions.max_tokens,
+        )?;
+        self.check_context_length(
+            encoding.token_ids.len(),
+            tool_tokens,
+            stop_conditions.max_tokens.unwrap_or(0) as usize,
+        )?;
+
         if !stop_conditions.ignore_eos.unwrap_or(false) {
             builder.eos_token_ids(self.model_info.eos_token_ids());
         }
 
         builder.token_ids(encoding.token_ids);
-        builder.sampling_options(request.extract_sampling_options()?);
+        // validation passed, so this is always set
+        builder.sampling_options(sampling_options.unwrap_or_default());
         builder.stop_conditions(stop_conditions);
         builder.annotations(request.annotations().unwrap_or_default());
+        builder.output_constraint(request.output_constraint());
         builder.mdc_sum(Some(self.mdcsum.clone()));
 
         Ok((builder.build()?, annotations))
     }
 
+    /// How many tokens the request's prompt takes once the prompt template is applied, the same
+    /// count the engine would see. Nothing else is checked. This runs the tokenizer, call it from
+    /// a blocking task.
+    pub fn count_prompt_tokens<R: OAIChatLikeRequest + NvExtProvider>(
+        &self,
+        request: &R,
+    ) -> Result<usize> {
+        Ok(self.prompt_token_ids(request)?.len())
+    }
+
+    /// The request's prompt as the engine would see it, tokenized. Call it from a blocking task.
+    pub fn prompt_token_ids<R: OAIChatLikeRequest + NvExtProvider>(
+        &self,
+        request: &R,
+    ) -> Result<Vec<TokenIdType>> {
+        let formatted_prompt = self.format_prompt(request)?;
+        Ok(self.tokenizer.encode(&formatted_prompt)?.token_ids)
+    }
+
+    /// The prompt sent to the engine: the request rendered with the prompt template, or its raw
+    /// prompt if it asks for that, between the prompt prefix and suffix.
+    fn format_prompt<R: OAIChatLikeRequest + NvExtProvider>(&self, request: &R) -> Result<String> {
+        let prompt = self.render_prompt(request)?;
+        let (prefix, suffix) = (&self.options.prompt_prefix, &self.options.prompt_suffix);
+        if prefix.is_none() && suffix.is_none() {
+            return Ok(prompt);
+        }
+        Ok(format!(
+            "{}{prompt}{}",
+            prefix.as_deref().unwrap_or_default(),
+            suffix.as_deref().unwrap_or_default()
+        ))
+    }
+
+    fn render_prompt<R: OAIChatLikeRequest + NvExtProvider>(&self, request: &R) -> Result<String> {
+        let use_raw_prompt = request
+            .nvext()
+            .is_some_and(|ext| ext.use_raw_prompt.unwrap_or(false));
+
+        if use_raw_prompt {
+            match request.raw_prompt() {
+                Some(prompt) => return Ok(prompt),
+                None => tracing::warn!("Raw prompt requested but not available"),
+            }
+        }
+        if self.options.merge_system_messages {
+            self.formatter.render(&MergedSystemMessages(request))
+        } else {
+            self.formatter.render(request)
+        }
+    }
+
+    /// How many tokens the request's tool definitions take, for the tools the prompt template did
+    /// not already include in the prompt. Many templates ignore tools, or the engine adds them
+    /// itself, but they still need room in the context.
+    fn unrendered_tool_tokens<R: OAIChatLikeRequest>(
+        &self,
+        request: &R,
+        formatted_prompt: &str,
+    ) -> Result<usize> {
+        let Some(tools) = request.tools() else {
+            return Ok(0);
+        };
+        let serde_json::Value::Array(tools) = serde_json::to_value(&tools)? else {
+            return Ok(0);
+        };
+        let mut tokens = 0;
+        for tool in tools {
+            let rendered = tool["function"]["name"]
+                .as_str()
+                .is_some_and(|name| !name.is_empty() && formatted_prompt.contains(name));
+            if !rendered {
+                tokens += self.tokenizer.encode(&tool.to_string())?.token_ids.len();
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Reject the request if it combines features the engine has not declared support for
+    fn check_capabilities<R: OAIChatLikeRequest>(&self, request: &R) -> Result<()> {
+        let has_tools = request
+            .tools()
+            .is_some_and(|tools| tools.len().is_some_and(|len| len > 0));
+        if has_tools
+            && request.wants_json_schema()
+            && !self.options.capabilities.tools_with_json_schema
+        {
+            return Err(HttpError {
+                code: 400,
+                message: "This model does not support combining `tools` with \
+                          `response_format: json_schema`. Remove one of them from the request."
+                    .to_string(),
+            })?;
+        }
+        if request.wants_logprobs() && !self.options.capabilities.logprobs {
+            return Err(HttpError {
+                code: 400,
+                message: "This model's engine does not report log probabilities, so it does not \
+                          support `logprobs`. Remove it from the request."
+                    .to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reject the request if it has more stop sequences than we allow
+    fn check_stop_sequences(&self, stop: Option<&[String]>) -> Result<()> {
+        let count = stop.map_or(0, |stop| stop.len());
+        let limit = self.options.max_stop_sequences;
+        if count <= limit {
+            return Ok(());
+        }
+        Err(HttpError {
+            code: 400,
+            message: format!("stop has {count} sequences, at most {limit} are allowed"),
+        })?
+    }
+
+    /// Reject the request if it asks to stop on tokens the model doesn't have
+    fn check_stop_token_ids(&self, stop_token_ids: Option<&[TokenIdType]>) -> Result<()> {
+        let vocab_size = self.model_info.vocab_size();
+        let Some(stop_token_ids) = stop_token_ids else {
+            return Ok(());
+        };
+        if vocab_size == 0 {
+            return Ok(());
+        }
+        match stop_token_ids
+            .iter()
+            .find(|token_id| **token_id as usize >= vocab_size)
+        {
+            Some(token_id) => Err(HttpError {
+                code: 400,
+                message: format!(
+                    "stop_token_ids contains {token_id}, which is not in the model's vocabulary \
+                     of {vocab_size} tokens"
+                ),
+            })?,
+            None => Ok(()),
+        }
+    }
+
+    /// Lower `max_tokens` so that the prompt and the completion fit in `max_total_tokens`.
+    /// Requests without a `max_tokens`, nor a default for it, get whatever the prompt leaves.
+    fn fit_total_tokens(
+        &self,
+        prompt_tokens: usize,
+        max_tokens: Option<u32>,
+    ) -> Result<Option<u32>> {
+        let Some(max_total_tokens) = self.options.max_total_tokens else {
+            return Ok(max_tokens);
+        };
+        if prompt_tokens >= max_total_tokens {
+            return Err(HttpError {
+                code: 400,
+                message: format!(
+                    "This server allows at most {max_total_tokens} tokens per request, prompt and \
+                     completion together. However, the prompt alone is {prompt_tokens} tokens, \
+                     leaving no room for the completion. Please reduce the length of the \
+                     messages or tools."
+                ),
+            })?;
+        }
+        let left = u32::try_from(max_total_tokens - prompt_tokens).unwrap_or(u32::MAX);
+        Ok(Some(
+            max_tokens.map_or(left, |max_tokens| max_tokens.min(left)),
+        ))
+    }
+
+    /// Reject the request if the prompt, the tool definitions and the requested output do not
+    /// fit in the model's context window.
+    fn check_context_length(
+        &self,
+        prompt_tokens: usize,
+        tool_tokens: usize,
+        max_tokens: usize,
+    ) -> Result<()> {
+        let context_length = self.model_info.max_position_embeddings();
+        let requested = prompt_tokens + tool_tokens + max_tokens;
+        if context_length == 0 || requested <= context_length {
+            return Ok(());
+        }
+        Err(HttpError {
+            code: 400,
+            message: format!(
+                "This model's maximum context length is {context_length} tokens. However, you \
+                 requested {requested} tokens ({prompt_tokens} in the messages, {tool_tokens} in \
+                 the tool definitions, {max_tokens} in the completion). Please reduce the length \
+                 of the messages, tools or completion."
+            ),
+        })?
+    }
+
+    /// How to parse tool calls out of the response to `request`, and whether it must make
+    /// one. None if it has no tools, or asks for none to be called, or we don't know the
+    /// model's format.
+    fn tool_call_format(
+        &self,
+        request: &NvCreateChatCompletionRequest,
+    ) -> Result<Option<(ToolCallFormat, ToolCallingMatcher)>> {
+        use async_openai::types::ChatCompletionToolChoiceOption;
+
+        let has_tools = request
+            .inner
+            .tools
+            .as_ref()
+            .is_some_and(|tools| !tools.is_empty());
+        let Some(format) = self.tool_call_format.filter(|_| has_tools) else {
+            return Ok(None);
+        };
+        let tool_choice = match &request.inner.tool_choice {
+            None | Some(ChatCompletionToolChoiceOption::Auto) => ToolChoice::Auto,
+            Some(ChatCompletionToolChoiceOption::None) => return Ok(None),
+            Some(ChatCompletionToolChoiceOption::Required) => ToolChoice::Required,
+            Some(ChatCompletionToolChoiceOption::Named(named)) => ToolChoice::Tool(Tool {
+                tp: ToolType::Function,
+                function: tools::Function {
+                    description: None,
+                    name: named.function.name.clone(),
+                    parameters: None,
+                },
+            }),
+        };
+        Ok(Some((format, ToolCallingMatcher::new(tool_choice)?)))
+    }
+
     pub fn transform_postprocessor_stream<Resp: Send + Sync + 'static + std::fmt::Debug>(
         stream: ManyOut<Annotated<BackendOutput>>,
         generator: Box<dyn DeltaGeneratorExt<Resp>>,
@@ -246,6 +675,21 @@ impl OpenAIPreprocessor {
     }
 }
 
+/// The response to a request with `max_tokens: 0`, which the engine never sees: no tokens,
+/// finished because of the length limit. The usage still reports the prompt's tokens.
+fn prompt_only_response(context: Arc<dyn AsyncEngineContext>) -> ManyOut<Annotated<BackendOutput>> {
+    let output = BackendOutput {
+        token_ids: vec![],
+        tokens: vec![],
+        text: Some(String::new()),
+        cum_log_probs: None,
+        log_probs: None,
+        finish_reason: Some(FinishReason::Length),
+    };
+    let stream = stream::iter([Annotated::from_data(output)]);
+    ResponseStream::new(Box::pin(stream), context)
+}
+
 // for pals, we do not want to add the generation prompt to the formatted prompt
 // we also need to know if the template support this add_generation_prompt bool
 // any prompt template that does not support this should return an error
@@ -273,6 +717,7 @@ impl
         // create a response generator
         let response_generator = request.response_generator();
         let mut response_generator = Box::new(response_generator);
+        let tool_call_format = self.tool_call_format(&request)?;
 
         // convert the chat completion request to a common completion request
         let (common_request, annotations) = self.preprocess_request(&request)?;
@@ -290,11 +735,20 @@ impl
             .collect();
         let annotations_stream = stream::iter(annotations);
 
-        // forward the common completion request to the next operator
-        let response_stream = next.generate(common_request).await?;
+        // forward the common completion request to the next operator, unless there is nothing
+        // to generate
+        let response_stream = if common_request.stop_conditions.max_tokens == Some(0) {
+            prompt_only_response(common_request.context())
+        } else {
+            next.generate(common_request).await?
+        };
 
         // transform the postprocessor stream
         let stream = Self::transform_postprocessor_stream(response_stream, response_generator);
+        let stream = match tool_call_format {
+            Some((format, matcher)) => tools::parse_tool_call_stream(stream, format, matcher),
+            None => stream,
+        };
         let context = stream.context();
 
         // prepend the annotations to the response stream
@@ -322,7 +776,10 @@ impl
         >,
     ) -> Result<ManyOut<Annotated<CompletionResponse>>, Error> {
         // unpack the request
-        let (request, context) = request.into_parts();
+        let (mut request, context) = request.into_parts();
+        if self.options.ignore_unsupported_logprobs && !self.options.capabilities.logprobs {
+            request.inner.logprobs = None;
+        }
 
         // create a response generator
         let response_generator = request.response_generator();
@@ -343,8 +800,13 @@ impl
             .collect();
         let annotations_stream = stream::iter(annotations);
 
-        // forward the common completion request to the next operator
-        let response_stream = next.generate(common_request).await?;
+        // forward the common completion request to the next operator, unless there is nothing
+        // to generate
+        let response_stream = if common_request.stop_conditions.max_tokens == Some(0) {
+            prompt_only_response(common_request.context())
+        } else {
+            next.generate(common_request).await?
+        };
 
         // transform the postprocessor stream
         let stream = Self::transform_postprocessor_stream(response_stream, response_generator);
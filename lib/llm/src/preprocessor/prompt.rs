@@ -34,6 +34,8 @@ use anyhow::Result;
 use minijinja::value::Value;
 use std::sync::Arc;
 
+use crate::protocols::common::OutputConstraint;
+
 mod template;
 
 pub use template::ContextMixins;
@@ -47,6 +49,18 @@ pub trait OAIChatLikeRequest {
     fn tool_choice(&self) -> Option<Value> {
         None
     }
+    /// The request asks for output matching a JSON schema, `response_format: json_schema`
+    fn wants_json_schema(&self) -> bool {
+        false
+    }
+    /// What `response_format` asks the engine to constrain the output to
+    fn output_constraint(&self) -> Option<OutputConstraint> {
+        None
+    }
+    /// The request asks for the log probabilities of the generated tokens
+    fn wants_logprobs(&self) -> bool {
+        false
+    }
 
     fn should_add_generation_prompt(&self) -> bool;
 }
@@ -59,3 +73,73 @@ pub trait OAIPromptFormatter: Send + Sync + 'static {
 pub enum PromptFormatter {
     OAI(Arc<dyn OAIPromptFormatter>),
 }
+
+/// A request with all its system messages merged into one, in place of the first. Some templates
+/// raise an error if there is more than one system message.
+pub(crate) struct MergedSystemMessages<'a>(pub &'a dyn OAIChatLikeRequest);
+
+impl OAIChatLikeRequest for MergedSystemMessages<'_> {
+    fn messages(&self) -> Value {
+        merge_system_messages(self.0.messages())
+    }
+    fn tools(&self) -> Option<Value> {
+        self.0.tools()
+    }
+    fn tool_choice(&self) -> Option<Value> {
+        self.0.tool_choice()
+    }
+    fn wants_json_schema(&self) -> bool {
+        self.0.wants_json_schema()
+    }
+    fn output_constraint(&self) -> Option<OutputConstraint> {
+        self.0.output_constraint()
+    }
+    fn wants_logprobs(&self) -> bool {
+        self.0.wants_logprobs()
+    }
+    fn should_add_generation_prompt(&self) -> bool {
+        self.0.should_add_generation_prompt()
+    }
+}
+
+/// The system messages' contents are joined with a blank line. Messages are returned unchanged
+/// if there are fewer than two system messages.
+fn merge_system_messages(messages: Value) -> Value {
+    let Ok(serde_json::Value::Array(list)) = serde_json::to_value(&messages) else {
+        return messages;
+    };
+    let is_system = |message: &serde_json::Value| message["role"] == "system";
+    if list.iter().filter(|m| is_system(m)).count() < 2 {
+        return messages;
+    }
+    let content = list
+        .iter()
+        .filter(|m| is_system(m))
+        .map(|m| message_text(&m["content"]))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let mut merged = Vec::with_capacity(list.len());
+    let mut content = Some(content);
+    for mut message in list {
+        if !is_system(&message) {
+            merged.push(message);
+        } else if let Some(content) = content.take() {
+            message["content"] = content.into();
+            merged.push(message);
+        }
+    }
+    Value::from_serialize(&merged)
+}
+
+/// A message's `content`, either a string or an array of text parts
+fn message_text(content: &serde_json::Value) -> String {
+    match content {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect(),
+        _ => String::new(),
+    }
+}
@@ -15,10 +15,12 @@
 
 use std::{collections::HashSet, sync::Arc};
 
-use anyhow::{Ok, Result};
-use minijinja::Environment;
+use anyhow::{Context, Ok, Result};
+use either::Either;
+use minijinja::{Environment, UndefinedBehavior};
 
 use crate::model_card::model::{ModelDeploymentCard, PromptContextMixin, PromptFormatterArtifact};
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
 
 mod context;
 mod formatters;
@@ -26,28 +28,43 @@ mod oai;
 mod tokcfg;
 
 use super::{OAIChatLikeRequest, OAIPromptFormatter, PromptFormatter};
-use tokcfg::ChatTemplate;
+use tokcfg::{ChatTemplate, ChatTemplateValue};
 
 impl PromptFormatter {
     pub async fn from_mdc(mdc: ModelDeploymentCard) -> Result<PromptFormatter> {
-        match mdc
-            .prompt_formatter
-            .ok_or(anyhow::anyhow!("MDC does not contain a prompt formatter"))?
-        {
-            PromptFormatterArtifact::HfTokenizerConfigJson(file) => {
-                let content = std::fs::read_to_string(file)?;
-                let config: ChatTemplate = serde_json::from_str(&content)?;
-                Self::from_parts(
-                    config,
-                    mdc.prompt_context
-                        .map_or(ContextMixins::default(), |x| ContextMixins::new(&x)),
-                )
-            }
-            PromptFormatterArtifact::GGUF(gguf_path) => {
-                let config = ChatTemplate::from_gguf(&gguf_path)?;
-                Self::from_parts(config, ContextMixins::default())
-            }
+        let Some((config, context)) = chat_template_config(&mdc)? else {
+            anyhow::bail!("MDC does not contain a prompt formatter");
+        };
+        Self::from_parts(config, context)
+    }
+
+    /// Fail early if the card has no chat template, or its template can't render a simple
+    /// request, otherwise every chat request would fail.
+    pub fn check_chat_template(mdc: &ModelDeploymentCard) -> Result<()> {
+        let (config, context) = chat_template_config(mdc)?.unwrap_or_default();
+        if config.chat_template.is_none() {
+            anyhow::bail!(
+                "model {} has no chat template; pass --chat-template",
+                mdc.service_name
+            );
+        }
+        let mut formatter = HfTokenizerConfigJsonFormatter::new(config, context)?;
+        // A misspelled variable would otherwise render as an empty string. Templates can still
+        // test for optional variables with `is defined` or `if`.
+        formatter
+            .env
+            .set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": mdc.service_name,
+            "messages": [{"role": "user", "content": "Hello"}],
+        }))?;
+        if let Err(err) = formatter.render(&request) {
+            anyhow::bail!(
+                "The chat template of model {} fails to render a sample request: {err:#}",
+                mdc.service_name
+            );
         }
+        Ok(())
     }
 
     pub fn from_parts(config: ChatTemplate, context: ContextMixins) -> Result<PromptFormatter> {
@@ -56,6 +73,41 @@ impl PromptFormatter {
     }
 }
 
+/// The card's chat template config, with any explicit template in place of the one in the
+/// tokenizer config. None if the card has neither.
+fn chat_template_config(
+    mdc: &ModelDeploymentCard,
+) -> Result<Option<(ChatTemplate, ContextMixins)>> {
+    let (mut config, context) = match &mdc.prompt_formatter {
+        Some(PromptFormatterArtifact::HfTokenizerConfigJson(file)) => {
+            let content = std::fs::read_to_string(file)?;
+            let config: ChatTemplate = serde_json::from_str(&content)?;
+            let context = mdc
+                .prompt_context
+                .as_ref()
+                .map_or(ContextMixins::default(), |x| ContextMixins::new(x));
+            (config, context)
+        }
+        Some(PromptFormatterArtifact::GGUF(gguf_path)) => {
+            let config = ChatTemplate::from_gguf(gguf_path)?;
+            (config, ContextMixins::default())
+        }
+        None if mdc.chat_template.is_some() || mdc.chat_template_file.is_some() => {
+            (ChatTemplate::default(), ContextMixins::default())
+        }
+        None => return Ok(None),
+    };
+    // An inline template overrides a template file, which overrides the tokenizer config
+    if let Some(template) = &mdc.chat_template {
+        config.chat_template = Some(ChatTemplateValue(Either::Left(template.clone())));
+    } else if let Some(path) = &mdc.chat_template_file {
+        let template = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed reading chat template from {}", path.display()))?;
+        config.chat_template = Some(ChatTemplateValue(Either::Left(template)));
+    }
+    Ok(Some((config, context)))
+}
+
 /// Chat Template Jinja Renderer
 ///
 /// Manages a Jinja environment with registered templates for chat formatting.
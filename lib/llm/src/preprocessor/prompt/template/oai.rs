@@ -17,6 +17,7 @@ use super::*;
 
 use minijinja::{context, value::Value};
 
+use crate::protocols::common::OutputConstraint;
 use crate::protocols::openai::{
     chat_completions::NvCreateChatCompletionRequest, completions::CompletionRequest,
 };
@@ -43,6 +44,20 @@ impl OAIChatLikeRequest for NvCreateChatCompletionRequest {
         }
     }
 
+    fn wants_json_schema(&self) -> bool {
+        matches!(
+            self.inner.response_format,
+            Some(async_openai::types::ResponseFormat::JsonSchema { .. })
+        )
+    }
+
+    fn output_constraint(&self) -> Option<OutputConstraint> {
+        self.inner
+            .response_format
+            .as_ref()
+            .and_then(OutputConstraint::from_response_format)
+    }
+
     fn should_add_generation_prompt(&self) -> bool {
         if let Some(last) = self.inner.messages.last() {
             matches!(
@@ -66,12 +81,11 @@ impl OAIChatLikeRequest for CompletionRequest {
             },
         );
 
-        // Convert to a JSON string first
-        let json_string =
-            serde_json::to_string(&vec![message]).expect("Serialization to JSON string failed");
+        Value::from_serialize(vec![message])
+    }
 
-        // Convert to MiniJinja Value
-        minijinja::value::Value::from_safe_string(json_string)
+    fn wants_logprobs(&self) -> bool {
+        self.inner.logprobs.is_some()
     }
 
     fn should_add_generation_prompt(&self) -> bool {
@@ -13,9 +13,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod parser;
 mod request;
 mod response;
 
+pub use parser::*;
 pub use request::*;
 pub use response::*;
 use serde_json::Value;
@@ -27,6 +29,7 @@ use uuid::Uuid;
 /// Supports multiple formats for tool calls:
 /// - Single/multiple function calls with parameters/arguments
 /// - Auto or user selected tool usage
+#[derive(Clone, Debug)]
 pub struct ToolCallingMatcher {
     tool_choice: ToolChoice,
 }
@@ -106,10 +109,16 @@ impl ToolCallingMatcher {
                 })
                 .collect::<anyhow::Result<Vec<_>>>()?)
         } else {
-            if matches!(self.tool_choice, ToolChoice::Tool(_)) {
-                anyhow::bail!("Tool choice was required but no tools were called.")
-            }
+            self.check_called(false)?;
             Ok(Vec::new())
         }
     }
+
+    /// Fails if the tool choice required a call, and none was made
+    pub fn check_called(&self, called: bool) -> anyhow::Result<()> {
+        if !called && matches!(self.tool_choice, ToolChoice::Tool(_) | ToolChoice::Required) {
+            anyhow::bail!("Tool choice was required but no tools were called.")
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,520 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tool calls in a model's generated text, turned into structured `tool_calls` as it streams.
+//!
+//! Models write tool calls in whatever syntax their chat template taught them. The
+//! [`ToolCallFormat`] in the model deployment card says which one to expect.
+
+use std::collections::HashMap;
+
+use async_openai::types::{
+    ChatCompletionMessageToolCallChunk, ChatCompletionToolType, FinishReason, FunctionCallStream,
+};
+use dynamo_runtime::engine::{AsyncEngineContextProvider, ResponseStream};
+use dynamo_runtime::pipeline::ManyOut;
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::{ToolCallResponse, ToolCallingMatcher};
+use crate::protocols::openai::chat_completions::NvCreateChatCompletionStreamResponse;
+
+/// How a model writes its tool calls. Each call is a JSON object with a `name`, and the
+/// arguments as `arguments` or `parameters`.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, strum::EnumString, strum::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ToolCallFormat {
+    /// The whole reply is a call, or a JSON list of them, as Llama 3.1 and later are prompted to
+    /// do. Replies starting with anything but `{` or `[` are text.
+    Json,
+
+    /// The calls follow a `<|tool_call|>` token, as one object or a list, up to
+    /// `<|/tool_call|>` or the end of the reply. Phi-4 and Granite.
+    ToolCallToken,
+
+    /// Each call is inside `<tool_call>` .. `</tool_call>`. Hermes and Qwen 2.5.
+    Hermes,
+}
+
+impl ToolCallFormat {
+    /// The text before and after a call, None if the reply is nothing but calls
+    fn tags(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            ToolCallFormat::Json => None,
+            ToolCallFormat::ToolCallToken => Some(("<|tool_call|>", "<|/tool_call|>")),
+            ToolCallFormat::Hermes => Some(("<tool_call>", "</tool_call>")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum State {
+    /// Text, which may yet start a call
+    Text,
+    /// Inside a call
+    Call,
+    /// Text, and no calls can follow. A [`ToolCallFormat::Json`] reply which isn't a call.
+    OnlyText,
+}
+
+/// Splits one choice's streamed text into content and tool calls
+#[derive(Debug)]
+pub struct ToolCallParser {
+    format: ToolCallFormat,
+    /// Turns the text of a call into calls, following the request's `tool_choice`
+    matcher: ToolCallingMatcher,
+    state: State,
+    /// Text we hold back, because it's a call or may be the start of one
+    pending: String,
+    /// How many calls were found so far
+    calls: u32,
+}
+
+impl ToolCallParser {
+    pub fn new(format: ToolCallFormat, matcher: ToolCallingMatcher) -> Self {
+        ToolCallParser {
+            format,
+            matcher,
+            state: State::Text,
+            pending: String::new(),
+            calls: 0,
+        }
+    }
+
+    /// Take the next piece of generated text. Returns the text which is content, and the calls
+    /// completed by it.
+    pub fn push(&mut self, text: &str) -> (String, Vec<ToolCallResponse>) {
+        self.pending.push_str(text);
+        let mut content = String::new();
+        let mut calls = vec![];
+        match self.format.tags() {
+            None => self.push_untagged(&mut content),
+            Some((start, end)) => self.push_tagged(start, end, &mut content, &mut calls),
+        }
+        (content, calls)
+    }
+
+    fn push_untagged(&mut self, content: &mut String) {
+        if self.state == State::Text {
+            match self.pending.trim_start().chars().next() {
+                // Only whitespace so far, can't tell
+                None => return,
+                Some('{') | Some('[') => self.state = State::Call,
+                Some(_) => self.state = State::OnlyText,
+            }
+        }
+        // A call is only complete at the end of the reply
+        if self.state == State::OnlyText {
+            content.push_str(&std::mem::take(&mut self.pending));
+        }
+    }
+
+    fn push_tagged(
+        &mut self,
+        start: &str,
+        end: &str,
+        content: &mut String,
+        calls: &mut Vec<ToolCallResponse>,
+    ) {
+        loop {
+            match self.state {
+                State::Text => {
+                    if let Some(pos) = self.pending.find(start) {
+                        content.push_str(&self.pending[..pos]);
+                        self.pending.drain(..pos + start.len());
+                        self.state = State::Call;
+                        continue;
+                    }
+                    // Keep back what may be the beginning of `start`
+                    let keep = (1..start.len())
+                        .rev()
+                        .filter(|&n| start.is_char_boundary(n))
+                        .find(|&n| self.pending.ends_with(&start[..n]))
+                        .unwrap_or(0);
+                    let send = self.pending.len() - keep;
+                    content.push_str(&self.pending[..send]);
+                    self.pending.drain(..send);
+                    return;
+                }
+                State::Call => {
+                    let Some(pos) = self.pending.find(end) else {
+                        return;
+                    };
+                    let call: String = self.pending.drain(..pos + end.len()).collect();
+                    let call = &call[..pos];
+                    match self.parse(call) {
+                        Some(parsed) => calls.extend(parsed),
+                        None => {
+                            content.push_str(start);
+                            content.push_str(call);
+                            content.push_str(end);
+                        }
+                    }
+                    self.state = State::Text;
+                }
+                State::OnlyText => {
+                    content.push_str(&std::mem::take(&mut self.pending));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// The reply is complete. Returns the rest of the content and the last calls.
+    pub fn finish(&mut self) -> (String, Vec<ToolCallResponse>) {
+        let pending = std::mem::take(&mut self.pending);
+        if self.state != State::Call {
+            return (pending, vec![]);
+        }
+        self.state = State::Text;
+        match self.parse(&pending) {
+            Some(calls) => (String::new(), calls),
+            // Not a call after all
+            None => match self.format.tags() {
+                Some((start, _)) => (format!("{start}{pending}"), vec![]),
+                None => (pending, vec![]),
+            },
+        }
+    }
+
+    /// Whether any tool calls were found so far
+    pub fn has_calls(&self) -> bool {
+        self.calls > 0
+    }
+
+    /// Fails if the request's `tool_choice` required a call and the reply made none
+    pub fn check_called(&self) -> anyhow::Result<()> {
+        self.matcher.check_called(self.has_calls())
+    }
+
+    /// The calls in `text`, one JSON object or a list of them. None if it's anything else.
+    fn parse(&mut self, text: &str) -> Option<Vec<ToolCallResponse>> {
+        let calls = self.matcher.get_call(text).ok()?;
+        if calls.is_empty() {
+            return None;
+        }
+        self.calls += calls.len() as u32;
+        Some(calls)
+    }
+}
+
+/// Turn tool calls in the content of `stream` into `tool_calls` deltas. The choices that made
+/// calls finish with `tool_calls`. A choice which made none when `matcher` requires one is
+/// followed by an error.
+pub fn parse_tool_call_stream(
+    stream: ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+    format: ToolCallFormat,
+    matcher: ToolCallingMatcher,
+) -> ManyOut<Annotated<NvCreateChatCompletionStreamResponse>> {
+    let context = stream.context();
+    let output = async_stream::stream! {
+        let mut stream = stream;
+        let mut parsers: HashMap<u32, ToolCallParser> = HashMap::new();
+        // A chunk to copy the id and model from for what's left at the end
+        let mut last = None;
+        while let Some(mut response) = stream.next().await {
+            let mut errors = vec![];
+            if let Some(data) = response.data.as_mut() {
+                for choice in data.inner.choices.iter_mut() {
+                    let parser = parsers
+                        .entry(choice.index)
+                        .or_insert_with(|| ToolCallParser::new(format, matcher.clone()));
+                    let (mut content, mut calls) =
+                        parser.push(choice.delta.content.as_deref().unwrap_or_default());
+                    if choice.finish_reason.is_some() {
+                        let (rest, last_calls) = parser.finish();
+                        content.push_str(&rest);
+                        calls.extend(last_calls);
+                        if parser.has_calls() && choice.finish_reason == Some(FinishReason::Stop) {
+                            choice.finish_reason = Some(FinishReason::ToolCalls);
+                        }
+                        if let Err(err) = parser.check_called() {
+                            errors.push(format!("choice {}: {err}", choice.index));
+                        }
+                    }
+                    choice.delta.content = (!content.is_empty()).then_some(content);
+                    choice.delta.tool_calls = tool_call_chunks(parser, calls);
+                }
+                parsers.retain(|index, _| {
+                    !data
+                        .inner
+                        .choices
+                        .iter()
+                        .any(|choice| choice.index == *index && choice.finish_reason.is_some())
+                });
+                last = Some(data.clone());
+            }
+            yield response;
+            for error in errors {
+                yield Annotated::from_error(error);
+            }
+        }
+
+        // The stream ended without a finish reason, send what the parsers still hold
+        let Some(mut last) = last else {
+            return;
+        };
+        let mut choices = vec![];
+        let mut errors = vec![];
+        for (index, mut parser) in parsers {
+            let (content, calls) = parser.finish();
+            if let Err(err) = parser.check_called() {
+                errors.push(format!("choice {index}: {err}"));
+            }
+            if content.is_empty() && calls.is_empty() {
+                continue;
+            }
+            #[allow(deprecated)]
+            choices.push(async_openai::types::ChatChoiceStream {
+                index,
+                delta: async_openai::types::ChatCompletionStreamResponseDelta {
+                    content: (!content.is_empty()).then_some(content),
+                    function_call: None,
+                    tool_calls: None,
+                    role: None,
+                    refusal: None,
+                },
+                finish_reason: None,
+                logprobs: None,
+            });
+            let choice = choices.last_mut().unwrap();
+            choice.delta.tool_calls = tool_call_chunks(&parser, calls);
+            if parser.has_calls() {
+                choice.finish_reason = Some(FinishReason::ToolCalls);
+            }
+        }
+        if !choices.is_empty() {
+            choices.sort_by_key(|choice| choice.index);
+            last.inner.choices = choices;
+            last.inner.usage = None;
+            yield Annotated::from_data(last);
+        }
+        for error in errors {
+            yield Annotated::from_error(error);
+        }
+    };
+    ResponseStream::new(Box::pin(output), context)
+}
+
+/// The deltas for `calls`, the last ones `parser` found, or None if there are none
+fn tool_call_chunks(
+    parser: &ToolCallParser,
+    calls: Vec<ToolCallResponse>,
+) -> Option<Vec<ChatCompletionMessageToolCallChunk>> {
+    if calls.is_empty() {
+        return None;
+    }
+    let first = parser.calls - calls.len() as u32;
+    let chunks = (first..)
+        .zip(calls)
+        .map(|(index, call)| ChatCompletionMessageToolCallChunk {
+            index,
+            id: Some(call.id),
+            r#type: Some(ChatCompletionToolType::Function),
+            function: Some(FunctionCallStream {
+                name: Some(call.function.name),
+                arguments: Some(call.function.arguments),
+            }),
+        })
+        .collect();
+    Some(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::tools::ToolChoice;
+
+    fn auto() -> ToolCallingMatcher {
+        ToolCallingMatcher::new(ToolChoice::Auto).unwrap()
+    }
+
+    /// Push `pieces` one at a time, then finish. The calls as (name, arguments).
+    fn run(format: ToolCallFormat, pieces: &[&str]) -> (String, Vec<(String, String)>) {
+        let mut parser = ToolCallParser::new(format, auto());
+        let mut content = String::new();
+        let mut calls = vec![];
+        for piece in pieces {
+            let (text, found) = parser.push(piece);
+            content.push_str(&text);
+            calls.extend(found);
+        }
+        let (text, found) = parser.finish();
+        content.push_str(&text);
+        calls.extend(found);
+        let calls = calls
+            .into_iter()
+            .map(|call| (call.function.name, call.function.arguments))
+            .collect();
+        (content, calls)
+    }
+
+    fn call(name: &str, arguments: &str) -> (String, String) {
+        (name.to_string(), arguments.to_string())
+    }
+
+    #[test]
+    fn test_hermes() {
+        let (content, calls) = run(
+            ToolCallFormat::Hermes,
+            &[
+                "Let me check. <tool",
+                "_call>\n{\"name\": \"get_weather\", ",
+                "\"arguments\": {\"city\": \"Paris\"}}\n</tool_call>",
+                "<tool_call>{\"name\": \"get_time\", \"arguments\": {}}</tool_call>",
+            ],
+        );
+        assert_eq!(content, "Let me check. ");
+        assert_eq!(
+            calls,
+            vec![
+                call("get_weather", r#"{"city":"Paris"}"#),
+                call("get_time", "{}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tool_call_token() {
+        // No closing token, the calls run to the end of the reply
+        let (content, calls) = run(
+            ToolCallFormat::ToolCallToken,
+            &[
+                "<|tool_",
+                "call|>[{\"name\": \"search\", \"parameters\": {\"q\": \"rust\"}}]",
+            ],
+        );
+        assert_eq!(content, "");
+        assert_eq!(calls, vec![call("search", r#"{"q":"rust"}"#)]);
+    }
+
+    #[test]
+    fn test_json() {
+        let (content, calls) = run(
+            ToolCallFormat::Json,
+            &[" ", "{\"name\": \"search\", ", "\"arguments\": {\"q\": 1}}"],
+        );
+        assert_eq!(content, "");
+        assert_eq!(calls, vec![call("search", r#"{"q":1}"#)]);
+
+        let (content, calls) = run(ToolCallFormat::Json, &["Hello", " {\"name\": \"x\"}"]);
+        assert_eq!(content, "Hello {\"name\": \"x\"}");
+        assert!(calls.is_empty());
+    }
+
+    #[test]
+    fn test_required_call() {
+        let required = ToolCallingMatcher::new(ToolChoice::Required).unwrap();
+        let mut parser = ToolCallParser::new(ToolCallFormat::Hermes, required.clone());
+        parser.push("<tool_call>{\"name\": \"x\", \"arguments\": {}}</tool_call>");
+        parser.finish();
+        assert!(parser.check_called().is_ok());
+
+        let mut parser = ToolCallParser::new(ToolCallFormat::Hermes, required);
+        parser.push("No tools today");
+        parser.finish();
+        assert!(parser.check_called().is_err());
+
+        // Calls are optional with auto
+        let mut parser = ToolCallParser::new(ToolCallFormat::Hermes, auto());
+        parser.push("No tools today");
+        parser.finish();
+        assert!(parser.check_called().is_ok());
+    }
+
+    #[test]
+    fn test_text_is_not_held_back() {
+        let mut parser = ToolCallParser::new(ToolCallFormat::Hermes, auto());
+        assert_eq!(parser.push("a < b").0, "a < b");
+        // Could be the start of a tag
+        assert_eq!(parser.push(", <tool").0, ", ");
+        assert_eq!(parser.push("s>").0, "<tools>");
+    }
+
+    #[test]
+    fn test_not_a_call() {
+        let (content, calls) = run(
+            ToolCallFormat::Hermes,
+            &["<tool_call>not json</tool_call> and <tool_call>{\"unclosed"],
+        );
+        assert_eq!(
+            content,
+            "<tool_call>not json</tool_call> and <tool_call>{\"unclosed"
+        );
+        assert!(calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_stream() {
+        use crate::protocols::openai::chat_completions::NvCreateChatCompletionResponse;
+        use dynamo_runtime::pipeline::Context;
+
+        let chunk = |content: &str, finish_reason: Option<&str>| {
+            let chunk: NvCreateChatCompletionStreamResponse =
+                serde_json::from_value(serde_json::json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion.chunk",
+                    "created": 1,
+                    "model": "test",
+                    "choices": [{
+                        "index": 0,
+                        "delta": {"role": "assistant", "content": content},
+                        "finish_reason": finish_reason,
+                    }],
+                }))
+                .unwrap();
+            Annotated::from_data(chunk)
+        };
+        let chunks = vec![
+            chunk("Checking.<tool_", None),
+            chunk("call>{\"name\": \"get_weather\", \"arguments\": ", None),
+            chunk("{\"city\": \"Paris\"}}</tool_call>", None),
+            chunk("", Some("stop")),
+        ];
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(chunks)),
+            Context::new(()).context(),
+        );
+        let stream = parse_tool_call_stream(stream, ToolCallFormat::Hermes, auto());
+
+        let response = NvCreateChatCompletionResponse::from_annotated_stream(Box::pin(stream))
+            .await
+            .unwrap();
+        let choice = &response.inner.choices[0];
+        assert_eq!(choice.message.content.as_deref(), Some("Checking."));
+        assert_eq!(choice.finish_reason, Some(FinishReason::ToolCalls));
+        let calls = choice.message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 1);
+        assert!(calls[0].id.starts_with("call-"));
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+
+        // tool_choice required, but the model only wrote text
+        let required = ToolCallingMatcher::new(ToolChoice::Required).unwrap();
+        let stream = ResponseStream::new(
+            Box::pin(futures::stream::iter(vec![chunk("Sunny.", Some("stop"))])),
+            Context::new(()).context(),
+        );
+        let responses: Vec<_> = parse_tool_call_stream(stream, ToolCallFormat::Hermes, required)
+            .collect()
+            .await;
+        assert_eq!(responses.len(), 2);
+        assert_eq!(responses[1].event.as_deref(), Some("error"));
+    }
+}
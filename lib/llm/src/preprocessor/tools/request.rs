@@ -31,6 +31,9 @@ pub enum ToolChoice {
     #[serde(rename = "auto")]
     /// Allow automatic selection of any given tool, or none.
     Auto,
+    #[serde(rename = "required")]
+    /// Force selection of at least one of the given tools.
+    Required,
     #[serde(untagged)]
     /// Force selection of a given tool.
     Tool(Tool),
@@ -93,6 +93,41 @@ impl std::str::FromStr for FinishReason {
     }
 }
 
+/// Why generation ended, as decided by the [`crate::backend::Backend`] decode loop and sent
+/// ahead of the final response as a [`crate::backend::ANNOTATION_STOP_CAUSE`] annotation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopCause {
+    /// The model finished: EOS, a stop sequence or a tool call
+    Stop,
+
+    /// The request's `max_tokens` was reached
+    Length,
+
+    /// The engine stopped short of `max_tokens`, usually for lack of context
+    ServerCap,
+}
+
+impl StopCause {
+    /// For engines which don't run behind the backend, the cause from the OpenAI finish reason
+    /// alone. Content filter, error and cancelled are not stops.
+    pub fn from_finish_reason(finish_reason: &str) -> Option<StopCause> {
+        match finish_reason {
+            "stop" | "eos" | "tool_calls" | "function_call" => Some(StopCause::Stop),
+            "length" => Some(StopCause::Length),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopCause::Stop => "stop",
+            StopCause::Length => "length",
+            StopCause::ServerCap => "server_cap",
+        }
+    }
+}
+
 /// LLM Inference Engines can accept a variety of input types. Not all Engines will support all
 /// input types. For example, the trtllm::AsyncEngine only supports `PromptType::Tokens` as an
 /// input type. The higher-level `Backend` class is a general wrapper around Engines that will
@@ -225,6 +260,17 @@ pub struct StopConditions {
 }
 
 impl StopConditions {
+    /// Add the model's EOS tokens to the hidden stop tokens. Request `stop` sequences and
+    /// stop tokens augment the EOS tokens, they never replace them.
+    pub fn merge_eos_token_ids(&mut self, eos_token_ids: &[TokenIdType]) {
+        let stop_tokens = self.stop_token_ids_hidden.get_or_insert_with(Vec::new);
+        for eos_token in eos_token_ids {
+            if !stop_tokens.contains(eos_token) {
+                stop_tokens.push(*eos_token);
+            }
+        }
+    }
+
     pub fn apply_ignore_eos(&mut self) {
         if self.ignore_eos.unwrap_or(false) {
             self.min_tokens = self.max_tokens;
@@ -301,6 +347,10 @@ pub struct SamplingOptions {
 
     /// The seed to use when sampling
     pub seed: Option<i64>,
+
+    /// Report the log probability of each generated token, along with this many of the most
+    /// likely alternatives. Completions `logprobs`.
+    pub logprobs: Option<u32>,
 }
 
 impl SamplingOptions {
@@ -313,6 +363,46 @@ impl SamplingOptions {
         self.top_k = None;
         self.min_p = None;
     }
+
+    /// The penalties which are set, by their OpenAI names. vllm and sglang take them as
+    /// `SamplingParams` keyword arguments of the same names.
+    pub fn penalty_kwargs(&self) -> impl Iterator<Item = (&'static str, f64)> {
+        [
+            ("frequency_penalty", self.frequency_penalty),
+            ("presence_penalty", self.presence_penalty),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value as f64)))
+    }
+}
+
+/// What the engine must constrain its output to, from the request's `response_format`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputConstraint {
+    /// Any JSON object, `response_format: json_object`
+    JsonObject,
+
+    /// JSON matching this schema, `response_format: json_schema`
+    JsonSchema(serde_json::Value),
+}
+
+impl OutputConstraint {
+    /// None for `response_format: text`, which is no constraint at all
+    pub fn from_response_format(format: &async_openai::types::ResponseFormat) -> Option<Self> {
+        use async_openai::types::ResponseFormat;
+        match format {
+            ResponseFormat::Text => None,
+            ResponseFormat::JsonObject => Some(OutputConstraint::JsonObject),
+            ResponseFormat::JsonSchema { json_schema } => Some(OutputConstraint::JsonSchema(
+                // A schema-less json_schema is any JSON object
+                json_schema
+                    .schema
+                    .clone()
+                    .unwrap_or_else(|| serde_json::json!({ "type": "object" })),
+            )),
+        }
+    }
 }
 
 /// Collection of options that control what information the inference engine returns in the response.
@@ -16,7 +16,7 @@
 use derive_builder::Builder;
 use serde::{Deserialize, Serialize};
 
-use super::{SamplingOptions, StopConditions};
+use super::{OutputConstraint, SamplingOptions, StopConditions};
 use crate::protocols::TokenIdType;
 
 /// [`PreprocessedRequest`] is the internal representation of an LLM request. The [`dynamo.llm-preprocessor`]
@@ -47,6 +47,12 @@ pub struct PreprocessedRequest {
     /// User requested annotations for the request
     #[builder(default)]
     pub annotations: Vec<String>,
+
+    /// The output must be JSON, or match a schema. Engines which can't do that are wrapped to
+    /// reject these requests, see [`crate::engines::make_unconstrained_engine_core`].
+    #[builder(default)]
+    #[serde(default)]
+    pub output_constraint: Option<OutputConstraint>,
 }
 
 impl PreprocessedRequest {
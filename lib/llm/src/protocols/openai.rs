@@ -15,6 +15,7 @@
 
 pub mod chat_completions;
 pub mod completions;
+pub mod embeddings;
 pub mod models;
 pub mod nvext;
 
@@ -27,8 +28,9 @@ use std::{
 
 use super::{
     common::{self, SamplingOptionsProvider, StopConditionsProvider},
-    ContentProvider,
+    ContentProvider, TokenIdType,
 };
+use crate::http::service::error::{FieldError, ValidationError};
 
 /// Minimum allowed value for OpenAI's `temperature` sampling option
 pub const MIN_TEMPERATURE: f32 = 0.0;
@@ -66,6 +68,43 @@ pub const MAX_PRESENCE_PENALTY: f32 = 2.0;
 /// Allowed range of values for OpenAI's `presence_penalty` sampling option
 pub const PRESENCE_PENALTY_RANGE: (f32, f32) = (MIN_PRESENCE_PENALTY, MAX_PRESENCE_PENALTY);
 
+/// OpenAI's `service_tier` request field, used as a scheduling hint.
+///
+/// Tiers we don't know about are treated as `default`, so new OpenAI tiers don't fail requests.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase", from = "String")]
+pub enum ServiceTier {
+    Auto,
+    #[default]
+    Default,
+    /// Lower priority, scheduled after other requests
+    Flex,
+}
+
+impl From<String> for ServiceTier {
+    fn from(tier: String) -> Self {
+        match tier.as_str() {
+            "auto" => ServiceTier::Auto,
+            "flex" => ServiceTier::Flex,
+            "default" => ServiceTier::Default,
+            other => {
+                tracing::debug!("Unknown service_tier '{other}', using default");
+                ServiceTier::Default
+            }
+        }
+    }
+}
+
+impl ServiceTier {
+    /// Scheduling priority, higher is served first
+    pub fn priority(&self) -> i32 {
+        match self {
+            ServiceTier::Auto | ServiceTier::Default => 0,
+            ServiceTier::Flex => -1,
+        }
+    }
+}
+
 /// Usage statistics for the completion request
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CompletionUsage {
@@ -140,6 +179,12 @@ trait OpenAISamplingOptionsProvider {
 
     fn get_presence_penalty(&self) -> Option<f32>;
 
+    fn get_seed(&self) -> Option<i64>;
+
+    fn get_logprobs(&self) -> Option<u32> {
+        None
+    }
+
     fn nvext(&self) -> Option<&nvext::NvExt>;
 }
 
@@ -150,6 +195,10 @@ trait OpenAIStopConditionsProvider {
 
     fn get_stop(&self) -> Option<Vec<String>>;
 
+    fn get_stop_token_ids(&self) -> Option<Vec<TokenIdType>> {
+        None
+    }
+
     fn nvext(&self) -> Option<&nvext::NvExt>;
 }
 
@@ -160,15 +209,30 @@ impl<T: OpenAISamplingOptionsProvider> SamplingOptionsProvider for T {
         //     return Err(format!("Error validating sampling options: {}", e));
         // }
 
-        let mut temperature = validate_range(self.get_temperature(), &TEMPERATURE_RANGE)
-            .map_err(|e| anyhow::anyhow!("Error validating temperature: {}", e))?;
-        let mut top_p = validate_range(self.get_top_p(), &TOP_P_RANGE)
-            .map_err(|e| anyhow::anyhow!("Error validating top_p: {}", e))?;
-        let frequency_penalty =
-            validate_range(self.get_frequency_penalty(), &FREQUENCY_PENALTY_RANGE)
-                .map_err(|e| anyhow::anyhow!("Error validating frequency_penalty: {}", e))?;
-        let presence_penalty = validate_range(self.get_presence_penalty(), &PRESENCE_PENALTY_RANGE)
-            .map_err(|e| anyhow::anyhow!("Error validating presence_penalty: {}", e))?;
+        // check them all, so every out of range option is reported at once
+        let mut errors = Vec::new();
+        let mut temperature = check_range(
+            "temperature",
+            self.get_temperature(),
+            &TEMPERATURE_RANGE,
+            &mut errors,
+        );
+        let mut top_p = check_range("top_p", self.get_top_p(), &TOP_P_RANGE, &mut errors);
+        let frequency_penalty = check_range(
+            "frequency_penalty",
+            self.get_frequency_penalty(),
+            &FREQUENCY_PENALTY_RANGE,
+            &mut errors,
+        );
+        let presence_penalty = check_range(
+            "presence_penalty",
+            self.get_presence_penalty(),
+            &PRESENCE_PENALTY_RANGE,
+            &mut errors,
+        );
+        if !errors.is_empty() {
+            return Err(ValidationError { errors }.into());
+        }
 
         if let Some(nvext) = self.nvext() {
             let greedy = nvext.greed_sampling.unwrap_or(false);
@@ -188,9 +252,10 @@ impl<T: OpenAISamplingOptionsProvider> SamplingOptionsProvider for T {
             top_p,
             top_k: None,
             min_p: None,
-            seed: None,
+            seed: self.get_seed(),
             use_beam_search: None,
             length_penalty: None,
+            logprobs: self.get_logprobs(),
         })
     }
 }
@@ -198,26 +263,22 @@ impl<T: OpenAISamplingOptionsProvider> SamplingOptionsProvider for T {
 impl<T: OpenAIStopConditionsProvider> StopConditionsProvider for T {
     fn extract_stop_conditions(&self) -> Result<common::StopConditions> {
         let max_tokens = self.get_max_tokens();
-        let min_tokens = self.get_min_tokens();
+        let mut min_tokens = self.get_min_tokens();
+        // The number of stop sequences is limited by the pre-processor
         let stop = self.get_stop();
 
-        if let Some(stop) = &stop {
-            if stop.len() > 4 {
-                anyhow::bail!("stop conditions must be less than 4")
-            }
-        }
-
         let mut ignore_eos = None;
 
         if let Some(nvext) = self.nvext() {
             ignore_eos = nvext.ignore_eos;
+            min_tokens = min_tokens.or(nvext.min_tokens);
         }
 
         Ok(common::StopConditions {
             max_tokens,
             min_tokens,
             stop,
-            stop_token_ids_hidden: None,
+            stop_token_ids_hidden: self.get_stop_token_ids(),
             ignore_eos,
         })
     }
@@ -275,6 +336,25 @@ where
     Ok(Some(value))
 }
 
+/// [`validate_range`], adding the error for option `name` to `errors` instead of returning it
+fn check_range<T>(
+    name: &str,
+    value: Option<T>,
+    range: &(T, T),
+    errors: &mut Vec<FieldError>,
+) -> Option<T>
+where
+    T: PartialOrd + Display,
+{
+    validate_range(value, range).unwrap_or_else(|e| {
+        errors.push(FieldError {
+            param: Some(name.to_string()),
+            message: format!("Error validating {name}: {e}"),
+        });
+        None
+    })
+}
+
 // todo - move to common location
 /// scale value in `src` range to `dst` range
 pub fn scale_value<T>(value: &T, src: &(T, T), dst: &(T, T)) -> Result<T>
@@ -313,6 +393,19 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_service_tier() {
+        let tier: ServiceTier = serde_json::from_str(r#""flex""#).unwrap();
+        assert_eq!(tier, ServiceTier::Flex);
+        let tier: ServiceTier = serde_json::from_str(r#""scale""#).unwrap();
+        assert_eq!(tier, ServiceTier::Default);
+        assert!(ServiceTier::Flex.priority() < ServiceTier::Default.priority());
+        assert_eq!(
+            serde_json::to_string(&ServiceTier::Flex).unwrap(),
+            r#""flex""#
+        );
+    }
+
     #[test]
     fn test_validate_range() {
         assert_eq!(validate_range(Some(0.5), &(0.0, 1.0)).unwrap(), Some(0.5));
@@ -17,15 +17,19 @@ use super::nvext::NvExt;
 use super::nvext::NvExtProvider;
 use super::OpenAISamplingOptionsProvider;
 use super::OpenAIStopConditionsProvider;
+use super::ServiceTier;
+use crate::protocols::TokenIdType;
 use dynamo_runtime::protocols::annotated::AnnotationsProvider;
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
 mod aggregator;
 mod delta;
+mod json_repair;
 
 pub use aggregator::DeltaAggregator;
 pub use delta::DeltaGenerator;
+pub use json_repair::{repair_json, JsonRepair};
 
 /// A request structure for creating a chat completion, extending OpenAI's
 /// `CreateChatCompletionRequest` with [`NvExt`] extensions.
@@ -34,6 +38,10 @@ pub use delta::DeltaGenerator;
 /// - `inner`: The base OpenAI chat completion request, embedded using `serde(flatten)`.
 /// - `nvext`: The optional NVIDIA extension field. See [`NvExt`] for
 ///   more details.
+/// - `service_tier`: OpenAI's `service_tier`. Parsed here instead of in `inner` so that tiers
+///   async-openai doesn't know about, such as `flex`, are accepted.
+/// - `stop_token_ids`: Token IDs which stop generation, as in vllm's API. Only applied by engines
+///   where we do the pre-processing.
 #[derive(Serialize, Deserialize, Validate, Debug, Clone)]
 pub struct NvCreateChatCompletionRequest {
     #[serde(flatten)]
@@ -41,6 +49,73 @@ pub struct NvCreateChatCompletionRequest {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nvext: Option<NvExt>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<ServiceTier>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_token_ids: Option<Vec<TokenIdType>>,
+}
+
+/// Request fields, here or in `nvext`, which take a floating point number
+const FLOAT_FIELDS: &[&str] = &[
+    "temperature",
+    "top_p",
+    "frequency_penalty",
+    "presence_penalty",
+    "repetition_penalty",
+];
+
+/// Request fields, here or in `nvext`, which take an integer
+const INTEGER_FIELDS: &[&str] = &[
+    "max_tokens",
+    "max_completion_tokens",
+    "n",
+    "seed",
+    "top_logprobs",
+    "top_k",
+];
+
+impl NvCreateChatCompletionRequest {
+    /// Parse a request, accepting numeric fields given as strings (`"temperature": "0.7"`) and
+    /// integer fields given as whole floats (`"max_tokens": 100.0`), which some clients send.
+    /// Anything else must be as strict parsing expects.
+    pub fn from_lenient_json(mut value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        coerce_numbers(&mut value);
+        if let Some(nvext) = value.get_mut("nvext") {
+            coerce_numbers(nvext);
+        }
+        serde_json::from_value(value)
+    }
+}
+
+/// Turn the known numeric fields of this JSON object into numbers where they can be
+fn coerce_numbers(value: &mut serde_json::Value) {
+    let Some(fields) = value.as_object_mut() else {
+        return;
+    };
+    for (name, field) in fields.iter_mut() {
+        let is_integer = INTEGER_FIELDS.contains(&name.as_str());
+        if !is_integer && !FLOAT_FIELDS.contains(&name.as_str()) {
+            continue;
+        }
+        let number = match field {
+            serde_json::Value::String(s) => s.trim().parse::<f64>().ok(),
+            serde_json::Value::Number(n) if is_integer && n.is_f64() => n.as_f64(),
+            _ => None,
+        };
+        let Some(number) = number else {
+            continue;
+        };
+        let coerced = if is_integer {
+            (number.fract() == 0.0).then(|| serde_json::Value::from(number as i64))
+        } else {
+            serde_json::Number::from_f64(number).map(serde_json::Value::Number)
+        };
+        if let Some(coerced) = coerced {
+            *field = coerced;
+        }
+    }
 }
 
 /// A response structure for unary chat completion responses, embedding OpenAI's
@@ -130,6 +205,11 @@ impl OpenAISamplingOptionsProvider for NvCreateChatCompletionRequest {
         self.inner.presence_penalty
     }
 
+    /// Retrieves the seed for sampling, if set.
+    fn get_seed(&self) -> Option<i64> {
+        self.inner.seed
+    }
+
     /// Returns a reference to the optional `NvExt` extension, if available.
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
@@ -168,8 +248,75 @@ impl OpenAIStopConditionsProvider for NvCreateChatCompletionRequest {
         })
     }
 
+    /// Retrieves the token IDs which stop the chat completion response.
+    fn get_stop_token_ids(&self) -> Option<Vec<TokenIdType>> {
+        self.stop_token_ids.clone()
+    }
+
     /// Returns a reference to the optional `NvExt` extension, if available.
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(extra: serde_json::Value) -> serde_json::Value {
+        let mut request = serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        request
+    }
+
+    #[test]
+    fn test_lenient_integer_as_float() {
+        let json = request(serde_json::json!({"temperature": 1, "max_tokens": 100.0}));
+        // Strict parsing takes the integer temperature, but not the float max_tokens
+        assert!(serde_json::from_value::<NvCreateChatCompletionRequest>(json.clone()).is_err());
+
+        let request = NvCreateChatCompletionRequest::from_lenient_json(json).unwrap();
+        assert_eq!(request.inner.temperature, Some(1.0));
+        #[allow(deprecated)]
+        let max_tokens = request.inner.max_tokens;
+        assert_eq!(max_tokens, Some(100));
+
+        // A fractional integer is still wrong
+        let json = self::request(serde_json::json!({"max_tokens": 100.5}));
+        assert!(NvCreateChatCompletionRequest::from_lenient_json(json).is_err());
+    }
+
+    #[test]
+    fn test_lenient_string_as_number() {
+        let json = request(serde_json::json!({
+            "temperature": "0.7",
+            "top_p": " 0.9 ",
+            "seed": "42",
+            "nvext": {"top_k": "5", "repetition_penalty": "1.1"},
+        }));
+        assert!(serde_json::from_value::<NvCreateChatCompletionRequest>(json.clone()).is_err());
+
+        let request = NvCreateChatCompletionRequest::from_lenient_json(json).unwrap();
+        assert_eq!(request.inner.temperature, Some(0.7));
+        assert_eq!(request.inner.top_p, Some(0.9));
+        assert_eq!(request.inner.seed, Some(42));
+        // and reaches the engine
+        let sampling_options =
+            crate::protocols::common::SamplingOptionsProvider::extract_sampling_options(&request)
+                .unwrap();
+        assert_eq!(sampling_options.seed, Some(42));
+        let nvext = request.nvext.unwrap();
+        assert_eq!(nvext.top_k, Some(5));
+        assert_eq!(nvext.repetition_penalty, Some(1.1));
+
+        // Not a number at all
+        let json = self::request(serde_json::json!({"temperature": "warm"}));
+        assert!(NvCreateChatCompletionRequest::from_lenient_json(json).is_err());
+    }
+}
@@ -59,6 +59,8 @@ struct DeltaChoice {
     finish_reason: Option<async_openai::types::FinishReason>,
     /// Optional log probabilities for the chat choice.
     logprobs: Option<async_openai::types::ChatChoiceLogprobs>,
+    /// The tool calls, keyed by their index. A call may arrive in pieces.
+    tool_calls: HashMap<u32, async_openai::types::ChatCompletionMessageToolCall>,
 }
 
 impl Default for DeltaAggregator {
@@ -134,6 +136,7 @@ impl DeltaAggregator {
                                     role: choice.delta.role,
                                     finish_reason: None,
                                     logprobs: choice.logprobs,
+                                    tool_calls: HashMap::new(),
                                 });
 
                         // Append content if available.
@@ -141,6 +144,11 @@ impl DeltaAggregator {
                             state_choice.text.push_str(content);
                         }
 
+                        // Append tool call pieces if available.
+                        for chunk in choice.delta.tool_calls.into_iter().flatten() {
+                            state_choice.push_tool_call(chunk);
+                        }
+
                         // Update finish reason if provided.
                         if let Some(finish_reason) = choice.finish_reason {
                             state_choice.finish_reason = Some(finish_reason);
@@ -185,6 +193,34 @@ impl DeltaAggregator {
     }
 }
 
+impl DeltaChoice {
+    /// Add a piece of a tool call. The first piece of a call has its id and name, the arguments
+    /// may be split over several.
+    fn push_tool_call(&mut self, chunk: async_openai::types::ChatCompletionMessageToolCallChunk) {
+        let call = self.tool_calls.entry(chunk.index).or_insert_with(|| {
+            async_openai::types::ChatCompletionMessageToolCall {
+                id: String::new(),
+                r#type: async_openai::types::ChatCompletionToolType::Function,
+                function: async_openai::types::FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                },
+            }
+        });
+        if let Some(id) = chunk.id {
+            call.id = id;
+        }
+        if let Some(function) = chunk.function {
+            if let Some(name) = function.name {
+                call.function.name = name;
+            }
+            if let Some(arguments) = function.arguments {
+                call.function.arguments.push_str(&arguments);
+            }
+        }
+    }
+}
+
 #[allow(deprecated)]
 impl From<DeltaChoice> for async_openai::types::ChatChoice {
     /// Converts a [`DeltaChoice`] into an [`async_openai::types::ChatChoice`].
@@ -192,11 +228,20 @@ impl From<DeltaChoice> for async_openai::types::ChatChoice {
     /// # Note
     /// The `function_call` field is deprecated.
     fn from(delta: DeltaChoice) -> Self {
+        let mut tool_calls: Vec<_> = delta.tool_calls.into_iter().collect();
+        tool_calls.sort_by_key(|(index, _)| *index);
+        let tool_calls: Vec<_> = tool_calls.into_iter().map(|(_, call)| call).collect();
+        // A reply which is only tool calls has no content
+        let content = if delta.text.is_empty() && !tool_calls.is_empty() {
+            None
+        } else {
+            Some(delta.text)
+        };
         async_openai::types::ChatChoice {
             message: async_openai::types::ChatCompletionResponseMessage {
                 role: delta.role.expect("delta should have a Role"),
-                content: Some(delta.text),
-                tool_calls: None,
+                content,
+                tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
                 refusal: None,
                 function_call: None,
                 audio: None,
@@ -208,6 +253,57 @@ impl From<DeltaChoice> for async_openai::types::ChatChoice {
     }
 }
 
+#[allow(deprecated)]
+impl From<NvCreateChatCompletionResponse> for NvCreateChatCompletionStreamResponse {
+    /// The complete response as a single chunk, each choice's message becoming its delta.
+    fn from(response: NvCreateChatCompletionResponse) -> Self {
+        let response = response.inner;
+        let choices = response
+            .choices
+            .into_iter()
+            .map(|choice| async_openai::types::ChatChoiceStream {
+                index: choice.index,
+                delta: async_openai::types::ChatCompletionStreamResponseDelta {
+                    content: choice.message.content,
+                    function_call: None,
+                    tool_calls: choice.message.tool_calls.map(|calls| {
+                        (0..)
+                            .zip(calls)
+                            .map(|(index, call)| {
+                                async_openai::types::ChatCompletionMessageToolCallChunk {
+                                    index,
+                                    id: Some(call.id),
+                                    r#type: Some(call.r#type),
+                                    function: Some(async_openai::types::FunctionCallStream {
+                                        name: Some(call.function.name),
+                                        arguments: Some(call.function.arguments),
+                                    }),
+                                }
+                            })
+                            .collect()
+                    }),
+                    role: Some(choice.message.role),
+                    refusal: choice.message.refusal,
+                },
+                finish_reason: choice.finish_reason,
+                logprobs: choice.logprobs,
+            })
+            .collect();
+        NvCreateChatCompletionStreamResponse {
+            inner: async_openai::types::CreateChatCompletionStreamResponse {
+                id: response.id,
+                choices,
+                created: response.created,
+                model: response.model,
+                service_tier: response.service_tier,
+                system_fingerprint: response.system_fingerprint,
+                object: "chat.completion.chunk".to_string(),
+                usage: response.usage,
+            },
+        }
+    }
+}
+
 impl NvCreateChatCompletionResponse {
     /// Converts an SSE stream into a [`NvCreateChatCompletionResponse`].
     ///
@@ -0,0 +1,164 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Repair of the ways a model's JSON output usually goes wrong, even with guided decoding:
+//! a markdown code fence around it, trailing commas, and strings, arrays and objects left open
+//! because generation stopped early.
+
+/// What [`repair_json`] did to the text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonRepair {
+    /// It was valid JSON already
+    Valid,
+
+    /// This is the repaired text
+    Repaired(String),
+
+    /// It is not JSON we know how to repair
+    Failed,
+}
+
+/// Check that `text` is JSON, repairing it if it isn't
+pub fn repair_json(text: &str) -> JsonRepair {
+    if is_json(text) {
+        return JsonRepair::Valid;
+    }
+    match close_json(strip_code_fence(text)) {
+        Some(repaired) if is_json(&repaired) => JsonRepair::Repaired(repaired),
+        _ => JsonRepair::Failed,
+    }
+}
+
+fn is_json(text: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(text).is_ok()
+}
+
+/// The text inside a ```` ```json ```` code fence, or all of it if there is none
+fn strip_code_fence(text: &str) -> &str {
+    let text = text.trim();
+    let Some(rest) = text.strip_prefix("```") else {
+        return text;
+    };
+    // Skip the language tag
+    let rest = rest.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+    rest.trim_end().strip_suffix("```").unwrap_or(rest).trim()
+}
+
+/// Drop trailing commas, and close whatever is still open at the end. None if brackets don't
+/// match.
+fn close_json(text: &str) -> Option<String> {
+    let mut out = String::with_capacity(text.len() + 8);
+    let mut open = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            out.push(c);
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => open.push('}'),
+            '[' => open.push(']'),
+            '}' | ']' => {
+                if open.pop() != Some(c) {
+                    return None;
+                }
+                drop_trailing_comma(&mut out);
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+
+    if in_string {
+        if escaped {
+            out.pop();
+        }
+        out.push('"');
+    }
+    drop_trailing_comma(&mut out);
+    // A key without its value
+    if out.trim_end().ends_with(':') {
+        out.push_str(" null");
+    }
+    while let Some(close) = open.pop() {
+        drop_trailing_comma(&mut out);
+        out.push(close);
+    }
+    Some(out)
+}
+
+fn drop_trailing_comma(out: &mut String) {
+    let trimmed = out.trim_end().len();
+    if out[..trimmed].ends_with(',') {
+        out.truncate(trimmed - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repaired(text: &str) -> String {
+        match repair_json(text) {
+            JsonRepair::Repaired(repaired) => repaired,
+            other => panic!("{text} was not repaired: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_valid_json_is_left_alone() {
+        assert_eq!(
+            repair_json(r#"{"a": [1, 2], "b": "x,}"}"#),
+            JsonRepair::Valid
+        );
+    }
+
+    #[test]
+    fn test_trailing_commas() {
+        assert_eq!(
+            repaired(r#"{"a": 1, "b": [2, 3,], }"#),
+            r#"{"a": 1, "b": [2, 3]}"#
+        );
+        // Commas inside strings are content
+        assert_eq!(repaired(r#"{"a": "1,",}"#), r#"{"a": "1,"}"#);
+    }
+
+    #[test]
+    fn test_unclosed() {
+        assert_eq!(repaired(r#"{"a": {"b": [1, 2"#), r#"{"a": {"b": [1, 2]}}"#);
+        assert_eq!(repaired(r#"{"a": "unfinished"#), r#"{"a": "unfinished"}"#);
+        assert_eq!(repaired(r#"{"a": 1, "b":"#), r#"{"a": 1, "b": null}"#);
+        assert_eq!(repaired(r#"["a", "b\"#), r#"["a", "b"]"#);
+    }
+
+    #[test]
+    fn test_code_fence() {
+        assert_eq!(repaired("```json\n{\"a\": 1,}\n```"), r#"{"a": 1}"#);
+    }
+
+    #[test]
+    fn test_unrepairable() {
+        assert_eq!(repair_json(r#"{"a": 1]"#), JsonRepair::Failed);
+        assert_eq!(repair_json("Sure! Here is the JSON"), JsonRepair::Failed);
+    }
+}
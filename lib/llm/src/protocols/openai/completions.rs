@@ -191,6 +191,14 @@ impl OpenAISamplingOptionsProvider for CompletionRequest {
         self.inner.presence_penalty
     }
 
+    fn get_seed(&self) -> Option<i64> {
+        self.inner.seed
+    }
+
+    fn get_logprobs(&self) -> Option<u32> {
+        self.inner.logprobs.map(u32::from)
+    }
+
     fn nvext(&self) -> Option<&NvExt> {
         self.nvext.as_ref()
     }
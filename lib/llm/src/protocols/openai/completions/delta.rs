@@ -13,7 +13,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{CompletionChoice, CompletionRequest, CompletionResponse};
+use std::collections::HashMap;
+
+use super::{CompletionChoice, CompletionRequest, CompletionResponse, LogprobResult};
 use crate::protocols::common;
 use crate::protocols::openai::CompletionUsage;
 
@@ -23,7 +25,7 @@ impl CompletionRequest {
     pub fn response_generator(&self) -> DeltaGenerator {
         let options = DeltaGeneratorOptions {
             enable_usage: true,
-            enable_logprobs: false,
+            enable_logprobs: self.inner.logprobs.is_some(),
         };
 
         DeltaGenerator::new(self.inner.model.clone(), options)
@@ -45,6 +47,9 @@ pub struct DeltaGenerator {
     system_fingerprint: Option<String>,
     usage: CompletionUsage,
 
+    // number of characters emitted so far, used for the logprobs `text_offset`
+    text_offset: i32,
+
     options: DeltaGeneratorOptions,
 }
 
@@ -62,6 +67,7 @@ impl DeltaGenerator {
             model,
             system_fingerprint: None,
             usage: CompletionUsage::default(),
+            text_offset: 0,
             options,
         }
     }
@@ -75,6 +81,7 @@ impl DeltaGenerator {
         index: u64,
         text: Option<String>,
         finish_reason: Option<String>,
+        logprobs: Option<LogprobResult>,
     ) -> CompletionResponse {
         // todo - update for tool calling
 
@@ -88,7 +95,7 @@ impl DeltaGenerator {
                 text: text.unwrap_or_default(),
                 index,
                 finish_reason,
-                logprobs: None,
+                logprobs,
             }],
             usage: if self.options.enable_usage {
                 Some(self.usage.clone())
@@ -109,7 +116,11 @@ impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGe
             self.usage.completion_tokens += delta.token_ids.len() as i32;
         }
 
-        // todo logprobs
+        let logprobs = if self.options.enable_logprobs {
+            self.create_logprobs(&delta)?
+        } else {
+            None
+        };
 
         let finish_reason = match delta.finish_reason {
             Some(common::FinishReason::EoS) => Some("stop".to_string()),
@@ -124,6 +135,51 @@ impl crate::protocols::openai::DeltaGeneratorExt<CompletionResponse> for DeltaGe
 
         // create choice
         let index = 0;
-        Ok(self.create_choice(index, delta.text, finish_reason))
+        Ok(self.create_choice(index, delta.text, finish_reason, logprobs))
+    }
+}
+
+impl DeltaGenerator {
+    /// Build the legacy completions style logprobs for the tokens in this delta.
+    ///
+    /// The engine only reports the log probability of the sampled token, so `top_logprobs`
+    /// contains a single entry per position. Requests for engines which don't report scores are
+    /// rejected by the pre-processor, so missing scores here are an engine bug.
+    fn create_logprobs(
+        &mut self,
+        delta: &common::llm_backend::BackendOutput,
+    ) -> anyhow::Result<Option<LogprobResult>> {
+        if delta.token_ids.is_empty() {
+            return Ok(None);
+        }
+        let Some(log_probs) = delta.log_probs.as_ref() else {
+            anyhow::bail!("logprobs requested but the engine does not provide log probabilities");
+        };
+        if log_probs.len() != delta.token_ids.len() {
+            anyhow::bail!(
+                "engine returned {} log probabilities for {} tokens",
+                log_probs.len(),
+                delta.token_ids.len()
+            );
+        }
+
+        let mut result = LogprobResult {
+            tokens: Vec::with_capacity(log_probs.len()),
+            token_logprobs: Vec::with_capacity(log_probs.len()),
+            top_logprobs: Vec::with_capacity(log_probs.len()),
+            text_offset: Vec::with_capacity(log_probs.len()),
+        };
+        for (i, logprob) in log_probs.iter().enumerate() {
+            let token = delta.tokens.get(i).cloned().flatten().unwrap_or_default();
+            let logprob = *logprob as f32;
+            result.text_offset.push(self.text_offset);
+            self.text_offset += token.chars().count() as i32;
+            result.token_logprobs.push(logprob);
+            result
+                .top_logprobs
+                .push(HashMap::from([(token.clone(), logprob)]));
+            result.tokens.push(token);
+        }
+        Ok(Some(result))
     }
 }
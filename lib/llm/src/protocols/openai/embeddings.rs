@@ -0,0 +1,159 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use anyhow::Result;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::nvext::NvExt;
+use crate::protocols::{Annotated, DataStream};
+
+/// A request structure for creating embeddings, extending OpenAI's `CreateEmbeddingRequest`
+/// with [`NvExt`] extensions.
+///
+/// # Fields
+/// - `inner`: The base OpenAI embeddings request, embedded using `serde(flatten)`.
+/// - `nvext`: The optional NVIDIA extension field. See [`NvExt`] for more details.
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct NvCreateEmbeddingRequest {
+    #[serde(flatten)]
+    pub inner: async_openai::types::CreateEmbeddingRequest,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nvext: Option<NvExt>,
+}
+
+/// A response structure for embeddings, embedding OpenAI's `CreateEmbeddingResponse`.
+///
+/// # Fields
+/// - `inner`: The base OpenAI embeddings response, embedded using `serde(flatten)`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NvCreateEmbeddingResponse {
+    #[serde(flatten)]
+    pub inner: async_openai::types::CreateEmbeddingResponse,
+}
+
+impl NvCreateEmbeddingRequest {
+    /// How many embeddings the request asks for: one per string or token array of a batch
+    pub fn input_count(&self) -> usize {
+        use async_openai::types::EmbeddingInput;
+        match &self.inner.input {
+            EmbeddingInput::String(_) | EmbeddingInput::IntegerArray(_) => 1,
+            EmbeddingInput::StringArray(inputs) => inputs.len(),
+            EmbeddingInput::ArrayOfIntegerArray(inputs) => inputs.len(),
+        }
+    }
+}
+
+impl NvCreateEmbeddingResponse {
+    /// Combines an engine's responses into one. Engines may send all the embeddings at once or
+    /// spread them over several responses; either way they come out in input order.
+    pub async fn from_annotated_stream(
+        mut stream: DataStream<Annotated<NvCreateEmbeddingResponse>>,
+    ) -> Result<NvCreateEmbeddingResponse> {
+        let mut aggregate: Option<NvCreateEmbeddingResponse> = None;
+        while let Some(response) = stream.next().await {
+            let Some(response) = response.into_result()? else {
+                continue;
+            };
+            match aggregate.as_mut() {
+                None => aggregate = Some(response),
+                Some(aggregate) => {
+                    let usage = &mut aggregate.inner.usage;
+                    usage.prompt_tokens += response.inner.usage.prompt_tokens;
+                    usage.total_tokens += response.inner.usage.total_tokens;
+                    aggregate.inner.data.extend(response.inner.data);
+                }
+            }
+        }
+        let Some(mut aggregate) = aggregate else {
+            anyhow::bail!("Engine returned no embeddings");
+        };
+        aggregate
+            .inner
+            .data
+            .sort_by_key(|embedding| embedding.index);
+        Ok(aggregate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::{CreateEmbeddingResponse, Embedding, EmbeddingUsage};
+
+    fn response(indexes: &[u32]) -> Annotated<NvCreateEmbeddingResponse> {
+        Annotated::from_data(NvCreateEmbeddingResponse {
+            inner: CreateEmbeddingResponse {
+                object: "list".to_string(),
+                model: "test".to_string(),
+                data: indexes
+                    .iter()
+                    .map(|&index| Embedding {
+                        index,
+                        object: "embedding".to_string(),
+                        embedding: vec![index as f32],
+                    })
+                    .collect(),
+                usage: EmbeddingUsage {
+                    prompt_tokens: indexes.len() as u32,
+                    total_tokens: indexes.len() as u32,
+                },
+            },
+        })
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_in_input_order() {
+        let stream = futures::stream::iter(vec![response(&[2]), response(&[0, 1])]);
+        let aggregate = NvCreateEmbeddingResponse::from_annotated_stream(Box::pin(stream))
+            .await
+            .unwrap();
+        let indexes: Vec<_> = aggregate.inner.data.iter().map(|e| e.index).collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+        assert_eq!(aggregate.inner.data[2].embedding, vec![2.0]);
+        assert_eq!(aggregate.inner.usage.total_tokens, 3);
+    }
+
+    #[tokio::test]
+    async fn test_aggregate_error() {
+        let stream = futures::stream::iter(vec![
+            response(&[0]),
+            Annotated::from_error("out of memory".to_string()),
+        ]);
+        let err = NvCreateEmbeddingResponse::from_annotated_stream(Box::pin(stream))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("out of memory"), "{err}");
+    }
+
+    #[test]
+    fn test_input_count() {
+        let request: NvCreateEmbeddingRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "input": ["a", "b", "c"],
+        }))
+        .unwrap();
+        assert_eq!(request.input_count(), 3);
+
+        let request: NvCreateEmbeddingRequest = serde_json::from_value(serde_json::json!({
+            "model": "test",
+            "input": "a",
+        }))
+        .unwrap();
+        assert_eq!(request.input_count(), 1);
+    }
+}
@@ -31,6 +31,11 @@ pub struct NvExt {
     #[builder(default, setter(strip_option))]
     pub ignore_eos: Option<bool>,
 
+    /// Stop sequences and EOS only take effect once the model generated this many tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub min_tokens: Option<u32>,
+
     #[builder(default, setter(strip_option))] // NIM LLM might default to -1
     #[validate(custom(function = "validate_top_k"))]
     #[serde(default, skip_serializing_if = "Option::is_none")]
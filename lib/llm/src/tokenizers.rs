@@ -23,9 +23,12 @@ pub mod sp;
 // #[doc = include_str!("../README.md")]
 
 use std::hash::{DefaultHasher, Hash, Hasher};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::{ops::Deref, path::Path};
 
+use serde::Serialize;
+
 use crate::protocols::TokenIdType;
 pub use anyhow::{Error, Result};
 
@@ -91,6 +94,15 @@ impl Tokenizer {
     pub fn decode_stream(&self, skip_special_tokens: bool) -> DecodeStream {
         DecodeStream::new(self.0.clone(), skip_special_tokens)
     }
+
+    /// As [`Tokenizer::decode_stream`], producing text chunks at the given granularity
+    pub fn decode_stream_with_granularity(
+        &self,
+        skip_special_tokens: bool,
+        granularity: StreamGranularity,
+    ) -> DecodeStream {
+        DecodeStream::new(self.0.clone(), skip_special_tokens).with_granularity(granularity)
+    }
 }
 
 impl Deref for Tokenizer {
@@ -150,6 +162,34 @@ pub fn create_tokenizer_from_file(file_path: &str) -> Result<Arc<dyn traits::Tok
     }
 }
 
+/// How [`DecodeStream`] splits the decoded text into chunks.
+///
+/// A multi-byte character, such as an emoji, can be spread over several byte fallback tokens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamGranularity {
+    /// A chunk for every token as it arrives. The bytes of a character which isn't complete yet
+    /// come through as U+FFFD, and the token completing it brings the whole character.
+    Token,
+
+    /// Hold tokens back until they make complete UTF-8 characters, so chunks never contain a
+    /// partial character.
+    #[default]
+    Utf8,
+}
+
+impl FromStr for StreamGranularity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "token" => Ok(StreamGranularity::Token),
+            "utf8" | "utf-8" => Ok(StreamGranularity::Utf8),
+            _ => anyhow::bail!("Invalid stream granularity '{s}', expected token or utf8"),
+        }
+    }
+}
+
 /// DecodeStream will keep the state necessary to produce individual chunks of
 /// strings given an input stream of token_ids.
 ///
@@ -186,6 +226,12 @@ pub struct DecodeStream {
     /// of the text of all the ids
     /// read is the prefix kept only for starting side effects of the prefix
     read_index: usize,
+
+    granularity: StreamGranularity,
+
+    /// Bytes after the prefix already emitted for an incomplete character, in
+    /// [`StreamGranularity::Token`] mode
+    partial_len: usize,
 }
 
 impl DecodeStream {
@@ -197,9 +243,16 @@ impl DecodeStream {
             prefix: "".to_string(),
             prefix_index: 0,
             read_index: 0,
+            granularity: StreamGranularity::default(),
+            partial_len: 0,
         }
     }
 
+    pub fn with_granularity(mut self, granularity: StreamGranularity) -> Self {
+        self.granularity = granularity;
+        self
+    }
+
     /// Step appends a token_id to the internal state and tries to produce a text chunk.
     ///
     /// The method only fails if the internal state is corrupted.
@@ -207,13 +260,26 @@ impl DecodeStream {
     /// Returning `None` means the given id is not enough to produce a chunk.
     /// This typically happens with `byte_fallback` options where some tokens do not
     /// represent valid UTF-8, and only follow-up token_ids will help produce
-    /// a valid chunk.
+    /// a valid chunk. In [`StreamGranularity::Token`] mode those tokens produce
+    /// U+FFFD chunks instead.
     pub fn step(&mut self, id: u32) -> Result<Option<String>> {
         self.ids.push(id);
         let string = self
             .tokenizer
             .decode(self.ids.as_slice(), self.skip_special_tokens)?;
 
+        if self.granularity == StreamGranularity::Token
+            && string.ends_with('�')
+            && string.len() > self.prefix.len() + self.partial_len
+        {
+            if !(string.starts_with(&self.prefix)) {
+                anyhow::bail!("Detokenizer failure: invalid prefix");
+            }
+            let start = self.prefix.len() + self.partial_len;
+            self.partial_len = string.len() - self.prefix.len();
+            return Ok(Some(string.get(start..).unwrap_or_default().to_string()));
+        }
+
         if string.len() > self.prefix.len() && !string.ends_with('�') {
             if !(string.starts_with(&self.prefix)) {
                 anyhow::bail!("Detokenizer failure: invalid prefix");
@@ -225,6 +291,7 @@ impl DecodeStream {
                 .decode(self.ids.as_slice(), self.skip_special_tokens)?;
             self.read_index = self.prefix_index;
             self.prefix_index = new_prefix_index;
+            self.partial_len = 0;
             Ok(Some(new_text.to_string()))
         } else {
             Ok(None)
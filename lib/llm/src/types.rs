@@ -52,4 +52,17 @@ pub mod openai {
             Annotated<NvCreateChatCompletionStreamResponse>,
         >;
     }
+
+    pub mod embeddings {
+        use super::*;
+
+        pub use protocols::openai::embeddings::{
+            NvCreateEmbeddingRequest, NvCreateEmbeddingResponse,
+        };
+
+        /// A [`ServerStreamingEngine`] implementation for the OpenAI Embeddings API. Engines
+        /// usually send a single response, it is a stream so that remote endpoints can serve it.
+        pub type OpenAIEmbeddingsEngine =
+            ServerStreamingEngine<NvCreateEmbeddingRequest, Annotated<NvCreateEmbeddingResponse>>;
+    }
 }
@@ -13,8 +13,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use dynamo_llm::backend::Backend;
+use std::sync::Arc;
+
+use async_stream::stream;
+use dynamo_llm::backend::{Backend, Decoder, StopTrigger, ANNOTATION_STOP_CAUSE};
 use dynamo_llm::model_card::model::ModelDeploymentCard;
+use dynamo_llm::protocols::common::llm_backend::{BackendInput, LLMEngineOutput};
+use dynamo_llm::protocols::common::{SamplingOptions, StopCause, StopConditions};
+use dynamo_runtime::pipeline::{
+    async_trait, AsyncEngine, AsyncEngineContextProvider, Context, Error, ManyOut, Operator,
+    ResponseStream, SingleIn,
+};
+use dynamo_runtime::protocols::annotated::Annotated;
+use futures::StreamExt;
 
 #[tokio::test]
 async fn test_sequence_factory() {
@@ -32,3 +43,127 @@ async fn test_sequence_factory() {
     let output = decode_stream.step(1).unwrap();
     assert_eq!(output, None);
 }
+
+#[tokio::test]
+async fn test_custom_stop_still_halts_at_eos() {
+    let mdc = ModelDeploymentCard::from_local_path("tests/data/sample-models/TinyLlama_v1.1", None)
+        .await
+        .unwrap();
+    let eos_token_ids = mdc
+        .model_info
+        .get_model_info()
+        .await
+        .unwrap()
+        .eos_token_ids();
+    assert_eq!(eos_token_ids, vec![2]);
+
+    let operator = Backend::from_mdc(mdc).await.unwrap();
+
+    let mut stop_conditions = StopConditions {
+        stop: Some(vec!["###".to_string()]),
+        stop_token_ids_hidden: Some(vec![13]),
+        ..Default::default()
+    };
+    stop_conditions.merge_eos_token_ids(&eos_token_ids);
+    stop_conditions.apply_ignore_eos();
+    assert_eq!(stop_conditions.stop, Some(vec!["###".to_string()]));
+    assert_eq!(stop_conditions.stop_token_ids_hidden, Some(vec![13, 2]));
+
+    // Hello world</s>Hello
+    let mut decoder = Decoder::new(operator.tokenizer.decode_stream(false), stop_conditions);
+    let result = decoder.process_token_ids(&[15043, 3186, 2, 15043]).unwrap();
+    assert_eq!(result.tokens.len(), 3);
+    assert!(matches!(
+        result.stop_trigger,
+        Some(StopTrigger::HiddenStopTokenDetected(2))
+    ));
+}
+
+/// Generates `tokens` one at a time, then finishes with `finish`
+struct TokensEngine {
+    tokens: Vec<u32>,
+    finish: fn() -> LLMEngineOutput,
+}
+
+#[async_trait]
+impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+    for TokensEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<BackendInput>,
+    ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+        let (_, context) = request.transfer(());
+        let tokens = self.tokens.clone();
+        let finish = self.finish;
+        let stream = stream! {
+            for token in tokens {
+                yield Annotated::from_data(LLMEngineOutput {
+                    token_ids: vec![token],
+                    finish_reason: None,
+                    ..LLMEngineOutput::stop()
+                });
+            }
+            yield Annotated::from_data(finish());
+        };
+        Ok(ResponseStream::new(Box::pin(stream), context.context()))
+    }
+}
+
+#[tokio::test]
+async fn test_stop_cause() {
+    let mdc = ModelDeploymentCard::from_local_path("tests/data/sample-models/TinyLlama_v1.1", None)
+        .await
+        .unwrap();
+    let backend = Backend::from_mdc(mdc).await.unwrap();
+
+    // The stop cause the backend sends, ahead of the response which ends generation
+    let stop_cause = |tokens: Vec<u32>, finish: fn() -> LLMEngineOutput| {
+        let backend = backend.clone();
+        async move {
+            let request = BackendInput::builder()
+                .token_ids(vec![1])
+                .stop_conditions(StopConditions {
+                    max_tokens: Some(3),
+                    stop_token_ids_hidden: Some(vec![2]),
+                    ..Default::default()
+                })
+                .sampling_options(SamplingOptions::default())
+                .build()
+                .unwrap();
+            let engine = Arc::new(TokensEngine { tokens, finish });
+            let stream = backend
+                .generate(Context::new(request), engine)
+                .await
+                .unwrap();
+            let responses: Vec<_> = stream.collect().await;
+            let position = responses
+                .iter()
+                .position(|response| response.event.as_deref() == Some(ANNOTATION_STOP_CAUSE))?;
+            assert!(responses[position + 1].data.is_some());
+            let comment = responses[position].comment.as_ref().unwrap();
+            Some(serde_json::from_str::<StopCause>(&comment[0]).unwrap())
+        }
+    };
+
+    // Hello world</s>: the decoder found EOS
+    assert_eq!(
+        stop_cause(vec![15043, 3186, 2], LLMEngineOutput::stop).await,
+        Some(StopCause::Stop)
+    );
+    // The engine stopped at max_tokens
+    assert_eq!(
+        stop_cause(vec![15043, 3186, 15043], LLMEngineOutput::length).await,
+        Some(StopCause::Length)
+    );
+    // The engine stopped short of max_tokens
+    assert_eq!(
+        stop_cause(vec![15043], LLMEngineOutput::length).await,
+        Some(StopCause::ServerCap)
+    );
+    // Cancelled isn't a stop
+    assert_eq!(
+        stop_cause(vec![15043], LLMEngineOutput::cancelled).await,
+        None
+    );
+}
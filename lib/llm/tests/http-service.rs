@@ -17,14 +17,18 @@ use anyhow::Error;
 use async_stream::stream;
 use dynamo_llm::http::service::{
     error::HttpError,
+    listener::HttpVersion,
     metrics::{Endpoint, RequestType, Status},
+    sampling_profiles::SamplingProfile,
     service_v2::HttpService,
-    Metrics,
+    Metrics, ModelMetadata,
 };
+use dynamo_llm::model_card::model::ModelDeploymentCard;
 use dynamo_llm::protocols::{
     openai::{
         chat_completions::{NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse},
         completions::{CompletionRequest, CompletionResponse},
+        embeddings::{NvCreateEmbeddingRequest, NvCreateEmbeddingResponse},
     },
     Annotated,
 };
@@ -36,7 +40,7 @@ use dynamo_runtime::{
 };
 use prometheus::{proto::MetricType, Registry};
 use reqwest::StatusCode;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 struct CounterEngine {}
 
@@ -65,7 +69,8 @@ impl
         let stream = stream! {
             tokio::time::sleep(std::time::Duration::from_millis(max_tokens)).await;
             for i in 0..10 {
-                let inner = generator.create_choice(i,Some(format!("choice {i}")), None, None);
+                let finish_reason = (i == 9).then_some(async_openai::types::FinishReason::Stop);
+                let inner = generator.create_choice(i,Some(format!("choice {i}")), finish_reason, None);
 
                 let output = NvCreateChatCompletionStreamResponse {
                     inner,
@@ -79,6 +84,168 @@ impl
     }
 }
 
+/// Replies with the `max_tokens` it was asked for
+struct MaxTokensEngine {}
+
+#[allow(deprecated)]
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for MaxTokensEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+
+        let max_tokens = request
+            .inner
+            .max_completion_tokens
+            .or(request.inner.max_tokens);
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            let inner = generator.create_choice(0, Some(format!("{max_tokens:?}")), None, None);
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Replies with the sampling parameters it was asked for, as JSON
+struct SamplingEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for SamplingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+
+        let sampling = serde_json::json!({
+            "temperature": request.inner.temperature,
+            "top_p": request.inner.top_p,
+            "frequency_penalty": request.inner.frequency_penalty,
+            "presence_penalty": request.inner.presence_penalty,
+        });
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            let inner = generator.create_choice(0, Some(sampling.to_string()), None, None);
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Replies with the `nvext.min_tokens` it was asked for
+struct MinTokensEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for MinTokensEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+
+        let min_tokens = request.nvext.as_ref().and_then(|nvext| nvext.min_tokens);
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            let inner = generator.create_choice(0, Some(format!("{min_tokens:?}")), None, None);
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Streams a chunk every 100ms and never finishes
+struct EndlessEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for EndlessEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            for i in 0.. {
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                let inner = generator.create_choice(i, Some(format!("tick {i}")), None, None);
+                yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+            }
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Stamps each chunk with its own `created`, like an engine reading the clock per token
+struct TickingEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for TickingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            for i in 0..5 {
+                let finish_reason = (i == 4).then_some(async_openai::types::FinishReason::Stop);
+                let mut inner = generator.create_choice(i, Some(format!("tick {i}")), finish_reason, None);
+                inner.created = i;
+                yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+            }
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
 struct AlwaysFailEngine {}
 
 #[async_trait]
@@ -115,6 +282,205 @@ impl AsyncEngine<SingleIn<CompletionRequest>, ManyOut<Annotated<CompletionRespon
     }
 }
 
+struct FailMidStreamEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for FailMidStreamEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            for i in 0..2 {
+                let inner = generator.create_choice(i, Some(format!("choice {i}")), None, None);
+                yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+            }
+            yield Annotated::from_error("engine died".to_string());
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Counts its tokens like a remote OpenAI server asked for `include_usage`: usage on every chunk,
+/// then a chunk with only the usage
+struct UsageEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for UsageEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+        let generator = request.response_generator();
+        let usage = |completion_tokens| async_openai::types::CompletionUsage {
+            prompt_tokens: 5,
+            completion_tokens,
+            total_tokens: 5 + completion_tokens,
+            prompt_tokens_details: None,
+            completion_tokens_details: None,
+        };
+
+        let stream = stream! {
+            for i in 0..3 {
+                let finish_reason = (i == 2).then_some(async_openai::types::FinishReason::Stop);
+                let mut inner = generator.create_choice(0, Some(format!("token {i}")), finish_reason, None);
+                inner.usage = Some(usage(i + 1));
+                yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+            }
+            let mut inner = generator.create_choice(0, None, None, None);
+            inner.choices.clear();
+            inner.usage = Some(usage(3));
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Ends the stream without sending anything
+struct EmptyEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for EmptyEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (_, context) = request.transfer(());
+        Ok(ResponseStream::new(
+            Box::pin(futures::stream::empty()),
+            context.context(),
+        ))
+    }
+}
+
+/// Panics when asked to "panic", straight away, or after one chunk when asked to "panic later".
+/// Otherwise replies "ok".
+struct PanicEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for PanicEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+
+        let prompt = match request.inner.messages.last() {
+            Some(async_openai::types::ChatCompletionRequestMessage::User(message)) => {
+                match &message.content {
+                    async_openai::types::ChatCompletionRequestUserMessageContent::Text(text) => {
+                        text.clone()
+                    }
+                    _ => String::new(),
+                }
+            }
+            _ => String::new(),
+        };
+        if prompt == "panic" {
+            panic!("engine double asked to panic");
+        }
+        let generator = request.response_generator();
+
+        let stream = stream! {
+            let inner = generator.create_choice(0, Some("ok".to_string()), None, None);
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+            if prompt == "panic later" {
+                panic!("engine double asked to panic later");
+            }
+            let inner = generator.create_choice(
+                0,
+                None,
+                Some(async_openai::types::FinishReason::Stop),
+                None,
+            );
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
+/// Embeds each input as its length, sending the embeddings one at a time, last input first
+struct LengthEmbeddingEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateEmbeddingRequest>,
+        ManyOut<Annotated<NvCreateEmbeddingResponse>>,
+        Error,
+    > for LengthEmbeddingEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateEmbeddingRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateEmbeddingResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let ctx = context.context();
+
+        let inputs = match request.inner.input {
+            async_openai::types::EmbeddingInput::String(input) => vec![input],
+            async_openai::types::EmbeddingInput::StringArray(inputs) => inputs,
+            _ => anyhow::bail!("Only text input supported"),
+        };
+        let model = request.inner.model;
+
+        let stream = stream! {
+            for (index, input) in inputs.into_iter().enumerate().rev() {
+                let inner = async_openai::types::CreateEmbeddingResponse {
+                    object: "list".to_string(),
+                    model: model.clone(),
+                    data: vec![async_openai::types::Embedding {
+                        index: index as u32,
+                        object: "embedding".to_string(),
+                        embedding: vec![input.len() as f32],
+                    }],
+                    usage: async_openai::types::EmbeddingUsage {
+                        prompt_tokens: 1,
+                        total_tokens: 1,
+                    },
+                };
+                yield Annotated::from_data(NvCreateEmbeddingResponse { inner });
+            }
+        };
+
+        Ok(ResponseStream::new(Box::pin(stream), ctx))
+    }
+}
+
 fn compare_counter(
     metrics: Arc<Metrics>,
     model: &str,
@@ -138,6 +504,7 @@ fn compute_index(endpoint: &Endpoint, request_type: &RequestType, status: &Statu
     let endpoint = match endpoint {
         Endpoint::Completions => 0,
         Endpoint::ChatCompletions => 1,
+        Endpoint::Embeddings => 2,
     };
 
     let request_type = match request_type {
@@ -330,6 +697,15 @@ async fn test_http_service() {
     let response = future.await.unwrap();
 
     assert!(response.status().is_success(), "{:?}", response);
+    let server_timing = response
+        .headers()
+        .get("server-timing")
+        .expect("Missing Server-Timing header")
+        .to_str()
+        .unwrap();
+    for metric in ["preprocess;dur=", "ttft;dur=", "generate;dur="] {
+        assert!(server_timing.contains(metric), "{server_timing}");
+    }
     inc_counter(
         Endpoint::ChatCompletions,
         RequestType::Unary,
@@ -463,3 +839,2582 @@ async fn test_http_service() {
     cancel_token.cancel();
     task.await.unwrap().unwrap();
 }
+
+#[tokio::test]
+async fn test_http_service_engine_fails_mid_stream() {
+    let service = HttpService::builder().port(8990).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(FailMidStreamEngine {}))
+        .unwrap();
+    let metrics = manager.metrics();
+
+    let request = serde_json::json!({
+        "model": "foo",
+        "messages": [{"role": "user", "content": "hi"}],
+        "stream": true,
+    });
+    let response = reqwest::Client::new()
+        .post("http://localhost:8990/v1/chat/completions")
+        .json(&request)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+
+    let body = response.text().await.unwrap();
+    let chunks: Vec<serde_json::Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+
+    // the two chunks the engine produced, then a terminal chunk for the error
+    assert_eq!(chunks.len(), 3, "{body}");
+    for (i, chunk) in chunks[..2].iter().enumerate() {
+        assert_eq!(
+            chunk["choices"][0]["delta"]["content"],
+            format!("choice {i}")
+        );
+    }
+    let last = &chunks[2]["choices"][0];
+    assert_eq!(last["finish_reason"], "error", "{body}");
+    assert!(last["delta"].get("content").is_none(), "{body}");
+    assert!(body.contains("event: error"), "{body}");
+    assert!(body.contains("engine died"), "{body}");
+    assert!(body.trim_end().ends_with("data: [DONE]"), "{body}");
+
+    // the request is not counted as a success
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    compare_counter(
+        metrics.clone(),
+        "foo",
+        &Endpoint::ChatCompletions,
+        &RequestType::Stream,
+        &Status::Success,
+        0,
+    );
+    compare_counter(
+        metrics,
+        "foo",
+        &Endpoint::ChatCompletions,
+        &RequestType::Stream,
+        &Status::Error,
+        1,
+    );
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_models_context_length() {
+    let service = HttpService::builder().port(8993).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    // A core engine, the context length comes from the card
+    let card =
+        ModelDeploymentCard::from_local_path("tests/data/sample-models/TinyLlama_v1.1", None)
+            .await
+            .unwrap();
+    manager
+        .add_chat_completions_model("tiny", Arc::new(CounterEngine {}))
+        .unwrap();
+    manager.set_model_metadata("tiny", ModelMetadata::from_card(&card).await.unwrap());
+
+    // A model we know nothing about
+    manager
+        .add_chat_completions_model("remote", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let models: serde_json::Value = reqwest::Client::new()
+        .get("http://localhost:8993/v1/models")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let model = |id: &str| {
+        models["data"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|m| m["id"] == id)
+            .cloned()
+            .unwrap()
+    };
+    assert_eq!(models["object"], "list");
+    assert_eq!(model("tiny")["object"], "model");
+    assert_eq!(model("tiny")["owned_by"], "dynemo");
+    assert_eq!(model("tiny")["context_length"], 2048);
+    assert!(model("remote").get("context_length").is_none());
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_max_connections() {
+    let service = HttpService::builder()
+        .port(8994)
+        .max_connections(1)
+        .build()
+        .unwrap();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    // Holds the only connection slot
+    let first = tokio::net::TcpStream::connect("127.0.0.1:8994")
+        .await
+        .unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    // A new connection is turned away. Depending on timing the client either reads our 503 or
+    // sees the connection closed.
+    match reqwest::Client::new()
+        .get("http://127.0.0.1:8994/v1/models")
+        .send()
+        .await
+    {
+        Ok(response) => assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE),
+        Err(err) => assert!(!err.is_timeout(), "{err}"),
+    }
+
+    // Once the slot is free new connections are served
+    drop(first);
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    let response = reqwest::Client::new()
+        .get("http://127.0.0.1:8994/v1/models")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+/// Loads "weights" in steps, telling the test after each one
+async fn staged_engine_load(
+    progress: dynamo_llm::http::service::health::StartupProgress,
+    steps: tokio::sync::mpsc::Sender<()>,
+    mut proceed: tokio::sync::mpsc::Receiver<()>,
+) -> Arc<CounterEngine> {
+    for stage in ["downloading", "loading weights"] {
+        progress.update(stage);
+        steps.send(()).await.unwrap();
+        proceed.recv().await.unwrap();
+    }
+    Arc::new(CounterEngine {})
+}
+
+#[tokio::test]
+async fn test_http_service_startup_progress() {
+    let service = HttpService::builder()
+        .port(8995)
+        .serve_before_ready(true)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+    let progress = service.startup_progress().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let client = &client;
+    let startup = move || async move {
+        let response = client
+            .get("http://localhost:8995/health/startup")
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        (status, response.json::<serde_json::Value>().await.unwrap())
+    };
+
+    // Port is up before the engine starts loading
+    let (status, body) = startup().await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "starting");
+
+    let (steps_tx, mut steps) = tokio::sync::mpsc::channel(1);
+    let (proceed, proceed_rx) = tokio::sync::mpsc::channel(1);
+    let loader = tokio::spawn(staged_engine_load(progress.clone(), steps_tx, proceed_rx));
+
+    for stage in ["downloading", "loading weights"] {
+        steps.recv().await.unwrap();
+        let (status, body) = startup().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["stage"], stage);
+        assert_eq!(body["message"], stage);
+        proceed.send(()).await.unwrap();
+    }
+
+    let engine = loader.await.unwrap();
+    manager.add_chat_completions_model("foo", engine).unwrap();
+    progress.set_ready();
+
+    let (status, body) = startup().await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_version() {
+    use dynamo_llm::http::service::version::VersionInfo;
+
+    let service = HttpService::builder().port(8961).build().unwrap();
+    let version = service.version().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    let get_version = || async {
+        let response = client
+            .get("http://localhost:8961/version")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.json::<VersionInfo>().await.unwrap()
+    };
+
+    // Before the binary fills it in
+    let reported = get_version().await;
+    assert_eq!(reported.version, env!("CARGO_PKG_VERSION"));
+    assert!(reported.features.is_empty());
+    assert!(reported.engine.is_none());
+
+    let info = VersionInfo {
+        version: "1.2.3".to_string(),
+        git_sha: Some("0123456789ab".to_string()),
+        features: vec!["vllm".to_string(), "python".to_string()],
+        engine: Some("vllm".to_string()),
+    };
+    version.set(info.clone());
+    assert_eq!(get_version().await, info);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_tokenize_count() {
+    use dynamo_llm::preprocessor::OpenAIPreprocessor;
+
+    let service = HttpService::builder().port(8996).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    // The mock tokenizer has no vocabulary, only the special tokens from the prompt template
+    // become tokens
+    let card = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    manager
+        .add_chat_completions_model("core", Arc::new(CounterEngine {}))
+        .unwrap();
+    manager.set_model_preprocessor("core", OpenAIPreprocessor::new(card).await.unwrap());
+
+    manager
+        .add_chat_completions_model("full", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let count = |model: &'static str| {
+        client
+            .post("http://localhost:8996/v1/tokenize-count")
+            .json(&serde_json::json!({
+                "model": model,
+                "messages": [
+                    {"role": "system", "content": "You are a helpful assistant."},
+                    {"role": "user", "content": "What is the weather like in Paris today?"},
+                ],
+            }))
+            .send()
+    };
+
+    // <|begin_of_text|> <|start_header_id|> <|end_header_id|> <|eot_id|> for the system message,
+    // <|start_header_id|> <|end_header_id|> for the user message, and the generation prompt
+    // <|eot_id|> <|start_header_id|> <|end_header_id|>
+    let response = count("core").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["model"], "core");
+    assert_eq!(body["prompt_tokens"], 9);
+
+    // The engine does its own templating
+    let response = count("full").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = count("missing").await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_system_fingerprint() {
+    let service = HttpService::builder()
+        .port(8997)
+        .system_fingerprint("fp_backend_rev_42")
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |stream: bool| {
+        client
+            .post("http://localhost:8997/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": stream,
+            }))
+            .send()
+    };
+
+    let body: serde_json::Value = request(false).await.unwrap().json().await.unwrap();
+    assert_eq!(body["system_fingerprint"], "fp_backend_rev_42");
+
+    let body = request(true).await.unwrap().text().await.unwrap();
+    let chunks = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 10);
+    for chunk in chunks {
+        assert_eq!(chunk["system_fingerprint"], "fp_backend_rev_42");
+    }
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+const CHAT_BODY: &str = r#"{"model": "foo", "messages": [{"role": "user", "content": "hi"}]}"#;
+
+#[tokio::test]
+async fn test_http_service_strict_content_type() {
+    let service = HttpService::builder().port(8998).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+
+    // no content type
+    let response = client
+        .post("http://localhost:8998/v1/chat/completions")
+        .body(CHAT_BODY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("application/json"));
+
+    let response = client
+        .post("http://localhost:8998/v1/chat/completions")
+        .header("content-type", "text/plain")
+        .body(CHAT_BODY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let response = client
+        .post("http://localhost:8998/v1/chat/completions")
+        .header("content-type", "application/json")
+        .body(CHAT_BODY)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_lenient_content_type() {
+    let service = HttpService::builder()
+        .port(8999)
+        .lenient_content_type(true)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    for content_type in [None, Some("text/plain"), Some("application/json")] {
+        let mut request = client
+            .post("http://localhost:8999/v1/chat/completions")
+            .body(CHAT_BODY);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        let response = request.send().await.unwrap();
+        assert!(
+            response.status().is_success(),
+            "{content_type:?}: {response:?}"
+        );
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["object"], "chat.completion");
+    }
+
+    // Still has to be JSON
+    let response = client
+        .post("http://localhost:8999/v1/chat/completions")
+        .header("content-type", "text/plain")
+        .body("hi")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_admin_config() {
+    let service = HttpService::builder()
+        .port(8988)
+        .admin_api_key("hunter2")
+        .build()
+        .unwrap();
+    service.resolved_config().set(serde_json::json!({
+        "in": "http",
+        "out": "vllm",
+        "model_name": "foo",
+        "flags": {"admin_api_key": "hunter2", "http_port": 8988},
+    }));
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get("http://localhost:8988/admin/config")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get("http://localhost:8988/admin/config")
+        .bearer_auth("wrong")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get("http://localhost:8988/admin/config")
+        .bearer_auth("hunter2")
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+    let body = response.text().await.unwrap();
+    assert!(!body.contains("hunter2"), "{body}");
+    let config: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(config["model_name"], "foo");
+    assert_eq!(config["in"], "http");
+    assert_eq!(config["out"], "vllm");
+    assert_eq!(config["flags"]["admin_api_key"], "<redacted>");
+    assert_eq!(config["flags"]["http_port"], 8988);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_debug_echo_headers() {
+    // Off by default
+    let service = HttpService::builder().port(8966).build().unwrap();
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    let response = reqwest::get("http://localhost:8966/debug/headers")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+
+    let service = HttpService::builder()
+        .port(8966)
+        .debug_echo_headers(true)
+        .build()
+        .unwrap();
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    let body = reqwest::Client::new()
+        .get("http://localhost:8966/debug/headers")
+        .header("X-Forwarded-For", "10.0.0.1")
+        .header("X-Custom", "one")
+        .header("X-Api-Key", "sk-secret")
+        .header("Cookie", "session=secret")
+        .bearer_auth("hunter2")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(!body.contains("hunter2"), "{body}");
+    assert!(!body.contains("secret"), "{body}");
+    let headers: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(headers["x-forwarded-for"], "10.0.0.1");
+    assert_eq!(headers["x-custom"], "one");
+    assert_eq!(headers["host"], "localhost:8966");
+    assert_eq!(headers["authorization"], "<redacted>");
+    assert_eq!(headers["x-api-key"], "<redacted>");
+    assert_eq!(headers["cookie"], "<redacted>");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_drains_on_shutdown() {
+    let service = HttpService::builder().port(8987).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    // CounterEngine waits max_tokens milliseconds before responding
+    let request = |max_tokens: u32| {
+        client
+            .post("http://localhost:8987/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": max_tokens,
+            }))
+            .send()
+    };
+
+    let in_flight = tokio::spawn(request(1000));
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    cancel_token.cancel();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let response = request(0).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert!(response.headers().contains_key("retry-after"));
+
+    let response = in_flight.await.unwrap().unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "chat.completion");
+
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_shutdown_closes_streams_after_drain() {
+    use futures::StreamExt;
+
+    let service = HttpService::builder()
+        .port(8955)
+        .drain_timeout(std::time::Duration::from_secs(1))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(EndlessEngine {}))
+        .unwrap();
+
+    let response = reqwest::Client::new()
+        .post("http://localhost:8955/v1/chat/completions")
+        .json(&serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+    let mut body = response.bytes_stream();
+    assert!(body.next().await.unwrap().is_ok());
+
+    cancel_token.cancel();
+
+    // 1s of drain, then at most 1s for the connections, before the stream is cut
+    tokio::time::timeout(std::time::Duration::from_secs(5), task)
+        .await
+        .expect("server still running with a stream open past the drain timeout")
+        .unwrap()
+        .unwrap();
+    let ended = tokio::time::timeout(std::time::Duration::from_secs(1), async {
+        while let Some(Ok(_)) = body.next().await {}
+    })
+    .await;
+    assert!(ended.is_ok(), "stream still open after the server stopped");
+}
+
+#[tokio::test]
+async fn test_http_service_shutdown_summary() {
+    let service = HttpService::builder().port(8964).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let runner = service.clone();
+    let task = tokio::spawn(async move { runner.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+    manager
+        .add_chat_completions_model("bar", Arc::new(AlwaysFailEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |model: &str| {
+        client
+            .post("http://localhost:8964/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": model,
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 3,
+            }))
+            .send()
+    };
+    for model in ["foo", "foo", "bar"] {
+        let response = request(model).await.unwrap();
+        // read the body so the request is finished before we shut down
+        response.bytes().await.unwrap();
+    }
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+
+    let summary = service.shutdown_summary();
+    assert_eq!(summary.requests, 3);
+    assert_eq!(summary.errors, 1);
+    assert!(summary.average_latency.is_some());
+    let report = summary.to_string();
+    assert!(
+        report.starts_with("served 3 requests, 1 errors"),
+        "{report}"
+    );
+}
+
+#[tokio::test]
+async fn test_http_service_sampling_profiles() {
+    let profiles = HashMap::from([
+        (
+            "creative".to_string(),
+            SamplingProfile {
+                temperature: Some(1.25),
+                top_p: Some(0.75),
+                presence_penalty: Some(0.5),
+                ..Default::default()
+            },
+        ),
+        (
+            "precise".to_string(),
+            SamplingProfile {
+                temperature: Some(0.25),
+                ..Default::default()
+            },
+        ),
+    ]);
+    let service = HttpService::builder()
+        .port(8963)
+        .sampling_profiles(profiles)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(SamplingEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let send = |profile: Option<&str>, extra: serde_json::Value| {
+        let mut request = serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        request
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        let mut builder = client
+            .post("http://localhost:8963/v1/chat/completions")
+            .json(&request);
+        if let Some(profile) = profile {
+            builder = builder.header("X-Sampling-Profile", profile);
+        }
+        builder.send()
+    };
+    let sampling_seen = |response: reqwest::Response| async move {
+        assert!(response.status().is_success(), "{:?}", response);
+        let body: serde_json::Value = response.json().await.unwrap();
+        let content = body["choices"][0]["message"]["content"].as_str().unwrap();
+        serde_json::from_str::<serde_json::Value>(content).unwrap()
+    };
+
+    // The profile's values fill in what the request doesn't set
+    let seen = sampling_seen(send(Some("creative"), serde_json::json!({})).await.unwrap()).await;
+    assert_eq!(seen["temperature"], 1.25);
+    assert_eq!(seen["top_p"], 0.75);
+    assert_eq!(seen["presence_penalty"], 0.5);
+    assert!(seen["frequency_penalty"].is_null());
+
+    // Fields set in the request win
+    let seen = sampling_seen(
+        send(
+            Some("creative"),
+            serde_json::json!({"temperature": 0.5, "frequency_penalty": 1.0}),
+        )
+        .await
+        .unwrap(),
+    )
+    .await;
+    assert_eq!(seen["temperature"], 0.5);
+    assert_eq!(seen["top_p"], 0.75);
+    assert_eq!(seen["frequency_penalty"], 1.0);
+
+    // No header, no profile
+    let seen = sampling_seen(send(None, serde_json::json!({})).await.unwrap()).await;
+    assert!(seen["temperature"].is_null());
+
+    let response = send(Some("wild"), serde_json::json!({})).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "Unknown sampling profile 'wild', available profiles: creative, precise"
+    );
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_default_max_tokens_per_model() {
+    let service = HttpService::builder().port(8986).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager.set_default_model_metadata(ModelMetadata {
+        default_max_tokens: Some(100),
+        ..Default::default()
+    });
+    for model in ["foo", "bar", "baz"] {
+        manager
+            .add_chat_completions_model(model, Arc::new(MaxTokensEngine {}))
+            .unwrap();
+    }
+    manager.set_model_default_max_tokens("foo", 16);
+    manager.set_model_default_max_tokens("bar", 4096);
+
+    let client = reqwest::Client::new();
+    let max_tokens_seen = |model: &str, max_tokens: Option<u32>| {
+        let mut request = serde_json::json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        if let Some(max_tokens) = max_tokens {
+            request["max_tokens"] = max_tokens.into();
+        }
+        let response = client
+            .post("http://localhost:8986/v1/chat/completions")
+            .json(&request)
+            .send();
+        async move {
+            let body: serde_json::Value = response.await.unwrap().json().await.unwrap();
+            body["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    assert_eq!(max_tokens_seen("foo", None).await, "Some(16)");
+    assert_eq!(max_tokens_seen("bar", None).await, "Some(4096)");
+    // No default of its own, the server-wide one applies
+    assert_eq!(max_tokens_seen("baz", None).await, "Some(100)");
+    // The request's own value wins
+    assert_eq!(max_tokens_seen("foo", Some(7)).await, "Some(7)");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_prefill_progress() {
+    let service = HttpService::builder()
+        .port(8985)
+        .prefill_progress_interval(std::time::Duration::from_millis(50))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    // CounterEngine waits max_tokens milliseconds before its first chunk, like a slow prefill
+    let request = |prefill_progress: bool| {
+        let mut request = client
+            .post("http://localhost:8985/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 300,
+                "stream": true,
+            }));
+        if prefill_progress {
+            request = request.header("x-prefill-progress", "true");
+        }
+        request.send()
+    };
+
+    let body = request(true).await.unwrap().text().await.unwrap();
+    let lines = body
+        .lines()
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    let first_prefill = lines
+        .iter()
+        .position(|line| *line == "event: prefill")
+        .expect("no prefill events");
+    let first_content = lines
+        .iter()
+        .position(|line| line.contains("choice 0"))
+        .expect("no content");
+    assert!(first_prefill < first_content, "{body}");
+    let prefill_events = lines[..first_content]
+        .iter()
+        .filter(|line| **line == "event: prefill")
+        .count();
+    assert!(prefill_events >= 2, "{body}");
+    // and none once content is flowing
+    assert!(
+        !lines[first_content..].contains(&"event: prefill"),
+        "{body}"
+    );
+
+    // Only when asked for
+    let body = request(false).await.unwrap().text().await.unwrap();
+    assert!(!body.contains("event: prefill"), "{body}");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_latency_breakdown() {
+    let service = HttpService::builder()
+        .port(8984)
+        .max_concurrent_requests(1)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    // CounterEngine waits max_tokens milliseconds before its first chunk, like a prefill
+    let request = |max_tokens: u32, annotations: Vec<&str>| {
+        client
+            .post("http://localhost:8984/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": max_tokens,
+                "stream": true,
+                "nvext": {"annotations": annotations},
+            }))
+            .send()
+    };
+
+    // Occupy the only slot, so the next request queues
+    let blocker = tokio::spawn(request(300, vec![]));
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let body = request(100, vec!["latency"])
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let lines = body.lines().collect::<Vec<_>>();
+    let event = lines
+        .iter()
+        .position(|line| *line == "event: latency")
+        .expect("no latency annotation");
+    let latency: serde_json::Value =
+        serde_json::from_str(lines[event + 1].strip_prefix(": ").unwrap()).unwrap();
+    let phase = |name: &str| latency[name].as_f64().unwrap();
+
+    assert!(phase("queue_ms") >= 150.0, "{latency}");
+    assert!(phase("prefill_ms") >= 80.0, "{latency}");
+    assert!(phase("decode_ms") >= 0.0, "{latency}");
+    let sum = phase("queue_ms") + phase("prefill_ms") + phase("decode_ms");
+    assert!((sum - phase("total_ms")).abs() < 1.0, "{latency}");
+
+    // Only when asked for
+    let body = blocker.await.unwrap().unwrap().text().await.unwrap();
+    assert!(!body.contains("event: latency"), "{body}");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_metrics_port() {
+    let service = HttpService::builder()
+        .port(8991)
+        .metrics_port(8992)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client = reqwest::Client::new();
+    for stream in [true, false] {
+        let response = client
+            .post("http://localhost:8991/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 20,
+                "stream": stream,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.text().await.unwrap();
+    }
+
+    // CounterEngine sends ten chunks of content per request
+    assert_eq!(manager.metrics().get_output_tokens("foo"), 20);
+
+    let metrics = client
+        .get("http://localhost:8992/metrics")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        metrics.contains(r#"nv_llm_http_service_output_tokens_total{model="foo"} 20"#),
+        "{metrics}"
+    );
+    assert!(
+        metrics.contains(r#"nv_llm_http_service_time_to_first_token_seconds_count{model="foo"} 2"#),
+        "{metrics}"
+    );
+    assert!(
+        metrics.contains(r#"nv_llm_http_service_inter_token_latency_seconds_count{model="foo"} 2"#),
+        "{metrics}"
+    );
+    assert!(
+        metrics.contains(r#"nv_llm_http_service_inflight_requests{model="foo"} 0"#),
+        "{metrics}"
+    );
+
+    // Only the metrics are served there
+    let response = client
+        .post("http://localhost:8992/v1/chat/completions")
+        .json(&serde_json::json!({"model": "foo", "messages": []}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+    assert!(client
+        .get("http://localhost:8992/metrics")
+        .send()
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_http_service_accept_conflicts_with_stream() {
+    let service = HttpService::builder().port(8983).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |stream: bool, accept: &str| {
+        client
+            .post("http://localhost:8983/v1/chat/completions")
+            .header("accept", accept)
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 1,
+                "stream": stream,
+            }))
+            .send()
+    };
+    let content_type = |response: &reqwest::Response| {
+        response.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    // The stream field wins over a contradicting Accept header
+    let response = request(true, "application/json").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(content_type(&response).starts_with("text/event-stream"));
+    assert!(response.text().await.unwrap().contains("data: [DONE]"));
+
+    let response = request(false, "text/event-stream").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(content_type(&response).starts_with("application/json"));
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["object"], "chat.completion");
+
+    // Headers which agree change nothing
+    let response = request(true, "text/event-stream").await.unwrap();
+    assert!(content_type(&response).starts_with("text/event-stream"));
+    let response = request(false, "*/*").await.unwrap();
+    assert!(content_type(&response).starts_with("application/json"));
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_debug_token_ids() {
+    use dynamo_llm::preprocessor::OpenAIPreprocessor;
+
+    let card = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(card).await.unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |port: u16, model: &'static str, stream: bool| {
+        client
+            .post(format!("http://localhost:{port}/v1/chat/completions"))
+            .header("x-debug-token-ids", "true")
+            .json(&serde_json::json!({
+                "model": model,
+                "messages": [
+                    {"role": "system", "content": "You are a helpful assistant."},
+                    {"role": "user", "content": "What is the weather like in Paris today?"},
+                ],
+                "max_tokens": 1,
+                "stream": stream,
+            }))
+            .send()
+    };
+
+    for (port, enabled) in [(8982, true), (8981, false)] {
+        let service = HttpService::builder()
+            .port(port)
+            .debug_token_ids(enabled)
+            .build()
+            .unwrap();
+        let manager = service.model_manager().clone();
+
+        let token = CancellationToken::new();
+        let cancel_token = token.clone();
+        let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+        manager
+            .add_chat_completions_model("core", Arc::new(CounterEngine {}))
+            .unwrap();
+        manager.set_model_preprocessor("core", preprocessor.clone());
+        manager
+            .add_chat_completions_model("full", Arc::new(CounterEngine {}))
+            .unwrap();
+
+        for stream in [false, true] {
+            let response = request(port, "core", stream).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let token_ids = response
+                .headers()
+                .get("x-debug-token-ids")
+                .map(|value| value.to_str().unwrap().to_string());
+            if enabled {
+                // The mock tokenizer has no vocabulary, only the special tokens from the prompt
+                // template: <|begin_of_text|>, then <|start_header_id|> <|end_header_id|>
+                // <|eot_id|> around each message, then the generation prompt.
+                assert_eq!(
+                    token_ids.as_deref(),
+                    Some("[128000,128006,128007,128009,128006,128007,128009,128006,128007]")
+                );
+            } else {
+                assert_eq!(token_ids, None);
+            }
+
+            // The engine does its own tokenization
+            let response = request(port, "full", stream).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(response.headers().get("x-debug-token-ids").is_none());
+        }
+
+        cancel_token.cancel();
+        task.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_http_service_admin_requests() {
+    let service = HttpService::builder()
+        .port(8980)
+        .admin_api_key("hunter2")
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let list = || {
+        client
+            .get("http://localhost:8980/admin/requests")
+            .bearer_auth("hunter2")
+            .send()
+    };
+
+    let response = client
+        .get("http://localhost:8980/admin/requests")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let requests: serde_json::Value = list().await.unwrap().json().await.unwrap();
+    assert_eq!(requests, serde_json::json!([]));
+
+    // CounterEngine waits max_tokens milliseconds before its first chunk. The response headers
+    // come back right away, so both are in flight once `send` returns.
+    let stream = || {
+        client
+            .post("http://localhost:8980/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 500,
+                "stream": true,
+            }))
+            .send()
+    };
+    let (first, second) = tokio::join!(stream(), stream());
+    let (first, second) = (first.unwrap(), second.unwrap());
+
+    let requests: serde_json::Value = list().await.unwrap().json().await.unwrap();
+    let requests = requests.as_array().unwrap();
+    assert_eq!(requests.len(), 2, "{requests:?}");
+    for request in requests {
+        assert_eq!(request["model"], "foo");
+        assert!(request["id"].as_str().is_some_and(|id| !id.is_empty()));
+        assert!(request["elapsed_ms"].is_u64());
+        assert_eq!(request["tokens"], 0);
+    }
+    assert_ne!(requests[0]["id"], requests[1]["id"]);
+
+    // Finished requests are no longer listed
+    first.text().await.unwrap();
+    second.text().await.unwrap();
+    let requests: serde_json::Value = list().await.unwrap().json().await.unwrap();
+    assert_eq!(requests, serde_json::json!([]));
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_response_cache() {
+    let service = HttpService::builder()
+        .port(8979)
+        .response_cache_size(8)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |temperature: f32| {
+        client
+            .post("http://localhost:8979/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 1,
+                "temperature": temperature,
+            }))
+            .send()
+    };
+    let x_cache = |response: &reqwest::Response| {
+        response
+            .headers()
+            .get("x-cache")
+            .map(|value| value.to_str().unwrap().to_string())
+    };
+
+    let response = request(0.0).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(x_cache(&response).as_deref(), Some("MISS"));
+    let first: serde_json::Value = response.json().await.unwrap();
+
+    let response = request(0.0).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(x_cache(&response).as_deref(), Some("HIT"));
+    let second: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(first["choices"], second["choices"]);
+    // A replay is a response of its own
+    assert_ne!(first["id"], second["id"]);
+    assert!(second["created"].as_u64().unwrap() >= first["created"].as_u64().unwrap());
+
+    // and counts as a request, which is no longer in flight
+    let metrics = manager.metrics();
+    compare_counter(
+        metrics.clone(),
+        "foo",
+        &Endpoint::ChatCompletions,
+        &RequestType::Unary,
+        &Status::Success,
+        2,
+    );
+    assert_eq!(metrics.get_inflight_count("foo"), 0);
+
+    // Sampled output isn't cached
+    let response = request(0.7).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(x_cache(&response), None);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_model_priority() {
+    let service = HttpService::builder()
+        .port(8978)
+        .max_concurrent_requests(1)
+        .model_priorities(HashMap::from([("high".to_string(), 10)]))
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    for model in ["low", "high"] {
+        manager
+            .add_chat_completions_model(model, Arc::new(CounterEngine {}))
+            .unwrap();
+    }
+
+    let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+    let send = |name: &'static str, model: &'static str, max_tokens: u32| {
+        let done_tx = done_tx.clone();
+        tokio::spawn(async move {
+            reqwest::Client::new()
+                .post("http://localhost:8978/v1/chat/completions")
+                .json(&serde_json::json!({
+                    "model": model,
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "max_tokens": max_tokens,
+                }))
+                .send()
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            done_tx.send(name).unwrap();
+        })
+    };
+
+    // Occupy the only slot, then build up a backlog of low priority requests
+    let mut tasks = vec![send("running", "low", 300)];
+    for name in ["low-1", "low-2", "low-3"] {
+        tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+        tasks.push(send(name, "low", 10));
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+    tasks.push(send("high", "high", 10));
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+    let mut order = Vec::new();
+    while let Ok(name) = done_rx.try_recv() {
+        order.push(name);
+    }
+    assert_eq!(order, vec!["running", "high", "low-1", "low-2", "low-3"]);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_created_is_per_response() {
+    let service = HttpService::builder().port(8977).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(TickingEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |stream: bool| {
+        client
+            .post("http://localhost:8977/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": stream,
+            }))
+            .send()
+    };
+    let now = || {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+
+    let before = now();
+    let body = request(true).await.unwrap().text().await.unwrap();
+    let after = now();
+    let created = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| {
+            let chunk: serde_json::Value = serde_json::from_str(data).unwrap();
+            chunk["created"].as_u64().unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(created.len(), 5, "{body}");
+    assert!(created.iter().all(|c| *c == created[0]), "{created:?}");
+    assert!((before..=after).contains(&created[0]), "{created:?}");
+
+    let before = now();
+    let response: serde_json::Value = request(false).await.unwrap().json().await.unwrap();
+    let after = now();
+    let created = response["created"].as_u64().unwrap();
+    assert!((before..=after).contains(&created), "{response}");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_head_chat_completions() {
+    let service = HttpService::builder().port(8976).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    // Any request reaching the engine fails
+    manager
+        .add_chat_completions_model("foo", Arc::new(AlwaysFailEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let url = "http://localhost:8976/v1/chat/completions";
+
+    let response = client.head(url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.bytes().await.unwrap().is_empty());
+
+    for method in [reqwest::Method::GET, reqwest::Method::PUT] {
+        let response = client.request(method, url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    let response = client
+        .post(url)
+        .json(&serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_done_follows_last_chunk() {
+    let service = HttpService::builder().port(8975).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let mut response = reqwest::Client::new()
+        .post("http://localhost:8975/v1/chat/completions")
+        .json(&serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Read until `[DONE]`, which comes right behind the last content chunk
+    let mut received = String::new();
+    while !received.contains("data: [DONE]") {
+        let bytes = response
+            .chunk()
+            .await
+            .unwrap()
+            .expect("body ended before [DONE]");
+        received.push_str(std::str::from_utf8(&bytes).unwrap());
+    }
+    let last_content = received.find("choice 9").expect("no last chunk");
+    assert!(last_content < received.find("data: [DONE]").unwrap());
+    assert!(received.trim_end().ends_with("data: [DONE]"), "{received}");
+    // and nothing after it, the body ends
+    assert!(response.chunk().await.unwrap().is_none());
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_engine_header() {
+    let service = HttpService::builder().port(8974).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_named_chat_completions_model("foo", "counter", Arc::new(CounterEngine {}))
+        .unwrap();
+    manager
+        .add_named_chat_completions_model("foo", "max-tokens", Arc::new(MaxTokensEngine {}))
+        .unwrap();
+    assert!(manager
+        .add_named_chat_completions_model("foo", "counter", Arc::new(CounterEngine {}))
+        .is_err());
+    manager
+        .add_chat_completions_model("bar", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |model: &str, engine: Option<&str>| {
+        let mut request = client
+            .post("http://localhost:8974/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": model,
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 7,
+            }));
+        if let Some(engine) = engine {
+            request = request.header("x-engine", engine);
+        }
+        request.send()
+    };
+    let content = |response: serde_json::Value| {
+        response["choices"][0]["message"]["content"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    };
+
+    // The first engine added serves requests which don't pick one
+    let response = request("foo", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(content(response.json().await.unwrap()).starts_with("choice 0"));
+
+    let response = request("foo", Some("max-tokens")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(content(response.json().await.unwrap()), "Some(7)");
+
+    let response = request("foo", Some("counter")).await.unwrap();
+    assert!(content(response.json().await.unwrap()).starts_with("choice 0"));
+
+    // Unknown engines are named in the error, with the ones available
+    let response = request("foo", Some("vllm")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error = response.text().await.unwrap();
+    assert!(error.contains("'vllm'"), "{error}");
+    assert!(error.contains("counter, max-tokens"), "{error}");
+
+    let response = request("bar", Some("counter")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // Unknown models are reported the way OpenAI does, so its clients raise NotFoundError
+    let response = request("baz", Some("counter")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "model_not_found");
+    assert_eq!(body["error"]["param"], "model");
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_allow_any_model() {
+    let token = CancellationToken::new();
+    let mut tasks = Vec::new();
+    // 8960 rejects models it doesn't serve, 8959 serves them with the one it has
+    for (port, allow_any_model) in [(8960, false), (8959, true)] {
+        let service = HttpService::builder()
+            .port(port)
+            .allow_any_model(allow_any_model)
+            .build()
+            .unwrap();
+        service
+            .model_manager()
+            .add_chat_completions_model("foo", Arc::new(MaxTokensEngine {}))
+            .unwrap();
+        let token = token.clone();
+        tasks.push(tokio::spawn(async move { service.run(token).await }));
+    }
+
+    let client = reqwest::Client::new();
+    let request = |port: u16, model: &str| {
+        client
+            .post(format!("http://localhost:{port}/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": model,
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": 7,
+            }))
+            .send()
+    };
+
+    let response = request(8960, "gpt-4o").await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "model_not_found");
+    assert_eq!(body["error"]["type"], "invalid_request_error");
+    assert!(
+        body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("gpt-4o"),
+        "{body}"
+    );
+
+    for port in [8960, 8959] {
+        let response = request(port, "foo").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    let response = request(8959, "gpt-4o").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "Some(7)");
+
+    token.cancel();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_http_service_repair_json() {
+    let service = HttpService::builder()
+        .port(8958)
+        .repair_json(true)
+        .build()
+        .unwrap();
+    // The echo engine answers with the last user message
+    service
+        .model_manager()
+        .add_chat_completions_model("foo", dynamo_llm::engines::make_engine_full())
+        .unwrap();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+
+    let client = reqwest::Client::new();
+    let request = |content: &str, format: &str, stream: bool| {
+        client
+            .post("http://localhost:8958/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": content}],
+                "response_format": {"type": format},
+                "stream": stream,
+            }))
+            .send()
+    };
+    let trailing_comma = r#"{"a": 1, "b": [2, 3,],}"#;
+
+    let response = request(trailing_comma, "json_object", false).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-json-repair"], "repaired");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["choices"][0]["message"]["content"],
+        r#"{"a": 1, "b": [2, 3]}"#
+    );
+
+    // Left as generated, and flagged
+    let response = request("{\"a\": 1]", "json_object", false).await.unwrap();
+    assert_eq!(response.headers()["x-json-repair"], "failed");
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], "{\"a\": 1]");
+
+    // Not asked for JSON
+    let response = request(trailing_comma, "text", false).await.unwrap();
+    assert!(!response.headers().contains_key("x-json-repair"));
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["choices"][0]["message"]["content"], trailing_comma);
+
+    // Streamed content was already sent, the repair comes after it
+    let body = request(trailing_comma, "json_object", true)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let lines = body.lines().collect::<Vec<_>>();
+    let event = lines
+        .iter()
+        .position(|line| *line == "event: json_repair")
+        .expect("no json_repair annotation");
+    let repairs: serde_json::Value =
+        serde_json::from_str(lines[event + 1].strip_prefix(": ").unwrap()).unwrap();
+    assert_eq!(
+        repairs,
+        serde_json::json!([{"index": 0, "repaired": r#"{"a": 1, "b": [2, 3]}"#}])
+    );
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_default_finish_reason() {
+    let token = CancellationToken::new();
+    let mut tasks = Vec::new();
+    // 8973 fills in the missing finish reason, 8972 is strict about it
+    for (port, strict) in [(8973, false), (8972, true)] {
+        let service = HttpService::builder()
+            .port(port)
+            .strict_finish_reason(strict)
+            .build()
+            .unwrap();
+        // MaxTokensEngine never sets a finish reason
+        service
+            .model_manager()
+            .add_chat_completions_model("foo", Arc::new(MaxTokensEngine {}))
+            .unwrap();
+        let token = token.clone();
+        tasks.push(tokio::spawn(async move { service.run(token).await }));
+    }
+
+    let client = reqwest::Client::new();
+    let request = |port: u16, stream: bool| {
+        client
+            .post(format!("http://localhost:{port}/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": stream,
+            }))
+            .send()
+    };
+
+    let response: serde_json::Value = request(8973, false).await.unwrap().json().await.unwrap();
+    assert_eq!(
+        response["choices"][0]["finish_reason"], "stop",
+        "{response}"
+    );
+
+    let body = request(8973, true).await.unwrap().text().await.unwrap();
+    let chunks = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+        .collect::<Vec<_>>();
+    // the engine's chunk, then one with the finish reason and no content
+    assert_eq!(chunks.len(), 2, "{body}");
+    let last = &chunks[1]["choices"][0];
+    assert_eq!(last["finish_reason"], "stop", "{body}");
+    assert!(last["delta"].get("content").is_none(), "{body}");
+
+    let response = request(8972, false).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let body = request(8972, true).await.unwrap().text().await.unwrap();
+    assert!(body.contains("event: error"), "{body}");
+    assert!(body.contains("without a finish reason"), "{body}");
+
+    token.cancel();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_http_service_empty_stream() {
+    let token = CancellationToken::new();
+    let mut tasks = Vec::new();
+    // 8955 makes up an empty response, 8954 is strict about it
+    for (port, strict) in [(8955, false), (8954, true)] {
+        let service = HttpService::builder()
+            .port(port)
+            .strict_finish_reason(strict)
+            .build()
+            .unwrap();
+        service
+            .model_manager()
+            .add_chat_completions_model("foo", Arc::new(EmptyEngine {}))
+            .unwrap();
+        let token = token.clone();
+        tasks.push(tokio::spawn(async move { service.run(token).await }));
+    }
+
+    let client = reqwest::Client::new();
+    let request = |port: u16, stream: bool| {
+        client
+            .post(format!("http://localhost:{port}/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": stream,
+            }))
+            .send()
+    };
+
+    let body = request(8955, true).await.unwrap().text().await.unwrap();
+    assert!(body.contains("event: warning"), "{body}");
+    let chunks = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 2, "{body}");
+    assert_eq!(chunks[1], "[DONE]");
+    let chunk: serde_json::Value = serde_json::from_str(chunks[0]).unwrap();
+    assert_eq!(chunk["model"], "foo");
+    assert_eq!(chunk["choices"][0]["finish_reason"], "stop", "{body}");
+    assert_eq!(chunk["choices"][0]["delta"]["content"], "", "{body}");
+
+    let response = request(8955, false).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response["choices"][0]["finish_reason"], "stop");
+    assert_eq!(response["choices"][0]["message"]["content"], "");
+
+    let body = request(8954, true).await.unwrap().text().await.unwrap();
+    assert!(body.contains("event: error"), "{body}");
+    assert!(body.contains("without any output"), "{body}");
+
+    token.cancel();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_http_service_engine_panic() {
+    let service = HttpService::builder().port(8953).build().unwrap();
+    service
+        .model_manager()
+        .add_chat_completions_model(
+            "foo",
+            dynamo_llm::engines::make_panic_safe_engine_full(Arc::new(PanicEngine {})),
+        )
+        .unwrap();
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token).await });
+
+    let client = reqwest::Client::new();
+    let request = |content: &str, stream: bool| {
+        client
+            .post("http://localhost:8953/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": content}],
+                "stream": stream,
+            }))
+            .send()
+    };
+
+    // Panic before the first response
+    let response = request("panic", false).await.unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("engine double asked to panic"), "{body}");
+
+    // Panic while streaming
+    let body = request("panic later", true)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(body.contains("\"ok\""), "{body}");
+    assert!(body.contains("event: error"), "{body}");
+    assert!(
+        body.contains("engine double asked to panic later"),
+        "{body}"
+    );
+
+    // The server is still up for everyone else
+    let response = request("hi", false).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(response["choices"][0]["message"]["content"], "ok");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_openai_engine() {
+    use dynamo_runtime::pipeline::Context;
+    use futures::StreamExt;
+
+    // Our own HTTP service is the OpenAI compatible server at the other end
+    let service = HttpService::builder().port(8962).build().unwrap();
+    let manager = service.model_manager().clone();
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token).await });
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let engine = dynamo_llm::engines::openai::make_engine("http://localhost:8962/", None).unwrap();
+    let request = |model: &str| {
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .unwrap();
+        Context::new(request)
+    };
+
+    let responses = engine
+        .generate(request("foo"))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(responses.len(), 10);
+    for (i, response) in responses.iter().enumerate() {
+        let choice = &response.data.as_ref().unwrap().inner.choices[0];
+        assert_eq!(choice.delta.content, Some(format!("choice {i}")));
+    }
+    let last = &responses[9].data.as_ref().unwrap().inner.choices[0];
+    assert_eq!(
+        last.finish_reason,
+        Some(async_openai::types::FinishReason::Stop)
+    );
+
+    // The server's errors come back as error responses
+    let responses = engine
+        .generate(request("bar"))
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(responses.len(), 1);
+    assert!(responses[0].is_error());
+    let error = responses[0].comment.as_ref().unwrap().join(" ");
+    assert!(error.contains("404"), "{error}");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_include_usage() {
+    let service = HttpService::builder().port(8956).build().unwrap();
+    let manager = service.model_manager().clone();
+    manager
+        .add_chat_completions_model("usage", Arc::new(UsageEngine {}))
+        .unwrap();
+    // CounterEngine doesn't count its tokens, it sends one per chunk
+    manager
+        .add_chat_completions_model("counter", Arc::new(CounterEngine {}))
+        .unwrap();
+    let token = CancellationToken::new();
+    let task = tokio::spawn({
+        let token = token.clone();
+        async move { service.run(token).await }
+    });
+
+    let client = reqwest::Client::new();
+    let chunks = |model: &str, include_usage: bool| {
+        let request = client
+            .post("http://localhost:8956/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": model,
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+                "stream_options": {"include_usage": include_usage},
+            }))
+            .send();
+        async move {
+            let body = request.await.unwrap().text().await.unwrap();
+            body.lines()
+                .filter_map(|line| line.strip_prefix("data: "))
+                .filter(|data| *data != "[DONE]")
+                .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+                .collect::<Vec<_>>()
+        }
+    };
+
+    let chunks_with_usage = chunks("usage", true).await;
+    // The engine's usage chunk is replaced by ours
+    assert_eq!(chunks_with_usage.len(), 4);
+    let (last, content) = chunks_with_usage.split_last().unwrap();
+    for chunk in content {
+        assert!(chunk["usage"].is_null(), "{chunk}");
+        assert_eq!(chunk["choices"].as_array().unwrap().len(), 1);
+    }
+    assert_eq!(last["choices"], serde_json::json!([]));
+    assert_eq!(last["usage"]["prompt_tokens"], 5);
+    assert_eq!(last["usage"]["completion_tokens"], 3);
+    assert_eq!(last["usage"]["total_tokens"], 8);
+    assert_eq!(last["id"], content[0]["id"]);
+
+    let chunks_with_usage = chunks("counter", true).await;
+    assert_eq!(chunks_with_usage.len(), 11);
+    let last = chunks_with_usage.last().unwrap();
+    assert_eq!(last["choices"], serde_json::json!([]));
+    assert_eq!(last["usage"]["completion_tokens"], 10);
+    assert_eq!(last["usage"]["total_tokens"], 10);
+
+    // Not asked for
+    let chunks_without = chunks("counter", false).await;
+    assert_eq!(chunks_without.len(), 10);
+    assert!(chunks_without
+        .iter()
+        .all(|chunk| !chunk["choices"].as_array().unwrap().is_empty()));
+
+    token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+/// An OpenAI compatible server which answers every request with the same one chunk stream, and
+/// counts the connections it was sent them on
+async fn counting_upstream(
+    listener: tokio::net::TcpListener,
+    connections: Arc<std::sync::atomic::AtomicUsize>,
+) {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let chunk = serde_json::json!({
+        "id": "1",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": "foo",
+        "choices": [{"index": 0, "delta": {"content": "hi"}, "finish_reason": "stop"}],
+    });
+    let body = format!("data: {chunk}\n\ndata: [DONE]\n\n");
+    let response = format!(
+        "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ncontent-length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    loop {
+        let (socket, _) = listener.accept().await.unwrap();
+        connections.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let response = response.clone();
+        tokio::spawn(async move {
+            let mut socket = BufReader::new(socket);
+            // Keep-alive: any number of requests, one after the other
+            loop {
+                let mut content_length = 0;
+                let mut line = String::new();
+                loop {
+                    line.clear();
+                    if socket.read_line(&mut line).await.unwrap_or(0) == 0 {
+                        return;
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(len) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = len.trim().parse().unwrap();
+                    }
+                }
+                let mut request = vec![0; content_length];
+                socket.read_exact(&mut request).await.unwrap();
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn test_openai_engine_reuses_connections() {
+    use dynamo_llm::engines::openai::{make_engine_with_pool, UpstreamPool};
+    use dynamo_runtime::pipeline::Context;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:8957")
+        .await
+        .unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let upstream = tokio::spawn(counting_upstream(listener, connections.clone()));
+
+    let request = || {
+        let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .unwrap();
+        Context::new(request)
+    };
+    let send_three = |pool: UpstreamPool| async move {
+        let engine = make_engine_with_pool("http://127.0.0.1:8957", None, pool).unwrap();
+        for _ in 0..3 {
+            let responses = engine
+                .generate(request())
+                .await
+                .unwrap()
+                .collect::<Vec<_>>()
+                .await;
+            assert_eq!(responses.len(), 1);
+            let choice = &responses[0].data.as_ref().unwrap().inner.choices[0];
+            assert_eq!(choice.delta.content.as_deref(), Some("hi"));
+        }
+    };
+
+    send_three(UpstreamPool::default()).await;
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+
+    // Without a pool every request has its own
+    send_three(UpstreamPool {
+        max_idle: 0,
+        ..Default::default()
+    })
+    .await;
+    assert_eq!(connections.load(Ordering::SeqCst), 4);
+
+    upstream.abort();
+}
+
+#[tokio::test]
+async fn test_http_service_tee() {
+    let (sink, mut tee) = tokio::sync::mpsc::channel(64);
+    let service = HttpService::builder()
+        .port(8971)
+        .tee_sink(sink)
+        .build()
+        .unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let request = |tee: bool| {
+        let mut request = client
+            .post("http://localhost:8971/v1/chat/completions")
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+            }));
+        if tee {
+            request = request.header("x-tee", "true");
+        }
+        request.send()
+    };
+    let content = |chunk: &serde_json::Value| chunk["choices"][0]["delta"]["content"].clone();
+
+    let body = request(true).await.unwrap().text().await.unwrap();
+    let chunks = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str::<serde_json::Value>(data).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 10, "{body}");
+
+    // The sink got every chunk the client did
+    let mut teed = Vec::new();
+    while let Ok(event) = tee.try_recv() {
+        assert_eq!(event.model, "foo");
+        teed.push(event.chunk["data"].clone());
+    }
+    assert_eq!(teed.len(), chunks.len());
+    for (client, teed) in chunks.iter().zip(&teed) {
+        assert_eq!(content(client), content(teed));
+    }
+
+    // Only when asked for
+    request(false).await.unwrap().text().await.unwrap();
+    assert!(tee.try_recv().is_err());
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_http2() {
+    let token = CancellationToken::new();
+    let mut tasks = Vec::new();
+    for (port, http_version) in [(8970, HttpVersion::Auto), (8969, HttpVersion::Http1)] {
+        let service = HttpService::builder()
+            .port(port)
+            .http_version(http_version)
+            .build()
+            .unwrap();
+        service
+            .model_manager()
+            .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+            .unwrap();
+        let token = token.clone();
+        tasks.push(tokio::spawn(async move { service.run(token).await }));
+    }
+
+    // h2c with prior knowledge, as HTTP/2 clients of a plaintext service do
+    let client = reqwest::Client::builder()
+        .http2_prior_knowledge()
+        .build()
+        .unwrap();
+    let request = |port: u16| {
+        client
+            .post(format!("http://localhost:{port}/v1/chat/completions"))
+            .json(&serde_json::json!({
+                "model": "foo",
+                "messages": [{"role": "user", "content": "hi"}],
+                "stream": true,
+            }))
+            .send()
+    };
+
+    let response = request(8970).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_2);
+    let body = response.text().await.unwrap();
+    let chunks = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .collect::<Vec<_>>();
+    assert_eq!(chunks.len(), 11, "{body}");
+    for (i, chunk) in chunks[..10].iter().enumerate() {
+        let chunk: serde_json::Value = serde_json::from_str(chunk).unwrap();
+        assert_eq!(
+            chunk["choices"][0]["delta"]["content"],
+            format!("choice {i}")
+        );
+    }
+    assert_eq!(chunks[10], "[DONE]");
+
+    // HTTP/1.1 clients are still served in auto mode
+    let response = reqwest::get("http://localhost:8970/v1/models")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), reqwest::Version::HTTP_11);
+
+    // but HTTP/2 is refused when only HTTP/1.1 is served
+    assert!(request(8969).await.is_err());
+
+    token.cancel();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_http_service_max_n() {
+    let service = HttpService::builder().port(8968).max_n(2).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let send = |n: Option<u8>| {
+        let mut request = serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        if let Some(n) = n {
+            request["n"] = n.into();
+        }
+        client
+            .post("http://localhost:8968/v1/chat/completions")
+            .json(&request)
+            .send()
+    };
+
+    for n in [None, Some(1), Some(2)] {
+        let response = send(n).await.unwrap();
+        assert!(response.status().is_success(), "n={n:?}: {response:?}");
+    }
+
+    let response = send(Some(3)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "n must be at most 2, got 3");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_embeddings() {
+    let service = HttpService::builder().port(8967).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_embeddings_model("embedder", Arc::new(LengthEmbeddingEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let response: serde_json::Value = client
+        .post("http://localhost:8967/v1/embeddings")
+        .json(&serde_json::json!({
+            "model": "embedder",
+            "input": ["a", "bbb", "cc"],
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response["object"], "list");
+    assert_eq!(response["model"], "embedder");
+    assert_eq!(response["usage"]["total_tokens"], 3);
+    let data = response["data"].as_array().unwrap();
+    let indexes: Vec<_> = data.iter().map(|e| e["index"].as_u64().unwrap()).collect();
+    assert_eq!(indexes, vec![0, 1, 2]);
+    let embeddings: Vec<_> = data.iter().map(|e| e["embedding"][0].clone()).collect();
+    assert_eq!(embeddings, vec![1.0, 3.0, 2.0]);
+
+    // A single input gets a single embedding
+    let response: serde_json::Value = client
+        .post("http://localhost:8967/v1/embeddings")
+        .json(&serde_json::json!({"model": "embedder", "input": "abcd"}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(response["data"][0]["embedding"][0], 4.0);
+
+    // Embeddings models are listed with the others
+    let models: serde_json::Value = reqwest::get("http://localhost:8967/v1/models")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(models["data"][0]["id"], "embedder");
+
+    // But can't be used for chat
+    let response = client
+        .post("http://localhost:8967/v1/chat/completions")
+        .json(&serde_json::json!({
+            "model": "embedder",
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_max_chunk_chars() {
+    const MAX_CHUNK_CHARS: usize = 3;
+    let service = HttpService::builder()
+        .port(8965)
+        .max_chunk_chars(MAX_CHUNK_CHARS)
+        .build()
+        .unwrap();
+    service
+        .model_manager()
+        .add_chat_completions_model("foo", Arc::new(CounterEngine {}))
+        .unwrap();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    let body = reqwest::Client::new()
+        .post("http://localhost:8965/v1/chat/completions")
+        .json(&serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stream": true,
+        }))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let chunks: Vec<serde_json::Value> = body
+        .lines()
+        .filter_map(|line| line.strip_prefix("data: "))
+        .filter(|data| *data != "[DONE]")
+        .map(|data| serde_json::from_str(data).unwrap())
+        .collect();
+
+    // Each "choice {i}" delta is sent as "cho", "ice", " {i}"
+    let pieces: Vec<&str> = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str())
+        .collect();
+    assert_eq!(pieces.len(), 30, "{body}");
+    assert!(
+        pieces
+            .iter()
+            .all(|piece| piece.chars().count() <= MAX_CHUNK_CHARS),
+        "{pieces:?}"
+    );
+    let expected = (0..10).map(|i| format!("choice {i}")).collect::<String>();
+    assert_eq!(pieces.concat(), expected);
+
+    // Only the last piece of the last delta finishes the response
+    let finish_reasons: Vec<&str> = chunks
+        .iter()
+        .filter_map(|chunk| chunk["choices"][0]["finish_reason"].as_str())
+        .collect();
+    assert_eq!(finish_reasons, vec!["stop"]);
+    assert_eq!(
+        chunks.last().unwrap()["choices"][0]["finish_reason"],
+        "stop"
+    );
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_min_tokens() {
+    let service = HttpService::builder().port(8952).build().unwrap();
+    let manager = service.model_manager().clone();
+
+    let token = CancellationToken::new();
+    let cancel_token = token.clone();
+    let task = tokio::spawn(async move { service.run(token.clone()).await });
+
+    manager
+        .add_chat_completions_model("foo", Arc::new(MinTokensEngine {}))
+        .unwrap();
+
+    let client = reqwest::Client::new();
+    let min_tokens = |nvext: serde_json::Value| {
+        let client = client.clone();
+        async move {
+            let response: serde_json::Value = client
+                .post("http://localhost:8952/v1/chat/completions")
+                .json(&serde_json::json!({
+                    "model": "foo",
+                    "messages": [{"role": "user", "content": "hi"}],
+                    "nvext": nvext,
+                }))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            response["choices"][0]["message"]["content"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        }
+    };
+
+    // The client's nvext reaches the engine
+    assert_eq!(
+        min_tokens(serde_json::json!({"min_tokens": 5})).await,
+        "Some(5)"
+    );
+    assert_eq!(min_tokens(serde_json::json!({})).await, "None");
+
+    cancel_token.cancel();
+    task.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn test_http_service_bind_error() {
+    // hold the port, so the service can't have it
+    let taken = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = taken.local_addr().unwrap().port();
+
+    let service = HttpService::builder()
+        .host("127.0.0.1")
+        .port(port)
+        .build()
+        .unwrap();
+    let handle = service.spawn(CancellationToken::new()).await;
+    let err = handle.await.unwrap().unwrap_err();
+    assert!(
+        err.to_string().contains("could not bind to address"),
+        "{err}"
+    );
+}
@@ -0,0 +1,118 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use anyhow::Error;
+use async_stream::stream;
+use futures::StreamExt;
+
+use dynamo_llm::length_router::PromptLengthRouter;
+use dynamo_llm::preprocessor::estimate_prompt_tokens;
+use dynamo_llm::protocols::{
+    openai::chat_completions::{
+        NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+    },
+    Annotated,
+};
+use dynamo_runtime::pipeline::{
+    async_trait, AsyncEngine, AsyncEngineContextProvider, Context, ManyOut, ResponseStream,
+    SingleIn,
+};
+
+/// Replies with its own name
+struct NamedEngine {
+    name: &'static str,
+}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for NamedEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let generator = request.response_generator();
+        let name = self.name.to_string();
+        let stream = stream! {
+            let inner = generator.create_choice(0, Some(name), None, None);
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+        Ok(ResponseStream::new(Box::pin(stream), context.context()))
+    }
+}
+
+fn chat_request(prompt: &str) -> NvCreateChatCompletionRequest {
+    serde_json::from_value(serde_json::json!({
+        "model": "test",
+        "messages": [{"role": "user", "content": prompt}],
+    }))
+    .unwrap()
+}
+
+async fn routed_to(router: &PromptLengthRouter, prompt: &str) -> String {
+    let mut stream = router
+        .generate(Context::new(chat_request(prompt)))
+        .await
+        .unwrap();
+    let response = stream.next().await.unwrap().data.unwrap();
+    response.inner.choices[0].delta.content.clone().unwrap()
+}
+
+#[test]
+fn test_estimate_prompt_tokens() {
+    // 384 characters of content, four characters per token, and four for the message itself
+    let request = chat_request(&"a".repeat(384));
+    assert_eq!(estimate_prompt_tokens(&request), 100);
+}
+
+#[test]
+fn test_estimate_prompt_tokens_multiple_messages() {
+    let request: NvCreateChatCompletionRequest = serde_json::from_value(serde_json::json!({
+        "model": "test",
+        "messages": [
+            {"role": "system", "content": "a".repeat(40)},
+            {"role": "user", "content": [{"type": "text", "text": "b".repeat(40)}]},
+            {"role": "assistant", "content": "c".repeat(38)},
+        ],
+    }))
+    .unwrap();
+    // The roles and the content part's type are not prompt text: 118 characters of content
+    // round up to 30 tokens, plus four per message
+    assert_eq!(estimate_prompt_tokens(&request), 30 + 3 * 4);
+}
+
+#[tokio::test]
+async fn test_long_and_short_prompts_route_to_different_engines() {
+    let router = PromptLengthRouter::new(
+        100,
+        Arc::new(NamedEngine { name: "short" }),
+        Arc::new(NamedEngine { name: "long" }),
+    );
+
+    assert_eq!(routed_to(&router, "Hello, how are you?").await, "short");
+    assert_eq!(routed_to(&router, &"a".repeat(384)).await, "short");
+    assert_eq!(routed_to(&router, &"a".repeat(385)).await, "long");
+    assert_eq!(
+        routed_to(&router, &"The quick brown fox. ".repeat(100)).await,
+        "long"
+    );
+}
@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use async_stream::stream;
+use dynamo_llm::http::service::{axum, metrics_export::MetricsExport, service_v2::HttpService};
+use dynamo_llm::protocols::{
+    openai::chat_completions::{
+        NvCreateChatCompletionRequest, NvCreateChatCompletionStreamResponse,
+    },
+    Annotated,
+};
+use dynamo_runtime::{
+    pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContextProvider, ManyOut, ResponseStream, SingleIn,
+    },
+    CancellationToken,
+};
+use tokio::sync::mpsc;
+
+const REQUESTS_TOTAL: &str = "nv_llm_http_service_requests_total";
+
+struct HelloEngine {}
+
+#[async_trait]
+impl
+    AsyncEngine<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+        Error,
+    > for HelloEngine
+{
+    async fn generate(
+        &self,
+        request: SingleIn<NvCreateChatCompletionRequest>,
+    ) -> Result<ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>, Error> {
+        let (request, context) = request.transfer(());
+        let generator = request.response_generator();
+        let stream = stream! {
+            let inner = generator.create_choice(0, Some("hello".to_string()), None, None);
+            yield Annotated::from_data(NvCreateChatCompletionStreamResponse { inner });
+        };
+        Ok(ResponseStream::new(Box::pin(stream), context.context()))
+    }
+}
+
+/// Start the service pushing to `export`, and send it one chat request
+async fn serve_one_request(port: u16, export: MetricsExport) -> CancellationToken {
+    let service = HttpService::builder()
+        .port(port)
+        .metrics_export(vec![export])
+        .metrics_export_interval(Duration::from_millis(100))
+        .build()
+        .unwrap();
+    service
+        .model_manager()
+        .add_chat_completions_model("foo", Arc::new(HelloEngine {}))
+        .unwrap();
+
+    let token = CancellationToken::new();
+    service.spawn(token.clone()).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("http://localhost:{port}/v1/chat/completions"))
+        .json(&serde_json::json!({
+            "model": "foo",
+            "messages": [{"role": "user", "content": "hi"}],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "{:?}", response);
+    token
+}
+
+#[tokio::test]
+async fn test_metrics_pushed_to_statsd() {
+    let collector = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let export = format!("statsd={}", collector.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let token = serve_one_request(8991, export).await;
+
+    let mut buf = vec![0u8; 65536];
+    let line = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let n = collector.recv(&mut buf).await.unwrap();
+            let packet = String::from_utf8_lossy(&buf[..n]).to_string();
+            if let Some(line) = packet.lines().find(|line| line.starts_with(REQUESTS_TOTAL)) {
+                return line.to_string();
+            }
+        }
+    })
+    .await
+    .expect("no request counter pushed to statsd");
+
+    assert!(
+        line.starts_with(&format!("{REQUESTS_TOTAL}:1|c|#")),
+        "{line}"
+    );
+    assert!(line.contains("model:foo"), "{line}");
+    assert!(line.contains("status:success"), "{line}");
+
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_metrics_pushed_to_otlp() {
+    let (tx, mut rx) = mpsc::unbounded_channel::<serde_json::Value>();
+    let collector = axum::Router::new().route(
+        "/v1/metrics",
+        axum::routing::post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+            let tx = tx.clone();
+            async move {
+                let _ = tx.send(body);
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, collector).await });
+
+    let export = format!("otlp=http://{addr}").parse().unwrap();
+    let token = serve_one_request(8992, export).await;
+
+    let requests_total = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let body = rx.recv().await.unwrap();
+            let metrics = body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"]
+                .as_array()
+                .cloned()
+                .unwrap_or_default();
+            if let Some(metric) = metrics.into_iter().find(|m| m["name"] == REQUESTS_TOTAL) {
+                return metric;
+            }
+        }
+    })
+    .await
+    .expect("no request counter pushed to the OTLP collector");
+
+    let sum = &requests_total["sum"];
+    assert_eq!(sum["isMonotonic"], true);
+    let point = &sum["dataPoints"][0];
+    assert_eq!(point["asDouble"], 1.0);
+    assert!(point["attributes"]
+        .as_array()
+        .unwrap()
+        .contains(&serde_json::json!({"key": "model", "value": {"stringValue": "foo"}})));
+
+    token.cancel();
+}
@@ -13,7 +13,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::io::Write;
+
+use dynamo_llm::model_card::archive;
 use dynamo_llm::model_card::model::{ModelDeploymentCard, PromptFormatterArtifact, TokenizerKind};
+use dynamo_llm::preprocessor::prompt::PromptFormatter;
+use dynamo_llm::protocols::openai::chat_completions::NvCreateChatCompletionRequest;
 use tempfile::tempdir;
 
 const HF_PATH: &str = "tests/data/sample-models/TinyLlama_v1.1";
@@ -72,3 +77,413 @@ async fn test_missing_required_files() {
     // Should fail because config.json is missing
     assert!(err.contains("unable to extract"));
 }
+
+#[tokio::test]
+async fn test_model_card_from_tar_gz_archive() {
+    let temp_dir = tempdir().unwrap();
+    let archive_path = temp_dir.path().join("TinyLlama_v1.1.tar.gz");
+    let gz = flate2::write::GzEncoder::new(
+        std::fs::File::create(&archive_path).unwrap(),
+        flate2::Compression::fast(),
+    );
+    let mut tar = tar::Builder::new(gz);
+    tar.append_dir_all("TinyLlama_v1.1", HF_PATH).unwrap();
+    tar.into_inner().unwrap().finish().unwrap();
+
+    assert!(archive::is_archive(&archive_path));
+    assert_eq!(
+        archive::archive_stem(&archive_path).as_deref(),
+        Some("TinyLlama_v1.1")
+    );
+
+    let cache_dir = temp_dir.path().join("cache");
+    let model_dir = archive::extract(&archive_path, &cache_dir).unwrap();
+    assert!(model_dir.join("config.json").exists());
+
+    // Second extraction re-uses the first
+    assert_eq!(
+        archive::extract(&archive_path, &cache_dir).unwrap(),
+        model_dir
+    );
+
+    let mdc = ModelDeploymentCard::from_local_path(&model_dir, None)
+        .await
+        .unwrap();
+    let info = mdc.model_info.get_model_info().await.unwrap();
+    assert_eq!(info.model_type(), "llama");
+}
+
+#[tokio::test]
+async fn test_model_card_from_local_archive_path() {
+    let temp_dir = tempdir().unwrap();
+    let archive_path = temp_dir.path().join("tiny.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive_path).unwrap());
+    for f in ["config.json", "tokenizer.json", "tokenizer_config.json"] {
+        zip.start_file(
+            format!("TinyLlama_v1.1/{f}"),
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        zip.write_all(&std::fs::read(format!("{HF_PATH}/{f}")).unwrap())
+            .unwrap();
+    }
+    zip.finish().unwrap();
+
+    // The card loader unpacks the archive into the cache itself
+    let cache_home = temp_dir.path().join("cache");
+    std::env::set_var("XDG_CACHE_HOME", &cache_home);
+    let mdc = ModelDeploymentCard::from_local_path(&archive_path, None)
+        .await
+        .unwrap();
+    assert_eq!(mdc.service_name, "tiny");
+    let info = mdc.model_info.get_model_info().await.unwrap();
+    assert_eq!(info.model_type(), "llama");
+    assert!(archive::default_cache_dir().starts_with(&cache_home));
+    assert!(std::fs::read_dir(archive::default_cache_dir())
+        .unwrap()
+        .next()
+        .is_some());
+
+    let mdc = ModelDeploymentCard::from_local_path(&archive_path, Some("named"))
+        .await
+        .unwrap();
+    assert_eq!(mdc.service_name, "named");
+}
+
+#[test]
+fn test_archive_path_traversal_rejected() {
+    let temp_dir = tempdir().unwrap();
+    let archive_path = temp_dir.path().join("evil.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive_path).unwrap());
+    zip.start_file("../evil.txt", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(b"gotcha").unwrap();
+    zip.finish().unwrap();
+
+    let cache_dir = temp_dir.path().join("cache");
+    let result = archive::extract(&archive_path, &cache_dir);
+    assert!(result.is_err());
+    assert!(!cache_dir.join("evil.txt").exists());
+}
+
+/// A copy of the sample model with our own tokenizer_config.json
+fn sample_repo_with_tokenizer_config(dir: &std::path::Path, tokenizer_config: &str) {
+    for f in ["config.json", "tokenizer.json"] {
+        std::fs::copy(format!("{HF_PATH}/{f}"), dir.join(f)).unwrap();
+    }
+    std::fs::write(dir.join("tokenizer_config.json"), tokenizer_config).unwrap();
+}
+
+async fn render_hi(mdc: &ModelDeploymentCard) -> String {
+    let PromptFormatter::OAI(formatter) = PromptFormatter::from_mdc(mdc.clone()).await.unwrap();
+    let request: NvCreateChatCompletionRequest = serde_json::from_str(
+        r#"{"model": "test", "messages": [{"role": "user", "content": "hi"}]}"#,
+    )
+    .unwrap();
+    formatter.render(&request).unwrap()
+}
+
+#[tokio::test]
+async fn test_chat_template_from_tokenizer_config_only() {
+    let temp_dir = tempdir().unwrap();
+    sample_repo_with_tokenizer_config(
+        temp_dir.path(),
+        r#"{"bos_token": "<s>", "chat_template": "config: {{ messages[0].content }}"}"#,
+    );
+
+    let mdc = ModelDeploymentCard::from_local_path(temp_dir.path(), None)
+        .await
+        .unwrap();
+    assert!(mdc.chat_template_file.is_none());
+    assert_eq!(render_hi(&mdc).await, "config: hi");
+}
+
+#[tokio::test]
+async fn test_chat_template_priority() {
+    let temp_dir = tempdir().unwrap();
+    sample_repo_with_tokenizer_config(
+        temp_dir.path(),
+        r#"{"bos_token": "<s>", "chat_template": "config: {{ messages[0].content }}"}"#,
+    );
+    std::fs::write(
+        temp_dir.path().join("chat_template.jinja"),
+        "jinja: {{ messages[0].content }}",
+    )
+    .unwrap();
+
+    // chat_template.jinja beats tokenizer_config.json
+    let mut mdc = ModelDeploymentCard::from_local_path(temp_dir.path(), None)
+        .await
+        .unwrap();
+    assert_eq!(render_hi(&mdc).await, "jinja: hi");
+
+    // An explicit template beats both
+    let explicit = temp_dir.path().join("explicit.jinja");
+    std::fs::write(&explicit, "explicit: {{ messages[0].content }}").unwrap();
+    mdc.set_chat_template_file(&explicit).unwrap();
+    assert_eq!(render_hi(&mdc).await, "explicit: hi");
+
+    assert!(mdc
+        .set_chat_template_file(&temp_dir.path().join("missing.jinja"))
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_tokenizer_from_separate_dir() {
+    // Weights and config only, no tokenizer
+    let weights_dir = tempdir().unwrap();
+    std::fs::copy(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct/config.json",
+        weights_dir.path().join("config.json"),
+    )
+    .unwrap();
+    assert!(
+        ModelDeploymentCard::from_local_path(weights_dir.path(), None)
+            .await
+            .is_err()
+    );
+
+    let tokenizer_dir = std::path::Path::new(HF_PATH);
+    let mdc = ModelDeploymentCard::from_local_path_with_tokenizer(
+        weights_dir.path(),
+        Some(tokenizer_dir),
+        Some("split"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(mdc.service_name, "split");
+
+    // Model config from the weights dir
+    let info = mdc.model_info.get_model_info().await.unwrap();
+    assert_eq!(info.max_position_embeddings(), 8192);
+
+    // Tokenizer files from the tokenizer dir
+    let tokenizer_dir = tokenizer_dir.canonicalize().unwrap();
+    match &mdc.tokenizer {
+        TokenizerKind::HfTokenizerJson(file) => {
+            assert!(file.starts_with(tokenizer_dir.to_str().unwrap()))
+        }
+        other => panic!("Unexpected tokenizer {other:?}"),
+    }
+    match &mdc.prompt_formatter {
+        Some(PromptFormatterArtifact::HfTokenizerConfigJson(file)) => {
+            assert!(file.starts_with(tokenizer_dir.to_str().unwrap()))
+        }
+        other => panic!("Unexpected prompt formatter {other:?}"),
+    }
+}
+
+#[test]
+fn test_sharded_safetensors_from_index() {
+    use dynamo_llm::model_card::weights;
+
+    let dir = std::path::Path::new("tests/data/sample-models/sharded-safetensors");
+    let files = weights::safetensors_files(dir).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            dir.join("model-00001-of-00002.safetensors"),
+            dir.join("model-00002-of-00002.safetensors"),
+        ]
+    );
+
+    // An engine which loads every .safetensors file it finds sees only the indexed shards, not a
+    // stray copy of the weights, alongside the rest of the repo
+    let model_dir = tempdir().unwrap();
+    for name in [
+        weights::SAFETENSORS_INDEX_FILE,
+        "model-00001-of-00002.safetensors",
+        "model-00002-of-00002.safetensors",
+    ] {
+        std::fs::copy(dir.join(name), model_dir.path().join(name)).unwrap();
+    }
+    std::fs::write(model_dir.path().join("consolidated.safetensors"), b"").unwrap();
+    std::fs::write(model_dir.path().join("config.json"), b"{}").unwrap();
+    let files = weights::safetensors_files(model_dir.path()).unwrap();
+    let engine_dir = tempdir().unwrap();
+    weights::link_shards(model_dir.path(), &files, engine_dir.path()).unwrap();
+    let mut seen: Vec<_> = std::fs::read_dir(engine_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    seen.sort();
+    assert_eq!(
+        seen,
+        vec![
+            "config.json",
+            "model-00001-of-00002.safetensors",
+            "model-00002-of-00002.safetensors",
+            weights::SAFETENSORS_INDEX_FILE,
+        ]
+    );
+    let shard = engine_dir.path().join("model-00002-of-00002.safetensors");
+    assert_eq!(
+        std::fs::read(shard).unwrap(),
+        std::fs::read(dir.join("model-00002-of-00002.safetensors")).unwrap()
+    );
+
+    // A missing shard is reported up front, not half way through loading
+    let incomplete = tempdir().unwrap();
+    std::fs::copy(
+        dir.join(weights::SAFETENSORS_INDEX_FILE),
+        incomplete.path().join(weights::SAFETENSORS_INDEX_FILE),
+    )
+    .unwrap();
+    std::fs::copy(
+        dir.join("model-00001-of-00002.safetensors"),
+        incomplete.path().join("model-00001-of-00002.safetensors"),
+    )
+    .unwrap();
+    let err = weights::safetensors_files(incomplete.path()).unwrap_err();
+    assert!(
+        err.to_string().contains("model-00002-of-00002.safetensors"),
+        "{err}"
+    );
+
+    // Without an index only model.safetensors counts
+    assert!(weights::safetensors_files(std::path::Path::new(HF_PATH)).is_err());
+}
+
+#[tokio::test]
+async fn test_check_chat_template() {
+    let temp_dir = tempdir().unwrap();
+    sample_repo_with_tokenizer_config(temp_dir.path(), r#"{"bos_token": "<s>"}"#);
+
+    let mut mdc = ModelDeploymentCard::from_local_path(temp_dir.path(), Some("no-template"))
+        .await
+        .unwrap();
+    let err = PromptFormatter::check_chat_template(&mdc).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "model no-template has no chat template; pass --chat-template"
+    );
+
+    // --chat-template fixes it
+    let explicit = temp_dir.path().join("explicit.jinja");
+    std::fs::write(&explicit, "explicit: {{ messages[0].content }}").unwrap();
+    mdc.set_chat_template_file(&explicit).unwrap();
+    PromptFormatter::check_chat_template(&mdc).unwrap();
+
+    // An inline template beats a file
+    mdc.set_chat_template("inline: {{ messages[0].content }}");
+    PromptFormatter::check_chat_template(&mdc).unwrap();
+    assert_eq!(render_hi(&mdc).await, "inline: hi");
+
+    // Optional variables are fine, misspelled ones are not
+    mdc.set_chat_template(
+        "{% if system is defined %}{{ system }}{% endif %}{{ messages[0].content }}",
+    );
+    PromptFormatter::check_chat_template(&mdc).unwrap();
+    mdc.set_chat_template("{% for m in mesages %}{{ m.content }}{% endfor %}");
+    let err = PromptFormatter::check_chat_template(&mdc).unwrap_err();
+    assert!(
+        err.to_string()
+            .starts_with("The chat template of model no-template fails to render a sample request"),
+        "{err}"
+    );
+    assert!(err.to_string().contains("undefined"), "{err}");
+}
+
+#[tokio::test]
+async fn test_gguf_missing_metadata() {
+    use candle_core::quantized::gguf_file::{self, Value};
+
+    let temp_dir = tempdir().unwrap();
+    let gguf = temp_dir.path().join("incomplete.gguf");
+    let arch = Value::String("llama".to_string());
+    let context_length = Value::U32(2048);
+    gguf_file::write(
+        &mut std::fs::File::create(&gguf).unwrap(),
+        &[
+            ("general.architecture", &arch),
+            ("llama.context_length", &context_length),
+        ],
+        &[],
+    )
+    .unwrap();
+
+    let err = ModelDeploymentCard::from_gguf(&gguf, None)
+        .await
+        .unwrap_err();
+    let err = format!("{err:#}");
+    // Every missing key is named, not just the first
+    assert!(err.contains("tokenizer.ggml.tokens"), "{err}");
+    assert!(err.contains("llama.block_count"), "{err}");
+    assert!(!err.contains("llama.context_length"), "{err}");
+}
+
+#[tokio::test]
+async fn test_gguf_tokenizer_from_separate_dir() {
+    use candle_core::quantized::gguf_file::{self, Value};
+
+    let temp_dir = tempdir().unwrap();
+    let gguf = temp_dir.path().join("tiny.gguf");
+    let string = |s: &str| Value::String(s.to_string());
+    let tokens = Value::Array(["<unk>", "<s>", "</s>", "▁hi"].map(string).to_vec());
+    let scores = Value::Array([0.0, 0.0, 0.0, -1.0].map(Value::F32).to_vec());
+    let (arch, model, template) = (string("llama"), string("llama"), string("gguf: {{ x }}"));
+    let (context_length, embedding_length, block_count, head_count) = (
+        Value::U32(2048),
+        Value::U32(64),
+        Value::U32(1),
+        Value::U32(1),
+    );
+    let (bos, eos) = (Value::U32(1), Value::U32(2));
+    gguf_file::write(
+        &mut std::fs::File::create(&gguf).unwrap(),
+        &[
+            ("general.architecture", &arch),
+            ("llama.context_length", &context_length),
+            ("llama.embedding_length", &embedding_length),
+            ("llama.block_count", &block_count),
+            ("llama.attention.head_count", &head_count),
+            ("llama.attention.head_count_kv", &head_count),
+            ("tokenizer.ggml.model", &model),
+            ("tokenizer.ggml.tokens", &tokens),
+            ("tokenizer.ggml.scores", &scores),
+            ("tokenizer.ggml.bos_token_id", &bos),
+            ("tokenizer.ggml.eos_token_id", &eos),
+            ("tokenizer.chat_template", &template),
+        ],
+        &[],
+    )
+    .unwrap();
+
+    let mut mdc = ModelDeploymentCard::from_gguf(&gguf, None).await.unwrap();
+    assert!(matches!(mdc.tokenizer, TokenizerKind::GGUF(_)));
+
+    // Only a tokenizer.json, so the GGUF's chat template stays
+    let tokenizer_only = tempdir().unwrap();
+    std::fs::copy(
+        format!("{HF_PATH}/tokenizer.json"),
+        tokenizer_only.path().join("tokenizer.json"),
+    )
+    .unwrap();
+    mdc.set_tokenizer_dir(tokenizer_only.path()).await.unwrap();
+    assert!(matches!(mdc.tokenizer, TokenizerKind::HfTokenizerJson(_)));
+    assert!(matches!(
+        mdc.prompt_formatter,
+        Some(PromptFormatterArtifact::GGUF(_))
+    ));
+
+    // With a tokenizer_config.json its chat template replaces the GGUF's
+    let tokenizer_dir = std::path::Path::new(HF_PATH);
+    mdc.set_tokenizer_dir(tokenizer_dir).await.unwrap();
+    let tokenizer_dir = tokenizer_dir.canonicalize().unwrap();
+    match &mdc.tokenizer {
+        TokenizerKind::HfTokenizerJson(file) => {
+            assert!(file.starts_with(tokenizer_dir.to_str().unwrap()))
+        }
+        other => panic!("Unexpected tokenizer {other:?}"),
+    }
+    match &mdc.prompt_formatter {
+        Some(PromptFormatterArtifact::HfTokenizerConfigJson(file)) => {
+            assert!(file.starts_with(tokenizer_dir.to_str().unwrap()))
+        }
+        other => panic!("Unexpected prompt formatter {other:?}"),
+    }
+
+    // The model config is still the GGUF's
+    let info = mdc.model_info.get_model_info().await.unwrap();
+    assert_eq!(info.max_position_embeddings(), 2048);
+}
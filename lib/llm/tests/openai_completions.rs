@@ -14,7 +14,8 @@
 // limitations under the License.
 
 use async_openai::types::CreateCompletionRequestArgs;
-use dynamo_llm::protocols::openai::{self, completions::CompletionRequest};
+use dynamo_llm::protocols::common::llm_backend::BackendOutput;
+use dynamo_llm::protocols::openai::{self, completions::CompletionRequest, DeltaGeneratorExt};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -124,3 +125,47 @@ fn build_samples() -> Result<Vec<CompletionSample>, String> {
 
     Ok(samples)
 }
+
+fn backend_output(tokens: &[&str], log_probs: Option<Vec<f64>>) -> BackendOutput {
+    BackendOutput {
+        token_ids: (0..tokens.len() as u32).collect(),
+        tokens: tokens.iter().map(|t| Some(t.to_string())).collect(),
+        text: Some(tokens.concat()),
+        cum_log_probs: None,
+        log_probs,
+        finish_reason: None,
+    }
+}
+
+#[test]
+fn completions_logprobs_shape() {
+    let inner = CreateCompletionRequestArgs::default()
+        .model("gpt-3.5-turbo")
+        .prompt("What is the meaning of life?")
+        .logprobs(1)
+        .build()
+        .unwrap();
+    let request = CompletionRequest { inner, nvext: None };
+    let mut generator = request.response_generator();
+
+    let response = generator
+        .choice_from_postprocessor(backend_output(&["The", " answer"], Some(vec![-0.5, -1.0])))
+        .unwrap();
+    let logprobs = response.choices[0].logprobs.clone().unwrap();
+    assert_eq!(logprobs.tokens, vec!["The", " answer"]);
+    assert_eq!(logprobs.token_logprobs, vec![-0.5, -1.0]);
+    assert_eq!(logprobs.text_offset, vec![0, 3]);
+    assert_eq!(logprobs.top_logprobs.len(), 2);
+    assert_eq!(logprobs.top_logprobs[1].get(" answer"), Some(&-1.0));
+
+    // offsets continue across deltas
+    let response = generator
+        .choice_from_postprocessor(backend_output(&[" is"], Some(vec![-0.25])))
+        .unwrap();
+    let logprobs = response.choices[0].logprobs.clone().unwrap();
+    assert_eq!(logprobs.text_offset, vec![10]);
+
+    // engines that don't provide scores can't honor the request
+    let err = generator.choice_from_postprocessor(backend_output(&["42"], None));
+    assert!(err.is_err());
+}
@@ -248,7 +248,12 @@ impl Request {
             .build()
             .unwrap();
 
-        NvCreateChatCompletionRequest { inner, nvext: None }
+        NvCreateChatCompletionRequest {
+            inner,
+            nvext: None,
+            service_tier: None,
+            stop_token_ids: None,
+        }
     }
 }
 
@@ -469,3 +474,959 @@ async fn test_multi_turn_with_continuation() {
       insta::assert_snapshot!(formatted_prompt);
     });
 }
+
+/// The local mock Llama 3.1 model with its context window cut to `context_length` tokens
+fn mock_llama_with_context_length(context_length: usize) -> tempfile::TempDir {
+    let src = PathBuf::from("tests/data/sample-models/mock-llama-3.1-8b-instruct");
+    let dir = tempfile::tempdir().unwrap();
+    for entry in std::fs::read_dir(&src).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, dir.path().join(path.file_name().unwrap())).unwrap();
+    }
+    let config_path = dir.path().join("config.json");
+    let mut config: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&config_path).unwrap()).unwrap();
+    config["max_position_embeddings"] = context_length.into();
+    std::fs::write(&config_path, config.to_string()).unwrap();
+    dir
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tool_definitions_count_toward_context_length() {
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::OpenAIPreprocessor;
+
+    const CONTEXT_LENGTH: usize = 1024;
+    let model_dir = mock_llama_with_context_length(CONTEXT_LENGTH);
+    let mdc = ModelDeploymentCard::from_local_path(model_dir.path(), None)
+        .await
+        .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    let request = |max_tokens: usize, tools: Option<serde_json::Value>| {
+        let mut request = serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": "What is the weather like in Paris today?"}],
+            "max_tokens": max_tokens,
+        });
+        if let Some(tools) = tools {
+            request["tools"] = tools;
+        }
+        serde_json::from_value::<NvCreateChatCompletionRequest>(request).unwrap()
+    };
+
+    // Fill the window exactly with the completion
+    let (input, _) = preprocessor.preprocess_request(&request(0, None)).unwrap();
+    let borderline = CONTEXT_LENGTH - input.token_ids.len();
+    assert!(preprocessor
+        .preprocess_request(&request(borderline, None))
+        .is_ok());
+
+    // The template doesn't render tools, but they still count
+    let tools = serde_json::json!([{
+        "type": "function",
+        "function": {
+            "name": "get_current_weather",
+            "description": "Get the current weather in a given location. ".repeat(20),
+            "parameters": {
+                "type": "object",
+                "properties": {"location": {"type": "string"}},
+                "required": ["location"],
+            },
+        },
+    }]);
+    let err = preprocessor
+        .preprocess_request(&request(borderline, Some(tools)))
+        .unwrap_err();
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    assert!(err.message.contains("tool definitions"), "{}", err.message);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_penalties_forwarded_and_validated() {
+    use std::sync::{Arc, Mutex};
+
+    use dynamo_llm::backend::Backend;
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::{BackendInput, OpenAIPreprocessor};
+    use dynamo_llm::protocols::common::llm_backend::LLMEngineOutput;
+    use dynamo_llm::protocols::openai::chat_completions::NvCreateChatCompletionStreamResponse;
+    use dynamo_llm::protocols::Annotated;
+    use dynamo_runtime::pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContextProvider, Context, Error, ManyOut, Operator,
+        ResponseStream, ServiceBackend, ServiceFrontend, SingleIn, Source,
+    };
+    use futures::StreamExt;
+
+    /// Notes the penalties it would pass on to vllm or sglang
+    struct PenaltyEngine {
+        seen: Mutex<Vec<(&'static str, f64)>>,
+    }
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+        for PenaltyEngine
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<BackendInput>,
+        ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+            let (request, context) = request.into_parts();
+            self.seen
+                .lock()
+                .unwrap()
+                .extend(request.sampling_options.penalty_kwargs());
+            let output = futures::stream::iter([Annotated::from_data(LLMEngineOutput::stop())]);
+            Ok(ResponseStream::new(Box::pin(output), context.context()))
+        }
+    }
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc.clone()).await.unwrap();
+    let engine = Arc::new(PenaltyEngine {
+        seen: Mutex::new(vec![]),
+    });
+
+    let frontend = ServiceFrontend::<
+        SingleIn<NvCreateChatCompletionRequest>,
+        ManyOut<Annotated<NvCreateChatCompletionStreamResponse>>,
+    >::new();
+    let preprocessor_operator = preprocessor.into_operator();
+    let backend = Backend::from_mdc(mdc).await.unwrap().into_operator();
+    let pipeline = frontend
+        .link(preprocessor_operator.forward_edge())
+        .unwrap()
+        .link(backend.forward_edge())
+        .unwrap()
+        .link(ServiceBackend::from_engine(engine.clone()))
+        .unwrap()
+        .link(backend.backward_edge())
+        .unwrap()
+        .link(preprocessor_operator.backward_edge())
+        .unwrap()
+        .link(frontend)
+        .unwrap();
+
+    let request = |frequency_penalty: f32, presence_penalty: f32| {
+        serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": "hi"}],
+            "frequency_penalty": frequency_penalty,
+            "presence_penalty": presence_penalty,
+        }))
+        .unwrap()
+    };
+
+    // What the engine receives
+    let stream = pipeline
+        .generate(Context::new(request(0.5, -1.5)))
+        .await
+        .unwrap();
+    stream.collect::<Vec<_>>().await;
+    assert_eq!(
+        *engine.seen.lock().unwrap(),
+        vec![("frequency_penalty", 0.5), ("presence_penalty", -1.5)]
+    );
+
+    for (frequency_penalty, presence_penalty) in [(2.5, 0.0), (0.0, -2.1)] {
+        let err = preprocessor
+            .preprocess_request(&request(frequency_penalty, presence_penalty))
+            .unwrap_err();
+        let err = err.downcast::<HttpError>().unwrap();
+        assert_eq!(err.code, 400);
+        assert!(err.message.contains("penalty"), "{}", err.message);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_stop_token_ids() {
+    use dynamo_llm::backend::{Backend, Decoder, StopTrigger};
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::OpenAIPreprocessor;
+
+    const START_HEADER_ID: u32 = 128006;
+    const END_HEADER_ID: u32 = 128007;
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc.clone()).await.unwrap();
+    let backend = Backend::from_mdc(mdc).await.unwrap();
+
+    let request = |stop_token_ids: Vec<u32>| {
+        serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stop_token_ids": stop_token_ids,
+        }))
+        .unwrap()
+    };
+
+    let (input, _) = preprocessor
+        .preprocess_request(&request(vec![START_HEADER_ID]))
+        .unwrap();
+    let stop_token_ids = input.stop_conditions.stop_token_ids_hidden.clone().unwrap();
+    // Along with the model's EOS
+    assert_eq!(stop_token_ids, vec![START_HEADER_ID, 128009]);
+
+    let mut decoder = Decoder::new(
+        backend.tokenizer.decode_stream(false),
+        input.stop_conditions,
+    );
+    let result = decoder
+        .process_token_ids(&[END_HEADER_ID, START_HEADER_ID, END_HEADER_ID])
+        .unwrap();
+    assert_eq!(result.tokens.len(), 2);
+    assert!(matches!(
+        result.stop_trigger,
+        Some(StopTrigger::HiddenStopTokenDetected(START_HEADER_ID))
+    ));
+
+    // Past the end of the vocabulary
+    let err = preprocessor
+        .preprocess_request(&request(vec![START_HEADER_ID, 128256]))
+        .unwrap_err();
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    assert!(err.message.contains("128256"), "{}", err.message);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_stop_sequences() {
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::{OpenAIPreprocessor, PreprocessorOptions};
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let options = PreprocessorOptions {
+        max_stop_sequences: 2,
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc, options)
+        .await
+        .unwrap();
+
+    let request = |stop: &[&str]| {
+        serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": "hi"}],
+            "stop": stop,
+        }))
+        .unwrap()
+    };
+
+    let (input, _) = preprocessor
+        .preprocess_request(&request(&["a", "b"]))
+        .unwrap();
+    assert_eq!(input.stop_conditions.stop.unwrap().len(), 2);
+
+    let err = preprocessor
+        .preprocess_request(&request(&["a", "b", "c"]))
+        .unwrap_err();
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    assert!(err.message.contains("at most 2"), "{}", err.message);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_total_tokens() {
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::{OpenAIPreprocessor, PreprocessorOptions};
+
+    const MAX_TOTAL_TOKENS: usize = 200;
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let options = PreprocessorOptions {
+        max_total_tokens: Some(MAX_TOTAL_TOKENS),
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc, options)
+        .await
+        .unwrap();
+
+    let request = |content: &str, max_tokens: Option<u32>| {
+        let mut request = serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": content}],
+        });
+        if let Some(max_tokens) = max_tokens {
+            request["max_tokens"] = max_tokens.into();
+        }
+        serde_json::from_value::<NvCreateChatCompletionRequest>(request).unwrap()
+    };
+
+    // A small completion from a short prompt fits as asked
+    let (input, _) = preprocessor
+        .preprocess_request(&request("hi", Some(10)))
+        .unwrap();
+    assert_eq!(input.stop_conditions.max_tokens, Some(10));
+
+    // Without max_tokens, the completion gets what the prompt leaves
+    let (input, _) = preprocessor
+        .preprocess_request(&request("hi", None))
+        .unwrap();
+    let short_prompt = input.token_ids.len();
+    assert_eq!(
+        input.stop_conditions.max_tokens,
+        Some((MAX_TOTAL_TOKENS - short_prompt) as u32)
+    );
+
+    // A larger prompt shrinks the completion by as much
+    let (input, _) = preprocessor
+        .preprocess_request(&request(&"hi ".repeat(50), Some(1000)))
+        .unwrap();
+    let long_prompt = input.token_ids.len();
+    assert!(long_prompt > short_prompt);
+    assert_eq!(
+        input.stop_conditions.max_tokens,
+        Some((MAX_TOTAL_TOKENS - long_prompt) as u32)
+    );
+
+    // A prompt which doesn't leave room for any completion is rejected
+    let err = preprocessor
+        .preprocess_request(&request(&"hi ".repeat(500), None))
+        .unwrap_err();
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    assert!(
+        err.message.contains("at most 200 tokens"),
+        "{}",
+        err.message
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_max_total_tokens_with_default_max_tokens() {
+    use dynamo_llm::preprocessor::{OpenAIPreprocessor, PreprocessorOptions};
+
+    const DEFAULT_MAX_TOKENS: u32 = 20;
+    let preprocessor = |max_total_tokens: usize| async move {
+        let mdc = ModelDeploymentCard::from_local_path(
+            "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+            None,
+        )
+        .await
+        .unwrap();
+        let options = PreprocessorOptions {
+            max_total_tokens: Some(max_total_tokens),
+            default_max_tokens: Some(DEFAULT_MAX_TOKENS),
+            ..Default::default()
+        };
+        OpenAIPreprocessor::new_with_options(mdc, options)
+            .await
+            .unwrap()
+    };
+    let request = serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+        "model": "mock",
+        "messages": [{"role": "user", "content": "hi"}],
+    }))
+    .unwrap();
+
+    // The default applies, rather than everything the prompt leaves
+    let (input, _) = preprocessor(200)
+        .await
+        .preprocess_request(&request)
+        .unwrap();
+    let prompt = input.token_ids.len();
+    assert!(prompt + (DEFAULT_MAX_TOKENS as usize) < 200);
+    assert_eq!(input.stop_conditions.max_tokens, Some(DEFAULT_MAX_TOKENS));
+
+    // and is lowered when the prompt leaves less than that
+    let (input, _) = preprocessor(prompt + 5)
+        .await
+        .preprocess_request(&request)
+        .unwrap();
+    assert_eq!(input.stop_conditions.max_tokens, Some(5));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_tools_with_json_schema() {
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::{EngineCapabilities, OpenAIPreprocessor, PreprocessorOptions};
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+
+    let request = |tools: bool| {
+        let mut request = serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": "What's the weather in Paris?"}],
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {
+                    "name": "weather",
+                    "schema": {"type": "object", "properties": {"celsius": {"type": "number"}}},
+                },
+            },
+        });
+        if tools {
+            request["tools"] = serde_json::json!([{
+                "type": "function",
+                "function": {
+                    "name": "get_weather",
+                    "parameters": {"type": "object", "properties": {"city": {"type": "string"}}},
+                },
+            }]);
+        }
+        serde_json::from_value::<NvCreateChatCompletionRequest>(request).unwrap()
+    };
+
+    // The engine does not declare support, the combination is rejected
+    let preprocessor = OpenAIPreprocessor::new(mdc.clone()).await.unwrap();
+    preprocessor.preprocess_request(&request(false)).unwrap();
+    let err = preprocessor.preprocess_request(&request(true)).unwrap_err();
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    assert!(err.message.contains("json_schema"), "{}", err.message);
+
+    // The engine declares support, the request goes through
+    let options = PreprocessorOptions {
+        capabilities: EngineCapabilities {
+            tools_with_json_schema: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc, options)
+        .await
+        .unwrap();
+    preprocessor.preprocess_request(&request(true)).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_completions_logprobs_capability() {
+    use async_openai::types::CreateCompletionRequestArgs;
+    use dynamo_llm::http::service::error::HttpError;
+    use dynamo_llm::preprocessor::{EngineCapabilities, OpenAIPreprocessor, PreprocessorOptions};
+    use dynamo_llm::protocols::openai::completions::CompletionRequest;
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+
+    let request = |logprobs: Option<u8>| {
+        let mut inner = CreateCompletionRequestArgs::default();
+        inner.model("mock").prompt("What is the meaning of life?");
+        if let Some(logprobs) = logprobs {
+            inner.logprobs(logprobs);
+        }
+        CompletionRequest {
+            inner: inner.build().unwrap(),
+            nvext: None,
+        }
+    };
+
+    // The engine doesn't report scores, so the request fails before it is sent anywhere
+    let preprocessor = OpenAIPreprocessor::new(mdc.clone()).await.unwrap();
+    preprocessor.preprocess_request(&request(None)).unwrap();
+    let err = preprocessor
+        .preprocess_request(&request(Some(1)))
+        .unwrap_err();
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    assert!(err.message.contains("logprobs"), "{}", err.message);
+
+    let options = PreprocessorOptions {
+        capabilities: EngineCapabilities {
+            logprobs: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc, options)
+        .await
+        .unwrap();
+    preprocessor.preprocess_request(&request(Some(1))).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_completions_logprobs_end_to_end() {
+    use std::sync::{Arc, Mutex};
+
+    use async_openai::types::CreateCompletionRequestArgs;
+    use dynamo_llm::backend::Backend;
+    use dynamo_llm::preprocessor::{
+        BackendInput, EngineCapabilities, OpenAIPreprocessor, PreprocessorOptions,
+    };
+    use dynamo_llm::protocols::common::llm_backend::LLMEngineOutput;
+    use dynamo_llm::protocols::openai::completions::{CompletionRequest, CompletionResponse};
+    use dynamo_llm::protocols::Annotated;
+    use dynamo_llm::tokenizers::traits::Encoder;
+    use dynamo_runtime::pipeline::{
+        async_trait, AsyncEngine, AsyncEngineContextProvider, Context, Error, ManyOut, Operator,
+        ResponseStream, ServiceBackend, ServiceFrontend, SingleIn, Source,
+    };
+    use futures::StreamExt;
+
+    /// Generates `token_ids`, scoring them -0.5, -1.0, ...
+    struct ScoringEngine {
+        token_ids: Vec<u32>,
+        seen: Mutex<Option<u32>>,
+    }
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<LLMEngineOutput>>, Error>
+        for ScoringEngine
+    {
+        async fn generate(
+            &self,
+            request: SingleIn<BackendInput>,
+        ) -> Result<ManyOut<Annotated<LLMEngineOutput>>, Error> {
+            let (request, context) = request.into_parts();
+            *self.seen.lock().unwrap() = request.sampling_options.logprobs;
+            let mut output: Vec<_> = self
+                .token_ids
+                .iter()
+                .enumerate()
+                .map(|(i, token_id)| {
+                    Annotated::from_data(LLMEngineOutput {
+                        token_ids: vec![*token_id],
+                        tokens: None,
+                        text: None,
+                        cum_log_probs: None,
+                        log_probs: Some(vec![-0.5 * (i + 1) as f64]),
+                        finish_reason: None,
+                    })
+                })
+                .collect();
+            output.push(Annotated::from_data(LLMEngineOutput::stop()));
+            Ok(ResponseStream::new(
+                Box::pin(futures::stream::iter(output)),
+                context.context(),
+            ))
+        }
+    }
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let backend = Backend::from_mdc(mdc.clone()).await.unwrap();
+    let engine = Arc::new(ScoringEngine {
+        token_ids: backend.tokenizer.encode("The answer").unwrap().token_ids,
+        seen: Mutex::new(None),
+    });
+    assert_eq!(engine.token_ids.len(), 2);
+
+    let options = PreprocessorOptions {
+        capabilities: EngineCapabilities {
+            logprobs: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc, options)
+        .await
+        .unwrap()
+        .into_operator();
+    let backend = backend.into_operator();
+    let frontend = ServiceFrontend::<
+        SingleIn<CompletionRequest>,
+        ManyOut<Annotated<CompletionResponse>>,
+    >::new();
+    let pipeline = frontend
+        .link(preprocessor.forward_edge())
+        .unwrap()
+        .link(backend.forward_edge())
+        .unwrap()
+        .link(ServiceBackend::from_engine(engine.clone()))
+        .unwrap()
+        .link(backend.backward_edge())
+        .unwrap()
+        .link(preprocessor.backward_edge())
+        .unwrap()
+        .link(frontend)
+        .unwrap();
+
+    let request = CompletionRequest {
+        inner: CreateCompletionRequestArgs::default()
+            .model("mock")
+            .prompt("What is the meaning of life?")
+            .logprobs(1)
+            .build()
+            .unwrap(),
+        nvext: None,
+    };
+    let stream = pipeline.generate(Context::new(request)).await.unwrap();
+    let logprobs: Vec<_> = stream
+        .filter_map(|response| async move {
+            assert!(!response.is_error(), "{response:?}");
+            response.data?.choices.into_iter().next()?.logprobs
+        })
+        .collect()
+        .await;
+
+    // The count reached the engine, and its scores came back in the legacy shape
+    assert_eq!(*engine.seen.lock().unwrap(), Some(1));
+    let tokens: Vec<_> = logprobs.iter().flat_map(|l| l.tokens.clone()).collect();
+    assert_eq!(tokens.concat(), "The answer");
+    let token_logprobs: Vec<_> = logprobs
+        .iter()
+        .flat_map(|l| l.token_logprobs.clone())
+        .collect();
+    assert_eq!(token_logprobs, vec![-0.5, -1.0]);
+    let text_offset: Vec<_> = logprobs
+        .iter()
+        .flat_map(|l| l.text_offset.clone())
+        .collect();
+    assert_eq!(text_offset, vec![0, tokens[0].len() as i32]);
+    let top_logprobs: Vec<_> = logprobs
+        .iter()
+        .flat_map(|l| l.top_logprobs.clone())
+        .collect();
+    assert_eq!(top_logprobs[1].get(&tokens[1]), Some(&-1.0));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_completions_echo_and_logprobs_ignored() {
+    use std::time::Duration;
+
+    use async_openai::types::CreateCompletionRequestArgs;
+    use dynamo_llm::backend::Backend;
+    use dynamo_llm::engines::{make_engine_core_with_delays, EchoDelays};
+    use dynamo_llm::preprocessor::{OpenAIPreprocessor, PreprocessorOptions};
+    use dynamo_llm::protocols::openai::completions::{CompletionRequest, CompletionResponse};
+    use dynamo_llm::protocols::Annotated;
+    use dynamo_runtime::pipeline::{
+        Context, ManyOut, Operator, ServiceBackend, ServiceFrontend, SingleIn, Source,
+    };
+    use futures::StreamExt;
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    // The echo engine doesn't report scores
+    let engine = make_engine_core_with_delays(EchoDelays {
+        first_token: Duration::ZERO,
+        between_tokens: Duration::ZERO,
+    });
+    let options = PreprocessorOptions {
+        ignore_unsupported_logprobs: true,
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc.clone(), options)
+        .await
+        .unwrap()
+        .into_operator();
+    let backend = Backend::from_mdc(mdc).await.unwrap().into_operator();
+    let frontend = ServiceFrontend::<
+        SingleIn<CompletionRequest>,
+        ManyOut<Annotated<CompletionResponse>>,
+    >::new();
+    let pipeline = frontend
+        .link(preprocessor.forward_edge())
+        .unwrap()
+        .link(backend.forward_edge())
+        .unwrap()
+        .link(ServiceBackend::from_engine(engine))
+        .unwrap()
+        .link(backend.backward_edge())
+        .unwrap()
+        .link(preprocessor.backward_edge())
+        .unwrap()
+        .link(frontend)
+        .unwrap();
+
+    let request = CompletionRequest {
+        inner: CreateCompletionRequestArgs::default()
+            .model("mock")
+            .prompt("What is the meaning of life?")
+            .echo(true)
+            .logprobs(1)
+            .max_tokens(5_u32)
+            .build()
+            .unwrap(),
+        nvext: None,
+    };
+    let stream = pipeline.generate(Context::new(request)).await.unwrap();
+    let responses: Vec<_> = stream.collect().await;
+
+    // Served, without the scores
+    assert!(responses.iter().all(|r| !r.is_error()), "{responses:?}");
+    let choices: Vec<_> = responses
+        .into_iter()
+        .filter_map(|r| r.data)
+        .flat_map(|data| data.choices)
+        .collect();
+    assert!(choices.iter().any(|choice| !choice.text.is_empty()));
+    assert!(choices.iter().all(|choice| choice.logprobs.is_none()));
+    assert!(choices.iter().any(|choice| choice.finish_reason.is_some()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_min_tokens() {
+    use dynamo_llm::backend::{Backend, Decoder, StopTrigger};
+    use dynamo_llm::preprocessor::{OpenAIPreprocessor, PreprocessorOptions};
+
+    const START_HEADER_ID: u32 = 128006;
+    const EOT_ID: u32 = 128009;
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let backend = Backend::from_mdc(mdc.clone()).await.unwrap();
+    let options = PreprocessorOptions {
+        min_tokens: Some(3),
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc.clone(), options)
+        .await
+        .unwrap();
+
+    let request = |nvext: serde_json::Value| {
+        serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+            "model": "mock",
+            "messages": [{"role": "user", "content": "hi"}],
+            "nvext": nvext,
+        }))
+        .unwrap()
+    };
+    // EOS first, then again once the minimum is reached
+    let generated = [EOT_ID, START_HEADER_ID, EOT_ID, START_HEADER_ID];
+    let decode = |preprocessor: &OpenAIPreprocessor, nvext: serde_json::Value| {
+        let (input, _) = preprocessor.preprocess_request(&request(nvext)).unwrap();
+        let mut decoder = Decoder::new(
+            backend.tokenizer.decode_stream(false),
+            input.stop_conditions,
+        );
+        decoder.process_token_ids(&generated).unwrap()
+    };
+
+    // The EOS in the first token is ignored, the one in the third stops generation
+    let result = decode(&preprocessor, serde_json::json!({}));
+    assert_eq!(result.tokens.len(), 3);
+    assert!(matches!(
+        result.stop_trigger,
+        Some(StopTrigger::HiddenStopTokenDetected(EOT_ID))
+    ));
+
+    // The request's own min_tokens wins
+    let result = decode(&preprocessor, serde_json::json!({"min_tokens": 5}));
+    assert_eq!(result.tokens.len(), generated.len());
+    assert!(result.stop_trigger.is_none());
+
+    // Without a minimum the first token stops generation
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+    let result = decode(&preprocessor, serde_json::json!({}));
+    assert_eq!(result.tokens.len(), 1);
+    assert!(matches!(
+        result.stop_trigger,
+        Some(StopTrigger::HiddenStopTokenDetected(EOT_ID))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_merge_system_messages() {
+    use dynamo_llm::preprocessor::{
+        OpenAIPreprocessor, PreprocessorOptions, ANNOTATION_FORMATTED_PROMPT,
+    };
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let request = serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+        "model": "mock",
+        "messages": [
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "hi"},
+            {"role": "system", "content": "Answer in French."},
+        ],
+        "nvext": {"annotations": [ANNOTATION_FORMATTED_PROMPT]},
+    }))
+    .unwrap();
+    let render = |preprocessor: &OpenAIPreprocessor| {
+        let (_, annotations) = preprocessor.preprocess_request(&request).unwrap();
+        annotations[ANNOTATION_FORMATTED_PROMPT].clone()
+    };
+    const SYSTEM_HEADER: &str = "<|start_header_id|>system<|end_header_id|>";
+
+    let options = PreprocessorOptions {
+        merge_system_messages: true,
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc.clone(), options)
+        .await
+        .unwrap();
+    let prompt = render(&preprocessor);
+    assert_eq!(prompt.matches(SYSTEM_HEADER).count(), 1, "{prompt}");
+    assert!(
+        prompt.contains("Be brief.\n\nAnswer in French.<|eot_id|><|start_header_id|>user"),
+        "{prompt}"
+    );
+
+    // Passed through as they are by default
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+    let prompt = render(&preprocessor);
+    assert_eq!(prompt.matches(SYSTEM_HEADER).count(), 2, "{prompt}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_prompt_prefix_suffix() {
+    use dynamo_llm::preprocessor::{
+        OpenAIPreprocessor, PreprocessorOptions, ANNOTATION_FORMATTED_PROMPT,
+    };
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let request = serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+        "model": "mock",
+        "messages": [{"role": "user", "content": "hi"}],
+        "nvext": {"annotations": [ANNOTATION_FORMATTED_PROMPT]},
+    }))
+    .unwrap();
+    let render = |preprocessor: &OpenAIPreprocessor| {
+        let (_, annotations) = preprocessor.preprocess_request(&request).unwrap();
+        annotations[ANNOTATION_FORMATTED_PROMPT].clone()
+    };
+
+    let unwrapped = render(&OpenAIPreprocessor::new(mdc.clone()).await.unwrap());
+    assert!(unwrapped.contains("hi"), "{unwrapped}");
+
+    let options = PreprocessorOptions {
+        prompt_prefix: Some("[GUARD]".to_string()),
+        prompt_suffix: Some("[/GUARD]".to_string()),
+        ..Default::default()
+    };
+    let preprocessor = OpenAIPreprocessor::new_with_options(mdc, options)
+        .await
+        .unwrap();
+    // Around the rendered prompt, the template doesn't see them
+    assert_eq!(render(&preprocessor), format!("[GUARD]{unwrapped}[/GUARD]"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_validation_errors_reported_together() {
+    use dynamo_llm::http::service::error::{HttpError, ValidationError};
+    use dynamo_llm::preprocessor::OpenAIPreprocessor;
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    let request = serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+        "model": "mock",
+        "messages": [],
+        "temperature": 3.0,
+        "top_p": 1.5,
+    }))
+    .unwrap();
+
+    let err = preprocessor.preprocess_request(&request).unwrap_err();
+    // each problem is kept against its field
+    let validation = err.downcast_ref::<ValidationError>().unwrap();
+    let params: Vec<_> = validation
+        .errors
+        .iter()
+        .map(|e| e.param.as_deref())
+        .collect();
+    assert_eq!(
+        params,
+        [Some("messages"), Some("temperature"), Some("top_p")]
+    );
+    // and the 400 lists them all
+    let err = err.downcast::<HttpError>().unwrap();
+    assert_eq!(err.code, 400);
+    for field in ["messages", "temperature", "top_p"] {
+        assert!(err.message.contains(field), "{}", err.message);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_zero_max_tokens() {
+    use dynamo_llm::preprocessor::{BackendInput, BackendOutput, OpenAIPreprocessor};
+    use dynamo_llm::protocols::Annotated;
+    use dynamo_runtime::pipeline::{
+        async_trait, AsyncEngine, Context, Error, ManyOut, Operator, SingleIn,
+    };
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    /// Fails the request if it gets that far
+    struct NoEngine;
+
+    #[async_trait]
+    impl AsyncEngine<SingleIn<BackendInput>, ManyOut<Annotated<BackendOutput>>, Error> for NoEngine {
+        async fn generate(
+            &self,
+            _request: SingleIn<BackendInput>,
+        ) -> Result<ManyOut<Annotated<BackendOutput>>, Error> {
+            anyhow::bail!("max_tokens: 0 should not reach the engine")
+        }
+    }
+
+    let mdc = ModelDeploymentCard::from_local_path(
+        "tests/data/sample-models/mock-llama-3.1-8b-instruct",
+        None,
+    )
+    .await
+    .unwrap();
+    let preprocessor = OpenAIPreprocessor::new(mdc).await.unwrap();
+
+    let request = serde_json::from_value::<NvCreateChatCompletionRequest>(serde_json::json!({
+        "model": "mock",
+        "messages": [{"role": "user", "content": "What is the weather like in Paris today?"}],
+        "max_tokens": 0,
+    }))
+    .unwrap();
+    let (input, _) = preprocessor.preprocess_request(&request).unwrap();
+
+    let stream = preprocessor
+        .generate(Context::new(request), Arc::new(NoEngine))
+        .await
+        .unwrap();
+    let responses: Vec<_> = stream.collect().await;
+    assert_eq!(responses.len(), 1);
+    let response = responses[0].data.as_ref().unwrap();
+    let choice = &response.inner.choices[0];
+    assert_eq!(choice.delta.content.as_deref(), Some(""));
+    assert_eq!(
+        choice.finish_reason,
+        Some(async_openai::types::FinishReason::Length)
+    );
+    let usage = response.inner.usage.as_ref().unwrap();
+    assert_eq!(usage.prompt_tokens as usize, input.token_ids.len());
+    assert_eq!(usage.completion_tokens, 0);
+}
@@ -143,3 +143,65 @@ fn test_sequence() {
     }
     assert_eq!(output, TEST_PROMPTS[0]);
 }
+
+/// Decode `text` token by token at `granularity`, returning the chunks
+fn stream_chunks(text: &str, granularity: StreamGranularity) -> (usize, Vec<String>) {
+    let tokenizer = HuggingFaceTokenizer::from_file(TINYLLAMA_TOKENIZER_PATH)
+        .expect("Failed to load remote HuggingFace tokenizer");
+    let shared_tokenizer = Arc::new(tokenizer);
+    let encoding = shared_tokenizer.encode(text).expect("Failed to encode");
+
+    let mut decoder =
+        DecodeStream::new(shared_tokenizer.clone(), false).with_granularity(granularity);
+    let chunks = encoding
+        .token_ids
+        .iter()
+        .filter_map(|token_id| decoder.step(*token_id).expect("Failed to decode token_id"))
+        .collect();
+    (encoding.token_ids.len(), chunks)
+}
+
+#[test]
+fn test_stream_granularity_split_emoji() {
+    // TinyLlama has no token for the emoji, it's four byte fallback tokens
+    let text = "I like 😀 a lot";
+
+    let (_, chunks) = stream_chunks(text, StreamGranularity::Utf8);
+    assert!(
+        chunks.iter().all(|chunk| !chunk.contains('\u{FFFD}')),
+        "{chunks:?}"
+    );
+    assert!(
+        chunks.iter().any(|chunk| chunk.contains('😀')),
+        "{chunks:?}"
+    );
+    assert_eq!(chunks.concat(), text);
+
+    let (num_tokens, chunks) = stream_chunks(text, StreamGranularity::Token);
+    assert_eq!(chunks.len(), num_tokens, "{chunks:?}");
+    assert_eq!(
+        chunks
+            .iter()
+            .filter(|chunk| chunk.contains('\u{FFFD}'))
+            .count(),
+        3,
+        "{chunks:?}"
+    );
+    assert!(
+        chunks.iter().any(|chunk| chunk.contains('😀')),
+        "{chunks:?}"
+    );
+}
+
+#[test]
+fn test_stream_granularity_from_str() {
+    assert_eq!(
+        "token".parse::<StreamGranularity>().unwrap(),
+        StreamGranularity::Token
+    );
+    assert_eq!(
+        "utf8".parse::<StreamGranularity>().unwrap(),
+        StreamGranularity::Utf8
+    );
+    assert!("bytes".parse::<StreamGranularity>().is_err());
+}
@@ -0,0 +1,66 @@
+// SPDX-FileCopyrightText: Copyright (c) 2024-2025 NVIDIA CORPORATION & AFFILIATES. All rights reserved.
+// SPDX-License-Identifier: Apache-2.0
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::path::Path;
+
+use dynamo_llm::engines::warmup_cache::WarmupCache;
+
+/// Stands in for an engine with an expensive graph capture at startup
+struct CompilingEngine {
+    compiled: bool,
+}
+
+impl CompilingEngine {
+    fn start(cache: &WarmupCache) -> Self {
+        let artifact = cache.dir().join("graphs.bin");
+        if cache.is_warm() {
+            assert!(artifact.exists());
+            return CompilingEngine { compiled: false };
+        }
+        std::fs::write(&artifact, b"captured graphs").unwrap();
+        cache.mark_warm().unwrap();
+        CompilingEngine { compiled: true }
+    }
+}
+
+fn start(root: &Path, tensor_parallel_size: u32) -> (CompilingEngine, WarmupCache) {
+    let config = serde_json::json!({"tensor_parallel_size": tensor_parallel_size});
+    let cache = WarmupCache::open(root, "meta-llama/Llama-3.1-8B-Instruct", &config).unwrap();
+    (CompilingEngine::start(&cache), cache)
+}
+
+#[test]
+fn test_warmup_cache_reused_until_config_changes() {
+    let root = tempfile::tempdir().unwrap();
+
+    let (engine, first_cache) = start(root.path(), 1);
+    assert!(engine.compiled);
+
+    // Restart, same config
+    let (engine, cache) = start(root.path(), 1);
+    assert!(!engine.compiled);
+    assert_eq!(cache.dir(), first_cache.dir());
+
+    // A different model's cache is left alone
+    let other = WarmupCache::open(root.path(), "other-model", &serde_json::json!({})).unwrap();
+    other.mark_warm().unwrap();
+
+    // Config changed, the old artifacts are no use
+    let (engine, cache) = start(root.path(), 2);
+    assert!(engine.compiled);
+    assert_ne!(cache.dir(), first_cache.dir());
+    assert!(!first_cache.dir().exists());
+    assert!(other.is_warm());
+}
@@ -94,6 +94,9 @@ pub struct ComponentEndpointInfo {
     pub namespace: String,
     pub lease_id: i64,
     pub transport: TransportType,
+    /// Region of the worker serving the endpoint, for clients to prefer nearby endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
 }
 
 /// A [Component] a discoverable entity in the distributed runtime.
@@ -342,6 +345,13 @@ impl Namespace {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// What the [`Component::service_name`] of every component in this namespace starts with,
+    /// to find all their endpoints in etcd. Other namespaces can share it, `a` and `a-b` both
+    /// start `a_`, so check the namespace of what is found.
+    pub fn service_name_prefix(&self) -> String {
+        Slug::unique_prefix(&format!("{}_", self.name))
+    }
 }
 
 // Custom validator function
@@ -15,7 +15,7 @@
 
 use crate::pipeline::{
     network::egress::push::{AddressedPushRouter, AddressedRequest, PushRouter},
-    AsyncEngine, Data, ManyOut, SingleIn,
+    AsyncEngine, Data, ManyOut, PipelineError, SingleIn,
 };
 use rand::Rng;
 use std::collections::HashMap;
@@ -72,7 +72,14 @@ pub struct Client<T: Data, U: Data> {
 #[derive(Clone, Debug)]
 enum EndpointSource {
     Static,
-    Dynamic(tokio::sync::watch::Receiver<Vec<i64>>),
+    Dynamic(tokio::sync::watch::Receiver<Vec<Instance>>),
+}
+
+/// An instance of the endpoint discovered in etcd
+#[derive(Clone, Debug, PartialEq)]
+struct Instance {
+    id: i64,
+    region: Option<String>,
 }
 
 impl<T, U> Client<T, U>
@@ -136,7 +143,8 @@ where
                         let key = String::from_utf8(kv.key().to_vec());
                         let val = serde_json::from_slice::<ComponentEndpointInfo>(kv.value());
                         if let (Ok(key), Ok(val)) = (key, val) {
-                            map.insert(key.clone(), val.lease_id);
+                            let instance = Instance { id: val.lease_id, region: val.region };
+                            map.insert(key.clone(), instance);
                         } else {
                             tracing::error!("Unable to parse put endpoint event; shutting down endpoint watcher for prefix: {}", prefix);
                             break;
@@ -153,9 +161,9 @@ where
                     }
                 }
 
-                let endpoint_ids: Vec<i64> = map.values().cloned().collect();
+                let instances: Vec<Instance> = map.values().cloned().collect();
 
-                if watch_tx.send(endpoint_ids).is_err() {
+                if watch_tx.send(instances).is_err() {
                     tracing::debug!("Unable to send watch updates; shutting down endpoint watcher for prefix: {}", prefix);
                     break;
                 }
@@ -188,7 +196,22 @@ where
     pub fn endpoint_ids(&self) -> Vec<i64> {
         match &self.endpoints {
             EndpointSource::Static => vec![0],
-            EndpointSource::Dynamic(watch_rx) => watch_rx.borrow().clone(),
+            EndpointSource::Dynamic(watch_rx) => watch_rx
+                .borrow()
+                .iter()
+                .map(|instance| instance.id)
+                .collect(),
+        }
+    }
+
+    /// The endpoints to pick from for a request: the ones in our region if there are any,
+    /// otherwise all of them
+    fn routable_endpoint_ids(&self) -> Vec<i64> {
+        match &self.endpoints {
+            EndpointSource::Static => vec![0],
+            EndpointSource::Dynamic(watch_rx) => {
+                prefer_region(&watch_rx.borrow(), self.endpoint.component.drt.region())
+            }
         }
     }
 
@@ -211,6 +234,24 @@ where
         Ok(())
     }
 
+    /// Wait up to `timeout` for the instance registered with lease `instance_id` to be
+    /// discovered. Errors listing the instances there are if it isn't.
+    pub async fn wait_for_instance(
+        &self,
+        instance_id: i64,
+        timeout: std::time::Duration,
+    ) -> Result<()> {
+        let EndpointSource::Dynamic(rx) = self.endpoints.clone() else {
+            anyhow::bail!(
+                "{} is a static endpoint, it has no instances to choose from",
+                self.endpoint.path()
+            );
+        };
+        wait_for_instance(rx, instance_id, timeout)
+            .await
+            .map_err(|err| anyhow::anyhow!("{}: {err}", self.endpoint.path()))
+    }
+
     /// Is this component know at startup and not discovered via etcd?
     pub fn is_static(&self) -> bool {
         matches!(self.endpoints, EndpointSource::Static)
@@ -221,13 +262,10 @@ where
         let counter = self.counter.fetch_add(1, Ordering::Relaxed);
 
         let endpoint_id = {
-            let endpoints = self.endpoint_ids();
+            let endpoints = self.routable_endpoint_ids();
             let count = endpoints.len();
             if count == 0 {
-                return Err(error!(
-                    "no endpoints found for endpoint {:?}",
-                    self.endpoint.etcd_path()
-                ));
+                return Err(PipelineError::NoEndpoints(self.endpoint.etcd_path()).into());
             }
             let offset = counter % count as u64;
             endpoints[offset as usize]
@@ -243,13 +281,10 @@ where
     /// Issue a request to a random endpoint
     pub async fn random(&self, request: SingleIn<T>) -> Result<ManyOut<U>> {
         let endpoint_id = {
-            let endpoints = self.endpoint_ids();
+            let endpoints = self.routable_endpoint_ids();
             let count = endpoints.len();
             if count == 0 {
-                return Err(error!(
-                    "no endpoints found for endpoint {:?}",
-                    self.endpoint.etcd_path()
-                ));
+                return Err(PipelineError::NoEndpoints(self.endpoint.etcd_path()).into());
             }
             let counter = rand::rng().random::<u64>();
             let offset = counter % count as u64;
@@ -293,6 +328,50 @@ where
     }
 }
 
+/// The ids of the instances in `region`, or of all of them if none are in it, or we don't have
+/// a region
+fn prefer_region(instances: &[Instance], region: Option<&str>) -> Vec<i64> {
+    let local: Vec<i64> = instances
+        .iter()
+        .filter(|instance| region.is_some() && instance.region.as_deref() == region)
+        .map(|instance| instance.id)
+        .collect();
+    if !local.is_empty() {
+        return local;
+    }
+    if region.is_some() && !instances.is_empty() {
+        tracing::trace!(
+            region,
+            "No endpoints in our region, using the other regions"
+        );
+    }
+    instances.iter().map(|instance| instance.id).collect()
+}
+
+/// Wait for `instance_id` to be among the instances in `rx`
+async fn wait_for_instance(
+    mut rx: tokio::sync::watch::Receiver<Vec<Instance>>,
+    instance_id: i64,
+    timeout: std::time::Duration,
+) -> Result<()> {
+    let found = rx.wait_for(|instances| instances.iter().any(|i| i.id == instance_id));
+    if let Ok(Ok(_)) = tokio::time::timeout(timeout, found).await {
+        return Ok(());
+    }
+    let running: Vec<String> = rx
+        .borrow()
+        .iter()
+        .map(|instance| format!("{:x}", instance.id))
+        .collect();
+    if running.is_empty() {
+        anyhow::bail!("instance {instance_id:x} not found, there are no instances running");
+    }
+    anyhow::bail!(
+        "instance {instance_id:x} not found, running instances: {}",
+        running.join(", ")
+    );
+}
+
 async fn router(endpoint: &Endpoint) -> Result<Arc<AddressedPushRouter>> {
     AddressedPushRouter::new(
         endpoint.component.drt.nats_client.client().clone(),
@@ -317,3 +396,56 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(id: i64, region: Option<&str>) -> Instance {
+        Instance {
+            id,
+            region: region.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_prefer_region() {
+        let instances = vec![
+            instance(1, Some("us-east")),
+            instance(2, Some("eu-west")),
+            instance(3, Some("us-east")),
+            instance(4, None),
+        ];
+
+        // Only the local endpoints while there are any
+        assert_eq!(prefer_region(&instances, Some("us-east")), vec![1, 3]);
+        assert_eq!(prefer_region(&instances, Some("eu-west")), vec![2]);
+
+        // Falls back to the other regions when none are local
+        let remote = vec![instance(2, Some("eu-west")), instance(4, None)];
+        assert_eq!(prefer_region(&remote, Some("us-east")), vec![2, 4]);
+
+        // Without a region of our own any endpoint will do
+        assert_eq!(prefer_region(&instances, None), vec![1, 2, 3, 4]);
+        assert!(prefer_region(&[], Some("us-east")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_instance() {
+        let (tx, rx) = tokio::sync::watch::channel(vec![instance(0x1a, None)]);
+        let timeout = std::time::Duration::from_secs(5);
+
+        // Discovered a little after the first
+        let waiting = tokio::spawn(wait_for_instance(rx.clone(), 0x2b, timeout));
+        tx.send(vec![instance(0x1a, None), instance(0x2b, None)])
+            .unwrap();
+        waiting.await.unwrap().unwrap();
+
+        let err = wait_for_instance(rx, 0x3c, std::time::Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("instance 3c not found"), "{err}");
+        assert!(err.contains("1a, 2b"), "{err}");
+    }
+}
@@ -121,6 +121,7 @@ impl EndpointConfigBuilder {
             namespace: endpoint.component.namespace.name.clone(),
             lease_id,
             transport: TransportType::NatsTcp(endpoint.subject_to(lease_id)),
+            region: endpoint.drt().region().map(str::to_string),
         };
 
         let info = serde_json::to_vec_pretty(&info)?;
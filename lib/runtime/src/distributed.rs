@@ -31,7 +31,7 @@ use tokio_util::sync::CancellationToken;
 impl DistributedRuntime {
     pub async fn new(runtime: Runtime, config: DistributedConfig) -> Result<Self> {
         let secondary = runtime.secondary();
-        let (etcd_config, nats_config, is_static) = config.dissolve();
+        let (etcd_config, nats_config, is_static, region) = config.dissolve();
 
         let runtime_clone = runtime.clone();
 
@@ -70,6 +70,7 @@ impl DistributedRuntime {
             tcp_server: Arc::new(OnceCell::new()),
             component_registry: component::Registry::new(),
             is_static,
+            region,
         })
     }
 
@@ -155,6 +156,12 @@ impl DistributedRuntime {
     pub fn child_token(&self) -> CancellationToken {
         self.runtime.child_token()
     }
+
+    /// The region our endpoints are registered in. Clients send requests to endpoints in the
+    /// same region while there are any.
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
 }
 
 #[derive(Dissolve)]
@@ -162,6 +169,8 @@ pub struct DistributedConfig {
     pub etcd_config: etcd::ClientOptions,
     pub nats_config: nats::ClientOptions,
     pub is_static: bool,
+    /// See [`DistributedRuntime::region`]
+    pub region: Option<String>,
 }
 
 impl DistributedConfig {
@@ -170,6 +179,7 @@ impl DistributedConfig {
             etcd_config: etcd::ClientOptions::default(),
             nats_config: nats::ClientOptions::default(),
             is_static,
+            region: None,
         }
     }
 
@@ -178,6 +188,7 @@ impl DistributedConfig {
             etcd_config: etcd::ClientOptions::default(),
             nats_config: nats::ClientOptions::default(),
             is_static: false,
+            region: None,
         };
 
         config.etcd_config.attach_lease = false;
@@ -88,4 +88,7 @@ pub struct DistributedRuntime {
     // Will only have static components that are not discoverable via etcd, they must be know at
     // startup. Will not start etcd.
     is_static: bool,
+
+    // Region our endpoints advertise, and which our clients prefer endpoints in
+    region: Option<String>,
 }
@@ -353,18 +353,13 @@ impl AsyncEngineContext for Controller {
 
     async fn stopped(&self) {
         let mut rx = self.rx.clone();
-        if *rx.borrow_and_update() != State::Live {
-            return;
-        }
-        let _ = rx.changed().await;
+        let _ = rx.wait_for(|state| *state != State::Live).await;
     }
 
     async fn killed(&self) {
+        // a stop changes the state too, but is not a kill
         let mut rx = self.rx.clone();
-        if *rx.borrow_and_update() == State::Killed {
-            return;
-        }
-        let _ = rx.changed().await;
+        let _ = rx.wait_for(|state| *state == State::Killed).await;
     }
 
     fn stop_generating(&self) {
@@ -372,7 +367,15 @@ impl AsyncEngineContext for Controller {
     }
 
     fn stop(&self) {
-        let _ = self.tx.send(State::Stopped);
+        // a killed stream stays killed
+        self.tx.send_if_modified(|state| {
+            if *state == State::Live {
+                *state = State::Stopped;
+                true
+            } else {
+                false
+            }
+        });
     }
 
     fn kill(&self) {
@@ -453,6 +456,25 @@ mod tests {
         assert_eq!(ctx.current.message, "Processed length: 5");
     }
 
+    #[tokio::test]
+    async fn test_stop_is_not_kill() {
+        let ctx = Context::new(());
+        let ctx = ctx.context();
+
+        ctx.stop_generating();
+        assert!(ctx.is_stopped());
+        assert!(!ctx.is_killed());
+        ctx.stopped().await;
+        let killed = tokio::time::timeout(std::time::Duration::from_millis(50), ctx.killed());
+        assert!(killed.await.is_err(), "a stop must not wake up killed()");
+
+        ctx.kill();
+        ctx.killed().await;
+        // stopping again doesn't undo the kill
+        ctx.stop();
+        assert!(ctx.is_killed());
+    }
+
     #[test]
     fn test_into_context() {
         let ctx = Context::new(Input {
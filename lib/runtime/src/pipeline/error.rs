@@ -84,6 +84,10 @@ pub enum PipelineError {
     #[error("Failed to establish a streaming connection: {0}")]
     ConnectionFailed(String),
 
+    /// No instance of the endpoint is registered, so there is nowhere to send the request.
+    #[error("no endpoints found for endpoint {0:?}")]
+    NoEndpoints(String),
+
     #[error("Generate Error: {0}")]
     GenerateError(Error),
 
@@ -155,7 +155,8 @@ where
         let _response = self
             .req_transport
             .request(address.to_string(), buffer)
-            .await?;
+            .await
+            .map_err(|err| PipelineError::ControlPlaneRequestError(err.to_string()))?;
 
         log::trace!(request_id, "awaiting transport handshake");
         let response_stream = response_stream_provider
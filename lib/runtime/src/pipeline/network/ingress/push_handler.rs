@@ -97,7 +97,20 @@ where
 
         let context = stream.context();
 
-        while let Some(resp) = stream.next().await {
+        loop {
+            // A killed request gets no more responses, so don't wait for the engine's next one.
+            // Dropping the stream tells engines which don't watch the context to stop.
+            let resp = tokio::select! {
+                biased;
+                _ = context.killed() => {
+                    tracing::debug!("Request {} was killed by the requester", context.id());
+                    break;
+                }
+                resp = stream.next() => match resp {
+                    Some(resp) => resp,
+                    None => break,
+                },
+            };
             tracing::trace!("Sending response: {:?}", resp);
             let resp_bytes = serde_json::to_vec(&resp)
                 .expect("fatal error: invalid response object - this should never happen");
@@ -186,4 +186,57 @@ mod tests {
 
         // assert!(data.is_none());
     }
+
+    #[tokio::test]
+    async fn test_tcp_stream_cancellation_reaches_the_responder() {
+        let options = server::ServerOptions::builder().port(9125).build().unwrap();
+        let server = server::TcpStreamServer::new(options).await.unwrap();
+
+        let requester = Context::new(());
+        let options = StreamOptions::builder()
+            .context(requester.context())
+            .enable_request_stream(false)
+            .enable_response_stream(true)
+            .build()
+            .unwrap();
+        let pending_connection = server.register(options).await;
+        let connection_info = pending_connection
+            .recv_stream
+            .as_ref()
+            .unwrap()
+            .connection_info
+            .clone();
+
+        // the remote worker, with its own copy of the request's context
+        let responder = Context::with_id((), requester.id().to_string());
+        let mut send_stream =
+            client::TcpClient::create_response_steam(responder.context(), connection_info)
+                .await
+                .unwrap();
+        send_stream.send_prologue(None).await.unwrap();
+        let recv_stream = pending_connection
+            .recv_stream
+            .unwrap()
+            .stream_provider
+            .await
+            .unwrap()
+            .unwrap();
+
+        let timeout = std::time::Duration::from_secs(5);
+
+        // stopping the request locally stops it on the remote, without killing it
+        requester.context().stop_generating();
+        tokio::time::timeout(timeout, responder.context().stopped())
+            .await
+            .expect("the stop never reached the responder");
+        assert!(!responder.context().is_killed());
+
+        // dropping the response stream, e.g. when the client disconnected, kills it
+        drop(recv_stream);
+        tokio::time::timeout(timeout, responder.context().killed())
+            .await
+            .expect("the kill never reached the responder");
+
+        drop(send_stream);
+    }
 }
@@ -50,6 +50,10 @@ pub struct Endpoint {
     pub namespace: String,
     pub component: String,
     pub name: String,
+    /// Only this instance of the endpoint, by the lease id it registered with. Written
+    /// `namespace/component/endpoint@<lease id>`, the id in hex as in the instance's etcd key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_id: Option<LeaseId>,
 }
 
 impl PartialEq<Vec<&str>> for Endpoint {
@@ -74,6 +78,7 @@ impl Default for Endpoint {
             namespace: DEFAULT_NAMESPACE.to_string(),
             component: DEFAULT_COMPONENT.to_string(),
             name: DEFAULT_ENDPOINT.to_string(),
+            instance_id: None,
         }
     }
 }
@@ -94,6 +99,8 @@ impl From<&str> for Endpoint {
     /// - "namespace.component.endpoint" -> ["namespace", "component", "endpoint"]
     /// - "namespace/component" -> ["namespace", "component", "DEFAULT_E"]
     /// - "namespace.component.endpoint.other.parts" -> ["namespace", "component", "endpoint_other_parts"]
+    /// - "namespace/component/endpoint@694d967ca5efd804" -> ["namespace", "component", "endpoint"],
+    ///   only instance 0x694d967ca5efd804
     ///
     /// # Examples
     /// ```ignore
@@ -107,6 +114,9 @@ impl From<&str> for Endpoint {
     fn from(input: &str) -> Self {
         let mut result = Endpoint::default();
 
+        let (input, instance_id) = split_instance_id(input);
+        result.instance_id = instance_id;
+
         // Split the input string on either '.' or '/'
         let elements: Vec<&str> = input
             .trim_matches([' ', '/', '.'])
@@ -147,7 +157,7 @@ impl FromStr for Endpoint {
     /// This is implemented in terms of [`From<&str>`].
     ///
     /// # Errors
-    /// Does not fail
+    /// If there is an `@` followed by something other than a lease id
     ///
     /// # Examples
     /// ```ignore
@@ -160,10 +170,36 @@ impl FromStr for Endpoint {
     /// assert_eq!(endpoint.name, "endpoint");
     /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, id)) = s.rsplit_once('@') {
+            if parse_instance_id(id).is_none() {
+                return Err(PipelineError::Generic(format!(
+                    "Invalid instance id '{id}' in '{s}', expected the lease id in hex, e.g. \
+                     namespace/component/endpoint@694d967ca5efd804"
+                )));
+            }
+        }
         Ok(Endpoint::from(s))
     }
 }
 
+/// The path and the instance id of `path@<instance id>`. The `@` stays in the path if what
+/// follows isn't an id.
+fn split_instance_id(input: &str) -> (&str, Option<LeaseId>) {
+    match input.rsplit_once('@') {
+        Some((path, id)) => match parse_instance_id(id) {
+            Some(id) => (path, Some(id)),
+            None => (input, None),
+        },
+        None => (input, None),
+    }
+}
+
+/// A lease id in hex, as it appears in etcd keys and NATS subjects
+fn parse_instance_id(id: &str) -> Option<LeaseId> {
+    let id = id.strip_prefix("0x").unwrap_or(id);
+    LeaseId::from_str_radix(id, 16).ok()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum RouterType {
@@ -247,6 +283,19 @@ mod tests {
         assert_eq!(endpoint.name, "endpoint3");
     }
 
+    #[test]
+    fn test_endpoint_instance_id() {
+        let endpoint: Endpoint = "ns/comp/generate@694d967ca5efd804".parse().unwrap();
+        assert_eq!(endpoint, vec!["ns", "comp", "generate"]);
+        assert_eq!(endpoint.instance_id, Some(0x694d967ca5efd804));
+
+        let endpoint: Endpoint = "ns/comp/generate".parse().unwrap();
+        assert_eq!(endpoint.instance_id, None);
+
+        let err = "ns/comp/generate@first".parse::<Endpoint>().unwrap_err();
+        assert!(err.to_string().contains("'first'"), "{err}");
+    }
+
     #[test]
     fn test_endpoint_from() {
         let result = Endpoint::from("component");
@@ -59,22 +59,33 @@ impl Slug {
     /// Like slugify but also add a four byte hash on the end, in case two different strings slug
     /// to the same thing.
     pub fn slugify_unique(s: &str) -> Slug {
-        let out = s
-            .to_lowercase()
-            .chars()
-            .map(|c| {
-                let is_valid = c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_';
-                if is_valid {
-                    c
-                } else {
-                    REPLACEMENT_CHAR
-                }
-            })
-            .collect::<String>();
+        let out = replace_invalid(s);
         let hash = blake3::hash(s.as_bytes()).to_string();
         let out = format!("{out}_{}", &hash[(hash.len() - 8)..]);
         Slug::new(out)
     }
+
+    /// What [`Slug::slugify_unique`] of every string starting with `prefix` starts with. Other
+    /// prefixes can share it, `a-` and `a_` are both `a_`.
+    pub fn unique_prefix(prefix: &str) -> String {
+        replace_invalid(prefix)
+            .trim_start_matches(REPLACEMENT_CHAR)
+            .to_string()
+    }
+}
+
+fn replace_invalid(s: &str) -> String {
+    s.to_lowercase()
+        .chars()
+        .map(|c| {
+            let is_valid = c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_';
+            if is_valid {
+                c
+            } else {
+                REPLACEMENT_CHAR
+            }
+        })
+        .collect()
 }
 
 impl fmt::Display for Slug {
@@ -164,3 +175,20 @@ impl PartialEq<str> for Slug {
         self.0 == other
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_prefix() {
+        for (prefix, rest) in [("ns_", "backend"), ("My-NS_", "llama"), ("_ns_", "x")] {
+            let slug = Slug::slugify_unique(&format!("{prefix}{rest}"));
+            assert!(
+                slug.to_string().starts_with(&Slug::unique_prefix(prefix)),
+                "{slug} {prefix}"
+            );
+        }
+        assert_eq!(Slug::unique_prefix("My-NS_"), "my_ns_");
+    }
+}
@@ -36,7 +36,13 @@ use super::{error, CancellationToken, Result, Runtime, RuntimeConfig};
 
 use futures::Future;
 use once_cell::sync::OnceCell;
-use std::{sync::Mutex, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
 use tokio::{signal, task::JoinHandle};
 
 static RT: OnceCell<tokio::runtime::Runtime> = OnceCell::new();
@@ -56,6 +62,33 @@ pub const DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_DEBUG: u64 = 5;
 /// Default graceful shutdown timeout in seconds in release mode
 pub const DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_RELEASE: u64 = 30;
 
+/// Set by the application with [`set_graceful_shutdown_timeout`], 0 if it didn't
+static GRACEFUL_SHUTDOWN_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+/// Give the application `timeout` to shut down, instead of the environment's or the default. For
+/// applications which only learn how long they need after they started, e.g. from their flags.
+/// Takes effect if called before the shutdown signal.
+pub fn set_graceful_shutdown_timeout(timeout: Duration) {
+    GRACEFUL_SHUTDOWN_TIMEOUT.store(timeout.as_secs().max(1), Ordering::Relaxed);
+}
+
+/// How many seconds the application has to shut down
+fn graceful_shutdown_timeout() -> u64 {
+    match GRACEFUL_SHUTDOWN_TIMEOUT.load(Ordering::Relaxed) {
+        0 => std::env::var(DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT)
+            .ok()
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or({
+                if cfg!(debug_assertions) {
+                    DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_DEBUG
+                } else {
+                    DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_RELEASE
+                }
+            }),
+        timeout => timeout,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Worker {
     runtime: Runtime,
@@ -106,17 +139,6 @@ impl Worker {
         let primary = runtime.primary();
         let secondary = runtime.secondary();
 
-        let timeout = std::env::var(DYN_WORKER_GRACEFUL_SHUTDOWN_TIMEOUT)
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok())
-            .unwrap_or({
-                if cfg!(debug_assertions) {
-                    DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_DEBUG
-                } else {
-                    DEFAULT_GRACEFUL_SHUTDOWN_TIMEOUT_RELEASE
-                }
-            });
-
         INIT.set(Mutex::new(Some(secondary.spawn(async move {
             // start signal handler
             tokio::spawn(signal_handler(runtime.cancellation_token.clone()));
@@ -130,13 +152,17 @@ impl Worker {
                 f(runtime).await
             });
 
-            tokio::select! {
+            // Read once the application had its chance to set it
+            let timeout = tokio::select! {
                 _ = cancel_token.cancelled() => {
+                    let timeout = graceful_shutdown_timeout();
                     tracing::debug!("{}", SHUTDOWN_MESSAGE);
                     tracing::debug!("{} {} seconds", SHUTDOWN_TIMEOUT_MESSAGE, timeout);
+                    timeout
                 }
 
                 _ = app_tx.closed() => {
+                    graceful_shutdown_timeout()
                 }
             };
 
@@ -209,3 +235,17 @@ async fn signal_handler(cancel_token: CancellationToken) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_graceful_shutdown_timeout() {
+        set_graceful_shutdown_timeout(Duration::from_secs(90));
+        assert_eq!(graceful_shutdown_timeout(), 90);
+        // Zero would mean unset
+        set_graceful_shutdown_timeout(Duration::ZERO);
+        assert_eq!(graceful_shutdown_timeout(), 1);
+    }
+}